@@ -17,19 +17,31 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 
+use std::{path::PathBuf, sync::LazyLock};
+
 use mimalloc::MiMalloc;
 use modules::{
+    alert::dispatch::SearchAlerts,
+    backup::restore::restore_backup,
+    blob::{crypto::DataKeys, migrate::migrate_blobs, BLOB_STORE},
     common::rustls::RustMailerTls,
     context::{executors::EmailClientExecutors, Initialize},
+    envelope::attachment::run_extraction_worker,
     error::BichonResult,
+    indexer::{manager::EmlIndexManager, reindex::EnvelopeReindexer},
+    legal_hold::LegalHolds,
     logger,
     rest::start_http_server,
     tasks::PeriodicTasks,
     token::root::ensure_root_token,
+    worm::WormLedger,
 };
 use tracing::info;
 
-use crate::modules::{common::signal::SignalManager, settings::dir::DataDirManager};
+use crate::modules::{
+    common::signal::SignalManager,
+    settings::{cli::SETTINGS, dir::DataDirManager, master_key::MasterKey},
+};
 
 mod modules;
 
@@ -47,6 +59,10 @@ static LOGO: &str = r#"
 "#;
 #[tokio::main]
 async fn main() -> BichonResult<()> {
+    // the worker answers on stdout, where logs may go
+    if SETTINGS.bichon_attachment_text_worker {
+        return run_extraction_worker();
+    }
     logger::initialize_logging();
     info!("{}", LOGO);
     info!("Starting bichon-server");
@@ -54,6 +70,23 @@ async fn main() -> BichonResult<()> {
     info!("Git:      [{}]", env!("GIT_HASH"));
     info!("GitHub:   https://github.com/rustmailer/bichon");
 
+    if let Some(target) = SETTINGS.bichon_migrate_blobs_to {
+        DataDirManager::initialize().await?;
+        DataKeys::initialize().await?;
+        return migrate_blobs(target).await;
+    }
+
+    if SETTINGS.bichon_convert_eml_store {
+        DataDirManager::initialize().await?;
+        DataKeys::initialize().await?;
+        return EmlIndexManager::convert_legacy_store();
+    }
+
+    if let Some(source) = &SETTINGS.bichon_restore_from {
+        DataDirManager::initialize().await?;
+        return restore_backup(PathBuf::from(source)).await;
+    }
+
     if let Err(error) = initialize().await {
         eprintln!("{:?}", error);
         return Err(error);
@@ -68,8 +101,16 @@ async fn initialize() -> BichonResult<()> {
     // SETTINGS.validate()?;
     SignalManager::initialize().await?;
     DataDirManager::initialize().await?;
+    DataKeys::initialize().await?;
+    EmlIndexManager::check_layout()?;
+    MasterKey::initialize().await?;
+    LazyLock::force(&BLOB_STORE);
     ensure_root_token().await?;
     RustMailerTls::initialize().await?;
+    EnvelopeReindexer::initialize().await?;
+    SearchAlerts::initialize().await?;
+    LegalHolds::initialize().await?;
+    WormLedger::initialize().await?;
     EmailClientExecutors::initialize().await?;
     PeriodicTasks::start_background_tasks();
     Ok(())
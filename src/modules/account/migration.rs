@@ -50,9 +50,11 @@ use crate::modules::database::{
     paginate_query_primary_scan_all_impl, secondary_find_impl, update_impl,
 };
 use crate::modules::error::code::ErrorCode;
+use crate::modules::legal_hold::LEGAL_HOLDS;
 use crate::modules::oauth2::token::OAuth2AccessToken;
 use crate::modules::rest::response::DataPage;
 use crate::modules::token::AccessToken;
+use crate::modules::worm::ensure_writable;
 use crate::raise_error;
 
 pub type AccountModel = AccountV2;
@@ -140,7 +142,7 @@ impl AccountV2 {
             use_proxy: request.use_proxy,
             folder_limit: request.folder_limit,
             use_dangerous: request.use_dangerous,
-            pgp_key: request.pgp_key,
+            pgp_key: request.pgp_key.filter(|k| !k.trim().is_empty()),
         })
     }
 
@@ -217,6 +219,8 @@ impl AccountV2 {
 
     pub async fn delete(account_id: u64) -> BichonResult<()> {
         let account = Self::get(account_id).await?;
+        ensure_writable("accounts")?;
+        LEGAL_HOLDS.ensure_account_not_held(account_id).await?;
         if let Err(error) = Self::cleanup_account_resources_sequential(&account).await {
             tracing::error!(
                 "[CLEANUP_ACCOUNT_ERROR] Account {}: failed to cleanup resources: {:#?}",
@@ -397,7 +401,7 @@ impl AccountV2 {
         }
 
         if let Some(pgp_key) = request.pgp_key {
-            new.pgp_key = Some(pgp_key);
+            new.pgp_key = Some(pgp_key).filter(|k| !k.trim().is_empty());
         }
 
         new.updated_at = utc_now!();
@@ -20,6 +20,7 @@
 pub mod dispatcher;
 pub mod entity;
 pub mod payload;
+pub mod pgp;
 pub mod since;
 pub mod state;
 pub mod migration;
@@ -20,6 +20,7 @@ use std::collections::BTreeSet;
 
 use crate::modules::account::entity::ImapConfig;
 use crate::modules::account::migration::{AccountModel, AccountType};
+use crate::modules::account::pgp::validate_public_key;
 use crate::modules::account::since::DateSince;
 use crate::modules::error::code::ErrorCode;
 use crate::modules::error::BichonResult;
@@ -43,6 +44,8 @@ pub struct AccountCreateRequest {
     pub sync_interval_min: Option<i64>,
     pub use_proxy: Option<u64>,
     pub use_dangerous: bool,
+    /// Armored OpenPGP public key. When set, archived messages are encrypted with it
+    /// before being stored, and only envelope metadata stays searchable.
     pub pgp_key: Option<String>,
 }
 
@@ -51,6 +54,9 @@ impl AccountCreateRequest {
         if let Some(date_since) = self.date_since.as_ref() {
             date_since.validate()?;
         }
+        if let Some(pgp_key) = self.pgp_key.as_deref().filter(|k| !k.trim().is_empty()) {
+            validate_public_key(pgp_key)?;
+        }
         match self.account_type {
             AccountType::IMAP => {
                 match &self.imap {
@@ -135,15 +141,28 @@ pub struct AccountUpdateRequest {
     pub use_proxy: Option<u64>,
 
     pub use_dangerous: Option<bool>,
-
+    /// Armored OpenPGP public key messages archived from now on are encrypted with, or an
+    /// empty string to store them unencrypted. Messages already stored are left as they are.
     pub pgp_key: Option<String>,
 }
 
+/// Secret key unlocking the messages of an account encrypted at rest.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize, Object)]
+pub struct PgpUnlockRequest {
+    /// Armored OpenPGP secret key matching the account's `pgp_key`.
+    pub private_key: String,
+    /// Passphrase of the secret key, if it is protected.
+    pub passphrase: Option<String>,
+}
+
 impl AccountUpdateRequest {
     pub fn validate_update_request(&self, account: &AccountModel) -> BichonResult<()> {
         if let Some(date_since) = self.date_since.as_ref() {
             date_since.validate()?;
         }
+        if let Some(pgp_key) = self.pgp_key.as_deref().filter(|k| !k.trim().is_empty()) {
+            validate_public_key(pgp_key)?;
+        }
         if matches!(account.account_type, AccountType::IMAP) {
             if let Some(mailboxes) = self.sync_folders.as_ref() {
                 if mailboxes.is_empty() {
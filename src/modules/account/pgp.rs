@@ -0,0 +1,259 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::{
+    sync::{Arc, LazyLock},
+    time::{Duration, Instant},
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use dashmap::DashMap;
+use pgp::{
+    crypto::sym::SymmetricKeyAlgorithm,
+    ser::Serialize,
+    types::{CompressionAlgorithm, PublicKeyTrait},
+    Deserializable, Message, SignedPublicKey, SignedSecretKey,
+};
+use poem::{FromRequest, Request, RequestBody};
+use rand_core::OsRng;
+
+use crate::{
+    modules::{
+        account::migration::AccountModel,
+        common::{auth::ClientContext, create_api_error_response},
+        error::{code::ErrorCode, BichonResult},
+    },
+    raise_error,
+};
+
+/// Header carrying the base64 of an armored OpenPGP secret key, to read the messages of
+/// an account encrypting them at rest without unlocking it first.
+const PGP_KEY_HEADER: &str = "x-bichon-pgp-key";
+/// Header carrying the passphrase of the key in `PGP_KEY_HEADER`.
+const PGP_PASSPHRASE_HEADER: &str = "x-bichon-pgp-passphrase";
+/// How long an account stays unlocked for a caller.
+const PGP_SESSION_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Unlocked secret key and when it was unlocked, per caller and account.
+type PgpSessions = DashMap<(String, u64), (Arc<PgpSecretKey>, Instant)>;
+
+/// Secret keys unlocked by callers, per caller and account.
+pub static PGP_SESSIONS: LazyLock<PgpSessions> = LazyLock::new(DashMap::new);
+
+/// Checks that `armored` is an OpenPGP public key able to encrypt.
+pub fn validate_public_key(armored: &str) -> BichonResult<()> {
+    let key = parse_public_key(armored)?;
+    encryption_key(&key).map(|_| ())
+}
+
+/// Whether a stored message is an OpenPGP message rather than a raw one. Raw messages
+/// start with an ASCII header, OpenPGP packets with a tag byte whose high bit is set.
+pub fn is_encrypted(eml: &[u8]) -> bool {
+    eml.first().is_some_and(|b| b & 0x80 != 0) && Message::from_bytes(eml).is_ok()
+}
+
+/// Encrypts a raw message with the PGP key of the account storing it, if it has one.
+pub fn seal_eml(pgp_key: Option<&str>, eml: &[u8]) -> BichonResult<Vec<u8>> {
+    match pgp_key {
+        Some(armored) => encrypt(armored, eml),
+        None => Ok(eml.to_vec()),
+    }
+}
+
+/// Encrypts `data` to the public key `armored`, compressed, as a binary OpenPGP message.
+pub fn encrypt(armored: &str, data: &[u8]) -> BichonResult<Vec<u8>> {
+    let key = parse_public_key(armored)?;
+    let message = Message::new_literal_bytes("", data)
+        .compress(CompressionAlgorithm::ZLIB)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    let encrypted = match encryption_key(&key)? {
+        Some(subkey) => {
+            message.encrypt_to_keys_seipdv1(OsRng, SymmetricKeyAlgorithm::AES256, &[subkey])
+        }
+        None => message.encrypt_to_keys_seipdv1(OsRng, SymmetricKeyAlgorithm::AES256, &[&key]),
+    };
+    encrypted
+        .and_then(|message| message.to_bytes())
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))
+}
+
+fn parse_public_key(armored: &str) -> BichonResult<SignedPublicKey> {
+    let (key, _) = SignedPublicKey::from_string(armored).map_err(|e| {
+        raise_error!(
+            format!("Invalid OpenPGP public key: {:#?}", e),
+            ErrorCode::InvalidParameter
+        )
+    })?;
+    Ok(key)
+}
+
+/// Subkey to encrypt to, or `None` to encrypt to the primary key.
+fn encryption_key(key: &SignedPublicKey) -> BichonResult<Option<&pgp::SignedPublicSubKey>> {
+    if let Some(subkey) = key.public_subkeys.iter().find(|k| k.is_encryption_key()) {
+        return Ok(Some(subkey));
+    }
+    if key.is_encryption_key() {
+        return Ok(None);
+    }
+    Err(raise_error!(
+        "The OpenPGP public key has no encryption key".into(),
+        ErrorCode::InvalidParameter
+    ))
+}
+
+/// Secret key, with its passphrase, able to decrypt the messages of an account.
+pub struct PgpSecretKey {
+    key: SignedSecretKey,
+    passphrase: String,
+}
+
+impl PgpSecretKey {
+    pub fn parse(armored: &str, passphrase: String) -> BichonResult<Self> {
+        let (key, _) = SignedSecretKey::from_string(armored).map_err(|e| {
+            raise_error!(
+                format!("Invalid OpenPGP secret key: {:#?}", e),
+                ErrorCode::InvalidParameter
+            )
+        })?;
+        Ok(Self { key, passphrase })
+    }
+
+    /// Checks that the key and its passphrase decrypt what is encrypted to `armored`.
+    pub fn verify(&self, armored: &str) -> BichonResult<()> {
+        let probe = encrypt(armored, b"bichon")?;
+        match self.decrypt(&probe) {
+            Ok(data) if data == b"bichon" => Ok(()),
+            _ => Err(raise_error!(
+                "The secret key or its passphrase does not match the account's PGP key".into(),
+                ErrorCode::InvalidParameter
+            )),
+        }
+    }
+
+    pub fn decrypt(&self, data: &[u8]) -> BichonResult<Vec<u8>> {
+        let message = Message::from_bytes(data)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        let passphrase = self.passphrase.clone();
+        let (message, _) = message.decrypt(|| passphrase, &[&self.key]).map_err(|e| {
+            raise_error!(
+                format!("Failed to decrypt the message: {:#?}", e),
+                ErrorCode::PgpKeyRequired
+            )
+        })?;
+        message
+            .get_content()
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+            .ok_or_else(|| {
+                raise_error!(
+                    "The decrypted message has no content".into(),
+                    ErrorCode::InternalError
+                )
+            })
+    }
+}
+
+/// Unlocks the messages of an account for the caller until `PGP_SESSION_TTL` elapses or
+/// the account is locked again.
+pub async fn unlock_account(
+    context: &ClientContext,
+    account_id: u64,
+    private_key: &str,
+    passphrase: String,
+) -> BichonResult<()> {
+    let account = AccountModel::get(account_id).await?;
+    let public_key = account.pgp_key.as_deref().ok_or_else(|| {
+        raise_error!(
+            format!("Account {} does not encrypt its messages", account_id),
+            ErrorCode::InvalidParameter
+        )
+    })?;
+    let key = PgpSecretKey::parse(private_key, passphrase)?;
+    key.verify(public_key)?;
+    PGP_SESSIONS.insert(
+        (context.principal(), account_id),
+        (Arc::new(key), Instant::now()),
+    );
+    Ok(())
+}
+
+pub fn lock_account(context: &ClientContext, account_id: u64) {
+    PGP_SESSIONS.remove(&(context.principal(), account_id));
+}
+
+/// Secret key given with a request, in the `x-bichon-pgp-key` and
+/// `x-bichon-pgp-passphrase` headers.
+#[derive(Clone, Default)]
+pub struct PgpCredentials(Option<Arc<PgpSecretKey>>);
+
+impl<'a> FromRequest<'a> for PgpCredentials {
+    async fn from_request(req: &'a Request, _body: &mut RequestBody) -> poem::Result<Self> {
+        let header = |name: &str| {
+            req.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        let Some(encoded) = header(PGP_KEY_HEADER) else {
+            return Ok(Self(None));
+        };
+        let invalid =
+            |message: &str| create_api_error_response(message, ErrorCode::InvalidParameter);
+        let armored = STANDARD
+            .decode(encoded.trim())
+            .ok()
+            .and_then(|bytes| String::from_utf8(bytes).ok())
+            .ok_or_else(|| invalid("The PGP key header must be a base64 encoded armored key"))?;
+        let passphrase = header(PGP_PASSPHRASE_HEADER).unwrap_or_default();
+        let key = PgpSecretKey::parse(&armored, passphrase)
+            .map_err(|_| invalid("The PGP key header does not hold an OpenPGP secret key"))?;
+        Ok(Self(Some(Arc::new(key))))
+    }
+}
+
+impl PgpCredentials {
+    /// Key to decrypt the messages of `account_id` with: the one given with the request,
+    /// else the one the caller unlocked the account with.
+    pub fn resolve(&self, context: &ClientContext, account_id: u64) -> Option<Arc<PgpSecretKey>> {
+        if let Some(key) = &self.0 {
+            return Some(key.clone());
+        }
+        let session = (context.principal(), account_id);
+        let key = PGP_SESSIONS.get(&session).and_then(|entry| {
+            let (key, unlocked_at) = entry.value();
+            (unlocked_at.elapsed() < PGP_SESSION_TTL).then(|| key.clone())
+        });
+        if key.is_none() {
+            PGP_SESSIONS.remove(&session);
+        }
+        key
+    }
+}
+
+/// Raw message from what is stored, decrypted with `key` if it was encrypted at rest.
+pub fn open_eml(eml: Vec<u8>, key: Option<&PgpSecretKey>) -> BichonResult<Vec<u8>> {
+    if !is_encrypted(&eml) {
+        return Ok(eml);
+    }
+    match key {
+        Some(key) => key.decrypt(&eml),
+        None => Err(raise_error!(
+            "This message is encrypted at rest. Unlock the account with its PGP secret key, or send the key with the request.".into(),
+            ErrorCode::PgpKeyRequired
+        )),
+    }
+}
@@ -0,0 +1,189 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::{
+    sync::{Arc, LazyLock, RwLock},
+    time::Duration,
+};
+
+use mail_send::{mail_builder::MessageBuilder, SmtpClientBuilder};
+
+use crate::{
+    modules::{
+        alert::{AlertEvent, AlertNotification, SearchAlert},
+        context::Initialize,
+        error::{code::ErrorCode, BichonResult},
+        settings::cli::SETTINGS,
+        token::AccessToken,
+    },
+    raise_error,
+};
+
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+pub static SEARCH_ALERTS: LazyLock<SearchAlerts> = LazyLock::new(SearchAlerts::new);
+
+/// Enabled alerts, kept in memory so that indexing a batch does not read the meta database
+/// when there are none.
+pub struct SearchAlerts {
+    enabled: RwLock<Arc<Vec<SearchAlert>>>,
+}
+
+impl Initialize for SearchAlerts {
+    async fn initialize() -> BichonResult<()> {
+        SEARCH_ALERTS.reload().await
+    }
+}
+
+impl SearchAlerts {
+    fn new() -> Self {
+        Self {
+            enabled: RwLock::new(Arc::new(Vec::new())),
+        }
+    }
+
+    pub fn enabled(&self) -> Arc<Vec<SearchAlert>> {
+        self.enabled.read().unwrap().clone()
+    }
+
+    pub async fn reload(&self) -> BichonResult<()> {
+        let alerts: Vec<SearchAlert> = SearchAlert::list_all()
+            .await?
+            .into_iter()
+            .filter(|a| a.enabled)
+            .collect();
+        *self.enabled.write().unwrap() = Arc::new(alerts);
+        Ok(())
+    }
+
+    /// Delivers the events in the background, so that slow channels do not hold up indexing.
+    pub fn notify(&'static self, events: Vec<(SearchAlert, AlertEvent)>) {
+        for (alert, event) in events {
+            tokio::spawn(async move {
+                if let Err(e) = Self::deliver(&alert, &event).await {
+                    tracing::warn!("Failed to deliver search alert '{}': {:#?}", alert.name, e);
+                }
+            });
+        }
+    }
+
+    async fn deliver(alert: &SearchAlert, event: &AlertEvent) -> BichonResult<()> {
+        // the owner may have lost access to the account since the alert was created
+        if SETTINGS.bichon_enable_access_token && alert.owner != "root" {
+            let token = AccessToken::list_all()
+                .await?
+                .into_iter()
+                .find(|token| AccessToken::owner_id(&token.token) == alert.owner);
+            let allowed = match (token, alert.filter.account_id) {
+                (Some(token), Some(account_id)) => token.can_access_account(account_id),
+                _ => false,
+            };
+            if !allowed {
+                return Ok(());
+            }
+        }
+
+        let channels = &alert.channels;
+        if channels.in_app {
+            AlertNotification::add(&alert.owner, event.clone()).await?;
+        }
+        // alerts stored before external channels were restricted to root are kept in-app
+        if alert.owner != "root" {
+            return Ok(());
+        }
+        if let Some(webhook) = &channels.webhook {
+            if let Err(e) = Self::post_webhook(webhook, event).await {
+                tracing::warn!("Search alert '{}' webhook failed: {:#?}", alert.name, e);
+            }
+        }
+        if let Some(recipients) = channels.email.as_ref().filter(|r| !r.is_empty()) {
+            if let Err(e) = Self::send_email(recipients, event).await {
+                tracing::warn!("Search alert '{}' email failed: {:#?}", alert.name, e);
+            }
+        }
+        Ok(())
+    }
+
+    async fn post_webhook(url: &str, event: &AlertEvent) -> BichonResult<()> {
+        let client = reqwest::Client::builder()
+            .timeout(WEBHOOK_TIMEOUT)
+            .build()
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        let response = client
+            .post(url)
+            .json(event)
+            .send()
+            .await
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::NetworkError))?;
+        if !response.status().is_success() {
+            return Err(raise_error!(
+                format!("Webhook responded with status {}", response.status()),
+                ErrorCode::HttpResponseError
+            ));
+        }
+        Ok(())
+    }
+
+    /// Sends the event through the local relay, without authentication or TLS.
+    async fn send_email(recipients: &[String], event: &AlertEvent) -> BichonResult<()> {
+        let (host, port) = SETTINGS
+            .bichon_alert_smtp_relay
+            .rsplit_once(':')
+            .and_then(|(host, port)| Some((host, port.parse::<u16>().ok()?)))
+            .ok_or_else(|| {
+                raise_error!(
+                    format!(
+                        "Invalid SMTP relay address: {}",
+                        SETTINGS.bichon_alert_smtp_relay
+                    ),
+                    ErrorCode::InvalidParameter
+                )
+            })?;
+
+        let mut body = format!(
+            "{} new message(s) matched the search alert '{}':\n\n",
+            event.matches.len(),
+            event.alert_name
+        );
+        for m in &event.matches {
+            body.push_str(&format!(
+                "- [{}/{}] {} | {}\n",
+                m.account_id, m.id, m.from, m.subject
+            ));
+        }
+        let message = MessageBuilder::new()
+            .from(SETTINGS.bichon_alert_mail_from.as_str())
+            .to(recipients.iter().map(String::as_str).collect::<Vec<_>>())
+            .subject(format!(
+                "[Bichon] {} new message(s) for '{}'",
+                event.matches.len(),
+                event.alert_name
+            ))
+            .text_body(body);
+
+        SmtpClientBuilder::new(host, port)
+            .implicit_tls(false)
+            .connect_plain()
+            .await
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::NetworkError))?
+            .send(message)
+            .await
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::NetworkError))?;
+        Ok(())
+    }
+}
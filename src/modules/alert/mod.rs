@@ -0,0 +1,417 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use itertools::Itertools;
+use native_db::*;
+use native_model::{native_model, Model};
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    id,
+    modules::{
+        alert::dispatch::SEARCH_ALERTS,
+        common::auth::ClientContext,
+        database::{
+            async_find_impl, batch_delete_impl, delete_impl, filter_by_secondary_key_impl,
+            insert_impl, list_all_impl, manager::DB_MANAGER, update_impl,
+        },
+        error::{code::ErrorCode, BichonResult},
+        message::{saved::filter_json, search::SearchFilter},
+    },
+    raise_error, utc_now,
+};
+
+pub mod dispatch;
+
+/// In-app notifications kept per owner; older ones are removed as new ones arrive.
+const MAX_NOTIFICATIONS: usize = 500;
+
+/// A search filter evaluated against every batch of newly archived envelopes. Matches are
+/// delivered through the configured channels.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+#[native_model(id = 10, version = 1)]
+#[native_db]
+pub struct SearchAlert {
+    /// The unique identifier for this alert.
+    #[primary_key]
+    pub id: u64,
+    /// Owner id of the access token owning this alert, or `root`.
+    #[secondary_key]
+    #[oai(skip)]
+    pub owner: String,
+    pub name: String,
+    #[serde(with = "filter_json")]
+    pub filter: SearchFilter,
+    pub channels: AlertChannels,
+    pub enabled: bool,
+    /// The creation timestamp of this record, represented as milliseconds since the Unix epoch.
+    pub created_at: i64,
+    /// The last update timestamp of this record, represented as milliseconds since the Unix epoch.
+    pub updated_at: i64,
+}
+
+/// Where the matches of an alert are delivered. At least one channel must be set.
+///
+/// Only the root token may set `webhook` and `email`: they make the server send requests
+/// and mail to arbitrary, possibly internal, destinations.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct AlertChannels {
+    /// URL receiving each `AlertEvent` as a JSON `POST`.
+    pub webhook: Option<String>,
+    /// Recipients of an email sent through the SMTP relay set by `bichon_alert_smtp_relay`.
+    pub email: Option<Vec<String>>,
+    /// Whether events are kept in the in-app notification list.
+    pub in_app: bool,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct SearchAlertRequest {
+    #[oai(validator(min_length = "1", max_length = "128"))]
+    pub name: String,
+    pub filter: SearchFilter,
+    pub channels: AlertChannels,
+    /// Defaults to `true`.
+    pub enabled: Option<bool>,
+}
+
+/// Envelope matched by an alert.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct AlertMatch {
+    pub id: u64,
+    pub account_id: u64,
+    pub mailbox_id: u64,
+    pub subject: String,
+    pub from: String,
+    pub internal_date: i64,
+}
+
+/// Envelopes of one indexed batch matching an alert, as delivered to every channel.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct AlertEvent {
+    pub alert_id: u64,
+    pub alert_name: String,
+    pub matches: Vec<AlertMatch>,
+    /// When the batch was indexed, represented as milliseconds since the Unix epoch.
+    pub triggered_at: i64,
+}
+
+/// An alert event kept for the in-app notification list.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+#[native_model(id = 11, version = 1)]
+#[native_db]
+pub struct AlertNotification {
+    #[primary_key]
+    pub id: u64,
+    #[secondary_key]
+    #[oai(skip)]
+    pub owner: String,
+    pub event: AlertEvent,
+    pub read: bool,
+}
+
+impl SearchAlert {
+    pub fn new(owner: String, request: SearchAlertRequest) -> Self {
+        Self {
+            id: id!(64),
+            owner,
+            name: request.name,
+            filter: request.filter,
+            channels: request.channels,
+            enabled: request.enabled.unwrap_or(true),
+            created_at: utc_now!(),
+            updated_at: utc_now!(),
+        }
+    }
+
+    /// Returns the alert `id` if it belongs to `owner`.
+    pub async fn get(owner: &str, id: u64) -> BichonResult<SearchAlert> {
+        async_find_impl::<SearchAlert>(DB_MANAGER.meta_db(), id)
+            .await?
+            .filter(|a| a.owner == owner)
+            .ok_or_else(|| {
+                raise_error!(
+                    format!("Search alert with id={} not found", id),
+                    ErrorCode::ResourceNotFound
+                )
+            })
+    }
+
+    pub async fn list(owner: &str) -> BichonResult<Vec<SearchAlert>> {
+        let alerts: Vec<SearchAlert> = filter_by_secondary_key_impl(
+            DB_MANAGER.meta_db(),
+            SearchAlertKey::owner,
+            owner.to_string(),
+        )
+        .await?;
+        Ok(alerts
+            .into_iter()
+            .filter(|a| a.owner == owner)
+            .sorted_by(|a, b| a.name.cmp(&b.name))
+            .collect())
+    }
+
+    pub async fn list_all() -> BichonResult<Vec<SearchAlert>> {
+        list_all_impl(DB_MANAGER.meta_db()).await
+    }
+
+    pub async fn save(&self) -> BichonResult<()> {
+        self.channels.validate()?;
+        insert_impl(DB_MANAGER.meta_db(), self.to_owned()).await?;
+        SEARCH_ALERTS.reload().await
+    }
+
+    pub async fn update(owner: &str, id: u64, request: SearchAlertRequest) -> BichonResult<()> {
+        request.channels.validate()?;
+        Self::get(owner, id).await?;
+        update_impl(
+            DB_MANAGER.meta_db(),
+            move |rw| {
+                rw.get()
+                    .primary::<SearchAlert>(id)
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                    .ok_or_else(|| {
+                        raise_error!(
+                            format!("Search alert with id={} not found", id),
+                            ErrorCode::ResourceNotFound
+                        )
+                    })
+            },
+            move |current| {
+                let mut updated = current.clone();
+                updated.name = request.name;
+                updated.filter = request.filter;
+                updated.channels = request.channels;
+                if let Some(enabled) = request.enabled {
+                    updated.enabled = enabled;
+                }
+                updated.updated_at = utc_now!();
+                Ok(updated)
+            },
+        )
+        .await?;
+        SEARCH_ALERTS.reload().await
+    }
+
+    pub async fn delete(owner: &str, id: u64) -> BichonResult<()> {
+        Self::get(owner, id).await?;
+        delete_impl(DB_MANAGER.meta_db(), move |rw| {
+            rw.get()
+                .primary::<SearchAlert>(id)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                .ok_or_else(|| {
+                    raise_error!("search alert missing".into(), ErrorCode::InternalError)
+                })
+        })
+        .await?;
+        SEARCH_ALERTS.reload().await
+    }
+
+    /// Deletes the alerts and notifications of `owner`, when its access token is deleted.
+    pub async fn clean(owner: &str) -> BichonResult<()> {
+        let owner = owner.to_string();
+        let alert_owner = owner.clone();
+        batch_delete_impl(DB_MANAGER.meta_db(), move |rw| {
+            let alerts: Vec<SearchAlert> = rw
+                .scan()
+                .secondary::<SearchAlert>(SearchAlertKey::owner)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                .start_with(alert_owner.clone())
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                .try_collect()
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            Ok(alerts
+                .into_iter()
+                .filter(|a| a.owner == alert_owner)
+                .collect())
+        })
+        .await?;
+        AlertNotification::clear(&owner).await?;
+        SEARCH_ALERTS.reload().await
+    }
+}
+
+impl AlertChannels {
+    pub fn validate(&self) -> BichonResult<()> {
+        if let Some(webhook) = &self.webhook {
+            let url = url::Url::parse(webhook)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InvalidParameter))?;
+            if !matches!(url.scheme(), "http" | "https") {
+                return Err(raise_error!(
+                    "The webhook URL must use http or https.".into(),
+                    ErrorCode::InvalidParameter
+                ));
+            }
+        }
+        if let Some(recipients) = &self.email {
+            for recipient in recipients {
+                if !email_address::EmailAddress::is_valid(recipient) {
+                    return Err(raise_error!(
+                        format!("Invalid email address: {}", recipient),
+                        ErrorCode::InvalidParameter
+                    ));
+                }
+            }
+        }
+        let has_email = self.email.as_ref().is_some_and(|r| !r.is_empty());
+        if self.webhook.is_none() && !has_email && !self.in_app {
+            return Err(raise_error!(
+                "At least one alert channel must be set.".into(),
+                ErrorCode::InvalidParameter
+            ));
+        }
+        Ok(())
+    }
+
+    /// Checks that the caller may use these channels: anything but in-app notifications
+    /// requires root.
+    pub fn authorize(&self, context: &ClientContext) -> BichonResult<()> {
+        if self.webhook.is_some() || self.email.as_ref().is_some_and(|r| !r.is_empty()) {
+            context.require_root().map_err(|_| {
+                raise_error!(
+                    "Only the root token can deliver alerts by webhook or email.".into(),
+                    ErrorCode::PermissionDenied
+                )
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl AlertNotification {
+    /// Notifications of `owner`, newest first.
+    pub async fn list(owner: &str) -> BichonResult<Vec<AlertNotification>> {
+        let notifications: Vec<AlertNotification> = filter_by_secondary_key_impl(
+            DB_MANAGER.meta_db(),
+            AlertNotificationKey::owner,
+            owner.to_string(),
+        )
+        .await?;
+        Ok(notifications
+            .into_iter()
+            .filter(|n| n.owner == owner)
+            .sorted_by(|a, b| b.event.triggered_at.cmp(&a.event.triggered_at))
+            .collect())
+    }
+
+    /// Stores an event for `owner`, dropping its oldest notifications beyond the limit.
+    pub async fn add(owner: &str, event: AlertEvent) -> BichonResult<()> {
+        let notification = AlertNotification {
+            id: id!(64),
+            owner: owner.to_string(),
+            event,
+            read: false,
+        };
+        insert_impl(DB_MANAGER.meta_db(), notification).await?;
+        let owner = owner.to_string();
+        batch_delete_impl(DB_MANAGER.meta_db(), move |rw| {
+            let notifications: Vec<AlertNotification> = rw
+                .scan()
+                .secondary::<AlertNotification>(AlertNotificationKey::owner)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                .start_with(owner.clone())
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                .try_collect()
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            Ok(notifications
+                .into_iter()
+                .filter(|n| n.owner == owner)
+                .sorted_by(|a, b| b.event.triggered_at.cmp(&a.event.triggered_at))
+                .skip(MAX_NOTIFICATIONS)
+                .collect())
+        })
+        .await?;
+        Ok(())
+    }
+
+    pub async fn mark_read(owner: &str, id: u64) -> BichonResult<()> {
+        let owner = owner.to_string();
+        update_impl(
+            DB_MANAGER.meta_db(),
+            move |rw| {
+                rw.get()
+                    .primary::<AlertNotification>(id)
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                    .filter(|n: &AlertNotification| n.owner == owner)
+                    .ok_or_else(|| {
+                        raise_error!(
+                            format!("Notification with id={} not found", id),
+                            ErrorCode::ResourceNotFound
+                        )
+                    })
+            },
+            |current| {
+                let mut updated = current.clone();
+                updated.read = true;
+                Ok(updated)
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn clear(owner: &str) -> BichonResult<()> {
+        let owner = owner.to_string();
+        batch_delete_impl(DB_MANAGER.meta_db(), move |rw| {
+            let notifications: Vec<AlertNotification> = rw
+                .scan()
+                .secondary::<AlertNotification>(AlertNotificationKey::owner)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                .start_with(owner.clone())
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                .try_collect()
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            Ok(notifications
+                .into_iter()
+                .filter(|n| n.owner == owner)
+                .collect())
+        })
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::AlertChannels;
+
+    #[test]
+    fn test_alert_channels_validation() {
+        assert!(AlertChannels::default().validate().is_err());
+        assert!(AlertChannels {
+            email: Some(vec![]),
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+        assert!(AlertChannels {
+            webhook: Some("ftp://example.com/hook".into()),
+            in_app: true,
+            ..Default::default()
+        }
+        .validate()
+        .is_err());
+        assert!(AlertChannels {
+            webhook: Some("https://example.com/hook".into()),
+            email: Some(vec!["legal@example.com".into()]),
+            in_app: false,
+        }
+        .validate()
+        .is_ok());
+    }
+}
@@ -0,0 +1,797 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+    fs::File,
+    path::{Path, PathBuf},
+    sync::{LazyLock, RwLock},
+};
+
+use chrono::Utc;
+use poem_openapi::{Enum, Object};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    bichon_version,
+    modules::{
+        blob::{
+            crypto::{BLOB_JOBS, BLOB_REENCRYPTION},
+            BLOB_STORE,
+        },
+        database::manager::DB_MANAGER,
+        error::{code::ErrorCode, BichonResult},
+        indexer::{
+            manager::{EML_INDEX_MANAGER, ENVELOPE_INDEX_MANAGER},
+            snapshot::{IndexSnapshot, META_FILE as INDEX_META_FILE},
+            version::ENVELOPE_SCHEMA_VERSION,
+        },
+        settings::{
+            cli::SETTINGS,
+            dir::{BLOB_DIR, DATA_DIR_MANAGER, EML_DIR, ENVELOPE_DIR, MAILBOX_FILE, META_FILE},
+        },
+        utils::encrypt::current_master_key_id,
+    },
+    raise_error, utc_now,
+};
+
+pub mod restore;
+
+/// Version of the backup layout, bumped whenever a release could not restore the backups
+/// of an earlier one as they are.
+pub const BACKUP_FORMAT_VERSION: u32 = 2;
+/// Written last, a backup without it is incomplete.
+pub const MANIFEST_FILE: &str = "manifest.json";
+/// Appended to the name of a tarball for the copy of its manifest written next to it, so
+/// that the backups of a directory are listed without reading through the tarballs.
+pub const SIDECAR_SUFFIX: &str = ".manifest.json";
+
+pub static BACKUP: LazyLock<Backup> = LazyLock::new(Backup::new);
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Enum)]
+pub enum BackupFormat {
+    /// A directory laid out like the data root.
+    #[default]
+    Directory,
+    /// An uncompressed tarball of that directory.
+    Tar,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct BackupRequest {
+    /// Directory, or tarball, to write the backup to. It must not exist, or be an empty
+    /// directory. Defaults to a new backup in `bichon_backup_dir`.
+    #[oai(validator(min_length = "1"))]
+    pub path: Option<String>,
+    pub format: Option<BackupFormat>,
+    /// Copy only the index segments and blobs added since the last backup of the
+    /// directory the backup is written to, and refer to that backup for the others. A
+    /// full backup is made when there is none.
+    pub incremental: Option<bool>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct BackupFile {
+    /// Path relative to the root of the backup.
+    pub path: String,
+    pub size: u64,
+    /// Name of the earlier backup, in the same directory, holding the file when an
+    /// incremental backup did not copy it again.
+    pub stored_in: Option<String>,
+}
+
+/// Describes a backup and the versions it was made with, checked on restore.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct BackupManifest {
+    pub format_version: u32,
+    pub bichon_version: String,
+    pub envelope_schema_version: u32,
+    /// Id of the master key the stored secrets are encrypted with, the server restoring
+    /// the backup needs the same `bichon_encrypt_password`.
+    pub master_key_id: String,
+    /// Whether message bodies and attachments are in the backup. They are not when kept
+    /// in an S3 blob store, which has to be backed up on its own.
+    pub blobs_included: bool,
+    /// Name of the backup, in the same directory, this incremental backup builds on.
+    pub parent: Option<String>,
+    /// Data key new blobs were encrypted with. Blobs are copied again once it changes, as
+    /// they may have been re-encrypted since.
+    pub data_key_id: Option<u32>,
+    /// The creation timestamp of the backup, represented as milliseconds since the Unix epoch.
+    pub created_at: i64,
+    /// Every file of the backup, those left in earlier backups included.
+    pub files: Vec<BackupFile>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct BackupStatus {
+    pub running: bool,
+    pub path: Option<String>,
+    pub format: BackupFormat,
+    /// Backup the running one builds on, when incremental.
+    pub parent: Option<String>,
+    /// Files written so far.
+    pub files: u64,
+    /// Bytes written so far.
+    pub bytes: u64,
+    /// Files left in earlier backups so far.
+    pub reused: u64,
+    /// Manifest of the last backup completed.
+    pub manifest: Option<BackupManifest>,
+    pub started_at: Option<i64>,
+    pub finished_at: Option<i64>,
+    /// Reason the last backup stopped, if it did not complete.
+    pub error: Option<String>,
+}
+
+/// Backs up the data root while the server runs. The index writers and the databases
+/// are paused while the files of their last commit are opened, so the backup holds
+/// the state of a single point in time.
+pub struct Backup {
+    status: RwLock<BackupStatus>,
+}
+
+impl Backup {
+    fn new() -> Self {
+        Self {
+            status: RwLock::new(BackupStatus::default()),
+        }
+    }
+
+    pub fn status(&self) -> BackupStatus {
+        self.status.read().unwrap().clone()
+    }
+
+    /// Starts a backup. Fails if one is already running, or if the blob store is being
+    /// re-encrypted.
+    pub fn start(&'static self, request: BackupRequest) -> BichonResult<()> {
+        let _jobs = BLOB_JOBS.lock().unwrap();
+        if BLOB_REENCRYPTION.status().running {
+            return Err(raise_error!(
+                "The EML store is being re-encrypted, retry once done.".into(),
+                ErrorCode::InvalidParameter
+            ));
+        }
+        let format = request.format.unwrap_or_default();
+        let path = match request.path {
+            Some(path) => PathBuf::from(path),
+            None => {
+                let dir = SETTINGS.bichon_backup_dir.as_ref().ok_or_else(|| {
+                    raise_error!(
+                        "Set the path of the backup, or bichon_backup_dir.".into(),
+                        ErrorCode::InvalidParameter
+                    )
+                })?;
+                PathBuf::from(dir).join(backup_name(format))
+            }
+        };
+        let incremental = request.incremental.unwrap_or(false);
+        let available = match format {
+            BackupFormat::Directory => {
+                !path.exists()
+                    || std::fs::read_dir(&path)
+                        .map(|mut entries| entries.next().is_none())
+                        .unwrap_or(false)
+            }
+            BackupFormat::Tar => !path.exists(),
+        };
+        if !available {
+            return Err(raise_error!(
+                format!("{:?} already exists", path),
+                ErrorCode::InvalidParameter
+            ));
+        }
+        {
+            let mut status = self.status.write().unwrap();
+            if status.running {
+                return Err(raise_error!(
+                    "A backup is already running.".into(),
+                    ErrorCode::InvalidParameter
+                ));
+            }
+            *status = BackupStatus {
+                running: true,
+                path: Some(path.display().to_string()),
+                format,
+                manifest: status.manifest.take(),
+                started_at: Some(utc_now!()),
+                ..Default::default()
+            };
+        }
+        tokio::spawn(async move {
+            let result = self.run(path, format, incremental).await;
+            let mut status = self.status.write().unwrap();
+            match result {
+                Ok(manifest) => {
+                    tracing::info!(
+                        "Backup {:?} completed: {} files, {} bytes",
+                        status.path,
+                        status.files,
+                        status.bytes
+                    );
+                    status.manifest = Some(manifest);
+                }
+                Err(e) => {
+                    tracing::error!("Backup {:?} failed: {:#?}", status.path, e);
+                    status.error = Some(format!("{:#?}", e));
+                }
+            }
+            status.running = false;
+            status.finished_at = Some(utc_now!());
+        });
+        Ok(())
+    }
+
+    fn progress(&self, size: u64) {
+        let mut status = self.status.write().unwrap();
+        status.files += 1;
+        status.bytes += size;
+    }
+
+    fn reused(&self) {
+        self.status.write().unwrap().reused += 1;
+    }
+
+    async fn run(
+        &'static self,
+        path: PathBuf,
+        format: BackupFormat,
+        incremental: bool,
+    ) -> BichonResult<BackupManifest> {
+        let data_key_id = BLOB_REENCRYPTION.status().active_key_id;
+        let mut writer = blocking(move || {
+            let parent = if incremental {
+                ParentBackup::latest(&path, data_key_id)?
+            } else {
+                None
+            };
+            BackupWriter::create(self, &path, format, parent)
+        })
+        .await?;
+        let parent = writer.parent.as_ref().map(|parent| parent.name.clone());
+        self.status.write().unwrap().parent = parent.clone();
+
+        // blobs are only added or removed while the EML store is written to: they are
+        // copied while the server runs, then those added meanwhile once it is paused
+        let blob_dir = BLOB_STORE.local_dir().map(Path::to_path_buf);
+        let mut blobs = HashSet::new();
+        if let Some(dir) = blob_dir.clone() {
+            (writer, blobs) = blocking(move || {
+                writer.add_blobs(&dir, &mut blobs)?;
+                Ok((writer, blobs))
+            })
+            .await?;
+        }
+
+        let (envelope_pause, envelope) = ENVELOPE_INDEX_MANAGER.pause_writes().await?;
+        let (eml_pause, eml) = EML_INDEX_MANAGER.pause_writes().await?;
+        writer = blocking(move || {
+            // an open write transaction keeps the other writers waiting, the files hold
+            // the last commit
+            let meta_db = DB_MANAGER.meta_db().clone();
+            let mailbox_db = DB_MANAGER.envelope_db().clone();
+            let meta_pause = meta_db
+                .rw_transaction()
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            let mailbox_pause = mailbox_db
+                .rw_transaction()
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            writer.add_file(Path::new(META_FILE), &DATA_DIR_MANAGER.meta_db)?;
+            writer.add_file(Path::new(MAILBOX_FILE), &DATA_DIR_MANAGER.mailbox_db)?;
+            drop(mailbox_pause);
+            drop(meta_pause);
+            Ok(writer)
+        })
+        .await?;
+        drop(envelope_pause);
+        if let Some(dir) = blob_dir.clone() {
+            writer = blocking(move || {
+                writer.add_blobs(&dir, &mut blobs)?;
+                Ok(writer)
+            })
+            .await?;
+        }
+        drop(eml_pause);
+
+        let manifest = blocking(move || {
+            writer.add_index(Path::new(ENVELOPE_DIR), envelope)?;
+            writer.add_index(Path::new(EML_DIR), eml)?;
+            writer.finish(BackupManifest {
+                format_version: BACKUP_FORMAT_VERSION,
+                bichon_version: bichon_version!().to_string(),
+                envelope_schema_version: ENVELOPE_SCHEMA_VERSION,
+                master_key_id: current_master_key_id().to_string(),
+                blobs_included: blob_dir.is_some(),
+                parent,
+                data_key_id,
+                created_at: utc_now!(),
+                files: Vec::new(),
+            })
+        })
+        .await?;
+
+        if let (Some(dir), Some(keep)) = (
+            SETTINGS.bichon_backup_dir.as_ref(),
+            SETTINGS.bichon_backup_keep_sets,
+        ) {
+            let dir = PathBuf::from(dir);
+            if let Err(e) = blocking(move || prune_backup_sets(&dir, keep as usize)).await {
+                tracing::error!("Failed to remove the old backup sets: {:#?}", e);
+            }
+        }
+        Ok(manifest)
+    }
+}
+
+/// Name of a new backup in `bichon_backup_dir`, in the order backups are made.
+fn backup_name(format: BackupFormat) -> String {
+    let name = format!("bichon-{}", Utc::now().format("%Y%m%dT%H%M%S%.3fZ"));
+    match format {
+        BackupFormat::Directory => name,
+        BackupFormat::Tar => format!("{}.tar", name),
+    }
+}
+
+async fn blocking<T, F>(f: F) -> BichonResult<T>
+where
+    F: FnOnce() -> BichonResult<T> + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+}
+
+/// Backup an incremental backup builds on, and the files it holds.
+struct ParentBackup {
+    name: String,
+    /// Size of every file of the backup, and the backup of the chain storing it.
+    files: HashMap<String, (u64, String)>,
+    /// Whether its blobs can be referred to, they are not once they may have been
+    /// re-encrypted.
+    blobs: bool,
+}
+
+impl ParentBackup {
+    /// The last backup completed in the directory of `path`, if one can be built on.
+    fn latest(path: &Path, data_key_id: Option<u32>) -> BichonResult<Option<Self>> {
+        let dir = path
+            .parent()
+            .filter(|dir| !dir.as_os_str().is_empty())
+            .unwrap_or(Path::new("."));
+        let Some((name, manifest)) = list_backups(dir)?
+            .into_iter()
+            .max_by_key(|(_, manifest)| manifest.created_at)
+        else {
+            tracing::info!("No backup to build on in {:?}, making a full backup", dir);
+            return Ok(None);
+        };
+        if manifest.format_version != BACKUP_FORMAT_VERSION {
+            tracing::info!(
+                "Backup {} has format version {}, making a full backup",
+                name,
+                manifest.format_version
+            );
+            return Ok(None);
+        }
+        let blobs = manifest.data_key_id == data_key_id;
+        if !blobs {
+            tracing::info!(
+                "The EML data key changed since backup {}, copying every blob again",
+                name
+            );
+        }
+        let files = manifest
+            .files
+            .into_iter()
+            .map(|file| {
+                let stored_in = file.stored_in.unwrap_or_else(|| name.clone());
+                (file.path, (file.size, stored_in))
+            })
+            .collect();
+        Ok(Some(Self { name, files, blobs }))
+    }
+}
+
+enum BackupTarget {
+    Directory(PathBuf),
+    Tar(tar::Builder<File>),
+}
+
+/// Writes the files of a backup, to a directory or a tarball.
+struct BackupWriter {
+    backup: &'static Backup,
+    path: PathBuf,
+    target: BackupTarget,
+    parent: Option<ParentBackup>,
+    files: Vec<BackupFile>,
+}
+
+impl BackupWriter {
+    fn create(
+        backup: &'static Backup,
+        path: &Path,
+        format: BackupFormat,
+        parent: Option<ParentBackup>,
+    ) -> BichonResult<Self> {
+        let target = match format {
+            BackupFormat::Directory => {
+                std::fs::create_dir_all(path)
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                BackupTarget::Directory(path.to_path_buf())
+            }
+            BackupFormat::Tar => {
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)
+                        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                }
+                let file = File::create_new(path)
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                BackupTarget::Tar(tar::Builder::new(file))
+            }
+        };
+        Ok(Self {
+            backup,
+            path: path.to_path_buf(),
+            target,
+            parent,
+            files: Vec::new(),
+        })
+    }
+
+    /// Adds `data` under `path`, relative to the root of the backup.
+    fn add(&mut self, path: &Path, data: &[u8]) -> BichonResult<()> {
+        self.write(path, data)?;
+        self.record(path, data.len() as u64);
+        Ok(())
+    }
+
+    fn write(&mut self, path: &Path, data: &[u8]) -> BichonResult<()> {
+        match &mut self.target {
+            BackupTarget::Directory(root) => {
+                let target = root.join(path);
+                if let Some(parent) = target.parent() {
+                    std::fs::create_dir_all(parent)
+                        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                }
+                std::fs::write(target, data)
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))
+            }
+            BackupTarget::Tar(builder) => {
+                let mut header = tar::Header::new_gnu();
+                header.set_size(data.len() as u64);
+                header.set_mode(0o600);
+                header.set_mtime((utc_now!() / 1000) as u64);
+                builder
+                    .append_data(&mut header, path, data)
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))
+            }
+        }
+    }
+
+    /// Adds the file at `source` under `path`, relative to the root of the backup.
+    fn add_file(&mut self, path: &Path, source: &Path) -> BichonResult<()> {
+        let size = match &mut self.target {
+            BackupTarget::Directory(root) => {
+                let target = root.join(path);
+                if let Some(parent) = target.parent() {
+                    std::fs::create_dir_all(parent)
+                        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                }
+                std::fs::copy(source, target)
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+            }
+            BackupTarget::Tar(builder) => {
+                let mut file = File::open(source)
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                let size = file
+                    .metadata()
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                    .len();
+                builder
+                    .append_file(path, &mut file)
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                size
+            }
+        };
+        self.record(path, size);
+        Ok(())
+    }
+
+    /// Refers to the copy of the file in the backup built on, if it holds `path` with the
+    /// same size.
+    fn reuse(&mut self, path: &Path, size: u64) -> bool {
+        let Some(parent) = &self.parent else {
+            return false;
+        };
+        let path = path_string(path);
+        let Some((_, stored_in)) = parent.files.get(&path).filter(|(s, _)| *s == size) else {
+            return false;
+        };
+        self.files.push(BackupFile {
+            path,
+            size,
+            stored_in: Some(stored_in.clone()),
+        });
+        self.backup.reused();
+        true
+    }
+
+    /// Adds the files of an index snapshot under `dir`. Segment files already in the
+    /// backup built on are left there, segments are never modified once written.
+    fn add_index(&mut self, dir: &Path, snapshot: IndexSnapshot) -> BichonResult<()> {
+        for (path, slice) in snapshot.files {
+            let path = dir.join(path);
+            if self.reuse(&path, slice.num_bytes().get_bytes()) {
+                continue;
+            }
+            let data = slice
+                .read_bytes()
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            self.add(&path, data.as_slice())?;
+        }
+        for (path, slice) in snapshot.extra {
+            let data = slice
+                .read_bytes()
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            self.add(&dir.join(path), data.as_slice())?;
+        }
+        self.add(&dir.join(INDEX_META_FILE), &snapshot.meta)
+    }
+
+    /// Adds the blobs of the filesystem store in `dir` not among `copied` yet. A blob is
+    /// stored under the hash of its content, those in the backup built on are left there.
+    fn add_blobs(&mut self, dir: &Path, copied: &mut HashSet<PathBuf>) -> BichonResult<()> {
+        let reuse = self.parent.as_ref().is_some_and(|parent| parent.blobs);
+        for path in list_files(dir)? {
+            // blobs being written are renamed into place once complete
+            if path.extension().is_some_and(|ext| ext == "tmp") || copied.contains(&path) {
+                continue;
+            }
+            let source = dir.join(&path);
+            let target = Path::new(BLOB_DIR).join(&path);
+            if reuse {
+                let size = match std::fs::metadata(&source) {
+                    Ok(metadata) => metadata.len(),
+                    Err(_) if !source.exists() => continue,
+                    Err(e) => {
+                        return Err(raise_error!(format!("{:#?}", e), ErrorCode::InternalError))
+                    }
+                };
+                if self.reuse(&target, size) {
+                    copied.insert(path);
+                    continue;
+                }
+            }
+            match self.add_file(&target, &source) {
+                Ok(()) => {}
+                // removed meanwhile, no longer referred to
+                Err(_) if !source.exists() => continue,
+                Err(e) => return Err(e),
+            }
+            copied.insert(path);
+        }
+        Ok(())
+    }
+
+    fn record(&mut self, path: &Path, size: u64) {
+        self.files.push(BackupFile {
+            path: path_string(path),
+            size,
+            stored_in: None,
+        });
+        self.backup.progress(size);
+    }
+
+    /// Writes the manifest, listing the files of the backup, and completes the backup.
+    fn finish(mut self, mut manifest: BackupManifest) -> BichonResult<BackupManifest> {
+        manifest.files = std::mem::take(&mut self.files);
+        let json = serde_json::to_vec_pretty(&manifest)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        self.write(Path::new(MANIFEST_FILE), &json)?;
+        if let BackupTarget::Tar(builder) = self.target {
+            builder
+                .into_inner()
+                .and_then(|file| file.sync_all())
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            std::fs::write(sidecar_path(&self.path), &json)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        }
+        Ok(manifest)
+    }
+}
+
+fn path_string(path: &Path) -> String {
+    path.to_string_lossy().replace('\\', "/")
+}
+
+/// Path of the copy of the manifest of the tarball at `path`.
+pub fn sidecar_path(path: &Path) -> PathBuf {
+    let mut sidecar = path.as_os_str().to_owned();
+    sidecar.push(SIDECAR_SUFFIX);
+    PathBuf::from(sidecar)
+}
+
+/// The backups completed in `dir`, by name, along with their manifests.
+pub fn list_backups(dir: &Path) -> BichonResult<Vec<(String, BackupManifest)>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(raise_error!(format!("{:#?}", e), ErrorCode::InternalError)),
+    };
+    let mut backups = Vec::new();
+    for entry in entries {
+        let entry =
+            entry.map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        let path = entry.path();
+        let manifest = if path.is_dir() {
+            path.join(MANIFEST_FILE)
+        } else {
+            sidecar_path(&path)
+        };
+        // incomplete, or not a backup
+        let Ok(json) = std::fs::read(&manifest) else {
+            continue;
+        };
+        match serde_json::from_slice(&json) {
+            Ok(manifest) => {
+                backups.push((entry.file_name().to_string_lossy().into_owned(), manifest))
+            }
+            Err(e) => tracing::warn!(
+                "Skipping backup {:?}, its manifest is invalid: {:#?}",
+                path,
+                e
+            ),
+        }
+    }
+    Ok(backups)
+}
+
+/// Removes the backup sets of `dir` but the `keep` most recent ones. A set is a full
+/// backup and the incremental backups built on it, none can be restored without it.
+fn prune_backup_sets(dir: &Path, keep: usize) -> BichonResult<()> {
+    let backups: HashMap<String, BackupManifest> = list_backups(dir)?.into_iter().collect();
+    let mut sets: HashMap<&str, Vec<&str>> = HashMap::new();
+    for name in backups.keys() {
+        let mut root = name.as_str();
+        // bounded, should manifests refer to each other
+        for _ in 0..backups.len() {
+            match backups
+                .get(root)
+                .and_then(|manifest| manifest.parent.as_deref())
+            {
+                Some(parent) => root = parent,
+                None => break,
+            }
+        }
+        sets.entry(root).or_default().push(name);
+    }
+    // sets whose full backup is gone cannot be restored, they are removed first
+    let mut sets: Vec<_> = sets
+        .into_iter()
+        .map(|(root, names)| {
+            let latest = names
+                .iter()
+                .map(|name| backups[*name].created_at)
+                .max()
+                .unwrap_or_default();
+            ((backups.contains_key(root), latest), root, names)
+        })
+        .collect();
+    sets.sort_by_key(|(order, _, _)| Reverse(*order));
+    for (_, root, names) in sets.into_iter().skip(keep) {
+        tracing::info!(
+            "Removing backup set {} from {:?}, {} backups",
+            root,
+            dir,
+            names.len()
+        );
+        for name in names {
+            let path = dir.join(name);
+            let removed = if path.is_dir() {
+                std::fs::remove_dir_all(&path)
+            } else {
+                std::fs::remove_file(&path).and_then(|_| std::fs::remove_file(sidecar_path(&path)))
+            };
+            removed.map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        }
+    }
+    Ok(())
+}
+
+/// Paths of the files under `dir`, relative to it.
+pub fn list_files(dir: &Path) -> BichonResult<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative) = pending.pop() {
+        let entries = match std::fs::read_dir(dir.join(&relative)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(raise_error!(format!("{:#?}", e), ErrorCode::InternalError)),
+        };
+        for entry in entries {
+            let entry =
+                entry.map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            let file_type = entry
+                .file_type()
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            let path = relative.join(entry.file_name());
+            if file_type.is_dir() {
+                pending.push(path);
+            } else if file_type.is_file() {
+                files.push(path);
+            }
+        }
+    }
+    Ok(files)
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::{prune_backup_sets, BackupManifest, MANIFEST_FILE};
+
+    fn add_backup(dir: &Path, name: &str, parent: Option<&str>, created_at: i64) {
+        let manifest = BackupManifest {
+            parent: parent.map(String::from),
+            created_at,
+            ..Default::default()
+        };
+        std::fs::create_dir(dir.join(name)).unwrap();
+        std::fs::write(
+            dir.join(name).join(MANIFEST_FILE),
+            serde_json::to_vec(&manifest).unwrap(),
+        )
+        .unwrap();
+    }
+
+    fn remaining(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_pruning_keeps_the_backups_a_kept_incremental_builds_on() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        add_backup(dir, "a-full", None, 1);
+        add_backup(dir, "a-incr1", Some("a-full"), 2);
+        add_backup(dir, "b-full", None, 3);
+        add_backup(dir, "b-incr1", Some("b-full"), 4);
+        // the latest backup builds on the oldest set, through an incremental
+        add_backup(dir, "a-incr2", Some("a-incr1"), 5);
+        // its full backup is gone, it cannot be restored
+        add_backup(dir, "c-incr1", Some("c-full"), 6);
+
+        prune_backup_sets(dir, 2).unwrap();
+        assert_eq!(
+            remaining(dir),
+            vec!["a-full", "a-incr1", "a-incr2", "b-full", "b-incr1"]
+        );
+
+        prune_backup_sets(dir, 1).unwrap();
+        assert_eq!(remaining(dir), vec!["a-full", "a-incr1", "a-incr2"]);
+    }
+}
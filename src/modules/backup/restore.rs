@@ -0,0 +1,444 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::{
+    collections::{HashMap, HashSet},
+    ffi::OsStr,
+    fs::File,
+    io::Read,
+    path::{Component, Path, PathBuf},
+};
+
+use chrono::DateTime;
+use semver::Version;
+
+use crate::{
+    bichon_version,
+    modules::{
+        backup::{list_files, sidecar_path, BackupManifest, BACKUP_FORMAT_VERSION, MANIFEST_FILE},
+        error::{code::ErrorCode, BichonResult},
+        indexer::version::ENVELOPE_SCHEMA_VERSION,
+        settings::{
+            cli::SETTINGS,
+            dir::{BLOB_DIR, DATA_DIR_MANAGER, EML_DIR, ENVELOPE_DIR, MAILBOX_FILE, META_FILE},
+        },
+        utils::encrypt::is_known_master_key,
+    },
+    raise_error,
+};
+
+/// Directory of the data root the backup is unpacked to and checked in, before its files
+/// are moved into place.
+const RESTORE_DIR: &str = "restore.tmp";
+
+/// Offline restore run by `bichon_restore_from`: unpacks a backup made by the backup API,
+/// a directory or a tarball, into an empty data root, once its versions and files are
+/// checked. The files an incremental backup refers to are read from the earlier backups
+/// of its chain, next to it.
+pub async fn restore_backup(source: PathBuf) -> BichonResult<()> {
+    tokio::task::spawn_blocking(move || restore(&source))
+        .await
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+}
+
+fn restore(source: &Path) -> BichonResult<()> {
+    let root = &DATA_DIR_MANAGER.root_dir;
+    for existing in [
+        &DATA_DIR_MANAGER.meta_db,
+        &DATA_DIR_MANAGER.mailbox_db,
+        &DATA_DIR_MANAGER.envelope_dir,
+        &DATA_DIR_MANAGER.eml_dir,
+    ] {
+        if existing.exists() {
+            return Err(raise_error!(
+                format!(
+                    "{:?} already exists, restore into an empty bichon_root_dir",
+                    existing
+                ),
+                ErrorCode::InvalidParameter
+            ));
+        }
+    }
+
+    let staging = root.join(RESTORE_DIR);
+    if staging.exists() {
+        std::fs::remove_dir_all(&staging)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    }
+    let manifest = read_manifest(source)?;
+    validate(&manifest)?;
+    let result =
+        unpack_chain(source, &manifest, &staging).and_then(|_| check_files(&manifest, &staging));
+    if let Err(e) = result {
+        let _ = std::fs::remove_dir_all(&staging);
+        return Err(e);
+    }
+
+    for name in [META_FILE, MAILBOX_FILE, ENVELOPE_DIR, EML_DIR] {
+        std::fs::rename(staging.join(name), root.join(name))
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    }
+    if manifest.blobs_included {
+        let blob_dir = SETTINGS
+            .bichon_blob_dir
+            .as_ref()
+            .map(PathBuf::from)
+            .unwrap_or_else(|| DATA_DIR_MANAGER.blob_dir.clone());
+        let blobs = staging.join(BLOB_DIR);
+        for path in list_files(&blobs)? {
+            move_file(&blobs.join(&path), &blob_dir.join(&path))?;
+        }
+    } else {
+        tracing::warn!(
+            "The backup holds no message bodies or attachments, they are read from the S3 \
+            blob store, which must hold them"
+        );
+    }
+    std::fs::remove_dir_all(&staging)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    tracing::info!(
+        "Restored the backup made on {} by Bichon {}, {} files, {} of them from earlier backups",
+        DateTime::from_timestamp_millis(manifest.created_at)
+            .map(|date| date.to_rfc3339())
+            .unwrap_or_default(),
+        manifest.bichon_version,
+        manifest.files.len(),
+        manifest
+            .files
+            .iter()
+            .filter(|file| file.stored_in.is_some())
+            .count()
+    );
+    Ok(())
+}
+
+/// Unpacks the files of the backup at `source` into `staging`, each from the backup of the
+/// chain storing it.
+fn unpack_chain(source: &Path, manifest: &BackupManifest, staging: &Path) -> BichonResult<()> {
+    let mut by_backup: HashMap<Option<&str>, HashSet<&str>> = HashMap::new();
+    for file in &manifest.files {
+        by_backup
+            .entry(file.stored_in.as_deref())
+            .or_default()
+            .insert(file.path.as_str());
+    }
+    // tarball entries are only unpacked into an existing directory
+    std::fs::create_dir_all(staging)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    let dir = source.parent().unwrap_or(Path::new("."));
+    for (stored_in, wanted) in by_backup {
+        let backup = match stored_in {
+            // names of backups in the same directory only
+            Some(name) if Path::new(name).file_name() == Some(OsStr::new(name)) => dir.join(name),
+            Some(name) => {
+                return Err(raise_error!(
+                    format!("The backup refers to an invalid backup name '{}'", name),
+                    ErrorCode::InvalidParameter
+                ))
+            }
+            None => source.to_path_buf(),
+        };
+        if !backup.exists() {
+            return Err(raise_error!(
+                format!(
+                    "The backup builds on {:?}, which is missing: keep the backups of a chain \
+                    in the same directory",
+                    backup
+                ),
+                ErrorCode::InvalidParameter
+            ));
+        }
+        tracing::info!(
+            "Unpacking {} files of backup {:?} into {:?}",
+            wanted.len(),
+            backup,
+            staging
+        );
+        unpack(&backup, staging, wanted)?;
+    }
+    Ok(())
+}
+
+/// Unpacks the `wanted` files of the backup at `source`, a directory or a tarball.
+fn unpack(source: &Path, staging: &Path, mut wanted: HashSet<&str>) -> BichonResult<()> {
+    if source.is_dir() {
+        for path in wanted {
+            let target = staging.join(path);
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            }
+            // missing files are reported once all are unpacked
+            if let Err(e) = std::fs::copy(source.join(path), target) {
+                tracing::warn!("Failed to copy '{}' from {:?}: {:#?}", path, source, e);
+            }
+        }
+        return Ok(());
+    }
+    let file = File::open(source)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    let mut archive = tar::Archive::new(file);
+    let entries = archive
+        .entries()
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    for entry in entries {
+        if wanted.is_empty() {
+            break;
+        }
+        let mut entry =
+            entry.map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        let path = entry
+            .path()
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+            .to_string_lossy()
+            .replace('\\', "/");
+        if wanted.remove(path.as_str()) {
+            entry
+                .unpack_in(staging)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads the manifest of the backup at `source`, from the copy next to a tarball when
+/// there is one.
+fn read_manifest(source: &Path) -> BichonResult<BackupManifest> {
+    let json = if source.is_dir() {
+        std::fs::read(source.join(MANIFEST_FILE)).ok()
+    } else {
+        match std::fs::read(sidecar_path(source)) {
+            Ok(json) => Some(json),
+            Err(_) => archived_manifest(source)?,
+        }
+    };
+    let json = json.ok_or_else(|| {
+        raise_error!(
+            format!("{:?} has no manifest, the backup is incomplete", source),
+            ErrorCode::InvalidParameter
+        )
+    })?;
+    serde_json::from_slice(&json)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InvalidParameter))
+}
+
+fn archived_manifest(source: &Path) -> BichonResult<Option<Vec<u8>>> {
+    let file = File::open(source)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InvalidParameter))?;
+    let mut archive = tar::Archive::new(file);
+    let entries = archive
+        .entries()
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InvalidParameter))?;
+    for entry in entries {
+        let mut entry =
+            entry.map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InvalidParameter))?;
+        let is_manifest = entry
+            .path()
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InvalidParameter))?
+            == Path::new(MANIFEST_FILE);
+        if is_manifest {
+            let mut json = Vec::new();
+            entry
+                .read_to_end(&mut json)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InvalidParameter))?;
+            return Ok(Some(json));
+        }
+    }
+    Ok(None)
+}
+
+/// Checks that this release can read the backup, that its files stay in the directory
+/// they are unpacked to and that its secrets can be decrypted.
+fn validate(manifest: &BackupManifest) -> BichonResult<()> {
+    let invalid = |message: String| raise_error!(message, ErrorCode::InvalidParameter);
+    if let Some(file) = manifest.files.iter().find(|file| !is_relative(&file.path)) {
+        return Err(invalid(format!(
+            "The backup lists an invalid file path '{}'",
+            file.path
+        )));
+    }
+    if manifest.format_version > BACKUP_FORMAT_VERSION {
+        return Err(invalid(format!(
+            "The backup has format version {}, this release reads up to version {}",
+            manifest.format_version, BACKUP_FORMAT_VERSION
+        )));
+    }
+    let made_by =
+        Version::parse(&manifest.bichon_version).map_err(|e| invalid(format!("{:#?}", e)))?;
+    let running = Version::parse(bichon_version!())
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    if made_by > running {
+        return Err(invalid(format!(
+            "The backup was made by Bichon {}, newer than this release ({})",
+            made_by, running
+        )));
+    }
+    if manifest.envelope_schema_version > ENVELOPE_SCHEMA_VERSION {
+        return Err(invalid(format!(
+            "The envelope index of the backup has schema version {}, newer than this release ({})",
+            manifest.envelope_schema_version, ENVELOPE_SCHEMA_VERSION
+        )));
+    }
+    if !is_known_master_key(&manifest.master_key_id) {
+        return Err(invalid(format!(
+            "The secrets of the backup are encrypted with master key {}, set \
+            bichon_encrypt_password or bichon_previous_encrypt_password to its password",
+            manifest.master_key_id
+        )));
+    }
+    if manifest.envelope_schema_version < ENVELOPE_SCHEMA_VERSION {
+        tracing::warn!(
+            "The envelope index of the backup has schema version {}, it is rebuilt to version \
+            {} once the server starts with bichon_auto_reindex, or through the reindex API",
+            manifest.envelope_schema_version,
+            ENVELOPE_SCHEMA_VERSION
+        );
+    }
+    Ok(())
+}
+
+/// Whether `path` is made of plain names only, so that joining it to a directory stays
+/// inside that directory.
+fn is_relative(path: &str) -> bool {
+    let path = Path::new(path);
+    path.components().next().is_some()
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+}
+
+/// Checks that the files of the backup unpacked in `dir` are complete.
+fn check_files(manifest: &BackupManifest, dir: &Path) -> BichonResult<()> {
+    let invalid = |message: String| raise_error!(message, ErrorCode::InvalidParameter);
+    for file in &manifest.files {
+        let size = std::fs::metadata(dir.join(&file.path))
+            .map(|metadata| metadata.len())
+            .map_err(|_| invalid(format!("'{}' is missing from the backup", file.path)))?;
+        if size != file.size {
+            return Err(invalid(format!(
+                "'{}' has {} bytes, {} expected",
+                file.path, size, file.size
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Moves a file, copying it when `target` is on another filesystem.
+fn move_file(source: &Path, target: &Path) -> BichonResult<()> {
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    }
+    if std::fs::rename(source, target).is_ok() {
+        return Ok(());
+    }
+    std::fs::copy(source, target)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    let _ = std::fs::remove_file(source);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use tantivy::{collector::Count, query::TermQuery, schema::IndexRecordOption, Index, Term};
+
+    use super::{check_files, is_relative, read_manifest, unpack_chain, validate};
+    use crate::{
+        id,
+        modules::{
+            backup::{BackupFormat, BACKUP},
+            indexer::schema::SchemaTools,
+            settings::dir::{EML_DIR, ENVELOPE_DIR},
+            testing,
+        },
+    };
+
+    /// Number of documents of the index in `dir` whose `field` is `value`.
+    fn count(dir: &Path, field: tantivy::schema::Field, value: u64) -> usize {
+        let index = Index::open_in_dir(dir).unwrap();
+        let query = TermQuery::new(Term::from_field_u64(field, value), IndexRecordOption::Basic);
+        index
+            .reader()
+            .unwrap()
+            .searcher()
+            .search(&query, &Count)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_incremental_backup_restores_through_its_parent() {
+        testing::run(async {
+            let dir = tempfile::tempdir().unwrap();
+            let account_id = testing::account_id();
+            let date = "Mon, 1 Jan 2024 10:00:00 +0000";
+            let (first, second) = (id!(64), id!(64));
+            let eml = testing::eml("a@acme.com", "b@acme.com", "First", date, "first body");
+            testing::archive(account_id, 1, first, &eml).await;
+            testing::commit().await;
+            let full = dir.path().join("full");
+            let full_manifest = BACKUP
+                .run(full.clone(), BackupFormat::Directory, true)
+                .await
+                .unwrap();
+            assert_eq!(full_manifest.parent, None);
+
+            let eml = testing::eml("a@acme.com", "b@acme.com", "Second", date, "second body");
+            testing::archive(account_id, 1, second, &eml).await;
+            testing::commit().await;
+            let incremental = dir.path().join("incremental.tar");
+            let manifest = BACKUP
+                .run(incremental.clone(), BackupFormat::Tar, true)
+                .await
+                .unwrap();
+            assert_eq!(manifest.parent.as_deref(), Some("full"));
+            let reused = manifest
+                .files
+                .iter()
+                .filter(|file| file.stored_in.as_deref() == Some("full"))
+                .count();
+            assert!(reused > 0);
+            assert!(reused < manifest.files.len());
+            assert_eq!(read_manifest(&incremental).unwrap(), manifest);
+
+            let staging = dir.path().join("restored");
+            validate(&manifest).unwrap();
+            unpack_chain(&incremental, &manifest, &staging).unwrap();
+            check_files(&manifest, &staging).unwrap();
+            for eid in [first, second] {
+                let f_id = SchemaTools::envelope_fields().f_id;
+                assert_eq!(count(&staging.join(ENVELOPE_DIR), f_id, eid), 1);
+                let f_id = SchemaTools::eml_fields().f_id;
+                assert_eq!(count(&staging.join(EML_DIR), f_id, eid), 1);
+            }
+        })
+    }
+
+    #[test]
+    fn test_manifest_paths_stay_in_the_backup() {
+        assert!(is_relative("eml/meta.json"));
+        assert!(is_relative("blobs/ab/cd"));
+        assert!(!is_relative(""));
+        assert!(!is_relative("/etc/passwd"));
+        assert!(!is_relative("../meta.db"));
+        assert!(!is_relative("eml/../../meta.db"));
+        assert!(!is_relative("./meta.db"));
+    }
+}
@@ -0,0 +1,433 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::{
+    collections::BTreeMap,
+    path::Path,
+    sync::{LazyLock, Mutex, RwLock},
+};
+
+use futures::future::BoxFuture;
+use poem_openapi::Object;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    modules::{
+        backup::BACKUP,
+        blob::{BlobStore, BLOB_STORE},
+        context::Initialize,
+        error::{code::ErrorCode, BichonResult},
+        indexer::manager::EML_INDEX_MANAGER,
+        settings::{cli::SETTINGS, system::SystemSetting},
+        utils::encrypt::{decrypt_bytes, decrypt_string_with, encrypt_bytes, encrypt_string_with},
+    },
+    raise_error, utc_now,
+};
+
+/// System setting holding the data keys, each encrypted with `bichon_encrypt_password`.
+const DATA_KEYS_SETTING: &str = "eml_data_keys";
+/// Start of an encrypted blob, followed by the id of its data key as a big-endian u32.
+/// Raw messages never start with a NUL byte.
+const SEALED_MAGIC: &[u8] = b"\0BCHENC1";
+
+pub static DATA_KEYS: LazyLock<DataKeys> = LazyLock::new(DataKeys::new);
+pub static BLOB_REENCRYPTION: LazyLock<BlobReencryption> = LazyLock::new(BlobReencryption::new);
+/// Held while a backup or a re-encryption starts: a backup copies blobs encrypted with
+/// the data keys of the moment, which a re-encryption retires, so neither starts while
+/// the other runs.
+pub static BLOB_JOBS: Mutex<()> = Mutex::new(());
+
+#[derive(Default, Deserialize, Serialize)]
+struct StoredDataKeys {
+    active: Option<u32>,
+    keys: Vec<StoredDataKey>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct StoredDataKey {
+    id: u32,
+    /// The hex key, encrypted with `bichon_encrypt_password`.
+    key: String,
+    created_at: i64,
+}
+
+#[derive(Clone, Default)]
+struct Keyring {
+    /// Key new blobs are encrypted with.
+    active: Option<u32>,
+    keys: BTreeMap<u32, ([u8; 32], i64)>,
+}
+
+/// Data keys of the blob store: blobs are encrypted with a data key, and data keys with
+/// the key-encryption key derived from `bichon_encrypt_password`. Changing the password
+/// only re-encrypts the data keys, rotating the data key re-encrypts every blob.
+///
+/// The envelope index is not covered: it stores the body and attachment text of messages
+/// in plaintext, for previews and snippets.
+pub struct DataKeys {
+    keyring: RwLock<Keyring>,
+}
+
+impl Initialize for DataKeys {
+    async fn initialize() -> BichonResult<()> {
+        let stored: StoredDataKeys = match SystemSetting::get_existing_value(DATA_KEYS_SETTING)? {
+            Some(value) => serde_json::from_str(&value)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?,
+            None => StoredDataKeys::default(),
+        };
+        let mut keyring = Keyring {
+            active: stored.active,
+            keys: BTreeMap::new(),
+        };
+        let mut changed = false;
+        for stored_key in stored.keys {
+            let (key, rewrapped) = unwrap_key(&stored_key.key)?;
+            changed |= rewrapped;
+            keyring
+                .keys
+                .insert(stored_key.id, (key, stored_key.created_at));
+        }
+        if changed {
+            tracing::info!("Re-encrypted the EML data keys with the new bichon_encrypt_password");
+        }
+        if SETTINGS.bichon_eml_encryption && keyring.active.is_none() {
+            let id = keyring.add_key()?;
+            tracing::info!("Created EML data key {}", id);
+            changed = true;
+        }
+        if changed {
+            save_keyring(&keyring).await?;
+        }
+        *DATA_KEYS.keyring.write().unwrap() = keyring;
+        Ok(())
+    }
+}
+
+impl DataKeys {
+    fn new() -> Self {
+        Self {
+            keyring: RwLock::new(Keyring::default()),
+        }
+    }
+
+    /// Encrypts a blob with the active data key, or returns `None` if blobs are stored
+    /// unencrypted.
+    pub fn seal(&self, data: &[u8]) -> BichonResult<Option<Vec<u8>>> {
+        if !SETTINGS.bichon_eml_encryption {
+            return Ok(None);
+        }
+        let keyring = self.keyring.read().unwrap();
+        let Some((id, (key, _))) = keyring
+            .active
+            .and_then(|id| keyring.keys.get_key_value(&id))
+        else {
+            return Err(raise_error!(
+                "No EML data key is available.".into(),
+                ErrorCode::InternalError
+            ));
+        };
+        let encrypted = encrypt_bytes(key, data)?;
+        let mut sealed = Vec::with_capacity(SEALED_MAGIC.len() + 4 + encrypted.len());
+        sealed.extend_from_slice(SEALED_MAGIC);
+        sealed.extend_from_slice(&id.to_be_bytes());
+        sealed.extend_from_slice(&encrypted);
+        Ok(Some(sealed))
+    }
+
+    /// Decrypts a blob, returned as is if it was stored unencrypted.
+    pub fn open(&self, data: Vec<u8>) -> BichonResult<Vec<u8>> {
+        let Some(rest) = data.strip_prefix(SEALED_MAGIC) else {
+            return Ok(data);
+        };
+        let Some((id, encrypted)) = rest.split_first_chunk::<4>() else {
+            return Err(raise_error!(
+                "Truncated encrypted blob".into(),
+                ErrorCode::InternalError
+            ));
+        };
+        let id = u32::from_be_bytes(*id);
+        let keyring = self.keyring.read().unwrap();
+        let (key, _) = keyring.keys.get(&id).ok_or_else(|| {
+            raise_error!(
+                format!("EML data key {} is missing", id),
+                ErrorCode::InternalError
+            )
+        })?;
+        decrypt_bytes(key, encrypted)
+    }
+
+    /// Makes a new data key the active one. Blobs keep their key until re-encrypted.
+    async fn rotate(&self) -> BichonResult<u32> {
+        if !SETTINGS.bichon_eml_encryption {
+            return Err(raise_error!(
+                "EML encryption is disabled, set bichon_eml_encryption to rotate its data key."
+                    .into(),
+                ErrorCode::InvalidParameter
+            ));
+        }
+        let mut keyring = self.keyring.read().unwrap().clone();
+        let id = keyring.add_key()?;
+        save_keyring(&keyring).await?;
+        *self.keyring.write().unwrap() = keyring;
+        tracing::info!("Rotated the EML data key to key {}", id);
+        Ok(id)
+    }
+
+    /// Forgets the data keys no blob is encrypted with anymore, once every blob was
+    /// written again with the active key, or unencrypted.
+    async fn retire_inactive(&self) -> BichonResult<()> {
+        let mut keyring = self.keyring.read().unwrap().clone();
+        let active = keyring.active.filter(|_| SETTINGS.bichon_eml_encryption);
+        let before = keyring.keys.len();
+        keyring.keys.retain(|id, _| Some(*id) == active);
+        let retired = before - keyring.keys.len();
+        if retired == 0 {
+            return Ok(());
+        }
+        keyring.active = active;
+        save_keyring(&keyring).await?;
+        *self.keyring.write().unwrap() = keyring;
+        tracing::info!("Retired {} unused EML data keys", retired);
+        Ok(())
+    }
+}
+
+impl Keyring {
+    fn add_key(&mut self) -> BichonResult<u32> {
+        let mut key = [0u8; 32];
+        SystemRandom::new().fill(&mut key).map_err(|_| {
+            raise_error!(
+                "Failed to generate a data key.".into(),
+                ErrorCode::InternalError
+            )
+        })?;
+        let id = self.keys.keys().next_back().map_or(1, |id| id + 1);
+        self.keys.insert(id, (key, utc_now!()));
+        self.active = Some(id);
+        Ok(id)
+    }
+}
+
+/// Decrypts a data key with `bichon_encrypt_password`, or with the previous password,
+/// in which case it has to be encrypted again.
+fn unwrap_key(wrapped: &str) -> BichonResult<([u8; 32], bool)> {
+    let (hex_key, rewrapped) = match decrypt_string_with(&SETTINGS.bichon_encrypt_password, wrapped)
+    {
+        Ok(hex_key) => (hex_key, false),
+        Err(_) => {
+            let previous = SETTINGS
+                .bichon_previous_encrypt_password
+                .as_deref()
+                .and_then(|password| decrypt_string_with(password, wrapped).ok());
+            let hex_key = previous.ok_or_else(|| {
+                raise_error!(
+                    "bichon_encrypt_password cannot decrypt the EML data keys. After changing it, set bichon_previous_encrypt_password to its former value.".into(),
+                    ErrorCode::InvalidParameter
+                )
+            })?;
+            (hex_key, true)
+        }
+    };
+    let key = hex::decode(hex_key)
+        .ok()
+        .and_then(|key| <[u8; 32]>::try_from(key).ok())
+        .ok_or_else(|| raise_error!("Malformed EML data key".into(), ErrorCode::InternalError))?;
+    Ok((key, rewrapped))
+}
+
+async fn save_keyring(keyring: &Keyring) -> BichonResult<()> {
+    let mut keys = Vec::with_capacity(keyring.keys.len());
+    for (id, (key, created_at)) in &keyring.keys {
+        keys.push(StoredDataKey {
+            id: *id,
+            key: encrypt_string_with(&SETTINGS.bichon_encrypt_password, &hex::encode(key))?,
+            created_at: *created_at,
+        });
+    }
+    let stored = StoredDataKeys {
+        active: keyring.active,
+        keys,
+    };
+    let value = serde_json::to_string(&stored)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    SystemSetting::set_value(DATA_KEYS_SETTING, value).await
+}
+
+/// Blob store encrypting what it writes with the active data key, and decrypting what
+/// it reads, unencrypted blobs included.
+pub struct EncryptedBlobStore {
+    inner: Box<dyn BlobStore>,
+}
+
+impl EncryptedBlobStore {
+    pub fn new(inner: Box<dyn BlobStore>) -> Self {
+        Self { inner }
+    }
+}
+
+impl BlobStore for EncryptedBlobStore {
+    fn put<'a>(&'a self, key: &'a str, data: &'a [u8]) -> BoxFuture<'a, BichonResult<()>> {
+        Box::pin(async move {
+            match DATA_KEYS.seal(data)? {
+                Some(sealed) => self.inner.put(key, &sealed).await,
+                None => self.inner.put(key, data).await,
+            }
+        })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, BichonResult<Option<Vec<u8>>>> {
+        Box::pin(async move {
+            self.inner
+                .get(key)
+                .await?
+                .map(|data| DATA_KEYS.open(data))
+                .transpose()
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, BichonResult<()>> {
+        self.inner.delete(key)
+    }
+
+    fn local_dir(&self) -> Option<&Path> {
+        self.inner.local_dir()
+    }
+}
+
+/// State of the encryption at rest of the EML store, and progress of its re-encryption.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize, Object)]
+pub struct EmlEncryptionStatus {
+    /// Whether new blobs are encrypted, see `bichon_eml_encryption`.
+    pub enabled: bool,
+    /// Data key new blobs are encrypted with.
+    pub active_key_id: Option<u32>,
+    /// Data keys blobs may still be encrypted with.
+    pub key_ids: Vec<u32>,
+    pub running: bool,
+    /// Number of blobs known so far, attachments are found while going through bodies.
+    pub total: u64,
+    /// Number of blobs processed so far.
+    pub processed: u64,
+    pub started_at: Option<i64>,
+    pub finished_at: Option<i64>,
+    /// Reason the last re-encryption stopped, if it did not complete.
+    pub error: Option<String>,
+}
+
+/// Writes every blob again in the background, encrypted with the active data key, or
+/// unencrypted if encryption was disabled. Data keys no longer used are then retired.
+pub struct BlobReencryption {
+    status: RwLock<EmlEncryptionStatus>,
+}
+
+impl BlobReencryption {
+    fn new() -> Self {
+        Self {
+            status: RwLock::new(EmlEncryptionStatus::default()),
+        }
+    }
+
+    pub fn status(&self) -> EmlEncryptionStatus {
+        let mut status = self.status.read().unwrap().clone();
+        let keyring = DATA_KEYS.keyring.read().unwrap();
+        status.enabled = SETTINGS.bichon_eml_encryption;
+        status.active_key_id = keyring.active.filter(|_| status.enabled);
+        status.key_ids = keyring.keys.keys().copied().collect();
+        status
+    }
+
+    /// Starts re-encrypting every blob, with a new data key if `rotate` is set. Fails if
+    /// a re-encryption or a backup is already running.
+    pub async fn start(&'static self, rotate: bool) -> BichonResult<()> {
+        {
+            let _jobs = BLOB_JOBS.lock().unwrap();
+            if BACKUP.status().running {
+                return Err(raise_error!(
+                    "A backup is running, retry once done.".into(),
+                    ErrorCode::InvalidParameter
+                ));
+            }
+            let mut status = self.status.write().unwrap();
+            if status.running {
+                return Err(raise_error!(
+                    "The EML store is already being re-encrypted.".into(),
+                    ErrorCode::InvalidParameter
+                ));
+            }
+            *status = EmlEncryptionStatus {
+                running: true,
+                started_at: Some(utc_now!()),
+                ..Default::default()
+            };
+        }
+        if rotate {
+            if let Err(e) = DATA_KEYS.rotate().await {
+                self.finish(Some(format!("{:#?}", e)));
+                return Err(e);
+            }
+        }
+        tokio::spawn(async move {
+            match self.run().await {
+                Ok(()) => {
+                    tracing::info!("EML store re-encryption completed");
+                    self.finish(None);
+                }
+                Err(e) => {
+                    tracing::error!("EML store re-encryption failed: {:#?}", e);
+                    self.finish(Some(format!("{:#?}", e)));
+                }
+            }
+        });
+        Ok(())
+    }
+
+    async fn run(&self) -> BichonResult<()> {
+        let store = BLOB_STORE.as_ref();
+        EML_INDEX_MANAGER
+            .migrate_blobs(Some(store), store, |processed, total| {
+                let mut status = self.status.write().unwrap();
+                status.processed = processed;
+                status.total = total;
+            })
+            .await?;
+        DATA_KEYS.retire_inactive().await
+    }
+
+    fn finish(&self, error: Option<String>) {
+        let mut status = self.status.write().unwrap();
+        status.running = false;
+        status.finished_at = Some(utc_now!());
+        status.error = error;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_open_unencrypted() {
+        let eml = b"From: a@example.com\r\n\r\nbody".to_vec();
+        assert_eq!(DATA_KEYS.open(eml.clone()).unwrap(), eml);
+        let mut sealed = SEALED_MAGIC.to_vec();
+        sealed.extend_from_slice(&42u32.to_be_bytes());
+        sealed.extend_from_slice(&[0u8; 32]);
+        assert!(DATA_KEYS.open(sealed).is_err());
+    }
+}
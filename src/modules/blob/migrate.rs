@@ -0,0 +1,56 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::modules::{
+    blob::{open_blob_store, BLOB_STORE},
+    error::BichonResult,
+    indexer::manager::EML_INDEX_MANAGER,
+    settings::cli::{BlobStoreKind, SETTINGS},
+};
+
+/// Offline migration run by `bichon_migrate_blobs_to`: copies every stored body and
+/// attachment, including those still held inline by the EML store, to the `target` store.
+/// The current store keeps its copies, to be removed once the server runs on `target`,
+/// and the content held inline is moved to it first, so that either store can be kept.
+pub async fn migrate_blobs(target: BlobStoreKind) -> BichonResult<()> {
+    let source = SETTINGS.bichon_blob_store;
+    tracing::info!(
+        "Migrating blobs from the {:?} store to the {:?} store",
+        source,
+        target
+    );
+    // only the content still held inline has to move to the current store
+    let moved = EML_INDEX_MANAGER
+        .migrate_blobs(None, BLOB_STORE.as_ref(), |_, _| {})
+        .await?;
+    let migrated = if source == target {
+        moved
+    } else {
+        tracing::info!("Moved {} inline blobs to the {:?} store", moved, source);
+        let target_store = open_blob_store(target)?;
+        EML_INDEX_MANAGER
+            .migrate_blobs(Some(BLOB_STORE.as_ref()), target_store.as_ref(), |_, _| {})
+            .await?
+    };
+    tracing::info!(
+        "Migrated {} blobs. Start the server with bichon_blob_store set to {}.",
+        migrated,
+        format!("{:?}", target).to_lowercase()
+    );
+    Ok(())
+}
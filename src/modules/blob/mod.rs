@@ -0,0 +1,104 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::LazyLock,
+};
+
+use futures::future::BoxFuture;
+
+use crate::{
+    modules::{
+        blob::{crypto::EncryptedBlobStore, fs::FsBlobStore, s3::S3BlobStore},
+        error::{code::ErrorCode, BichonResult},
+        settings::{
+            cli::{BlobStoreKind, SETTINGS},
+            dir::DATA_DIR_MANAGER,
+        },
+    },
+    raise_error,
+};
+
+pub mod crypto;
+pub mod fs;
+pub mod migrate;
+pub mod s3;
+
+/// Store holding raw message bodies and attachments, configured by `bichon_blob_store`.
+pub static BLOB_STORE: LazyLock<Box<dyn BlobStore>> = LazyLock::new(|| {
+    open_blob_store(SETTINGS.bichon_blob_store)
+        .unwrap_or_else(|e| panic!("Failed to open the blob store: {:#?}", e))
+});
+
+/// Content-addressed storage for the bytes the EML index refers to. Keys are built with
+/// `blob_key`, and a key is never written with different content.
+pub trait BlobStore: Send + Sync {
+    fn put<'a>(&'a self, key: &'a str, data: &'a [u8]) -> BoxFuture<'a, BichonResult<()>>;
+
+    /// Returns `None` if nothing is stored under `key`.
+    fn get<'a>(&'a self, key: &'a str) -> BoxFuture<'a, BichonResult<Option<Vec<u8>>>>;
+
+    /// Deleting a missing key succeeds.
+    fn delete<'a>(&'a self, key: &'a str) -> BoxFuture<'a, BichonResult<()>>;
+
+    /// Local directory of the store, to report its disk usage.
+    fn local_dir(&self) -> Option<&Path> {
+        None
+    }
+}
+
+/// Opens a store of the given kind, encrypting and decrypting blobs with the data keys.
+pub fn open_blob_store(kind: BlobStoreKind) -> BichonResult<Box<dyn BlobStore>> {
+    let store: Box<dyn BlobStore> = match kind {
+        BlobStoreKind::Filesystem => {
+            let dir = SETTINGS
+                .bichon_blob_dir
+                .as_ref()
+                .map(PathBuf::from)
+                .unwrap_or_else(|| DATA_DIR_MANAGER.blob_dir.clone());
+            Box::new(FsBlobStore::new(dir))
+        }
+        BlobStoreKind::S3 => {
+            let setting = |value: &Option<String>, name: &str| {
+                value.clone().filter(|v| !v.is_empty()).ok_or_else(|| {
+                    raise_error!(
+                        format!("'{}' is required by the s3 blob store.", name),
+                        ErrorCode::InvalidParameter
+                    )
+                })
+            };
+            Box::new(S3BlobStore::new(
+                &setting(&SETTINGS.bichon_s3_endpoint, "bichon_s3_endpoint")?,
+                setting(&SETTINGS.bichon_s3_bucket, "bichon_s3_bucket")?,
+                SETTINGS.bichon_s3_region.clone(),
+                setting(&SETTINGS.bichon_s3_access_key, "bichon_s3_access_key")?,
+                setting(&SETTINGS.bichon_s3_secret_key, "bichon_s3_secret_key")?,
+                SETTINGS.bichon_s3_prefix.clone(),
+            )?)
+        }
+    };
+    Ok(Box::new(EncryptedBlobStore::new(store)))
+}
+
+/// Key of a blob: its namespace, such as `messages` or `attachments`, then the hex
+/// SHA-256 of its content split as `ab/cdef…` to keep directories small.
+pub fn blob_key(namespace: &str, hash: &[u8]) -> String {
+    let hex = hex::encode(hash);
+    format!("{}/{}/{}", namespace, &hex[..2.min(hex.len())], hex)
+}
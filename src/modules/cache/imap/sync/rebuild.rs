@@ -29,6 +29,9 @@ use crate::{
         },
         error::{code::ErrorCode, BichonError, BichonResult},
         indexer::manager::{EML_INDEX_MANAGER, ENVELOPE_INDEX_MANAGER},
+        legal_hold::LEGAL_HOLDS,
+        message::{delete::delete_messages_impl, search::SearchFilter},
+        settings::cli::SETTINGS,
     },
     raise_error,
 };
@@ -140,17 +143,45 @@ pub async fn rebuild_cache_since_date(
     Ok(())
 }
 
+/// Removes the envelopes of a mailbox before fetching it again. Messages under legal hold,
+/// or every message in WORM mode, are kept, and replaced by their copy on the server if it
+/// is fetched again.
+async fn reset_mailbox(account_id: u64, mailbox_id: u64) -> BichonResult<()> {
+    if SETTINGS.bichon_worm_mode {
+        info!(
+            "Account {}: WORM mode, keeping the archived messages of mailbox {}",
+            account_id, mailbox_id
+        );
+        return Ok(());
+    }
+    let holds = LEGAL_HOLDS.filters();
+    if holds.is_empty() {
+        ENVELOPE_INDEX_MANAGER
+            .delete_mailbox_envelopes(account_id, vec![mailbox_id])
+            .await?;
+        EML_INDEX_MANAGER
+            .delete_mailbox_envelopes(account_id, vec![mailbox_id])
+            .await?;
+        return Ok(());
+    }
+    let filter = SearchFilter {
+        account_id: Some(account_id),
+        mailbox_id: Some(mailbox_id),
+        ..Default::default()
+    };
+    let unheld = ENVELOPE_INDEX_MANAGER.matching_ids(filter, holds).await?;
+    if !unheld.is_empty() {
+        delete_messages_impl(unheld).await?;
+    }
+    Ok(())
+}
+
 pub async fn rebuild_mailbox_cache(
     account: &AccountModel,
     local_mailbox: &MailBox,
     remote_mailbox: &MailBox,
 ) -> BichonResult<()> {
-    ENVELOPE_INDEX_MANAGER
-        .delete_mailbox_envelopes(account.id, vec![local_mailbox.id])
-        .await?;
-    EML_INDEX_MANAGER
-        .delete_mailbox_envelopes(account.id, vec![local_mailbox.id])
-        .await?;
+    reset_mailbox(account.id, local_mailbox.id).await?;
     if remote_mailbox.exists == 0 {
         info!(
             "Account {}: Mailbox '{}' has no emails on the remote server. The mailbox is empty, no envelopes to fetch.",
@@ -175,12 +206,7 @@ pub async fn rebuild_mailbox_cache_since_date(
     date_since: &DateSince,
     remote: &MailBox,
 ) -> BichonResult<()> {
-    ENVELOPE_INDEX_MANAGER
-        .delete_mailbox_envelopes(account.id, vec![local_mailbox_id])
-        .await?;
-    EML_INDEX_MANAGER
-        .delete_mailbox_envelopes(account.id, vec![local_mailbox_id])
-        .await?;
+    reset_mailbox(account.id, local_mailbox_id).await?;
     if remote.exists == 0 {
         info!(
             "Account {}: Mailbox '{}' has no emails on the remote server. The mailbox is empty, no envelopes to fetch.",
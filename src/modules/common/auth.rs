@@ -112,6 +112,15 @@ impl ClientContext {
         }
     }
 
+    /// Identifies the caller for data kept per access token: the owner id of the token, or
+    /// `root` for the root token and when access tokens are disabled.
+    pub fn principal(&self) -> String {
+        match &self.access_token {
+            Some(token) => AccessToken::owner_id(&token.token),
+            None => "root".into(),
+        }
+    }
+
     pub fn accessible_accounts(&self) -> BichonResult<Option<&BTreeSet<AccountInfo>>> {
         if !SETTINGS.bichon_enable_access_token || self.is_root {
             Ok(None) // All accounts are accessible
@@ -24,8 +24,12 @@ use crate::{
     bichon_version,
     modules::{
         account::migration::AccountModel,
+        blob::BLOB_STORE,
         error::{code::ErrorCode, BichonResult},
-        indexer::{manager::ENVELOPE_INDEX_MANAGER, schema::SchemaTools},
+        indexer::{
+            manager::{EML_INDEX_MANAGER, ENVELOPE_INDEX_MANAGER},
+            schema::SchemaTools,
+        },
         settings::dir::DATA_DIR_MANAGER,
         utils::get_total_size,
     },
@@ -38,6 +42,7 @@ pub struct DashboardStats {
     pub email_count: u64,                      // Total number of emails
     pub total_size_bytes: u64,                 // Total size of all emails (in bytes)
     pub storage_usage_bytes: u64,              // Actual storage used (in bytes)
+    pub deduplicated_bytes: u64,               // Bytes saved by deduplication
     pub index_usage_bytes: u64,                // Index storage size (in bytes)
     pub recent_activity: Vec<TimeBucket>,      // Email activity over recent days
     pub top_senders: Vec<Group>,               // Top 10 senders
@@ -45,6 +50,8 @@ pub struct DashboardStats {
     pub with_attachment_count: u64,            // Emails with attachments
     pub without_attachment_count: u64,         // Emails without attachments
     pub top_largest_emails: Vec<LargestEmail>, // Top 10 largest emails
+    pub attachment_deduplicated_bytes: u64,    // Part of it saved on attachments
+    pub top_deduplicated_attachments: Vec<DeduplicatedAttachment>, // Top 10 attachment savings
     pub system_version: String, // The semantic version string of the currently running backend service
     pub commit_hash: String,    // Git commit hash used to build this system version
 }
@@ -57,7 +64,18 @@ impl DashboardStats {
         stat.account_count = AccountModel::count().await?;
         stat.storage_usage_bytes = get_total_size(&DATA_DIR_MANAGER.eml_dir)
             .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-        stat.index_usage_bytes = get_total_size(&DATA_DIR_MANAGER.envelope_dir)
+        let eml = EML_INDEX_MANAGER.storage_stats()?;
+        // a remote blob store is counted by what the EML store refers to
+        stat.storage_usage_bytes += match BLOB_STORE.local_dir() {
+            Some(dir) => get_total_size(&dir.to_path_buf())
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?,
+            None => eml.stored_bytes,
+        };
+        let (saved, attachments) = EML_INDEX_MANAGER.deduplicated_attachments(10).await?;
+        stat.deduplicated_bytes = eml.shared_body_bytes + saved;
+        stat.attachment_deduplicated_bytes = saved;
+        stat.top_deduplicated_attachments = attachments;
+        stat.index_usage_bytes = get_total_size(&ENVELOPE_INDEX_MANAGER.index_dir())
             .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
         stat.system_version = bichon_version!().to_string();
         stat.commit_hash = env!("GIT_HASH").to_string();
@@ -111,3 +129,11 @@ impl LargestEmail {
         Ok(envelope)
     }
 }
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize, Object)]
+pub struct DeduplicatedAttachment {
+    pub name: String,     // File name the attachment was first stored with
+    pub size_bytes: u64,  // Decoded attachment size in bytes
+    pub copies: u64,      // Number of distinct stored emails containing it
+    pub saved_bytes: u64, // Bytes saved by storing it once
+}
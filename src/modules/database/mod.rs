@@ -17,15 +17,20 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use crate::modules::account::migration::{AccountV1, AccountV2};
+use crate::modules::alert::{AlertNotification, SearchAlert};
 use crate::modules::autoconfig::CachedMailSettings;
 use crate::modules::error::code::ErrorCode;
 use crate::modules::error::BichonResult;
+use crate::modules::legal_hold::LegalHold;
+use crate::modules::message::saved::SavedSearch;
 use crate::modules::oauth2::entity::OAuth2;
 use crate::modules::oauth2::pending::OAuth2PendingEntity;
 use crate::modules::oauth2::token::OAuth2AccessToken;
+use crate::modules::retention::RetentionRule;
 use crate::modules::settings::proxy::Proxy;
 use crate::modules::settings::system::SystemSetting;
 use crate::modules::token::AccessToken;
+use crate::modules::worm::LedgerEntry;
 use crate::raise_error;
 use db_type::{KeyOptions, ToKeyDefinition};
 use itertools::Itertools;
@@ -67,6 +72,12 @@ impl ModelsAdapter {
         self.register_model::<OAuth2PendingEntity>();
         self.register_model::<OAuth2AccessToken>();
         self.register_model::<Proxy>();
+        self.register_model::<SavedSearch>();
+        self.register_model::<SearchAlert>();
+        self.register_model::<AlertNotification>();
+        self.register_model::<RetentionRule>();
+        self.register_model::<LegalHold>();
+        self.register_model::<LedgerEntry>();
     }
 }
 
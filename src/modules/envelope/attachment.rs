@@ -0,0 +1,585 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+use std::{
+    io::{Cursor, Read},
+    path::PathBuf,
+    process::Stdio,
+    sync::LazyLock,
+    time::Duration,
+};
+
+use mail_parser::{Message, MessageParser, MimeHeaders};
+use quick_xml::{events::Event, Reader};
+use serde::{Deserialize, Serialize};
+use tantivy::TantivyDocument;
+use tokio::{io::AsyncWriteExt, process::Command, sync::Semaphore};
+
+use crate::{
+    modules::{
+        error::{code::ErrorCode, BichonResult},
+        indexer::{envelope::Envelope, schema::SchemaTools, tokenizer::stemmed_text},
+        settings::cli::SETTINGS,
+    },
+    raise_error,
+};
+
+/// At most this many characters of text are indexed per attachment.
+const MAX_TEXT_CHARS: usize = 200_000;
+/// Starts this executable as an attachment text extraction worker.
+const WORKER_FLAG: &str = "--bichon-attachment-text-worker";
+/// Upper bound on the decompressed size of a single part inside an office document.
+const MAX_ARCHIVE_ENTRY_BYTES: u64 = 64 * 1024 * 1024;
+
+pub static ATTACHMENT_TEXT_PIPELINE: LazyLock<AttachmentTextPipeline> =
+    LazyLock::new(AttachmentTextPipeline::with_default_extractors);
+
+/// Text content of a single attachment.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AttachmentText {
+    pub name: String,
+    pub text: String,
+}
+
+/// Pulls plain text out of one family of attachment formats.
+pub trait TextExtractor: Send + Sync {
+    /// Returns `true` when this extractor handles attachments with the given MIME type
+    /// (lowercase, without parameters) or file name (lowercase).
+    fn accepts(&self, mime_type: &str, file_name: &str) -> bool;
+
+    fn extract(&self, data: &[u8]) -> BichonResult<String>;
+}
+
+/// Command started to extract the attachment text of a message: it reads the raw message
+/// on stdin and writes the text of its attachments, as JSON, on stdout.
+pub struct WorkerCommand {
+    program: PathBuf,
+    args: Vec<String>,
+}
+
+impl WorkerCommand {
+    /// This executable, running [`run_extraction_worker`].
+    pub fn current_exe() -> Self {
+        let program = std::env::current_exe()
+            .ok()
+            .or_else(|| std::env::args_os().next().map(PathBuf::from))
+            .unwrap_or_else(|| PathBuf::from("bichon"));
+        Self {
+            program,
+            args: vec![WORKER_FLAG.to_string()],
+        }
+    }
+
+    #[cfg(test)]
+    pub fn new(program: impl Into<PathBuf>, args: &[&str]) -> Self {
+        Self {
+            program: program.into(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+        }
+    }
+}
+
+/// Ordered list of extractors run on every attachment of a message; the first extractor
+/// accepting an attachment is the only one used for it.
+pub struct AttachmentTextPipeline {
+    extractors: Vec<Box<dyn TextExtractor>>,
+    /// Bounds the number of worker processes running at once.
+    workers: Semaphore,
+    worker: WorkerCommand,
+}
+
+impl AttachmentTextPipeline {
+    pub fn new() -> Self {
+        let workers = std::thread::available_parallelism()
+            .map(|n| n.get())
+            .unwrap_or(4);
+        Self {
+            extractors: Vec::new(),
+            workers: Semaphore::new(workers),
+            worker: WorkerCommand::current_exe(),
+        }
+    }
+
+    pub fn with_default_extractors() -> Self {
+        Self::new()
+            .with(PdfExtractor)
+            .with(OfficeXmlExtractor)
+            .with(HtmlExtractor)
+            .with(PlainTextExtractor)
+    }
+
+    pub fn with(mut self, extractor: impl TextExtractor + 'static) -> Self {
+        self.extractors.push(Box::new(extractor));
+        self
+    }
+
+    #[cfg(test)]
+    pub fn with_worker(mut self, worker: WorkerCommand) -> Self {
+        self.worker = worker;
+        self
+    }
+
+    /// Extracts the text of every supported attachment of a raw message. Extractors run
+    /// in a worker process, killed after `bichon_attachment_text_timeout` seconds, in which
+    /// case the message is indexed without attachment contents.
+    pub async fn extract(&'static self, eml: &[u8]) -> Vec<AttachmentText> {
+        if !SETTINGS.bichon_attachment_text_extraction {
+            return Vec::new();
+        }
+        let extractable = MessageParser::new()
+            .parse(eml)
+            .is_some_and(|message| !self.extractable(&message).is_empty());
+        if !extractable {
+            return Vec::new();
+        }
+        let Ok(_permit) = self.workers.acquire().await else {
+            return Vec::new();
+        };
+        let timeout = Duration::from_secs(SETTINGS.bichon_attachment_text_timeout);
+        match tokio::time::timeout(timeout, self.run_worker(eml)).await {
+            Ok(Ok(texts)) => texts,
+            Ok(Err(e)) => {
+                tracing::warn!("Attachment text extraction failed: {:#?}", e);
+                Vec::new()
+            }
+            Err(_) => {
+                tracing::warn!(
+                    "Attachment text extraction timed out after {}s, skipping attachment contents",
+                    timeout.as_secs()
+                );
+                Vec::new()
+            }
+        }
+    }
+
+    /// Runs the worker command in a child process, killed once the returned future is
+    /// dropped.
+    async fn run_worker(&self, eml: &[u8]) -> BichonResult<Vec<AttachmentText>> {
+        // settings given as arguments to the server only reach the worker through its
+        // environment
+        let mut child = Command::new(&self.worker.program)
+            .args(&self.worker.args)
+            .env(
+                "BICHON_ATTACHMENT_TEXT_MAX_SIZE",
+                SETTINGS.bichon_attachment_text_max_size.to_string(),
+            )
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        if let Some(mut stdin) = child.stdin.take() {
+            // a worker exiting early is reported with its status and stderr below
+            let _ = stdin.write_all(eml).await;
+        }
+        let output = child
+            .wait_with_output()
+            .await
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        if !output.status.success() {
+            return Err(raise_error!(
+                format!(
+                    "The extraction worker exited with {}: {}",
+                    output.status,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+                ErrorCode::InternalError
+            ));
+        }
+        serde_json::from_slice(&output.stdout)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))
+    }
+
+    /// Builds the envelope document of a message, including the text of its attachments.
+    ///
+    /// A message `sealed` with the PGP key of its account keeps its body and attachments out
+    /// of the index, which would otherwise store them in plaintext: only its metadata is
+    /// indexed.
+    pub async fn to_document(
+        &'static self,
+        envelope: &Envelope,
+        mailbox_id: u64,
+        eml: &[u8],
+        sealed: bool,
+    ) -> BichonResult<TantivyDocument> {
+        if sealed {
+            let metadata = Envelope {
+                text: String::new(),
+                ..envelope.clone()
+            };
+            return metadata.to_document(mailbox_id);
+        }
+        let mut doc = envelope.to_document(mailbox_id)?;
+        if envelope.attachments.is_empty() {
+            return Ok(doc);
+        }
+        let fields = SchemaTools::envelope_fields();
+        for attachment in self.extract(eml).await {
+            doc.add_text(fields.f_attachment_content, &attachment.text);
+            doc.add_text(fields.f_attachment_content_name, &attachment.name);
+            if SETTINGS.bichon_stemming {
+                doc.add_pre_tokenized_text(
+                    fields.f_stemmed,
+                    stemmed_text(&attachment.text, envelope.language.as_deref()),
+                );
+            }
+        }
+        Ok(doc)
+    }
+
+    fn extract_blocking(&self, eml: &[u8]) -> Vec<AttachmentText> {
+        let Some(message) = MessageParser::new().parse(eml) else {
+            return Vec::new();
+        };
+        let mut texts = Vec::new();
+        for (name, data, extractor) in self.extractable(&message) {
+            match extractor.extract(data) {
+                Ok(text) => {
+                    let text = normalize_whitespace(&text);
+                    if !text.is_empty() {
+                        texts.push(AttachmentText {
+                            name: name.to_string(),
+                            text,
+                        });
+                    }
+                }
+                Err(e) => {
+                    tracing::debug!(
+                        "Failed to extract text from attachment '{}': {:#?}",
+                        name,
+                        e
+                    )
+                }
+            }
+        }
+        texts
+    }
+
+    /// Attachments of a message an extractor accepts, along with that extractor.
+    fn extractable<'a>(
+        &'a self,
+        message: &'a Message<'a>,
+    ) -> Vec<(&'a str, &'a [u8], &'a dyn TextExtractor)> {
+        let max_size = SETTINGS.bichon_attachment_text_max_size;
+        let mut extractable = Vec::new();
+        for attachment in message.attachments() {
+            let Some(name) = attachment.attachment_name() else {
+                continue;
+            };
+            let data = attachment.contents();
+            if data.is_empty() || data.len() > max_size {
+                continue;
+            }
+            let mime_type = attachment
+                .content_type()
+                .map(|ct| {
+                    format!(
+                        "{}/{}",
+                        ct.c_type.as_ref(),
+                        ct.c_subtype.as_deref().unwrap_or("")
+                    )
+                    .to_lowercase()
+                })
+                .unwrap_or_default();
+            let file_name = name.to_lowercase();
+            let Some(extractor) = self
+                .extractors
+                .iter()
+                .find(|e| e.accepts(&mime_type, &file_name))
+            else {
+                continue;
+            };
+            extractable.push((name, data, extractor.as_ref()));
+        }
+        extractable
+    }
+}
+
+/// Entry point of the worker process started with `bichon_attachment_text_worker`: reads
+/// a raw message on stdin and writes the text of its attachments, as JSON, on stdout.
+pub fn run_extraction_worker() -> BichonResult<()> {
+    let mut eml = Vec::new();
+    std::io::stdin()
+        .read_to_end(&mut eml)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    let texts = ATTACHMENT_TEXT_PIPELINE.extract_blocking(&eml);
+    serde_json::to_writer(std::io::stdout().lock(), &texts)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))
+}
+
+/// Collapses runs of whitespace and caps the text at [`MAX_TEXT_CHARS`] characters.
+fn normalize_whitespace(text: &str) -> String {
+    let mut normalized = String::new();
+    let mut chars = 0;
+    for word in text.split_whitespace() {
+        if chars >= MAX_TEXT_CHARS {
+            break;
+        }
+        if chars > 0 {
+            normalized.push(' ');
+            chars += 1;
+        }
+        normalized.push_str(word);
+        chars += word.chars().count();
+    }
+    if chars > MAX_TEXT_CHARS {
+        normalized = normalized.chars().take(MAX_TEXT_CHARS).collect();
+    }
+    normalized
+}
+
+fn has_extension(file_name: &str, extensions: &[&str]) -> bool {
+    file_name
+        .rsplit_once('.')
+        .is_some_and(|(_, ext)| extensions.contains(&ext))
+}
+
+pub struct PdfExtractor;
+
+impl TextExtractor for PdfExtractor {
+    fn accepts(&self, mime_type: &str, file_name: &str) -> bool {
+        mime_type == "application/pdf" || has_extension(file_name, &["pdf"])
+    }
+
+    fn extract(&self, data: &[u8]) -> BichonResult<String> {
+        // pdf-extract panics on some malformed documents
+        std::panic::catch_unwind(|| pdf_extract::extract_text_from_mem(data))
+            .map_err(|_| {
+                raise_error!(
+                    "PDF text extraction panicked".into(),
+                    ErrorCode::InternalError
+                )
+            })?
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))
+    }
+}
+
+/// DOCX, XLSX, ODT, ODS and ODP: zip archives holding the text in XML parts.
+pub struct OfficeXmlExtractor;
+
+impl TextExtractor for OfficeXmlExtractor {
+    fn accepts(&self, mime_type: &str, file_name: &str) -> bool {
+        has_extension(file_name, &["docx", "xlsx", "odt", "ods", "odp"])
+            || mime_type.ends_with("wordprocessingml.document")
+            || mime_type.ends_with("spreadsheetml.sheet")
+            || mime_type.starts_with("application/vnd.oasis.opendocument.")
+    }
+
+    fn extract(&self, data: &[u8]) -> BichonResult<String> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(data))
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        // The format is told apart by the archive content, the MIME type is not reliable.
+        let parts: &[&str] = if archive.index_for_name("word/document.xml").is_some() {
+            &["word/document.xml"]
+        } else if archive.index_for_name("xl/sharedStrings.xml").is_some() {
+            &["xl/sharedStrings.xml"]
+        } else {
+            &["content.xml"]
+        };
+        let mut text = String::new();
+        for part in parts {
+            let Ok(entry) = archive.by_name(part) else {
+                continue;
+            };
+            let mut xml = String::new();
+            entry
+                .take(MAX_ARCHIVE_ENTRY_BYTES)
+                .read_to_string(&mut xml)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            xml_text(&xml, &mut text)?;
+        }
+        Ok(text)
+    }
+}
+
+/// Appends the text nodes of an XML document, separated by spaces.
+fn xml_text(xml: &str, output: &mut String) -> BichonResult<()> {
+    let mut reader = Reader::from_str(xml);
+    loop {
+        match reader
+            .read_event()
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+        {
+            Event::Text(text) => {
+                let text = text
+                    .unescape()
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                output.push_str(&text);
+            }
+            Event::End(e) if is_text_break(e.local_name().as_ref()) => output.push(' '),
+            Event::Empty(e) if is_text_break(e.local_name().as_ref()) => output.push(' '),
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Elements ending a word: paragraphs, headings, tabs, line breaks, table cells and shared
+/// strings. Runs inside a paragraph can split a word, so they are concatenated as is.
+fn is_text_break(local_name: &[u8]) -> bool {
+    matches!(
+        local_name,
+        b"p" | b"h" | b"tab" | b"br" | b"tc" | b"si" | b"s" | b"line-break" | b"table-cell"
+    )
+}
+
+pub struct HtmlExtractor;
+
+impl TextExtractor for HtmlExtractor {
+    fn accepts(&self, mime_type: &str, file_name: &str) -> bool {
+        mime_type == "text/html" || has_extension(file_name, &["html", "htm"])
+    }
+
+    fn extract(&self, data: &[u8]) -> BichonResult<String> {
+        html2text::config::plain()
+            .allow_width_overflow()
+            .string_from_read(data, 100)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))
+    }
+}
+
+pub struct PlainTextExtractor;
+
+impl TextExtractor for PlainTextExtractor {
+    fn accepts(&self, mime_type: &str, file_name: &str) -> bool {
+        mime_type.starts_with("text/")
+            || has_extension(file_name, &["txt", "csv", "md", "log", "json", "xml"])
+    }
+
+    fn extract(&self, data: &[u8]) -> BichonResult<String> {
+        Ok(String::from_utf8_lossy(data).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::{Cursor, Write};
+
+    use tantivy::{DocAddress, Document, Index, IndexWriter, TantivyDocument};
+    use zip::write::SimpleFileOptions;
+
+    use super::{
+        normalize_whitespace, AttachmentText, AttachmentTextPipeline, OfficeXmlExtractor,
+        TextExtractor, WorkerCommand, ATTACHMENT_TEXT_PIPELINE, MAX_TEXT_CHARS,
+    };
+    use crate::modules::{
+        envelope::extractor::extract_envelope_from_eml,
+        indexer::{envelope::Envelope, schema::SchemaTools, tokenizer::register_tokenizers},
+    };
+
+    #[test]
+    fn test_docx_text_extraction() {
+        let mut buffer = Cursor::new(Vec::new());
+        let mut writer = zip::ZipWriter::new(&mut buffer);
+        writer
+            .start_file("word/document.xml", SimpleFileOptions::default())
+            .unwrap();
+        writer
+            .write_all(
+                br#"<w:document><w:body><w:p><w:r><w:t>Quarter</w:t></w:r><w:r><w:t>ly report &amp; forecast</w:t></w:r></w:p><w:p><w:r><w:t>Second</w:t></w:r></w:p></w:body></w:document>"#,
+            )
+            .unwrap();
+        writer.finish().unwrap();
+
+        let extractor = OfficeXmlExtractor;
+        assert!(extractor.accepts("application/octet-stream", "report.docx"));
+        let text = extractor.extract(buffer.get_ref()).unwrap();
+        assert_eq!(
+            normalize_whitespace(&text),
+            "Quarterly report & forecast Second"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sealed_envelope_stores_no_content() {
+        let eml = b"From: a@example.com\r\nTo: b@example.com\r\nSubject: Quarterly plan\r\n\
+            Message-ID: <sealed@example.com>\r\nMIME-Version: 1.0\r\n\
+            Content-Type: multipart/mixed; boundary=\"b\"\r\n\r\n\
+            --b\r\nContent-Type: text/plain\r\n\r\nconfidential body\r\n\
+            --b\r\nContent-Type: text/plain\r\n\
+            Content-Disposition: attachment; filename=\"notes.txt\"\r\n\r\n\
+            confidential attachment\r\n--b--\r\n";
+        let envelope = extract_envelope_from_eml(eml, 1, 2).unwrap();
+        assert!(envelope.text.contains("confidential"));
+
+        let index = Index::create_in_ram(SchemaTools::envelope_schema());
+        register_tokenizers(&index);
+        let mut writer: IndexWriter = index.writer(15_000_000).unwrap();
+        let document = ATTACHMENT_TEXT_PIPELINE
+            .to_document(&envelope, 2, eml, true)
+            .await
+            .unwrap();
+        writer.add_document(document).unwrap();
+        writer.commit().unwrap();
+
+        let searcher = index.reader().unwrap().searcher();
+        let stored: TantivyDocument = searcher.doc(DocAddress::new(0, 0)).unwrap();
+        let fields = SchemaTools::envelope_fields();
+        assert_eq!(stored.get_all(fields.f_attachment_content).count(), 0);
+        let restored = Envelope::from_tantivy_doc(&stored).await.unwrap();
+        assert_eq!(restored.subject, "Quarterly plan");
+        assert!(restored.text.is_empty());
+        let json = stored.to_json(searcher.schema());
+        assert!(!json.contains("confidential"));
+    }
+
+    #[test]
+    fn test_text_capped_by_characters() {
+        let text = "é".repeat(MAX_TEXT_CHARS + 10);
+        let normalized = normalize_whitespace(&text);
+        assert_eq!(normalized.chars().count(), MAX_TEXT_CHARS);
+        assert!(normalized.len() > MAX_TEXT_CHARS);
+    }
+    #[tokio::test]
+    async fn test_worker_output_parsed() {
+        let pipeline = AttachmentTextPipeline::new().with_worker(WorkerCommand::new(
+            "sh",
+            &[
+                "-c",
+                r#"cat > /dev/null; echo '[{"name":"notes.txt","text":"hello"}]'"#,
+            ],
+        ));
+        let texts = pipeline
+            .run_worker(b"Subject: x\r\n\r\nbody")
+            .await
+            .unwrap();
+        assert_eq!(
+            texts,
+            vec![AttachmentText {
+                name: "notes.txt".into(),
+                text: "hello".into()
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_worker_failure_reports_stderr() {
+        let pipeline = AttachmentTextPipeline::new().with_worker(WorkerCommand::new(
+            "sh",
+            &["-c", "echo 'extractor crashed' >&2; exit 3"],
+        ));
+        let error = pipeline
+            .run_worker(b"Subject: x\r\n\r\nbody")
+            .await
+            .unwrap_err();
+        assert!(format!("{:?}", error).contains("extractor crashed"));
+    }
+}
@@ -17,8 +17,10 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use crate::modules::common::AddrVec;
+use crate::modules::envelope::headers::indexed_headers;
 use crate::modules::error::code::ErrorCode;
 use crate::modules::error::BichonResult;
+use crate::modules::indexer::tokenizer::detect_language;
 use crate::modules::utils::create_hash;
 use crate::{calculate_hash, raise_error, utc_now};
 use crate::{id, modules::indexer::envelope::Envelope};
@@ -91,6 +93,9 @@ pub fn extract_envelope(fetch: &Fetch, account_id: u64, mailbox_id: u64) -> Bich
         .and_then(|add| add.address)
         .unwrap_or_else(|| "unknown".to_string());
 
+    let (from_name, recipient_names) = extract_display_names(&message);
+    let language = detect_language(&format!("{}\n{}", subject, text)).map(String::from);
+
     let attachments: Vec<String> = message
         .attachments()
         .filter_map(|att| att.attachment_name())
@@ -105,15 +110,22 @@ pub fn extract_envelope(fetch: &Fetch, account_id: u64, mailbox_id: u64) -> Bich
         subject,
         text,
         from,
+        from_name,
         to: to.unwrap_or_default(),
         cc: cc.unwrap_or_default(),
         bcc: bcc.unwrap_or_default(),
+        recipient_names,
         date,
         internal_date,
         size,
         thread_id,
         attachments,
         tags: None,
+        language,
+        headers: Some(indexed_headers(body)),
+        highlights: None,
+        matched_attachments: None,
+        legal_holds: None,
     };
     Ok(envelope)
 }
@@ -179,6 +191,9 @@ pub fn extract_envelope_from_eml(
         .and_then(|add| add.address)
         .unwrap_or_else(|| "unknown".to_string());
 
+    let (from_name, recipient_names) = extract_display_names(&message);
+    let language = detect_language(&format!("{}\n{}", subject, text)).map(String::from);
+
     let attachments: Vec<String> = message
         .attachments()
         .filter_map(|att| att.attachment_name())
@@ -193,19 +208,42 @@ pub fn extract_envelope_from_eml(
         subject,
         text,
         from,
+        from_name,
         to: to.unwrap_or_default(),
         cc: cc.unwrap_or_default(),
         bcc: bcc.unwrap_or_default(),
+        recipient_names,
         date,
         internal_date: date,
         size,
         thread_id,
         attachments,
         tags: None,
+        language,
+        headers: Some(indexed_headers(body)),
+        highlights: None,
+        matched_attachments: None,
+        legal_holds: None,
     };
     Ok(envelope)
 }
 
+/// Returns the sender display name and the display names of all recipients.
+fn extract_display_names(message: &Message) -> (Option<String>, Vec<String>) {
+    let non_empty = |name: Option<String>| name.filter(|n| !n.trim().is_empty());
+    let from_name = message
+        .from()
+        .and_then(|addr| AddrVec::from(addr).0.into_iter().next())
+        .and_then(|add| non_empty(add.name));
+    let recipient_names = [message.to(), message.cc(), message.bcc()]
+        .into_iter()
+        .flatten()
+        .flat_map(|addr| AddrVec::from(addr).0)
+        .filter_map(|add| non_empty(add.name))
+        .collect();
+    (from_name, recipient_names)
+}
+
 pub fn compute_thread_id(
     in_reply_to: Option<String>,
     references: Option<Vec<String>>,
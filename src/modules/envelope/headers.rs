@@ -0,0 +1,96 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+use std::collections::HashMap;
+
+use crate::modules::settings::cli::SETTINGS;
+
+/// Returns the header block of a raw message, up to and excluding the empty line that
+/// separates it from the body. A message without a body is all headers.
+pub fn raw_header_block(eml: &[u8]) -> &[u8] {
+    let mut line_start = 0;
+    while line_start < eml.len() {
+        let line_end = eml[line_start..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(eml.len(), |i| line_start + i);
+        let line = &eml[line_start..line_end];
+        if line.is_empty() || line == b"\r" {
+            return &eml[..line_start];
+        }
+        line_start = line_end + 1;
+    }
+    eml
+}
+
+/// Parses a header block into `(name, value)` pairs, in order. Folded lines are joined
+/// and values are trimmed; encoded words are left as they are.
+pub fn parse_headers(block: &[u8]) -> Vec<(String, String)> {
+    let block = String::from_utf8_lossy(block);
+    let mut headers: Vec<(String, String)> = Vec::new();
+    for line in block.lines() {
+        if line.starts_with([' ', '\t']) {
+            if let Some((_, value)) = headers.last_mut() {
+                value.push(' ');
+                value.push_str(line.trim());
+            }
+            continue;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.push((name.trim().to_string(), value.trim().to_string()));
+        }
+    }
+    headers
+}
+
+/// Values of the headers listed in `bichon_indexed_headers`, keyed by lowercase header
+/// name, in the order they appear in the message.
+pub fn indexed_headers(eml: &[u8]) -> HashMap<String, Vec<String>> {
+    let mut indexed: HashMap<String, Vec<String>> = HashMap::new();
+    for (name, value) in parse_headers(raw_header_block(eml)) {
+        let name = name.to_lowercase();
+        if value.is_empty() || SETTINGS.bichon_indexed_headers.get(&name).is_none() {
+            continue;
+        }
+        indexed.entry(name).or_default().push(value);
+    }
+    indexed
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse_headers, raw_header_block};
+
+    #[test]
+    fn test_parse_folded_headers() {
+        let eml = b"Received: from mail.example.com\r\n\t([192.0.2.1]) by mx.example.org\r\nList-Id: Dev <dev.example.com>\r\n\r\nBody: not a header\r\n";
+        let block = raw_header_block(eml);
+        assert!(block.ends_with(b"<dev.example.com>\r\n"));
+        assert_eq!(
+            parse_headers(block),
+            vec![
+                (
+                    "Received".to_string(),
+                    "from mail.example.com ([192.0.2.1]) by mx.example.org".to_string()
+                ),
+                ("List-Id".to_string(), "Dev <dev.example.com>".to_string()),
+            ]
+        );
+    }
+}
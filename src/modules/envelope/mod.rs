@@ -17,4 +17,6 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 
+pub mod attachment;
 pub mod extractor;
+pub mod headers;
@@ -36,10 +36,13 @@ pub enum ErrorCode {
     AccountDisabled = 20010,
     OAuth2ItemDisabled = 20050,
     MissingRefreshToken = 20060,
+    PgpKeyRequired = 20070,
 
     // Resource errors (30000–30999)
     ResourceNotFound = 30000,
     TooManyRequest = 30020,
+    LegalHold = 30030,
+    ArchiveImmutable = 30040,
 
     // Network connection errors (40000–40999)
     NetworkError = 40000,
@@ -64,11 +67,14 @@ impl ErrorCode {
             | ErrorCode::MissingConfiguration
             | ErrorCode::Incompatible => StatusCode::BAD_REQUEST,
             ErrorCode::PermissionDenied => StatusCode::UNAUTHORIZED,
-            ErrorCode::AccountDisabled | ErrorCode::OAuth2ItemDisabled => StatusCode::FORBIDDEN,
+            ErrorCode::AccountDisabled
+            | ErrorCode::OAuth2ItemDisabled
+            | ErrorCode::PgpKeyRequired => StatusCode::FORBIDDEN,
             ErrorCode::ResourceNotFound => StatusCode::NOT_FOUND,
             ErrorCode::RequestTimeout => StatusCode::REQUEST_TIMEOUT,
             ErrorCode::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
             ErrorCode::TooManyRequest => StatusCode::TOO_MANY_REQUESTS,
+            ErrorCode::LegalHold | ErrorCode::ArchiveImmutable => StatusCode::CONFLICT,
             ErrorCode::InternalError
             | ErrorCode::AutoconfigFetchFailed
             | ErrorCode::ImapCommandFailed
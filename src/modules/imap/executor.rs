@@ -16,9 +16,12 @@
 // You should have received a copy of the GNU Affero General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use crate::modules::account::migration::AccountModel;
+use crate::modules::account::pgp::seal_eml;
 use crate::modules::account::state::AccountRunningState;
 use crate::modules::cache::imap::mailbox::MailBox;
 use crate::modules::cache::imap::sync::flow::{generate_uid_sequence_hashset, BATCH_SIZE};
+use crate::modules::envelope::attachment::ATTACHMENT_TEXT_PIPELINE;
 use crate::modules::envelope::extractor::extract_envelope;
 use crate::modules::error::code::ErrorCode;
 use crate::modules::indexer::manager::{EML_INDEX_MANAGER, ENVELOPE_INDEX_MANAGER};
@@ -186,19 +189,36 @@ impl ImapExecutor {
 
         let mut count = 0;
         let fields = SchemaTools::eml_fields();
+        let pgp_key = AccountModel::get(account_id).await?.pgp_key;
         while let Some(fetch) = stream
             .try_next()
             .await
             .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::ImapCommandFailed))?
         {
             let envelope = extract_envelope(&fetch, account_id, mailbox_id)?;
-            ENVELOPE_INDEX_MANAGER
-                .add_document(envelope.id, envelope.to_document(mailbox_id)?)
-                .await;
             let body = fetch.body().ok_or_else(|| {
                 raise_error!("missing a body".into(), ErrorCode::ImapUnexpectedResult)
             })?;
-            EML_INDEX_MANAGER.add_document( envelope.id, doc!(fields.f_id => envelope.id, fields.f_account_id => account_id, fields.f_mailbox_id => mailbox_id, fields.f_eml => body)).await;
+            // sealed first, so that no envelope is indexed for a message that is not stored
+            let eml = match seal_eml(pgp_key.as_deref(), body) {
+                Ok(eml) => eml,
+                Err(e) => {
+                    tracing::error!(
+                        "Failed to encrypt message {} of mailbox {}: {:#?}",
+                        envelope.id,
+                        mailbox_id,
+                        e
+                    );
+                    continue;
+                }
+            };
+            let document = ATTACHMENT_TEXT_PIPELINE
+                .to_document(&envelope, mailbox_id, body, pgp_key.is_some())
+                .await?;
+            ENVELOPE_INDEX_MANAGER
+                .add_document(envelope.id, document)
+                .await;
+            EML_INDEX_MANAGER.add_document( envelope.id, doc!(fields.f_id => envelope.id, fields.f_account_id => account_id, fields.f_mailbox_id => mailbox_id, fields.f_eml => eml)).await;
             count += 1;
         }
         Ok(count)
@@ -222,19 +242,36 @@ impl ImapExecutor {
             .await
             .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::ImapCommandFailed))?;
         let fields = SchemaTools::eml_fields();
+        let pgp_key = AccountModel::get(account_id).await?.pgp_key;
         while let Some(fetch) = stream
             .try_next()
             .await
             .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::ImapCommandFailed))?
         {
             let envelope = extract_envelope(&fetch, account_id, mailbox_id)?;
-            ENVELOPE_INDEX_MANAGER
-                .add_document(envelope.id, envelope.to_document(mailbox_id)?)
-                .await;
             let body = fetch.body().ok_or_else(|| {
                 raise_error!("missing a body".into(), ErrorCode::ImapUnexpectedResult)
             })?;
-            EML_INDEX_MANAGER.add_document( envelope.id, doc!(fields.f_id => envelope.id, fields.f_account_id => account_id, fields.f_mailbox_id => mailbox_id, fields.f_eml => body)).await;
+            // sealed first, so that no envelope is indexed for a message that is not stored
+            let eml = match seal_eml(pgp_key.as_deref(), body) {
+                Ok(eml) => eml,
+                Err(e) => {
+                    tracing::error!(
+                        "Failed to encrypt message {} of mailbox {}: {:#?}",
+                        envelope.id,
+                        mailbox_id,
+                        e
+                    );
+                    continue;
+                }
+            };
+            let document = ATTACHMENT_TEXT_PIPELINE
+                .to_document(&envelope, mailbox_id, body, pgp_key.is_some())
+                .await?;
+            ENVELOPE_INDEX_MANAGER
+                .add_document(envelope.id, document)
+                .await;
+            EML_INDEX_MANAGER.add_document( envelope.id, doc!(fields.f_id => envelope.id, fields.f_account_id => account_id, fields.f_mailbox_id => mailbox_id, fields.f_eml => eml)).await;
         }
         Ok(())
     }
@@ -5,9 +5,12 @@ use tantivy::doc;
 use crate::{
     base64_decode_url_safe,
     modules::{
-        account::migration::{AccountModel, AccountType},
+        account::{
+            migration::{AccountModel, AccountType},
+            pgp::seal_eml,
+        },
         cache::imap::mailbox::{Attribute, AttributeEnum, MailBox},
-        envelope::extractor::extract_envelope_from_eml,
+        envelope::{attachment::ATTACHMENT_TEXT_PIPELINE, extractor::extract_envelope_from_eml},
         error::{code::ErrorCode, BichonResult},
         indexer::{
             manager::{EML_INDEX_MANAGER, ENVELOPE_INDEX_MANAGER},
@@ -131,8 +134,36 @@ impl ImportEmls {
                 }
             };
 
+            let sealed = account.pgp_key.is_some();
+            let document = match ATTACHMENT_TEXT_PIPELINE
+                .to_document(&envelope, mailbox_id, &decoded, sealed)
+                .await
+            {
+                Ok(document) => document,
+                Err(e) => {
+                    let error_msg = format!("Failed to index EML at index {}: {:?}", index, e);
+                    tracing::error!("{}", error_msg);
+                    failed_details.push(FailedEmlDetail {
+                        index,
+                        error_message: error_msg,
+                    });
+                    continue;
+                }
+            };
+            let eml = match seal_eml(account.pgp_key.as_deref(), &decoded) {
+                Ok(eml) => eml,
+                Err(e) => {
+                    let error_msg = format!("Failed to encrypt EML at index {}: {:?}", index, e);
+                    tracing::error!("{}", error_msg);
+                    failed_details.push(FailedEmlDetail {
+                        index,
+                        error_message: error_msg,
+                    });
+                    continue;
+                }
+            };
             ENVELOPE_INDEX_MANAGER
-                .add_document(envelope.id, envelope.to_document(mailbox_id).unwrap())
+                .add_document(envelope.id, document)
                 .await;
 
             EML_INDEX_MANAGER
@@ -142,7 +173,7 @@ impl ImportEmls {
                         fields.f_id => envelope.id,
                         fields.f_account_id => account_id,
                         fields.f_mailbox_id => mailbox_id,
-                        fields.f_eml => decoded
+                        fields.f_eml => eml
                     ),
                 )
                 .await;
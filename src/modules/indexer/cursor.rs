@@ -0,0 +1,344 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+use std::{cmp::Ordering, ops::Bound, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+use tantivy::{
+    collector::{CustomScorer, CustomSegmentScorer, ScoreSegmentTweaker, ScoreTweaker},
+    columnar::{Column, StrColumn},
+    DocId, Score, SegmentReader,
+};
+
+use crate::{
+    base64_decode_url_safe, base64_encode_url_safe,
+    modules::{
+        error::{code::ErrorCode, BichonResult},
+        indexer::fields::{F_DATE, F_FROM, F_ID, F_INTERNAL_DATE, F_SIZE},
+        message::search::SortField,
+    },
+    raise_error,
+};
+
+/// Value of the sort field for a single hit.
+#[derive(Clone, Debug, PartialEq, PartialOrd, Serialize, Deserialize)]
+pub enum SortValue {
+    I64(i64),
+    U64(u64),
+    Str(String),
+    Score(Score),
+}
+
+/// Position of the last hit returned to a client, used to resume a search right after it.
+///
+/// The envelope id breaks ties between hits sharing the same sort value, so every hit has a
+/// unique position regardless of how the index is segmented.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SearchCursor {
+    pub sort_by: SortField,
+    pub desc: bool,
+    pub value: SortValue,
+    pub id: u64,
+}
+
+impl SearchCursor {
+    pub fn encode(&self) -> BichonResult<String> {
+        let json = serde_json::to_vec(self)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        Ok(base64_encode_url_safe!(json))
+    }
+
+    pub fn decode(token: &str) -> BichonResult<Self> {
+        let bytes = base64_decode_url_safe!(token.as_bytes()).map_err(|_| {
+            raise_error!("Invalid search cursor.".into(), ErrorCode::InvalidParameter)
+        })?;
+        serde_json::from_slice(&bytes)
+            .map_err(|_| raise_error!("Invalid search cursor.".into(), ErrorCode::InvalidParameter))
+    }
+
+    pub fn from_key(sort_by: SortField, key: RankKey) -> Self {
+        Self {
+            sort_by,
+            desc: key.desc,
+            value: key.value.resolve(),
+            id: key.id,
+        }
+    }
+}
+
+/// Value of the sort field while hits are ranked.
+#[derive(Clone)]
+enum RankValue {
+    Value(SortValue),
+    /// Ordinal of the sender in the term dictionary of a segment. Hits of the same segment
+    /// are compared by ordinal, the sender is only read to compare hits of two segments.
+    Term(Arc<StrColumn>, Option<u64>),
+}
+
+impl RankValue {
+    fn resolve(&self) -> SortValue {
+        match self {
+            RankValue::Value(value) => value.clone(),
+            RankValue::Term(column, ord) => {
+                let mut value = String::new();
+                if let Some(ord) = ord {
+                    let _ = column.ord_to_str(*ord, &mut value);
+                }
+                SortValue::Str(value)
+            }
+        }
+    }
+}
+
+impl PartialEq for RankValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for RankValue {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        match (self, other) {
+            (RankValue::Term(column, ord), RankValue::Term(other_column, other_ord))
+                if Arc::ptr_eq(column, other_column) =>
+            {
+                Some(ord.cmp(other_ord))
+            }
+            (RankValue::Value(value), RankValue::Value(other_value)) => {
+                value.partial_cmp(other_value)
+            }
+            _ => self.resolve().partial_cmp(&other.resolve()),
+        }
+    }
+}
+
+/// Ranking key collected by `TopDocs`. Greater keys come first in the result list, so the
+/// comparison is reversed for ascending sorts.
+#[derive(Clone)]
+pub struct RankKey {
+    value: RankValue,
+    id: u64,
+    desc: bool,
+}
+
+impl PartialEq for RankKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+impl PartialOrd for RankKey {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        let ordering = self
+            .value
+            .partial_cmp(&other.value)?
+            .then(self.id.cmp(&other.id));
+        Some(if self.desc {
+            ordering
+        } else {
+            ordering.reverse()
+        })
+    }
+}
+
+/// Computes a [`RankKey`] per hit from fast fields (or the BM25 score), skipping every hit
+/// positioned at or before `after`. Skipped hits yield `None`, which ranks below any key.
+pub struct SortKeyScorer {
+    sort_by: SortField,
+    desc: bool,
+    after: Option<RankKey>,
+}
+
+impl SortKeyScorer {
+    pub fn new(sort_by: SortField, desc: bool, after: Option<&SearchCursor>) -> Self {
+        Self {
+            sort_by,
+            desc,
+            after: after.map(|cursor| RankKey {
+                value: RankValue::Value(cursor.value.clone()),
+                id: cursor.id,
+                desc,
+            }),
+        }
+    }
+
+    fn for_segment(&self, segment_reader: &SegmentReader) -> tantivy::Result<SegmentSortKeyScorer> {
+        let fast_fields = segment_reader.fast_fields();
+        let values = match self.sort_by {
+            SortField::InternalDate => {
+                SegmentValues::I64(fast_fields.column_opt::<i64>(F_INTERNAL_DATE)?)
+            }
+            SortField::Date => SegmentValues::I64(fast_fields.column_opt::<i64>(F_DATE)?),
+            SortField::Size => SegmentValues::U64(fast_fields.column_opt::<u64>(F_SIZE)?),
+            SortField::From => match fast_fields.str(F_FROM)? {
+                Some(column) => {
+                    let after = match &self.after {
+                        Some(RankKey {
+                            value: RankValue::Value(SortValue::Str(value)),
+                            ..
+                        }) => Some(AfterTerm::locate(&column, value)?),
+                        _ => None,
+                    };
+                    SegmentValues::Str(Arc::new(column), after)
+                }
+                None => SegmentValues::Missing,
+            },
+            SortField::Score => SegmentValues::Score,
+        };
+        Ok(SegmentSortKeyScorer {
+            values,
+            ids: fast_fields.column_opt::<u64>(F_ID)?,
+            desc: self.desc,
+            after: self.after.clone(),
+        })
+    }
+}
+
+enum SegmentValues {
+    I64(Option<Column<i64>>),
+    U64(Option<Column<u64>>),
+    /// Sender column, along with the position of the cursor sender in its dictionary.
+    Str(Arc<StrColumn>, Option<AfterTerm>),
+    /// No hit of the segment has a sender.
+    Missing,
+    Score,
+}
+
+/// Position of the sender of a cursor among the term ordinals of a segment.
+struct AfterTerm {
+    /// Ordinal of the sender, or of the first term after it when the segment lacks it.
+    ord: u64,
+    exact: bool,
+    empty: bool,
+}
+
+impl AfterTerm {
+    fn locate(column: &StrColumn, value: &str) -> tantivy::Result<Self> {
+        let dictionary = column.dictionary();
+        if let Some(ord) = dictionary.term_ord(value)? {
+            return Ok(Self {
+                ord,
+                exact: true,
+                empty: value.is_empty(),
+            });
+        }
+        let ord = match dictionary.term_bounds_to_ord(Bound::Included(value), Bound::Unbounded)? {
+            (Bound::Included(ord), _) | (Bound::Excluded(ord), _) => ord,
+            (Bound::Unbounded, _) => 0,
+        };
+        Ok(Self {
+            ord,
+            exact: false,
+            empty: value.is_empty(),
+        })
+    }
+
+    /// Compares a sender of the segment, by ordinal, with the sender of the cursor.
+    fn cmp(&self, ord: Option<u64>) -> Ordering {
+        match ord {
+            None if self.empty => Ordering::Equal,
+            None => Ordering::Less,
+            Some(ord) if self.exact => ord.cmp(&self.ord),
+            Some(ord) if ord < self.ord => Ordering::Less,
+            Some(_) => Ordering::Greater,
+        }
+    }
+}
+
+pub struct SegmentSortKeyScorer {
+    values: SegmentValues,
+    ids: Option<Column<u64>>,
+    desc: bool,
+    after: Option<RankKey>,
+}
+
+impl SegmentSortKeyScorer {
+    fn key(&mut self, doc: DocId, score: Score) -> Option<RankKey> {
+        let id = self.ids.as_ref().and_then(|c| c.first(doc)).unwrap_or(0);
+        let value = match &self.values {
+            SegmentValues::I64(column) => SortValue::I64(
+                column
+                    .as_ref()
+                    .and_then(|c| c.first(doc))
+                    .unwrap_or(i64::MIN),
+            ),
+            SegmentValues::U64(column) => {
+                SortValue::U64(column.as_ref().and_then(|c| c.first(doc)).unwrap_or(0))
+            }
+            SegmentValues::Str(column, after_term) => {
+                let ord = column.term_ords(doc).next();
+                if let (Some(after), Some(after_term)) = (&self.after, after_term) {
+                    let ordering = after_term.cmp(ord).then(id.cmp(&after.id));
+                    let ordering = if self.desc {
+                        ordering
+                    } else {
+                        ordering.reverse()
+                    };
+                    if ordering != Ordering::Less {
+                        return None;
+                    }
+                }
+                return Some(RankKey {
+                    value: RankValue::Term(column.clone(), ord),
+                    id,
+                    desc: self.desc,
+                });
+            }
+            SegmentValues::Missing => SortValue::Str(String::new()),
+            SegmentValues::Score => SortValue::Score(score),
+        };
+        let key = RankKey {
+            value: RankValue::Value(value),
+            id,
+            desc: self.desc,
+        };
+        match &self.after {
+            Some(after) if key >= *after => None,
+            _ => Some(key),
+        }
+    }
+}
+
+impl CustomScorer<Option<RankKey>> for SortKeyScorer {
+    type Child = SegmentSortKeyScorer;
+
+    fn segment_scorer(&self, segment_reader: &SegmentReader) -> tantivy::Result<Self::Child> {
+        self.for_segment(segment_reader)
+    }
+}
+
+impl CustomSegmentScorer<Option<RankKey>> for SegmentSortKeyScorer {
+    fn score(&mut self, doc: DocId) -> Option<RankKey> {
+        self.key(doc, 0.0)
+    }
+}
+
+impl ScoreTweaker<Option<RankKey>> for SortKeyScorer {
+    type Child = SegmentSortKeyScorer;
+
+    fn segment_tweaker(&self, segment_reader: &SegmentReader) -> tantivy::Result<Self::Child> {
+        self.for_segment(segment_reader)
+    }
+}
+
+impl ScoreSegmentTweaker<Option<RankKey>> for SegmentSortKeyScorer {
+    fn score(&mut self, doc: DocId, score: Score) -> Option<RankKey> {
+        self.key(doc, score)
+    }
+}
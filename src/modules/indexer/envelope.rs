@@ -17,13 +17,19 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 
+use std::collections::{BTreeMap, HashMap};
+
 use crate::modules::error::code::ErrorCode;
+use crate::modules::message::search::SearchHighlights;
+use crate::modules::settings::cli::SETTINGS;
 use crate::modules::utils::create_hash;
 use crate::modules::{error::BichonResult, indexer::schema::SchemaTools};
+use crate::modules::indexer::tokenizer::stemmed_text;
 use crate::raise_error;
+use crate::utc_now;
 use poem_openapi::Object;
 use serde::{Deserialize, Serialize};
-use tantivy::schema::Facet;
+use tantivy::schema::{Facet, Field, OwnedValue};
 use tantivy::{doc, schema::Value, TantivyDocument};
 
 #[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize, Object)]
@@ -36,15 +42,29 @@ pub struct Envelope {
     pub subject: String,
     pub text: String,
     pub from: String,
+    /// Display name of the sender, if any.
+    pub from_name: Option<String>,
     pub to: Vec<String>,
     pub cc: Vec<String>,
     pub bcc: Vec<String>,
+    /// Display names of the `to`, `cc` and `bcc` recipients that have one.
+    pub recipient_names: Vec<String>,
     pub date: i64,
     pub internal_date: i64,
     pub size: u32,
     pub thread_id: u64,
     pub attachments: Vec<String>,
     pub tags: Option<Vec<String>>,
+    /// Language detected from the subject and body (ISO 639-3 code such as `eng` or `jpn`).
+    pub language: Option<String>,
+    /// Values of the headers listed in `bichon_indexed_headers`, keyed by lowercase name.
+    pub headers: Option<HashMap<String, Vec<String>>>,
+    /// Only populated by full-text searches that request highlighting.
+    pub highlights: Option<SearchHighlights>,
+    /// Names of the attachments whose content matched the full-text query, if any.
+    pub matched_attachments: Option<Vec<String>>,
+    /// Ids of the legal holds pinning the message, if any.
+    pub legal_holds: Option<Vec<u64>>,
 }
 
 fn extract_u64_field(
@@ -123,15 +143,37 @@ impl Envelope {
         doc.add_u64(fields.f_uid, self.uid as u64);
         doc.add_text(fields.f_subject, &self.subject);
         doc.add_text(fields.f_text, &self.text);
+        if SETTINGS.bichon_stemming {
+            let language = self.language.as_deref();
+            doc.add_pre_tokenized_text(fields.f_stemmed, stemmed_text(&self.subject, language));
+            doc.add_pre_tokenized_text(fields.f_stemmed, stemmed_text(&self.text, language));
+        }
+        if let Some(language) = &self.language {
+            doc.add_text(fields.f_language, language);
+        }
+        doc.add_i64(fields.f_archived_at, utc_now!());
         doc.add_text(fields.f_from, &self.from);
+        doc.add_text(fields.f_from_addr, &self.from);
+        if let Some((_, domain)) = self.from.rsplit_once('@') {
+            doc.add_text(fields.f_from_domain, domain.to_lowercase());
+        }
+        if let Some(name) = &self.from_name {
+            doc.add_text(fields.f_from_name, name);
+        }
         for to in &self.to {
             doc.add_text(fields.f_to, to);
+            doc.add_text(fields.f_to_addr, to);
         }
         for cc in &self.cc {
             doc.add_text(fields.f_cc, cc);
+            doc.add_text(fields.f_cc_addr, cc);
         }
         for bcc in &self.bcc {
             doc.add_text(fields.f_bcc, bcc);
+            doc.add_text(fields.f_bcc_addr, bcc);
+        }
+        for name in &self.recipient_names {
+            doc.add_text(fields.f_recipient_names, name);
         }
         doc.add_i64(fields.f_date, self.date);
         doc.add_i64(fields.f_internal_date, self.internal_date);
@@ -141,9 +183,108 @@ impl Envelope {
             doc.add_text(fields.f_attachments, att);
         }
         doc.add_bool(fields.f_has_attachment, self.attachments.len() > 0);
+        self.add_headers(&mut doc);
         Ok(doc)
     }
 
+    fn add_headers(&self, doc: &mut TantivyDocument) {
+        let Some(headers) = &self.headers else {
+            return;
+        };
+        let fields = SchemaTools::envelope_fields();
+        let mut exact = BTreeMap::new();
+        let mut text = BTreeMap::new();
+        for (name, values) in headers {
+            let Some(indexed) = SETTINGS.bichon_indexed_headers.get(name) else {
+                continue;
+            };
+            let values =
+                OwnedValue::Array(values.iter().map(|v| OwnedValue::Str(v.clone())).collect());
+            if indexed.exact {
+                exact.insert(name.clone(), values.clone());
+            }
+            if indexed.text {
+                text.insert(name.clone(), values);
+            }
+        }
+        if !exact.is_empty() {
+            doc.add_object(fields.f_headers, exact);
+        }
+        if !text.is_empty() {
+            doc.add_object(fields.f_headers_text, text);
+        }
+    }
+
+    /// Reads the stored header objects. A header indexed both ways is stored in both.
+    fn stored_headers(doc: &TantivyDocument) -> Option<HashMap<String, Vec<String>>> {
+        let fields = SchemaTools::envelope_fields();
+        let mut headers: HashMap<String, Vec<String>> = HashMap::new();
+        let objects = |field: Field| {
+            doc.get_all(field)
+                .filter_map(|value| value.as_object())
+                .flatten()
+        };
+        for (name, values) in objects(fields.f_headers).chain(objects(fields.f_headers_text)) {
+            if headers.contains_key(name) {
+                continue;
+            }
+            let values: Vec<String> = values
+                .as_array()
+                .into_iter()
+                .flatten()
+                .filter_map(|v| v.as_str().map(String::from))
+                .collect();
+            headers.insert(name.to_string(), values);
+        }
+        (!headers.is_empty()).then_some(headers)
+    }
+
+    /// Rebuilds a complete document from the stored values of an indexed one. The values
+    /// of the fields that are indexed but not stored are derived again from stored ones.
+    pub fn restore_document(stored: &TantivyDocument) -> TantivyDocument {
+        let fields = SchemaTools::envelope_fields();
+        let derived = [
+            fields.f_from_addr,
+            fields.f_to_addr,
+            fields.f_cc_addr,
+            fields.f_bcc_addr,
+            fields.f_from_domain,
+            fields.f_stemmed,
+        ];
+        let mut doc = TantivyDocument::new();
+        for (field, value) in stored.field_values() {
+            if !derived.contains(&field) {
+                doc.add_field_value(field, value);
+            }
+        }
+        for (source, target) in [
+            (fields.f_from, fields.f_from_addr),
+            (fields.f_to, fields.f_to_addr),
+            (fields.f_cc, fields.f_cc_addr),
+            (fields.f_bcc, fields.f_bcc_addr),
+        ] {
+            for address in extract_vec_string_field(stored, source).unwrap_or_default() {
+                doc.add_text(target, &address);
+            }
+        }
+        if let Some((_, domain)) = stored
+            .get_first(fields.f_from)
+            .and_then(|v| v.as_str())
+            .and_then(|from| from.rsplit_once('@'))
+        {
+            doc.add_text(fields.f_from_domain, domain.to_lowercase());
+        }
+        if SETTINGS.bichon_stemming {
+            let language = stored.get_first(fields.f_language).and_then(|v| v.as_str());
+            for source in [fields.f_subject, fields.f_text, fields.f_attachment_content] {
+                for text in extract_vec_string_field(stored, source).unwrap_or_default() {
+                    doc.add_pre_tokenized_text(fields.f_stemmed, stemmed_text(&text, language));
+                }
+            }
+        }
+        doc
+    }
+
     pub async fn from_tantivy_doc(doc: &TantivyDocument) -> BichonResult<Self> {
         let fields = SchemaTools::envelope_fields();
         let account_id = extract_u64_field(doc, fields.f_account_id)?;
@@ -179,15 +320,28 @@ impl Envelope {
             subject: extract_string_field(doc, fields.f_subject)?,
             text: preview,
             from: extract_string_field(doc, fields.f_from)?,
+            from_name: doc
+                .get_first(fields.f_from_name)
+                .and_then(|v| v.as_str())
+                .map(String::from),
             to: extract_vec_string_field(doc, fields.f_to)?,
             cc: extract_vec_string_field(doc, fields.f_cc)?,
             bcc: extract_vec_string_field(doc, fields.f_bcc)?,
+            recipient_names: extract_vec_string_field(doc, fields.f_recipient_names)?,
             date: extract_i64_field(doc, fields.f_date)?,
             internal_date: extract_i64_field(doc, fields.f_internal_date)?,
             size: extract_u64_field(doc, fields.f_size)? as u32,
             thread_id: extract_u64_field(doc, fields.f_thread_id)?,
             attachments: extract_vec_string_field(doc, fields.f_attachments)?,
             tags: Some(tags),
+            language: doc
+                .get_first(fields.f_language)
+                .and_then(|v| v.as_str())
+                .map(String::from),
+            headers: Self::stored_headers(doc),
+            highlights: None,
+            matched_attachments: None,
+            legal_holds: None,
         };
         Ok(envelope)
     }
@@ -36,6 +36,20 @@ pub const F_THREAD_ID: &str = "thread_id";
 pub const F_ATTACHMENTS: &str = "attachments";
 pub const F_HAS_ATTACHMENT: &str = "has_attachment";
 pub const F_TAGS: &str = "tags";
+pub const F_FROM_ADDR: &str = "from_addr";
+pub const F_TO_ADDR: &str = "to_addr";
+pub const F_CC_ADDR: &str = "cc_addr";
+pub const F_BCC_ADDR: &str = "bcc_addr";
+pub const F_FROM_DOMAIN: &str = "from_domain";
+pub const F_FROM_NAME: &str = "from_name";
+pub const F_RECIPIENT_NAMES: &str = "recipient_names";
+pub const F_ATTACHMENT_CONTENT: &str = "attachment_content";
+pub const F_ATTACHMENT_CONTENT_NAME: &str = "attachment_content_name";
+pub const F_STEMMED: &str = "stemmed";
+pub const F_LANGUAGE: &str = "language";
+pub const F_HEADERS: &str = "headers";
+pub const F_HEADERS_TEXT: &str = "headers_text";
+pub const F_ARCHIVED_AT: &str = "archived_at";
 
 pub const F_ID: &str = "id";
 pub struct EnvelopeFields {
@@ -57,13 +71,40 @@ pub struct EnvelopeFields {
     pub f_attachments: Field,
     pub f_has_attachment: Field,
     pub f_tags: Field,
+    pub f_from_addr: Field,
+    pub f_to_addr: Field,
+    pub f_cc_addr: Field,
+    pub f_bcc_addr: Field,
+    pub f_from_domain: Field,
+    pub f_from_name: Field,
+    pub f_recipient_names: Field,
+    pub f_attachment_content: Field,
+    pub f_attachment_content_name: Field,
+    pub f_stemmed: Field,
+    pub f_language: Field,
+    pub f_headers: Field,
+    pub f_headers_text: Field,
+    pub f_archived_at: Field,
 }
 
 pub const F_EML: &str = "eml";
+pub const F_CONTENT_HASH: &str = "content_hash";
+pub const F_BLOB_HASH: &str = "blob_hash";
+pub const F_KIND: &str = "kind";
+pub const F_ATTACHMENT_HASH: &str = "attachment_hash";
+pub const F_LAYOUT: &str = "layout";
+pub const F_ATTACHMENT_NAME: &str = "attachment_name";
 
 pub struct EmlFields {
     pub f_id: Field,
     pub f_account_id: Field,
     pub f_mailbox_id: Field,
     pub f_eml: Field,
+    pub f_content_hash: Field,
+    pub f_blob_hash: Field,
+    pub f_size: Field,
+    pub f_kind: Field,
+    pub f_attachment_hash: Field,
+    pub f_layout: Field,
+    pub f_attachment_name: Field,
 }
@@ -21,33 +21,53 @@ use std::{
     collections::{HashMap, HashSet},
     ops::Bound,
     path::PathBuf,
-    sync::{Arc, LazyLock},
+    sync::{Arc, LazyLock, RwLock},
     time::Duration,
 };
 
 use crate::modules::message::tags::TagCount;
 use crate::{
+    generate_token,
     modules::{
-        account::migration::AccountModel,
+        account::{
+            migration::AccountModel,
+            pgp::{self, PgpSecretKey},
+        },
+        alert::{dispatch::SEARCH_ALERTS, AlertEvent, AlertMatch, SearchAlert},
+        blob::{blob_key, BlobStore, BLOB_STORE},
         common::signal::SIGNAL_MANAGER,
-        dashboard::{DashboardStats, Group, LargestEmail, TimeBucket},
+        dashboard::{DashboardStats, DeduplicatedAttachment, Group, LargestEmail, TimeBucket},
         error::{code::ErrorCode, BichonResult},
         indexer::{
+            cursor::{SearchCursor, SortKeyScorer},
             envelope::Envelope,
             fields::{
-                F_ACCOUNT_ID, F_FROM, F_HAS_ATTACHMENT, F_INTERNAL_DATE, F_MAILBOX_ID, F_SIZE,
-                F_TAGS, F_THREAD_ID, F_UID,
+                F_ACCOUNT_ID, F_CONTENT_HASH, F_FROM, F_FROM_DOMAIN, F_HAS_ATTACHMENT, F_ID,
+                F_INTERNAL_DATE, F_KIND, F_MAILBOX_ID, F_SIZE, F_TAGS, F_THREAD_ID, F_UID,
             },
             schema::SchemaTools,
+            skeleton::{self, SplitPart},
+            snapshot::{IndexSnapshot, WritePause},
+            tokenizer::{
+                default_terms, domain_term, header_term, query_stem_languages, register_tokenizers,
+            },
+            version::{is_compatible, SchemaVersion, ENVELOPE_SCHEMA_VERSION, SCHEMA_VERSION_FILE},
         },
-        message::search::SearchFilter,
+        message::search::{
+            FacetRequest, HighlightOptions, ScrollResult, SearchFacet, SearchFacets, SearchFilter,
+            SearchHighlights, SearchOptions, SearchResult, SortField, TagsMatch,
+        },
+        message::similar::SimilarEnvelope,
         rest::response::DataPage,
-        settings::dir::DATA_DIR_MANAGER,
+        settings::{cli::SETTINGS, dir::DATA_DIR_MANAGER},
+        worm::{LedgerAction, WORM_LEDGER},
     },
     raise_error, utc_now,
 };
 use chrono::Utc;
+use dashmap::DashMap;
 use mail_parser::{MessageParser, MimeHeaders};
+use ring::digest;
 use serde_json::json;
 use tantivy::{
     aggregation::{
@@ -57,18 +77,21 @@ use tantivy::{
         },
         AggregationCollector, Key,
     },
-    collector::{Count, FacetCollector, TopDocs},
-    query::{AllQuery, BooleanQuery, Occur, Query, QueryParser, RangeQuery, TermQuery},
-    schema::{Facet, IndexRecordOption, Value},
+    collector::{Count, DocSetCollector, FacetCollector, TopDocs},
+    doc,
+    query::{
+        AllQuery, BooleanQuery, ConstScoreQuery, EmptyQuery, MoreLikeThisQuery, Occur, PhraseQuery,
+        Query, QueryParser, RangeQuery, TermQuery, TermSetQuery,
+    },
+    schema::{Facet, Field, IndexRecordOption, OwnedValue, Value},
+    snippet::{Snippet, SnippetGenerator},
     store::{Compressor, ZstdCompressor},
-    DocAddress, Index, IndexBuilder, IndexReader, IndexSettings, IndexWriter, Order,
-    TantivyDocument, Term,
+    DocAddress, DocSet, Index, IndexBuilder, IndexReader, IndexSettings, IndexWriter, Order,
+    TantivyDocument, Term, TERMINATED,
 };
 use tantivy::{indexer::UserOperation, Searcher};
 use tokio::{
-    fs::File,
-    io::AsyncWriteExt,
-    sync::{mpsc, Mutex},
+    sync::{mpsc, oneshot, MappedMutexGuard, Mutex, MutexGuard},
     task,
 };
 
@@ -80,41 +103,331 @@ pub const ENVELOPE_BATCH_SIZE: usize = 1000;
 pub const EML_BATCH_SIZE: usize = 200;
 
 const MAX_BUFFER_DURATION: Duration = Duration::from_secs(30);
+const MAX_SCROLL_SESSIONS: usize = 100;
+/// Extension of the directory holding the index being rebuilt, next to the live one.
+const REBUILD_DIR_EXTENSION: &str = "rebuild";
+/// Extension the replaced index is moved to during a cutover, until it is removed.
+const OLD_DIR_EXTENSION: &str = "old";
 
 pub enum WriteMessage {
     Document((u64, TantivyDocument)),
+    /// Commits the documents queued so far, then replies.
+    Flush(oneshot::Sender<()>),
     Shutdown,
 }
 
 pub struct EnvelopeIndexManager {
-    index_writer: Arc<Mutex<IndexWriter>>,
+    /// Only `None` while the index is being swapped, under the lock.
+    index_writer: Arc<Mutex<Option<IndexWriter>>>,
     sender: mpsc::Sender<WriteMessage>,
+    live: RwLock<Arc<LiveIndex>>,
+    scrolls: DashMap<String, ScrollSession>,
+    /// Index built by a running reindex. Every write to the live index is applied to it
+    /// as well, so it is up to date when it replaces the live index.
+    rebuild: Mutex<Option<RebuildTarget>>,
+}
+
+/// Index serving searches.
+struct LiveIndex {
+    dir: PathBuf,
     reader: IndexReader,
     query_parser: QueryParser,
+    /// Parsers of the `stemmed` field in each language other than `bichon_default_language`,
+    /// empty when stemming is off or the index predates the field.
+    stem_parsers: Vec<(&'static str, QueryParser)>,
+    /// Fields of the index schema. Lower than in the current schema while an index built
+    /// with an older, compatible schema version awaits its rebuild.
+    num_fields: usize,
+    schema_version: SchemaVersion,
+    /// Index in `dir` when it was built with an incompatible schema, in which case an
+    /// empty index is served until its rebuild completes.
+    legacy: Option<LegacyIndex>,
+}
+
+impl LiveIndex {
+    /// Opens the index in `dir`. An index built with an incompatible schema is left as it
+    /// is, and an empty in-memory index with the current schema is served in its place.
+    fn open(dir: PathBuf) -> (Index, Self) {
+        let index = EnvelopeIndexManager::open_or_create_index(&dir);
+        if is_compatible(&index.schema(), &SchemaTools::envelope_schema()) {
+            let live = Self::new(dir, &index, None);
+            return (index, live);
+        }
+        let legacy = LegacyIndex::new(&dir, index);
+        let empty = Index::create_in_ram(SchemaTools::envelope_schema());
+        register_tokenizers(&empty);
+        let live = Self::new(dir, &empty, Some(legacy));
+        (empty, live)
+    }
+
+    fn new(dir: PathBuf, index: &Index, legacy: Option<LegacyIndex>) -> Self {
+        let reader = index
+            .reader()
+            .unwrap_or_else(|e| panic!("Failed to create IndexReader for {:?}: {}", dir, e));
+        let num_fields = index.schema().num_fields();
+        // fields added after the index was created are left out until it is rebuilt
+        let default_fields = SchemaTools::envelope_default_fields()
+            .into_iter()
+            .filter(|field| (field.field_id() as usize) < num_fields)
+            .collect();
+        let mut query_parser = QueryParser::for_index(index, default_fields);
+        query_parser.set_conjunction_by_default();
+        let f_stemmed = SchemaTools::envelope_fields().f_stemmed;
+        let stem_parsers = if SETTINGS.bichon_stemming && (f_stemmed.field_id() as usize) < num_fields
+        {
+            query_stem_languages()
+                .into_iter()
+                .map(|(language, tokenizers)| {
+                    let mut parser = QueryParser::new(index.schema(), vec![f_stemmed], tokenizers);
+                    parser.set_conjunction_by_default();
+                    (language, parser)
+                })
+                .collect()
+        } else {
+            Vec::new()
+        };
+        Self {
+            schema_version: SchemaVersion::read(&dir),
+            dir,
+            reader,
+            query_parser,
+            stem_parsers,
+            num_fields,
+            legacy,
+        }
+    }
+
+    /// Parses a full-text query. The default parser stems it in `bichon_default_language`;
+    /// a query is too short for its language to be detected, so it is also stemmed in the
+    /// filtered language, or else in every other language found in the index.
+    fn text_query(&self, text: &str, language: Option<&str>) -> BichonResult<Box<dyn Query>> {
+        let parse = |parser: &QueryParser| {
+            parser
+                .parse_query(text)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InvalidParameter))
+        };
+        let query = parse(&self.query_parser)?;
+        if self.stem_parsers.is_empty() {
+            return Ok(query);
+        }
+        let languages = match language {
+            Some(language) => vec![language.trim().to_lowercase()],
+            None => self.indexed_languages()?,
+        };
+        let mut queries = vec![(Occur::Should, query)];
+        for (language, parser) in &self.stem_parsers {
+            if languages.iter().any(|l| l == language) {
+                queries.push((Occur::Should, parse(parser)?));
+            }
+        }
+        Ok(match queries.len() {
+            1 => queries.remove(0).1,
+            _ => Box::new(BooleanQuery::new(queries)),
+        })
+    }
+
+    /// Languages detected in the indexed messages.
+    fn indexed_languages(&self) -> BichonResult<Vec<String>> {
+        let f_language = SchemaTools::envelope_fields().f_language;
+        let mut languages: Vec<String> = Vec::new();
+        for segment in self.reader.searcher().segment_readers() {
+            let index = segment
+                .inverted_index(f_language)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            let mut terms = index
+                .terms()
+                .stream()
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            while terms.advance() {
+                let language = String::from_utf8_lossy(terms.key());
+                if !languages.iter().any(|l| *l == language) {
+                    languages.push(language.into_owned());
+                }
+            }
+        }
+        Ok(languages)
+    }
+
+    fn has_field(&self, field: Field) -> bool {
+        (field.field_id() as usize) < self.num_fields
+    }
+
+    /// A query on a field missing from an outdated index matches nothing.
+    fn guard(&self, field: Field, query: Box<dyn Query>) -> Box<dyn Query> {
+        if self.has_field(field) {
+            query
+        } else {
+            Box::new(EmptyQuery)
+        }
+    }
+
+    /// Drops the values of fields missing from an outdated index.
+    fn project(&self, doc: TantivyDocument) -> TantivyDocument {
+        if self.num_fields == SchemaTools::envelope_schema().num_fields() {
+            return doc;
+        }
+        let mut projected = TantivyDocument::new();
+        for (field, value) in doc.field_values() {
+            if self.has_field(field) {
+                projected.add_field_value(field, value);
+            }
+        }
+        projected
+    }
+}
+
+struct RebuildTarget {
+    dir: PathBuf,
+    writer: IndexWriter,
+    reader: IndexReader,
+}
+
+/// Index found with an incompatible schema. It is only read, to carry over the values
+/// the rebuilt index cannot derive from the EML store.
+struct LegacyIndex {
+    index: Index,
+    reader: IndexReader,
+    /// Its envelope id field, unless its type changed.
+    f_id: Option<Field>,
+    /// Carried-over fields of the current schema, with the field of the same name and
+    /// type in this index.
+    fields: Vec<(Field, Field)>,
+}
+
+impl LegacyIndex {
+    fn new(dir: &PathBuf, index: Index) -> Self {
+        let reader = index
+            .reader()
+            .unwrap_or_else(|e| panic!("Failed to create IndexReader for {:?}: {}", dir, e));
+        let schema = index.schema();
+        let current = SchemaTools::envelope_schema();
+        let same_field = |field: Field| {
+            let entry = current.get_field_entry(field);
+            schema.get_field(entry.name()).ok().filter(|old| {
+                let old = schema.get_field_entry(*old);
+                old.field_type().value_type() == entry.field_type().value_type()
+                    && old.is_indexed() == entry.is_indexed()
+            })
+        };
+        let f_id = same_field(SchemaTools::envelope_fields().f_id);
+        let fields = carried_over_fields()
+            .into_iter()
+            .filter_map(|field| same_field(field).map(|old| (field, old)))
+            .collect();
+        Self {
+            index,
+            reader,
+            f_id,
+            fields,
+        }
+    }
+
+    /// Carried-over values of envelope `eid`, under the fields of the current schema.
+    /// Those missing from this index are taken from the `rebuilt` document.
+    async fn carried_over(
+        &self,
+        eid: u64,
+        rebuilt: &TantivyDocument,
+    ) -> BichonResult<TantivyDocument> {
+        let old = match self.f_id {
+            Some(f_id) => find_document(&self.reader.searcher(), f_id, eid).await?,
+            None => None,
+        };
+        let mut carried = TantivyDocument::new();
+        for field in carried_over_fields() {
+            let old_field = self
+                .fields
+                .iter()
+                .find(|(current, _)| *current == field)
+                .map(|(_, old_field)| *old_field);
+            let old_values: Vec<_> = match (&old, old_field) {
+                (Some(old), Some(old_field)) => old.get_all(old_field).collect(),
+                _ => Vec::new(),
+            };
+            if old_values.is_empty() {
+                for value in rebuilt.get_all(field) {
+                    carried.add_field_value(field, value);
+                }
+            } else {
+                for value in old_values {
+                    carried.add_field_value(field, value);
+                }
+            }
+        }
+        Ok(carried)
+    }
+}
+
+/// Fields only the envelope index knows, such as tags and IMAP UIDs, or that must keep
+/// their indexed value.
+fn carried_over_fields() -> [Field; 6] {
+    let f = SchemaTools::envelope_fields();
+    [
+        f.f_message_id,
+        f.f_mailbox_id,
+        f.f_uid,
+        f.f_internal_date,
+        f.f_tags,
+        f.f_archived_at,
+    ]
+}
+
+/// Stored document of envelope `eid`, looked up on the `f_id` field.
+async fn find_document(
+    searcher: &Searcher,
+    f_id: Field,
+    eid: u64,
+) -> BichonResult<Option<TantivyDocument>> {
+    let hits = searcher
+        .search(
+            &TermQuery::new(Term::from_field_u64(f_id, eid), IndexRecordOption::Basic),
+            &TopDocs::with_limit(1),
+        )
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    let Some((_, doc_address)) = hits.first() else {
+        return Ok(None);
+    };
+    searcher
+        .doc_async(*doc_address)
+        .await
+        .map(Some)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))
+}
+
+/// Open scroll session. Holding the `Searcher` pins the index generation it was created
+/// from, so the session keeps iterating over the same snapshot while new mail is indexed.
+struct ScrollSession {
+    searcher: Searcher,
+    query: Box<dyn Query>,
+    total: u64,
+    page_size: u64,
+    options: SearchOptions,
+    highlighter: Option<Highlighter>,
+    cursor: Option<SearchCursor>,
+    keep_alive: Duration,
+    expires_at: i64,
 }
 
 impl EnvelopeIndexManager {
     pub fn new() -> Self {
-        let index = Self::open_or_create_index(&DATA_DIR_MANAGER.envelope_dir);
-        let index_writer = Arc::new(Mutex::new(
-            index
-                .writer_with_num_threads(8, 536_870_912)
-                .unwrap_or_else(|e| {
-                    panic!(
-                        "Failed to create IndexWriter with 8 threads and 512MB buffer for {:?}: {}",
-                        DATA_DIR_MANAGER.envelope_dir, e
-                    )
-                }),
-        ));
-        let reader = index.reader().unwrap_or_else(|e| {
-            panic!(
-                "Failed to create IndexReader for {:?}: {}",
-                DATA_DIR_MANAGER.envelope_dir, e
-            )
-        });
-        let mut query_parser =
-            QueryParser::for_index(&index, SchemaTools::envelope_default_fields());
-        query_parser.set_conjunction_by_default();
+        let dir = Self::prepare_index_dir(&DATA_DIR_MANAGER.envelope_dir);
+        let (index, live) = LiveIndex::open(dir);
+        let index_writer = Arc::new(Mutex::new(Some(Self::create_writer(&index, &live.dir))));
+        if live.legacy.is_some() {
+            tracing::warn!(
+                "The envelope index in {:?} was built with an incompatible schema (version {}), \
+                 search returns nothing until it is rebuilt from the stored messages",
+                live.dir,
+                live.schema_version.version
+            );
+        } else if live.schema_version != SchemaVersion::current() {
+            tracing::warn!(
+                "The envelope index was built with schema version {} (current: {}), \
+                 search is degraded until it is rebuilt",
+                live.schema_version.version,
+                ENVELOPE_SCHEMA_VERSION
+            );
+        }
 
         let (sender, mut receiver) = mpsc::channel::<WriteMessage>(1000);
         task::spawn(async move {
@@ -132,6 +445,10 @@ impl EnvelopeIndexManager {
                                     ENVELOPE_INDEX_MANAGER.drain_and_commit(&mut buffer).await;
                                 }
                             }
+                            Some(WriteMessage::Flush(done)) => {
+                                ENVELOPE_INDEX_MANAGER.drain_and_commit(&mut buffer).await;
+                                let _ = done.send(());
+                            }
                             Some(WriteMessage::Shutdown) => {
                                 ENVELOPE_INDEX_MANAGER.drain_and_commit(&mut buffer).await;
                                 break;
@@ -153,8 +470,9 @@ impl EnvelopeIndexManager {
         Self {
             index_writer,
             sender,
-            reader,
-            query_parser,
+            live: RwLock::new(Arc::new(live)),
+            scrolls: DashMap::new(),
+            rebuild: Mutex::new(None),
         }
     }
 
@@ -162,17 +480,50 @@ impl EnvelopeIndexManager {
         let _ = self.sender.send(WriteMessage::Document((eid, doc))).await;
     }
 
+    /// Waits until the envelopes queued so far are committed.
+    #[cfg(test)]
+    pub async fn flush(&self) {
+        let (done, committed) = oneshot::channel();
+        if self.sender.send(WriteMessage::Flush(done)).await.is_ok() {
+            let _ = committed.await;
+        }
+    }
+
     async fn drain_and_commit(&self, buffer: &mut HashMap<u64, TantivyDocument>) {
         if buffer.is_empty() {
             return;
         }
-        let mut writer = self.index_writer.lock().await;
+        let mut writer = self.writer().await;
+        let live = self.live();
+        let f_id = SchemaTools::envelope_fields().f_id;
         let mut operations = Vec::new();
+        let mut rebuild_operations = Vec::new();
+        let mut rebuild = self.rebuild.lock().await;
+
+        // envelopes already in the index are updates of archived mail and do not trigger
+        // alerts
+        let alerts = SEARCH_ALERTS.enabled();
+        let mut new_ids = Vec::new();
+        if !alerts.is_empty() {
+            let eids: Vec<u64> = buffer.keys().copied().collect();
+            match Self::existing_ids(&live, &eids) {
+                Ok(existing) => {
+                    new_ids = eids
+                        .into_iter()
+                        .filter(|eid| !existing.contains(eid))
+                        .collect()
+                }
+                Err(e) => tracing::warn!("Search alerts skipped for this batch: {:#?}", e),
+            }
+        }
 
         for (eid, doc) in buffer.drain() {
-            let delete_term = Term::from_field_u64(SchemaTools::envelope_fields().f_id, eid);
-            operations.push(UserOperation::Delete(delete_term));
-            operations.push(UserOperation::Add(doc));
+            if rebuild.is_some() {
+                rebuild_operations.push(UserOperation::Delete(Term::from_field_u64(f_id, eid)));
+                rebuild_operations.push(UserOperation::Add(doc.clone()));
+            }
+            operations.push(UserOperation::Delete(Term::from_field_u64(f_id, eid)));
+            operations.push(UserOperation::Add(live.project(doc)));
         }
         if let Err(e) = writer.run(operations) {
             eprintln!("[FATAL] Tantivy run failed: {e:?}");
@@ -180,18 +531,440 @@ impl EnvelopeIndexManager {
         }
 
         fatal_commit(&mut writer);
+        Self::apply_to_rebuild(&mut rebuild, |target| {
+            target.writer.run(rebuild_operations)?;
+            target.writer.commit()?;
+            Ok(())
+        });
+        drop(rebuild);
+        drop(writer);
+
+        if !new_ids.is_empty() {
+            match self.evaluate_alerts(&live, &alerts, &new_ids).await {
+                Ok(events) => SEARCH_ALERTS.notify(events),
+                Err(e) => tracing::warn!("Failed to evaluate search alerts: {:#?}", e),
+            }
+        }
+    }
+
+    /// Ids among `eids` of the envelopes already in the index.
+    fn existing_ids(live: &LiveIndex, eids: &[u64]) -> BichonResult<HashSet<u64>> {
+        let searcher = Self::searcher(live)?;
+        let docs = searcher
+            .search(&Self::ids_query(eids), &DocSetCollector)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        let mut ids = HashSet::with_capacity(docs.len());
+        for address in docs {
+            let column = searcher
+                .segment_reader(address.segment_ord)
+                .fast_fields()
+                .u64(F_ID)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            if let Some(eid) = column.first(address.doc_id) {
+                ids.insert(eid);
+            }
+        }
+        Ok(ids)
+    }
+
+    fn ids_query(eids: &[u64]) -> TermSetQuery {
+        let f_id = SchemaTools::envelope_fields().f_id;
+        TermSetQuery::new(eids.iter().map(|eid| Term::from_field_u64(f_id, *eid)))
+    }
+
+    /// Runs the enabled alerts against the newly archived envelopes of a committed batch.
+    async fn evaluate_alerts(
+        &self,
+        live: &LiveIndex,
+        alerts: &[SearchAlert],
+        eids: &[u64],
+    ) -> BichonResult<Vec<(SearchAlert, AlertEvent)>> {
+        let searcher = Self::searcher(live)?;
+        let triggered_at = utc_now!();
+        let mut events = Vec::new();
+        for alert in alerts {
+            let filter = match self.filter_query(alert.filter.clone(), live) {
+                Ok(query) => query,
+                Err(e) => {
+                    tracing::warn!(
+                        "Search alert '{}' has an invalid filter: {:#?}",
+                        alert.name,
+                        e
+                    );
+                    continue;
+                }
+            };
+            let query = BooleanQuery::new(vec![
+                (Occur::Must, filter),
+                (Occur::Must, Box::new(Self::ids_query(eids))),
+            ]);
+            let docs = searcher
+                .search(&query, &DocSetCollector)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            if docs.is_empty() {
+                continue;
+            }
+            let mut matches = Vec::with_capacity(docs.len());
+            for address in docs {
+                let doc: TantivyDocument = searcher
+                    .doc_async(address)
+                    .await
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                let envelope = Envelope::from_tantivy_doc(&doc).await?;
+                matches.push(AlertMatch {
+                    id: envelope.id,
+                    account_id: envelope.account_id,
+                    mailbox_id: envelope.mailbox_id,
+                    subject: envelope.subject,
+                    from: envelope.from,
+                    internal_date: envelope.internal_date,
+                });
+            }
+            matches.sort_by_key(|m| m.internal_date);
+            events.push((
+                alert.clone(),
+                AlertEvent {
+                    alert_id: alert.id,
+                    alert_name: alert.name.clone(),
+                    matches,
+                    triggered_at,
+                },
+            ));
+        }
+        Ok(events)
+    }
+
+    /// Applies a write to the index being rebuilt, if any. A failure cancels the rebuild
+    /// instead of failing the write to the live index.
+    fn apply_to_rebuild<F>(rebuild: &mut Option<RebuildTarget>, f: F)
+    where
+        F: FnOnce(&mut RebuildTarget) -> tantivy::Result<()>,
+    {
+        let Some(target) = rebuild.as_mut() else {
+            return;
+        };
+        if let Err(e) = f(target) {
+            tracing::error!("Envelope index rebuild cancelled, write failed: {:#?}", e);
+            if let Some(target) = rebuild.take() {
+                Self::discard_rebuild(target);
+            }
+        }
+    }
+
+    async fn writer(&self) -> MappedMutexGuard<'_, IndexWriter> {
+        MutexGuard::map(self.index_writer.lock().await, |writer| {
+            writer
+                .as_mut()
+                .expect("the envelope IndexWriter is only taken while holding the lock")
+        })
+    }
+
+    fn live(&self) -> Arc<LiveIndex> {
+        self.live.read().unwrap().clone()
+    }
+
+    /// Completes a rebuild interrupted during its cutover and removes the leftovers of
+    /// cancelled ones. A rebuilt index is complete once its schema version file exists.
+    fn prepare_index_dir(index_dir: &PathBuf) -> PathBuf {
+        let rebuild_dir = index_dir.with_extension(REBUILD_DIR_EXTENSION);
+        let old_dir = index_dir.with_extension(OLD_DIR_EXTENSION);
+        if rebuild_dir.exists() {
+            if SchemaVersion::exists(&rebuild_dir) {
+                if index_dir.exists() {
+                    std::fs::remove_dir_all(index_dir).unwrap_or_else(|e| {
+                        panic!("Failed to remove replaced index {:?}: {}", index_dir, e)
+                    });
+                }
+                std::fs::rename(&rebuild_dir, index_dir).unwrap_or_else(|e| {
+                    panic!("Failed to move rebuilt index {:?}: {}", rebuild_dir, e)
+                });
+                tracing::info!("Promoted rebuilt envelope index {:?}", rebuild_dir);
+            } else {
+                tracing::warn!(
+                    "Removing incomplete envelope index rebuild {:?}",
+                    rebuild_dir
+                );
+                let _ = std::fs::remove_dir_all(&rebuild_dir);
+            }
+        }
+        if old_dir.exists() {
+            let _ = std::fs::remove_dir_all(&old_dir);
+        }
+        index_dir.clone()
     }
 
     fn open_or_create_index(index_dir: &PathBuf) -> Index {
-        if !index_dir.exists() {
-            std::fs::create_dir_all(&index_dir).unwrap_or_else(|e| {
+        let index = if !index_dir.exists() {
+            std::fs::create_dir_all(index_dir).unwrap_or_else(|e| {
                 panic!("Failed to create index directory {:?}: {}", index_dir, e)
             });
-            Index::create_in_dir(&index_dir, SchemaTools::envelope_schema())
-                .unwrap_or_else(|e| panic!("Failed to create index in {:?}: {}", index_dir, e))
+            let index = Index::create_in_dir(index_dir, SchemaTools::envelope_schema())
+                .unwrap_or_else(|e| panic!("Failed to create index in {:?}: {}", index_dir, e));
+            SchemaVersion::current()
+                .write(index_dir)
+                .unwrap_or_else(|e| panic!("Failed to write schema version: {:#?}", e));
+            index
         } else {
             open(&index_dir)
+        };
+        register_tokenizers(&index);
+        index
+    }
+
+    fn create_writer(index: &Index, index_dir: &PathBuf) -> IndexWriter {
+        index
+            .writer_with_num_threads(8, 536_870_912)
+            .unwrap_or_else(|e| {
+                panic!(
+                    "Failed to create IndexWriter with 8 threads and 512MB buffer for {:?}: {}",
+                    index_dir, e
+                )
+            })
+    }
+
+    /// Schema version of the live index, and whether it differs from the current one.
+    pub fn schema_version(&self) -> (SchemaVersion, bool) {
+        let version = self.live().schema_version.clone();
+        let outdated = version != SchemaVersion::current();
+        (version, outdated)
+    }
+
+    /// Whether the index on disk was built with an incompatible schema, and an empty index
+    /// is served until it is rebuilt.
+    pub fn is_incompatible(&self) -> bool {
+        self.live().legacy.is_some()
+    }
+
+    /// Directory of the live index, which is the rebuild directory after a cutover that
+    /// could not move it into place, until the next restart.
+    pub fn index_dir(&self) -> PathBuf {
+        self.live().dir.clone()
+    }
+
+    /// Blocks writes to the live index and opens the files of its last commit, to back it
+    /// up. Writes resume once the pause is dropped; the files stay readable.
+    pub async fn pause_writes(&self) -> BichonResult<(WritePause, IndexSnapshot)> {
+        let guard = self.index_writer.clone().lock_owned().await;
+        let live = self.live();
+        let searcher = live.reader.searcher();
+        // the index in memory is rebuilt from the EML store after a restore
+        let index = match &live.legacy {
+            Some(legacy) => &legacy.index,
+            None => searcher.index(),
+        };
+        let snapshot = IndexSnapshot::capture(index, &live.dir, &[SCHEMA_VERSION_FILE])?;
+        Ok((WritePause::new(guard), snapshot))
+    }
+
+    /// Creates an empty index with the current schema next to the live one and starts
+    /// applying every write to it.
+    pub async fn begin_rebuild(&self) -> BichonResult<()> {
+        let _writer = self.writer().await;
+        let mut rebuild = self.rebuild.lock().await;
+        if rebuild.is_some() {
+            return Err(raise_error!(
+                "The envelope index is already being rebuilt.".into(),
+                ErrorCode::InvalidParameter
+            ));
+        }
+        let live_dir = self.live().dir.clone();
+        if live_dir != DATA_DIR_MANAGER.envelope_dir {
+            return Err(raise_error!(
+                "A rebuilt envelope index is waiting to be moved into place, restart the server before rebuilding again.".into(),
+                ErrorCode::InvalidParameter
+            ));
+        }
+        let dir = live_dir.with_extension(REBUILD_DIR_EXTENSION);
+        if dir.exists() {
+            std::fs::remove_dir_all(&dir)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
         }
+        std::fs::create_dir_all(&dir)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        let index = Index::create_in_dir(&dir, SchemaTools::envelope_schema())
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        register_tokenizers(&index);
+        let writer = index
+            .writer_with_num_threads(4, 268_435_456)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        let reader = index
+            .reader()
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        *rebuild = Some(RebuildTarget {
+            dir,
+            writer,
+            reader,
+        });
+        Ok(())
+    }
+
+    /// Writes rebuilt documents to the index being rebuilt and commits them, returning how
+    /// many were written.
+    ///
+    /// What only the index knows, such as tags and IMAP UIDs, is carried over from the live
+    /// index, and envelopes missing from it are skipped. This runs under the writer lock so
+    /// that no envelope deleted or retagged meanwhile is written back in its previous state.
+    /// In place of an incompatible index, envelopes are all written, with the values that
+    /// index still holds under the same name and type.
+    pub async fn add_rebuilt_documents(
+        &self,
+        docs: Vec<(u64, TantivyDocument)>,
+    ) -> BichonResult<usize> {
+        let _writer = self.writer().await;
+        let live = self.live();
+        let searcher = Self::searcher(&live)?;
+        let mut rebuild = self.rebuild.lock().await;
+        let target = rebuild.as_mut().ok_or_else(|| {
+            raise_error!(
+                "The envelope index rebuild was cancelled.".into(),
+                ErrorCode::InternalError
+            )
+        })?;
+        let f = SchemaTools::envelope_fields();
+        let carried_over = carried_over_fields();
+        let mut operations = Vec::with_capacity(docs.len() * 2);
+        for (eid, doc) in docs {
+            let term = Term::from_field_u64(f.f_id, eid);
+            let live_doc = match find_document(&searcher, f.f_id, eid).await? {
+                Some(live_doc) => live_doc,
+                // the empty index served in place of an incompatible one only has new mail
+                None => match &live.legacy {
+                    Some(legacy) => legacy.carried_over(eid, &doc).await?,
+                    None => continue,
+                },
+            };
+            let mut rebuilt = TantivyDocument::new();
+            for (field, value) in doc.field_values() {
+                if !carried_over.contains(&field) {
+                    rebuilt.add_field_value(field, value);
+                }
+            }
+            for (field, value) in live_doc.field_values() {
+                if carried_over.contains(&field) {
+                    rebuilt.add_field_value(field, value);
+                }
+            }
+            // indexes from before the archive time was recorded only know the internal date
+            if live_doc.get_first(f.f_archived_at).is_none() {
+                if let Some(internal_date) =
+                    live_doc.get_first(f.f_internal_date).and_then(|v| v.as_i64())
+                {
+                    rebuilt.add_i64(f.f_archived_at, internal_date);
+                }
+            }
+            operations.push(UserOperation::Delete(term));
+            operations.push(UserOperation::Add(rebuilt));
+        }
+        let added = operations.len() / 2;
+        let result = target
+            .writer
+            .run(operations)
+            .and_then(|_| target.writer.commit());
+        if let Err(e) = result {
+            if let Some(target) = rebuild.take() {
+                Self::discard_rebuild(target);
+            }
+            return Err(raise_error!(format!("{:#?}", e), ErrorCode::InternalError));
+        }
+        Ok(added)
+    }
+
+    /// Snapshot of the live index, to walk through every envelope it holds.
+    pub fn live_searcher(&self) -> BichonResult<Searcher> {
+        self.create_searcher()
+    }
+
+    /// Stops a rebuild and removes the partially built index.
+    pub async fn cancel_rebuild(&self) -> bool {
+        match self.rebuild.lock().await.take() {
+            Some(target) => {
+                Self::discard_rebuild(target);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn discard_rebuild(target: RebuildTarget) {
+        let RebuildTarget {
+            dir,
+            writer,
+            reader,
+        } = target;
+        drop(reader);
+        if let Err(e) = writer.wait_merging_threads() {
+            tracing::warn!("Failed to stop the rebuild IndexWriter: {:#?}", e);
+        }
+        if let Err(e) = std::fs::remove_dir_all(&dir) {
+            tracing::warn!("Failed to remove {:?}: {:#?}", dir, e);
+        }
+    }
+
+    /// Atomically replaces the live index with the rebuilt one. Writes are blocked while
+    /// the indexes are swapped; searches keep using the previous index until then.
+    pub async fn finish_rebuild(&self) -> BichonResult<()> {
+        let mut writer_guard = self.index_writer.lock().await;
+        let target = self.rebuild.lock().await.take().ok_or_else(|| {
+            raise_error!(
+                "The envelope index rebuild was cancelled.".into(),
+                ErrorCode::InternalError
+            )
+        })?;
+        let RebuildTarget {
+            dir: rebuild_dir,
+            mut writer,
+            reader,
+        } = target;
+        drop(reader);
+        let completed = writer
+            .commit()
+            .and_then(|_| writer.wait_merging_threads())
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))
+            .and_then(|_| SchemaVersion::current().write(&rebuild_dir));
+        if let Err(e) = completed {
+            let _ = std::fs::remove_dir_all(&rebuild_dir);
+            return Err(e);
+        }
+
+        // the live writer must be stopped before its directory is moved away
+        let live_dir = self.live().dir.clone();
+        if let Some(live_writer) = writer_guard.take() {
+            if let Err(e) = live_writer.wait_merging_threads() {
+                tracing::warn!("Failed to stop the envelope IndexWriter: {:#?}", e);
+            }
+        }
+        let old_dir = live_dir.with_extension(OLD_DIR_EXTENSION);
+        let dir = match std::fs::rename(&live_dir, &old_dir) {
+            Ok(()) => {
+                // the rebuilt index is complete, a crash from here on is recovered at startup
+                std::fs::rename(&rebuild_dir, &live_dir).unwrap_or_else(|e| {
+                    panic!("Failed to move rebuilt index {:?}: {}", rebuild_dir, e)
+                });
+                live_dir
+            }
+            Err(e) => {
+                // open files cannot be moved on some platforms, the rebuilt index is served
+                // from where it is and moved into place at the next startup
+                tracing::warn!(
+                    "Serving the rebuilt envelope index from {:?} until restart: {:#?}",
+                    rebuild_dir,
+                    e
+                );
+                rebuild_dir
+            }
+        };
+        let index = Self::open_or_create_index(&dir);
+        *writer_guard = Some(Self::create_writer(&index, &dir));
+        *self.live.write().unwrap() = Arc::new(LiveIndex::new(dir, &index, None));
+        drop(writer_guard);
+
+        // scroll sessions opened on the previous index keep their snapshot until they end
+        if old_dir.exists() {
+            if let Err(e) = std::fs::remove_dir_all(&old_dir) {
+                tracing::warn!("Failed to remove the previous envelope index: {:#?}", e);
+            }
+        }
+        Ok(())
     }
 
     pub fn total_emails(&self) -> BichonResult<u64> {
@@ -221,68 +994,97 @@ impl EnvelopeIndexManager {
         Box::new(boolean_query)
     }
 
-    fn filter_query(
-        &self,
-        filter: SearchFilter,
-        parser: QueryParser,
-    ) -> BichonResult<Box<dyn Query>> {
+    fn filter_query(&self, filter: SearchFilter, live: &LiveIndex) -> BichonResult<Box<dyn Query>> {
         let f = SchemaTools::envelope_fields();
         let mut subqueries: Vec<(Occur, Box<dyn Query>)> = Vec::new();
 
         if let Some(ref text) = filter.text {
-            let query = parser
-                .parse_query(text)
-                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InvalidParameter))?;
-            subqueries.push((Occur::Must, Box::new(query)));
+            let query = live.text_query(text, filter.language.as_deref())?;
+            subqueries.push((Occur::Must, query));
         }
 
         if let Some(ref tags) = filter.tags {
             if !tags.is_empty() {
-                let mut should_queries: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+                let occur = match filter.tags_match.unwrap_or_default() {
+                    TagsMatch::Any => Occur::Should,
+                    TagsMatch::All => Occur::Must,
+                };
+                let mut tag_queries: Vec<(Occur, Box<dyn Query>)> = Vec::new();
 
                 for tag in tags {
-                    let facet = Facet::from_text(tag).map_err(|e| {
-                        raise_error!(format!("{:#?}", e), ErrorCode::InvalidParameter)
-                    })?;
-
-                    let term = Term::from_facet(f.f_tags, &facet);
-
-                    should_queries.push((
-                        Occur::Should,
-                        Box::new(TermQuery::new(term, IndexRecordOption::Basic)),
-                    ));
+                    tag_queries.push((occur, Self::tag_query(tag)?));
                 }
-                subqueries.push((Occur::Must, Box::new(BooleanQuery::new(should_queries))));
+                subqueries.push((Occur::Must, Box::new(BooleanQuery::new(tag_queries))));
             }
         }
 
-        for (field, opt_value) in [
-            (f.f_from, &filter.from),
-            (f.f_to, &filter.to),
-            (f.f_cc, &filter.cc),
-            (f.f_bcc, &filter.bcc),
+        for (field, fallback, opt_value) in [
+            (f.f_from_addr, f.f_from, &filter.from),
+            (f.f_to_addr, f.f_to, &filter.to),
+            (f.f_cc_addr, f.f_cc, &filter.cc),
+            (f.f_bcc_addr, f.f_bcc, &filter.bcc),
         ] {
             if let Some(ref v) = opt_value {
-                let term = Term::from_field_text(field, v);
                 subqueries.push((
                     Occur::Must,
-                    Box::new(TermQuery::new(term, IndexRecordOption::Basic)),
+                    Self::address_filter(field, fallback, v, live),
                 ));
             }
         }
 
-        if let Some(has) = filter.has_attachment {
-            if has {
+        if let Some(ref domain) = filter.from_domain {
+            subqueries.push((
+                Occur::Must,
+                live.guard(
+                    f.f_from_addr,
+                    Self::address_query(f.f_from_addr, &domain_term(domain)),
+                ),
+            ));
+        }
+
+        if let Some(ref domain) = filter.to_domain {
+            subqueries.push((
+                Occur::Must,
+                live.guard(f.f_to_addr, Self::recipient_query(&domain_term(domain), live)),
+            ));
+        }
+
+        for (field, opt_value) in [
+            (f.f_from_name, &filter.from_name),
+            (f.f_recipient_names, &filter.to_name),
+        ] {
+            if let Some(ref name) = opt_value {
+                let terms = default_terms(name);
+                if terms.is_empty() {
+                    continue;
+                }
+                let term_queries: Vec<(Occur, Box<dyn Query>)> = terms
+                    .iter()
+                    .map(|term| {
+                        let query: Box<dyn Query> = Box::new(TermQuery::new(
+                            Term::from_field_text(field, term),
+                            IndexRecordOption::Basic,
+                        ));
+                        (Occur::Must, query)
+                    })
+                    .collect();
                 subqueries.push((
                     Occur::Must,
-                    Box::new(TermQuery::new(
-                        Term::from_field_bool(f.f_has_attachment, true.into()),
-                        IndexRecordOption::Basic,
-                    )),
+                    live.guard(field, Box::new(BooleanQuery::new(term_queries))),
                 ));
             }
         }
 
+        if let Some(has) = filter.has_attachment {
+            subqueries.push((
+                Occur::Must,
+                Box::new(TermQuery::new(
+                    Term::from_field_bool(f.f_has_attachment, has),
+                    IndexRecordOption::Basic,
+                )),
+            ));
+        }
+
         if let Some(ref name) = filter.attachment_name {
             let term = Term::from_field_text(f.f_attachments, name);
             subqueries.push((
@@ -308,6 +1110,20 @@ impl EnvelopeIndexManager {
             subqueries.push((Occur::Must, Box::new(q)));
         }
 
+        if let Some(archived_since) = filter.archived_since {
+            // an outdated index only knows when the messages were received
+            let field = if live.has_field(f.f_archived_at) {
+                f.f_archived_at
+            } else {
+                f.f_internal_date
+            };
+            let q = RangeQuery::new(
+                Bound::Included(Term::from_field_i64(field, archived_since)),
+                Bound::Unbounded,
+            );
+            subqueries.push((Occur::Must, Box::new(q)));
+        }
+
         if let Some(account_id) = filter.account_id {
             let term = Term::from_field_u64(f.f_account_id, account_id);
             subqueries.push((
@@ -349,13 +1165,158 @@ impl EnvelopeIndexManager {
             ));
         }
 
+        if let Some(ref language) = filter.language {
+            let term = Term::from_field_text(f.f_language, &language.trim().to_lowercase());
+            subqueries.push((
+                Occur::Must,
+                live.guard(
+                    f.f_language,
+                    Box::new(TermQuery::new(term, IndexRecordOption::Basic)),
+                ),
+            ));
+        }
+
+        for (name, value) in filter.headers.iter().flatten() {
+            subqueries.push((Occur::Must, Self::header_query(name, value, live)?));
+        }
+
+        if let Some(ref exclude) = filter.exclude {
+            for account_id in exclude.account_ids.iter().flatten() {
+                let term = Term::from_field_u64(f.f_account_id, *account_id);
+                subqueries.push((
+                    Occur::MustNot,
+                    Box::new(TermQuery::new(term, IndexRecordOption::Basic)),
+                ));
+            }
+            for mailbox_id in exclude.mailbox_ids.iter().flatten() {
+                let term = Term::from_field_u64(f.f_mailbox_id, *mailbox_id);
+                subqueries.push((
+                    Occur::MustNot,
+                    Box::new(TermQuery::new(term, IndexRecordOption::Basic)),
+                ));
+            }
+            for from in exclude.from.iter().flatten() {
+                subqueries.push((
+                    Occur::MustNot,
+                    Self::address_filter(f.f_from_addr, f.f_from, from, live),
+                ));
+            }
+            for recipient in exclude.recipients.iter().flatten() {
+                subqueries.push((Occur::MustNot, Self::recipient_query(recipient, live)));
+            }
+            for tag in exclude.tags.iter().flatten() {
+                subqueries.push((Occur::MustNot, Self::tag_query(tag)?));
+            }
+        }
+
         if subqueries.is_empty() {
             return Ok(Box::new(AllQuery));
         }
 
+        // A boolean query made only of exclusions matches nothing in tantivy,
+        // so exclude from the whole index instead.
+        if subqueries.iter().all(|(occur, _)| *occur == Occur::MustNot) {
+            subqueries.push((Occur::Must, Box::new(AllQuery)));
+        }
+
         Ok(Box::new(BooleanQuery::new(subqueries)))
     }
 
+    /// Matches `value` against one token of an address field: a full address, a local part
+    /// or one of its words, or a domain in the `@acme.com` form.
+    fn address_query(field: Field, value: &str) -> Box<dyn Query> {
+        let term = Term::from_field_text(field, &value.trim().to_lowercase());
+        Box::new(TermQuery::new(term, IndexRecordOption::Basic))
+    }
+
+    /// Same as [`Self::address_query`], or an exact match on the raw address `fallback`
+    /// field in an index that predates the `*_addr` fields.
+    fn address_filter(
+        field: Field,
+        fallback: Field,
+        value: &str,
+        live: &LiveIndex,
+    ) -> Box<dyn Query> {
+        if live.has_field(field) {
+            Self::address_query(field, value)
+        } else {
+            let term = Term::from_field_text(fallback, value);
+            Box::new(TermQuery::new(term, IndexRecordOption::Basic))
+        }
+    }
+
+    /// Matches an indexed header: its whole value for headers indexed as `exact`, a phrase
+    /// of its text for headers indexed as `text`, and either of them for both.
+    fn header_query(name: &str, value: &str, live: &LiveIndex) -> BichonResult<Box<dyn Query>> {
+        let f = SchemaTools::envelope_fields();
+        let header = SETTINGS.bichon_indexed_headers.get(name).ok_or_else(|| {
+            raise_error!(
+                format!("Header '{}' is not indexed.", name.trim()),
+                ErrorCode::InvalidParameter
+            )
+        })?;
+        let mut queries: Vec<(Occur, Box<dyn Query>)> = Vec::new();
+        if header.exact {
+            let mut term = Term::from_field_json_path(f.f_headers, &header.name, false);
+            term.append_type_and_str(&header_term(value));
+            queries.push((
+                Occur::Should,
+                live.guard(
+                    f.f_headers,
+                    Box::new(TermQuery::new(term, IndexRecordOption::Basic)),
+                ),
+            ));
+        }
+        if header.text {
+            let terms: Vec<Term> = default_terms(value)
+                .iter()
+                .map(|token| {
+                    let mut term =
+                        Term::from_field_json_path(f.f_headers_text, &header.name, false);
+                    term.append_type_and_str(token);
+                    term
+                })
+                .collect();
+            let query: Box<dyn Query> = match terms.len() {
+                0 => Box::new(EmptyQuery),
+                1 => Box::new(TermQuery::new(
+                    terms.into_iter().next().unwrap(),
+                    IndexRecordOption::Basic,
+                )),
+                _ => Box::new(PhraseQuery::new(terms)),
+            };
+            queries.push((Occur::Should, live.guard(f.f_headers_text, query)));
+        }
+        Ok(Box::new(BooleanQuery::new(queries)))
+    }
+
+    /// Matches `value` against any of the `to`, `cc` or `bcc` addresses.
+    fn recipient_query(value: &str, live: &LiveIndex) -> Box<dyn Query> {
+        let f = SchemaTools::envelope_fields();
+        Box::new(BooleanQuery::new(
+            [
+                (f.f_to_addr, f.f_to),
+                (f.f_cc_addr, f.f_cc),
+                (f.f_bcc_addr, f.f_bcc),
+            ]
+            .into_iter()
+            .map(|(field, fallback)| {
+                (
+                    Occur::Should,
+                    Self::address_filter(field, fallback, value, live),
+                )
+            })
+            .collect(),
+        ))
+    }
+
+    fn tag_query(tag: &str) -> BichonResult<Box<dyn Query>> {
+        let facet = Facet::from_text(tag)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InvalidParameter))?;
+        let term = Term::from_facet(SchemaTools::envelope_fields().f_tags, &facet);
+        Ok(Box::new(TermQuery::new(term, IndexRecordOption::Basic)))
+    }
+
     fn thread_query(&self, account_id: u64, thread_id: u64) -> Box<dyn Query> {
         let account_query = TermQuery::new(
             Term::from_field_u64(SchemaTools::envelope_fields().f_account_id, account_id),
@@ -389,14 +1350,29 @@ impl EnvelopeIndexManager {
     }
 
     pub async fn delete_account_envelopes(&self, account_id: u64) -> BichonResult<()> {
-        let query = self.account_query(account_id);
-        let mut writer = self.index_writer.lock().await;
-        writer
-            .delete_query(query)
-            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        let query: Box<dyn Query> = self.account_query(account_id);
+        self.delete_queries(vec![query]).await
+    }
+
+    /// Deletes the matching envelopes from the live index and from the index being
+    /// rebuilt, if any.
+    async fn delete_queries(&self, queries: Vec<Box<dyn Query>>) -> BichonResult<()> {
+        let mut writer = self.writer().await;
+        for query in &queries {
+            writer
+                .delete_query(query.box_clone())
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        }
         writer
             .commit()
             .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        Self::apply_to_rebuild(&mut *self.rebuild.lock().await, |target| {
+            for query in queries {
+                target.writer.delete_query(query)?;
+            }
+            target.writer.commit()?;
+            Ok(())
+        });
         Ok(())
     }
 
@@ -413,27 +1389,19 @@ impl EnvelopeIndexManager {
         for mailbox_id in mailbox_ids {
             queries.push(self.mailbox_query(account_id, mailbox_id));
         }
-        let mut writer = self.index_writer.lock().await;
-        for query in queries {
-            writer
-                .delete_query(query)
-                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-        }
-        writer
-            .commit()
-            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-        Ok(())
+        self.delete_queries(queries).await
     }
 
     fn collect_facets_recursive(
         searcher: &Searcher,
+        query: &dyn Query,
         parent_facet: &str,
         all_facets: &mut Vec<TagCount>,
     ) -> BichonResult<()> {
         let mut facet_collector = FacetCollector::for_field(F_TAGS);
         facet_collector.add_facet(parent_facet);
         let facet_counts = searcher
-            .search(&AllQuery, &facet_collector)
+            .search(query, &facet_collector)
             .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
 
         for (facet, count) in facet_counts.get(parent_facet) {
@@ -441,16 +1409,16 @@ impl EnvelopeIndexManager {
                 tag: facet.to_string(),
                 count,
             });
-            Self::collect_facets_recursive(searcher, &facet.to_string(), all_facets)?;
+            Self::collect_facets_recursive(searcher, query, &facet.to_string(), all_facets)?;
         }
 
         Ok(())
     }
 
     pub async fn get_all_tags(&self) -> BichonResult<Vec<TagCount>> {
-        let searcher = self.reader.searcher();
+        let searcher = self.create_searcher()?;
         let mut all_facets = Vec::new();
-        Self::collect_facets_recursive(&searcher, "/", &mut all_facets)?;
+        Self::collect_facets_recursive(&searcher, &AllQuery, "/", &mut all_facets)?;
         Ok(all_facets)
     }
 
@@ -463,25 +1431,14 @@ impl EnvelopeIndexManager {
             return Ok(());
         }
 
-        let mut writer = self.index_writer.lock().await;
-
+        let mut queries = Vec::new();
         for (account_id, envelope_ids) in deletes {
             let unique_ids: HashSet<u64> = envelope_ids.iter().copied().collect();
-            if unique_ids.is_empty() {
-                continue;
-            }
             for eid in unique_ids {
-                let query = self.envelope_query(*account_id, eid);
-                writer
-                    .delete_query(query)
-                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                queries.push(self.envelope_query(*account_id, eid));
             }
         }
-        writer
-            .commit()
-            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-
-        Ok(())
+        self.delete_queries(queries).await
     }
 
     pub async fn update_envelope_tags(
@@ -494,8 +1451,9 @@ impl EnvelopeIndexManager {
             return Ok(());
         }
 
-        let searcher = self.create_searcher()?;
-        let mut writer = self.index_writer.lock().await;
+        let mut writer = self.writer().await;
+        let live = self.live();
+        let searcher = Self::searcher(&live)?;
 
         let f_tags = SchemaTools::envelope_fields().f_tags;
         let f_id = SchemaTools::envelope_fields().f_id;
@@ -519,8 +1477,9 @@ impl EnvelopeIndexManager {
                         .await
                         .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
 
+                    // fields that are only indexed are derived again from the stored ones
                     let mut new_doc = TantivyDocument::new();
-                    for (field, value) in old_doc.field_values() {
+                    for (field, value) in Envelope::restore_document(&old_doc).field_values() {
                         if field != f_tags {
                             new_doc.add_field_value(field, value);
                         }
@@ -531,86 +1490,601 @@ impl EnvelopeIndexManager {
 
                     let delete_term = Term::from_field_u64(f_id, *eid);
                     operations.push(UserOperation::Delete(delete_term));
+                    operations.push(UserOperation::Add(live.project(new_doc)));
+                }
+            }
+        }
+
+        writer
+            .run(operations)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+
+        // commit
+        writer
+            .commit()
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+
+        // envelopes already copied to the rebuilt index carry the previous tags, they are
+        // updated from their rebuilt document, which may hold more than the live one
+        Self::apply_to_rebuild(&mut *self.rebuild.lock().await, |target| {
+            target.reader.reload()?;
+            let searcher = target.reader.searcher();
+            let mut operations = Vec::new();
+            for eid in deduplicated_updates.values().flatten() {
+                let term = Term::from_field_u64(f_id, *eid);
+                let docs = searcher.search(
+                    &TermQuery::new(term.clone(), IndexRecordOption::Basic),
+                    &TopDocs::with_limit(1),
+                )?;
+                if let Some((_, doc_address)) = docs.first() {
+                    let old_doc: TantivyDocument = searcher.doc(*doc_address)?;
+                    let mut new_doc = TantivyDocument::new();
+                    for (field, value) in Envelope::restore_document(&old_doc).field_values() {
+                        if field != f_tags {
+                            new_doc.add_field_value(field, value);
+                        }
+                    }
+                    for tag in &tags {
+                        new_doc.add_facet(f_tags, tag);
+                    }
+                    operations.push(UserOperation::Delete(term));
                     operations.push(UserOperation::Add(new_doc));
                 }
             }
+            target.writer.run(operations)?;
+            target.writer.commit()?;
+            Ok(())
+        });
+
+        Ok(())
+    }
+
+    pub async fn search(
+        &self,
+        filter: SearchFilter,
+        page: u64,
+        page_size: u64,
+        options: SearchOptions,
+        search_after: Option<SearchCursor>,
+        facets: Option<FacetRequest>,
+    ) -> BichonResult<SearchResult> {
+        assert!(page > 0, "Page number must be greater than 0");
+        assert!(page_size > 0, "Page size must be greater than 0");
+        let live = self.live();
+        let highlight_query = self.highlight_query(&filter, &live)?;
+        let query = self.filter_query(filter, &live)?;
+        let searcher = Self::searcher(&live)?;
+        let total = searcher
+            .search(&query, &Count)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+            as u64;
+
+        let current_page = search_after.is_none().then_some(page);
+        if total == 0 {
+            return Ok(SearchResult {
+                current_page,
+                page_size: Some(page_size),
+                total_items: 0,
+                items: vec![],
+                total_pages: Some(0),
+                next_cursor: None,
+                facets: facets.map(|_| SearchFacets::default()),
+            });
+        }
+        let offset = if search_after.is_some() {
+            0
+        } else {
+            (page - 1) * page_size
+        };
+        let total_pages = total.div_ceil(page_size);
+        // facets cover every match, whichever page is asked for
+        let facets = match facets {
+            Some(request) => Some(Self::compute_facets(&searcher, query.as_ref(), &request)?),
+            None => None,
+        };
+        if offset > total {
+            return Ok(SearchResult {
+                current_page,
+                page_size: Some(page_size),
+                total_items: total,
+                items: vec![],
+                total_pages: Some(total_pages),
+                next_cursor: None,
+                facets,
+            });
+        }
+
+        let highlighter = highlight_query
+            .map(|q| Highlighter::new(&searcher, q.as_ref(), options.highlight.as_ref()))
+            .transpose()?;
+        let top_docs = TopDocs::with_limit(page_size as usize).and_offset(offset as usize);
+        let (items, last) = Self::collect_hits(
+            &searcher,
+            query.as_ref(),
+            top_docs,
+            &options,
+            search_after.as_ref(),
+            highlighter.as_ref(),
+        )
+        .await?;
+        let next_cursor = match last {
+            Some(cursor) if items.len() as u64 == page_size => Some(cursor.encode()?),
+            _ => None,
+        };
+        Ok(SearchResult {
+            current_page,
+            page_size: Some(page_size),
+            total_items: total,
+            items,
+            total_pages: Some(total_pages),
+            next_cursor,
+            facets,
+        })
+    }
+
+    /// Computes the requested facets over every document matching `query`.
+    fn compute_facets(
+        searcher: &Searcher,
+        query: &dyn Query,
+        request: &FacetRequest,
+    ) -> BichonResult<SearchFacets> {
+        let size = request.size();
+        let mut aggs = serde_json::Map::new();
+        for facet in &request.facets {
+            let (name, agg) = match facet {
+                SearchFacet::Account => (
+                    "accounts",
+                    json!({ "terms": { "field": F_ACCOUNT_ID, "size": size } }),
+                ),
+                SearchFacet::Mailbox => (
+                    "mailboxes",
+                    json!({ "terms": { "field": F_MAILBOX_ID, "size": size } }),
+                ),
+                SearchFacet::SenderDomain => (
+                    "sender_domains",
+                    json!({ "terms": { "field": F_FROM_DOMAIN, "size": size } }),
+                ),
+                SearchFacet::HasAttachment => (
+                    "has_attachment",
+                    json!({ "terms": { "field": F_HAS_ATTACHMENT } }),
+                ),
+                SearchFacet::DateHistogram => (
+                    "date_histogram",
+                    json!({
+                        "histogram": {
+                            "field": F_INTERNAL_DATE,
+                            "interval": request.interval.unwrap_or_default().millis(),
+                            "min_doc_count": 1
+                        }
+                    }),
+                ),
+                SearchFacet::Tag => continue,
+            };
+            aggs.insert(name.to_string(), agg);
+        }
+
+        let mut facets = SearchFacets::default();
+        if !aggs.is_empty() {
+            let aggregations: Aggregations = serde_json::from_value(aggs.into())
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            let agg_collector = AggregationCollector::from_aggs(aggregations, Default::default());
+            let agg_results = searcher
+                .search(query, &agg_collector)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+
+            if request.contains(SearchFacet::Account) {
+                facets.accounts = Some(Self::terms_groups(&agg_results, "accounts"));
+            }
+            if request.contains(SearchFacet::Mailbox) {
+                facets.mailboxes = Some(Self::terms_groups(&agg_results, "mailboxes"));
+            }
+            if request.contains(SearchFacet::SenderDomain) {
+                facets.sender_domains = Some(Self::terms_groups(&agg_results, "sender_domains"));
+            }
+            if request.contains(SearchFacet::HasAttachment) {
+                let groups = Self::terms_groups(&agg_results, "has_attachment")
+                    .into_iter()
+                    .map(|group| Group {
+                        key: (group.key == "1").to_string(),
+                        count: group.count,
+                    })
+                    .collect();
+                facets.has_attachment = Some(groups);
+            }
+            if request.contains(SearchFacet::DateHistogram) {
+                let mut histogram = Vec::new();
+                if let Some(AggregationResult::BucketResult(BucketResult::Histogram {
+                    buckets: BucketEntries::Vec(bucket_list),
+                })) = agg_results.0.get("date_histogram")
+                {
+                    for entry in bucket_list {
+                        if let Key::F64(ms) = entry.key {
+                            histogram.push(TimeBucket {
+                                timestamp_ms: ms as i64,
+                                count: entry.doc_count,
+                            });
+                        }
+                    }
+                }
+                facets.date_histogram = Some(histogram);
+            }
+        }
+
+        if request.contains(SearchFacet::Tag) {
+            let mut tags = Vec::new();
+            Self::collect_facets_recursive(searcher, query, "/", &mut tags)?;
+            tags.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.tag.cmp(&b.tag)));
+            tags.truncate(size);
+            facets.tags = Some(tags);
+        }
+        Ok(facets)
+    }
+
+    fn terms_groups(agg_results: &AggregationResults, name: &str) -> Vec<Group> {
+        let Some(AggregationResult::BucketResult(BucketResult::Terms { buckets, .. })) =
+            agg_results.0.get(name)
+        else {
+            return Vec::new();
+        };
+        buckets
+            .iter()
+            .map(|entry| Group {
+                key: match &entry.key {
+                    Key::Str(value) => value.clone(),
+                    Key::U64(value) => value.to_string(),
+                    Key::I64(value) => value.to_string(),
+                    Key::F64(value) => value.to_string(),
+                },
+                count: entry.doc_count,
+            })
+            .collect()
+    }
+
+    /// Number of envelopes matching `filter`.
+    pub async fn count(&self, filter: SearchFilter) -> BichonResult<u64> {
+        let live = self.live();
+        let query = self.filter_query(filter, &live)?;
+        let searcher = Self::searcher(&live)?;
+        let count = searcher
+            .search(&query, &Count)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        Ok(count as u64)
+    }
+
+    /// Envelope ids, by account, of the envelopes matching `filter` and none of `excluded`.
+    pub async fn matching_ids(
+        &self,
+        filter: SearchFilter,
+        excluded: Vec<SearchFilter>,
+    ) -> BichonResult<HashMap<u64, Vec<u64>>> {
+        let live = self.live();
+        let mut subqueries = vec![(Occur::Must, self.filter_query(filter, &live)?)];
+        for filter in excluded {
+            subqueries.push((Occur::MustNot, self.filter_query(filter, &live)?));
+        }
+        let searcher = Self::searcher(&live)?;
+        Self::envelope_ids(&searcher, &BooleanQuery::new(subqueries))
+    }
+
+    /// The envelopes of `ids`, envelope ids by account, that match `filter`.
+    pub async fn matching_among(
+        &self,
+        ids: &HashMap<u64, Vec<u64>>,
+        filter: SearchFilter,
+    ) -> BichonResult<HashMap<u64, Vec<u64>>> {
+        let eids: Vec<u64> = ids.values().flatten().copied().collect();
+        if eids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let live = self.live();
+        let query = BooleanQuery::new(vec![
+            (Occur::Must, self.filter_query(filter, &live)?),
+            (Occur::Must, Box::new(Self::ids_query(&eids))),
+        ]);
+        let searcher = Self::searcher(&live)?;
+        let mut matching = Self::envelope_ids(&searcher, &query)?;
+        matching.retain(|account_id, matched| match ids.get(account_id) {
+            Some(requested) => {
+                let requested: HashSet<u64> = requested.iter().copied().collect();
+                matched.retain(|eid| requested.contains(eid));
+                !matched.is_empty()
+            }
+            None => false,
+        });
+        Ok(matching)
+    }
+
+    /// Envelope ids, by account, of the envelopes matching `query`.
+    fn envelope_ids(
+        searcher: &Searcher,
+        query: &dyn Query,
+    ) -> BichonResult<HashMap<u64, Vec<u64>>> {
+        let docs = searcher
+            .search(query, &DocSetCollector)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        let mut ids: HashMap<u64, Vec<u64>> = HashMap::new();
+        for address in docs {
+            let fast_fields = searcher.segment_reader(address.segment_ord).fast_fields();
+            let eid = fast_fields
+                .u64(F_ID)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                .first(address.doc_id);
+            let account_id = fast_fields
+                .u64(F_ACCOUNT_ID)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                .first(address.doc_id);
+            if let (Some(eid), Some(account_id)) = (eid, account_id) {
+                ids.entry(account_id).or_default().push(eid);
+            }
+        }
+        Ok(ids)
+    }
+
+    /// Envelopes whose subject and body resemble those of envelope `eid`, best matches
+    /// first. `accounts` restricts the results; `None` searches every account.
+    pub async fn more_like_this(
+        &self,
+        account_id: u64,
+        eid: u64,
+        accounts: Option<Vec<u64>>,
+        limit: usize,
+    ) -> BichonResult<Vec<SimilarEnvelope>> {
+        let f = SchemaTools::envelope_fields();
+        let live = self.live();
+        let searcher = Self::searcher(&live)?;
+        let docs = searcher
+            .search(
+                self.envelope_query(account_id, eid).as_ref(),
+                &TopDocs::with_limit(1),
+            )
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        let Some((_, address)) = docs.first() else {
+            return Err(raise_error!(
+                format!("Envelope with id={} not found", eid),
+                ErrorCode::ResourceNotFound
+            ));
+        };
+        let doc: TantivyDocument = searcher
+            .doc_async(*address)
+            .await
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        let doc_fields = [f.f_subject, f.f_text]
+            .into_iter()
+            .map(|field| {
+                let values = doc
+                    .get_all(field)
+                    .filter_map(|v| v.as_str())
+                    .map(|s| OwnedValue::Str(s.to_string()))
+                    .collect();
+                (field, values)
+            })
+            .collect();
+        let similar = MoreLikeThisQuery::builder()
+            .with_min_doc_frequency(2)
+            .with_min_term_frequency(1)
+            .with_max_query_terms(30)
+            .with_min_word_length(3)
+            .with_document_fields(doc_fields);
+
+        let mut subqueries: Vec<(Occur, Box<dyn Query>)> = vec![
+            (Occur::Must, Box::new(similar)),
+            (
+                Occur::MustNot,
+                Box::new(TermQuery::new(
+                    Term::from_field_u64(f.f_id, eid),
+                    IndexRecordOption::Basic,
+                )),
+            ),
+        ];
+        if let Some(accounts) = accounts {
+            let accounts = TermSetQuery::new(
+                accounts
+                    .into_iter()
+                    .map(|id| Term::from_field_u64(f.f_account_id, id)),
+            );
+            subqueries.push((
+                Occur::Must,
+                Box::new(ConstScoreQuery::new(Box::new(accounts), 0.0)),
+            ));
         }
-
-        writer
-            .run(operations)
+        let hits = searcher
+            .search(&BooleanQuery::new(subqueries), &TopDocs::with_limit(limit))
             .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
 
-        // commit
-        writer
-            .commit()
+        let mut items = Vec::with_capacity(hits.len());
+        for (score, address) in hits {
+            let doc: TantivyDocument = searcher
+                .doc_async(address)
+                .await
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            items.push(SimilarEnvelope {
+                score,
+                envelope: Envelope::from_tantivy_doc(&doc).await?,
+            });
+        }
+        Ok(items)
+    }
+
+    /// The number of envelopes matching `filter` and the `limit` most recent of them, each
+    /// with its full body text, of which `Envelope::text` only holds a preview.
+    pub async fn latest_envelopes(
+        &self,
+        filter: SearchFilter,
+        limit: usize,
+    ) -> BichonResult<(u64, Vec<(Envelope, String)>)> {
+        let live = self.live();
+        let query = self.filter_query(filter, &live)?;
+        let searcher = Self::searcher(&live)?;
+        let top_docs =
+            TopDocs::with_limit(limit).order_by_fast_field::<i64>(F_INTERNAL_DATE, Order::Desc);
+        let (total, hits) = searcher
+            .search(&query, &(Count, top_docs))
             .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
 
-        Ok(())
+        let mut items = Vec::with_capacity(hits.len());
+        for (_, address) in hits {
+            let doc: TantivyDocument = searcher
+                .doc_async(address)
+                .await
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            let text = doc
+                .get_first(SchemaTools::envelope_fields().f_text)
+                .and_then(|v| v.as_str())
+                .unwrap_or_default()
+                .to_string();
+            items.push((Envelope::from_tantivy_doc(&doc).await?, text));
+        }
+        Ok((total as u64, items))
     }
 
-    pub async fn search(
+    /// Starts a scroll session over a snapshot of the index and returns its first batch.
+    pub async fn open_scroll(
         &self,
         filter: SearchFilter,
-        page: u64,
         page_size: u64,
-        desc: bool,
-    ) -> BichonResult<DataPage<Envelope>> {
-        assert!(page > 0, "Page number must be greater than 0");
-        assert!(page_size > 0, "Page size must be greater than 0");
-        let query = self.filter_query(filter, self.query_parser.clone())?;
-        let searcher = self.create_searcher()?;
+        options: SearchOptions,
+        keep_alive: Duration,
+    ) -> BichonResult<ScrollResult> {
+        self.purge_expired_scrolls();
+        if self.scrolls.len() >= MAX_SCROLL_SESSIONS {
+            return Err(raise_error!(
+                "Too many open scroll sessions, close unused ones or retry later.".into(),
+                ErrorCode::TooManyRequest
+            ));
+        }
+        let live = self.live();
+        let highlight_query = self.highlight_query(&filter, &live)?;
+        let query = self.filter_query(filter, &live)?;
+        let searcher = Self::searcher(&live)?;
         let total = searcher
             .search(&query, &Count)
             .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
             as u64;
+        let highlighter = highlight_query
+            .map(|q| Highlighter::new(&searcher, q.as_ref(), options.highlight.as_ref()))
+            .transpose()?;
+        let session = ScrollSession {
+            searcher,
+            query,
+            total,
+            page_size,
+            options,
+            highlighter,
+            cursor: None,
+            keep_alive,
+            expires_at: 0,
+        };
+        self.next_scroll_batch(generate_token!(128), session).await
+    }
 
-        if total == 0 {
-            return Ok(DataPage {
-                current_page: Some(page),
-                page_size: Some(page_size),
-                total_items: 0,
-                items: vec![],
-                total_pages: Some(0),
-            });
+    /// Returns the next batch of a scroll session and extends its keep-alive.
+    pub async fn scroll(&self, scroll_id: &str) -> BichonResult<ScrollResult> {
+        self.purge_expired_scrolls();
+        let (scroll_id, session) = self.scrolls.remove(scroll_id).ok_or_else(|| {
+            raise_error!(
+                format!("Scroll session '{}' not found or expired", scroll_id),
+                ErrorCode::ResourceNotFound
+            )
+        })?;
+        self.next_scroll_batch(scroll_id, session).await
+    }
+
+    /// Closes a scroll session, releasing the index snapshot it pins.
+    pub fn clear_scroll(&self, scroll_id: &str) -> bool {
+        self.scrolls.remove(scroll_id).is_some()
+    }
+
+    async fn next_scroll_batch(
+        &self,
+        scroll_id: String,
+        mut session: ScrollSession,
+    ) -> BichonResult<ScrollResult> {
+        let (items, last) = Self::collect_hits(
+            &session.searcher,
+            session.query.as_ref(),
+            TopDocs::with_limit(session.page_size as usize),
+            &session.options,
+            session.cursor.as_ref(),
+            session.highlighter.as_ref(),
+        )
+        .await?;
+        let has_more = items.len() as u64 == session.page_size && last.is_some();
+        let result = ScrollResult {
+            scroll_id: scroll_id.clone(),
+            total_items: session.total,
+            items,
+            has_more,
+        };
+        if has_more {
+            session.cursor = last;
+            session.expires_at = utc_now!() + session.keep_alive.as_millis() as i64;
+            self.scrolls.insert(scroll_id, session);
         }
-        let offset = (page - 1) * page_size;
-        let total_pages = total.div_ceil(page_size);
-        if offset > total {
-            return Ok(DataPage {
-                current_page: Some(page),
-                page_size: Some(page_size),
-                total_items: total,
-                items: vec![],
-                total_pages: Some(total_pages),
-            });
+        Ok(result)
+    }
+
+    fn purge_expired_scrolls(&self) {
+        let now = utc_now!();
+        self.scrolls.retain(|_, session| session.expires_at > now);
+    }
+
+    /// Parses the full-text part of `filter` on its own, so snippets and matched attachments
+    /// only reflect the terms the user searched for.
+    fn highlight_query(
+        &self,
+        filter: &SearchFilter,
+        live: &LiveIndex,
+    ) -> BichonResult<Option<Box<dyn Query>>> {
+        match &filter.text {
+            Some(text) => Ok(Some(live.query_parser.parse_query(text).map_err(|e| {
+                raise_error!(format!("{:#?}", e), ErrorCode::InvalidParameter)
+            })?)),
+            None => Ok(None),
         }
+    }
 
-        let order = if desc { Order::Desc } else { Order::Asc };
-        let mailbox_docs: Vec<(i64, DocAddress)> = searcher
-            .search(
-                &query,
-                &TopDocs::with_limit(page_size as usize)
-                    .and_offset(offset as usize)
-                    .order_by_fast_field(F_INTERNAL_DATE, order),
-            )
-            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-        let mut result = Vec::new();
+    /// Loads the envelopes of the hits ordered by `options`, starting right after `after`
+    /// when a cursor is given. Also returns the cursor of the last hit.
+    async fn collect_hits(
+        searcher: &Searcher,
+        query: &dyn Query,
+        top_docs: TopDocs,
+        options: &SearchOptions,
+        after: Option<&SearchCursor>,
+        highlighter: Option<&Highlighter>,
+    ) -> BichonResult<(Vec<Envelope>, Option<SearchCursor>)> {
+        // Every sort field except `Score` is read from a fast field, so no stored
+        // document is loaded until the page is known.
+        let scorer = SortKeyScorer::new(options.sort_by, options.desc, after);
+        let hits = if options.sort_by == SortField::Score {
+            searcher.search(query, &top_docs.tweak_score(scorer))
+        } else {
+            searcher.search(query, &top_docs.custom_score(scorer))
+        }
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
 
-        for (_, doc_address) in mailbox_docs {
+        let mut items = Vec::with_capacity(hits.len());
+        let mut last = None;
+        for (key, doc_address) in hits {
+            // hits at or before the cursor rank last and are dropped here
+            let Some(key) = key else {
+                break;
+            };
             let doc: TantivyDocument = searcher
                 .doc_async(doc_address)
                 .await
                 .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-            let envelope = Envelope::from_tantivy_doc(&doc).await?;
-            result.push(envelope);
+            let mut envelope = Envelope::from_tantivy_doc(&doc).await?;
+            if let Some(highlighter) = highlighter {
+                highlighter.apply(&doc, &mut envelope);
+            }
+            items.push(envelope);
+            last = Some(key);
         }
-        Ok(DataPage {
-            current_page: Some(page),
-            page_size: Some(page_size),
-            total_items: total,
-            items: result,
-            total_pages: Some(total_pages),
-        })
+        Ok((
+            items,
+            last.map(|key| SearchCursor::from_key(options.sort_by, key)),
+        ))
     }
 
     pub async fn list_mailbox_envelopes(
@@ -742,10 +2216,7 @@ impl EnvelopeIndexManager {
     }
 
     pub async fn top_10_largest_emails(&self) -> BichonResult<Vec<LargestEmail>> {
-        self.reader
-            .reload()
-            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-        let searcher = self.reader.searcher();
+        let searcher = self.create_searcher()?;
 
         let mailbox_docs: Vec<(u64, DocAddress)> = searcher
             .search(
@@ -767,10 +2238,7 @@ impl EnvelopeIndexManager {
     }
 
     pub async fn get_max_uid(&self, account_id: u64, mailbox_id: u64) -> BichonResult<Option<u64>> {
-        self.reader
-            .reload()
-            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-        let searcher = self.reader.searcher();
+        let searcher = self.create_searcher()?;
 
         let query = self.mailbox_query(account_id, mailbox_id);
         let agg_req: Aggregations = serde_json::from_value(json!({
@@ -899,10 +2367,14 @@ impl EnvelopeIndexManager {
     }
 
     fn create_searcher(&self) -> BichonResult<Searcher> {
-        self.reader
+        Self::searcher(&self.live())
+    }
+
+    fn searcher(live: &LiveIndex) -> BichonResult<Searcher> {
+        live.reader
             .reload()
             .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-        Ok(self.reader.searcher())
+        Ok(live.reader.searcher())
     }
 
     pub async fn get_dashboard_stats(&self) -> BichonResult<DashboardStats> {
@@ -1042,6 +2514,253 @@ impl EnvelopeIndexManager {
     }
 }
 
+/// Reports the attachments whose content matched a text query and, when highlighting is
+/// requested, builds highlighted fragments for the matched documents.
+struct Highlighter {
+    subject: SnippetGenerator,
+    text: SnippetGenerator,
+    /// `None` when the index predates attachment contents.
+    attachment: Option<SnippetGenerator>,
+    /// `None` when only the matched attachments are reported.
+    tags: Option<(String, String)>,
+}
+
+impl Highlighter {
+    fn new(
+        searcher: &Searcher,
+        query: &dyn Query,
+        options: Option<&HighlightOptions>,
+    ) -> BichonResult<Self> {
+        let f = SchemaTools::envelope_fields();
+        let fragment_size = options.cloned().unwrap_or_default().fragment_size();
+        let has_attachment_content =
+            (f.f_attachment_content.field_id() as usize) < searcher.schema().num_fields();
+        let generator = |field: Field| -> BichonResult<SnippetGenerator> {
+            let mut generator = SnippetGenerator::create(searcher, query, field)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            generator.set_max_num_chars(fragment_size);
+            Ok(generator)
+        };
+        Ok(Self {
+            subject: generator(f.f_subject)?,
+            text: generator(f.f_text)?,
+            attachment: has_attachment_content
+                .then(|| generator(f.f_attachment_content))
+                .transpose()?,
+            tags: options.map(|o| (o.pre_tag().to_string(), o.post_tag().to_string())),
+        })
+    }
+
+    fn apply(&self, doc: &TantivyDocument, envelope: &mut Envelope) {
+        let f = SchemaTools::envelope_fields();
+        let names = doc
+            .get_all(f.f_attachment_content_name)
+            .filter_map(|v| v.as_str());
+        let contents = doc
+            .get_all(f.f_attachment_content)
+            .filter_map(|v| v.as_str());
+        let mut matched = Vec::new();
+        let mut attachment = None;
+        for (name, content) in names.zip(contents) {
+            let Some(generator) = &self.attachment else {
+                break;
+            };
+            let snippet = generator.snippet(content);
+            if snippet.is_empty() {
+                continue;
+            }
+            if attachment.is_none() {
+                attachment = self.fragment(snippet);
+            }
+            matched.push(name.to_string());
+        }
+        if !matched.is_empty() {
+            envelope.matched_attachments = Some(matched);
+        }
+        if self.tags.is_some() {
+            envelope.highlights = Some(SearchHighlights {
+                subject: self.fragment(self.subject.snippet_from_doc(doc)),
+                text: self.fragment(self.text.snippet_from_doc(doc)),
+                attachment,
+            });
+        }
+    }
+
+    fn fragment(&self, mut snippet: Snippet) -> Option<String> {
+        let (pre_tag, post_tag) = self.tags.as_ref()?;
+        if snippet.is_empty() {
+            return None;
+        }
+        snippet.set_snippet_prefix_postfix(pre_tag, post_tag);
+        Some(snippet.to_html())
+    }
+}
+
+/// Kinds of documents in the EML store, see `SchemaTools::create_eml_schema`.
+const KIND_REFERENCE: u64 = 1;
+const KIND_BODY: u64 = 2;
+const KIND_ATTACHMENT: u64 = 3;
+
+/// Message as handed to `EmlIndexManager::add_document`.
+struct StoredMessage {
+    eid: u64,
+    account_id: u64,
+    mailbox_id: u64,
+    eml: Vec<u8>,
+}
+
+impl StoredMessage {
+    fn from_document(eid: u64, doc: &TantivyDocument) -> Option<Self> {
+        let fields = SchemaTools::eml_fields();
+        Some(Self {
+            eid,
+            account_id: doc.get_first(fields.f_account_id)?.as_u64()?,
+            mailbox_id: doc.get_first(fields.f_mailbox_id)?.as_u64()?,
+            eml: doc.get_first(fields.f_eml)?.as_bytes()?.to_vec(),
+        })
+    }
+
+    fn content_hash(&self) -> Vec<u8> {
+        digest::digest(&digest::SHA256, &self.eml).as_ref().to_vec()
+    }
+
+    /// Writes the reference to the message, and its body and attachments unless they
+    /// are already stored. Their content is returned, to be written to the blob store
+    /// before the documents referring to it are committed, unless `inline` keeps it in the
+    /// documents like stores did before blob stores.
+    fn operations(
+        self,
+        hash: &[u8],
+        blobs: &mut KnownBlobs,
+        inline: bool,
+    ) -> (Vec<UserOperation>, Vec<PendingBlob>) {
+        let fields = SchemaTools::eml_fields();
+        let mut operations = vec![UserOperation::Add(doc!(
+            fields.f_kind => KIND_REFERENCE,
+            fields.f_id => self.eid,
+            fields.f_account_id => self.account_id,
+            fields.f_mailbox_id => self.mailbox_id,
+            fields.f_content_hash => hash,
+            fields.f_size => self.eml.len() as u64
+        ))];
+        let mut pending = Vec::new();
+        if !blobs.insert(KIND_BODY, hash) {
+            return (operations, pending);
+        }
+        let mut body = doc!(
+            fields.f_kind => KIND_BODY,
+            fields.f_blob_hash => hash
+        );
+        let split = skeleton::split(&self.eml)
+            .and_then(|split| Some((serde_json::to_vec(&split.layout).ok()?, split)));
+        let content = match split {
+            Some((layout, split)) => {
+                body.add_bytes(fields.f_layout, layout.as_slice());
+                for attachment in split.attachments {
+                    let size = attachment.content.len() as u64;
+                    let key = skeleton::attachment_key(&attachment.hash, size);
+                    body.add_bytes(fields.f_attachment_hash, key.as_slice());
+                    if !blobs.insert(KIND_ATTACHMENT, &attachment.hash) {
+                        continue;
+                    }
+                    let mut doc = doc!(
+                        fields.f_kind => KIND_ATTACHMENT,
+                        fields.f_blob_hash => attachment.hash.as_slice(),
+                        fields.f_attachment_name => attachment.name,
+                        fields.f_size => size
+                    );
+                    if inline {
+                        doc.add_bytes(fields.f_eml, attachment.content.as_slice());
+                    } else {
+                        pending.push(PendingBlob {
+                            kind: KIND_ATTACHMENT,
+                            hash: attachment.hash,
+                            content: attachment.content,
+                        });
+                    }
+                    operations.push(UserOperation::Add(doc));
+                }
+                split.skeleton
+            }
+            None => self.eml,
+        };
+        body.add_u64(fields.f_size, content.len() as u64);
+        if inline {
+            body.add_bytes(fields.f_eml, content.as_slice());
+        } else {
+            pending.push(PendingBlob {
+                kind: KIND_BODY,
+                hash: hash.to_vec(),
+                content,
+            });
+        }
+        operations.push(UserOperation::Add(body));
+        (operations, pending)
+    }
+}
+
+/// Body or attachment to write to the blob store.
+struct PendingBlob {
+    kind: u64,
+    hash: Vec<u8>,
+    content: Vec<u8>,
+}
+
+impl PendingBlob {
+    fn key(&self) -> String {
+        blob_key(blob_namespace(self.kind), &self.hash)
+    }
+}
+
+/// Namespace of the blob store keys of a kind of document.
+fn blob_namespace(kind: u64) -> &'static str {
+    match kind {
+        KIND_ATTACHMENT => "attachments",
+        _ => "messages",
+    }
+}
+
+/// Bodies and attachments that are stored, or about to be.
+struct KnownBlobs<'a> {
+    /// Store to look them up in. Without one, every blob not written yet is considered
+    /// missing.
+    searcher: Option<&'a Searcher>,
+    added: HashSet<(u64, Vec<u8>)>,
+}
+
+impl<'a> KnownBlobs<'a> {
+    fn new(searcher: Option<&'a Searcher>) -> Self {
+        Self {
+            searcher,
+            added: HashSet::new(),
+        }
+    }
+
+    /// Returns `true` if the blob still has to be written.
+    fn insert(&mut self, kind: u64, hash: &[u8]) -> bool {
+        if !self.added.insert((kind, hash.to_vec())) {
+            return false;
+        }
+        !self.searcher.is_some_and(|searcher| {
+            searcher
+                .search(&blob_query(kind, hash), &Count)
+                .is_ok_and(|count| count > 0)
+        })
+    }
+}
+
+/// Size of the EML store, see `SchemaTools::create_eml_schema`.
+#[derive(Clone, Debug, Default)]
+pub struct EmlStorageStats {
+    /// Number of stored messages.
+    pub messages: u64,
+    /// Bytes of the distinct bodies and attachments actually stored.
+    pub stored_bytes: u64,
+    /// Bytes of the messages whose body is stored once for another message. Attachments
+    /// shared by distinct bodies are counted by `deduplicated_attachments`.
+    pub shared_body_bytes: u64,
+}
+
 pub struct EmlIndexManager {
     index_writer: Arc<Mutex<IndexWriter>>,
     sender: mpsc::Sender<WriteMessage>,
@@ -1051,16 +2770,10 @@ pub struct EmlIndexManager {
 impl EmlIndexManager {
     pub fn new() -> Self {
         let index = Self::open_or_create_index(&DATA_DIR_MANAGER.eml_dir);
-        let index_writer = Arc::new(Mutex::new(
-            index
-                .writer_with_num_threads(8, 536_870_912)
-                .unwrap_or_else(|e| {
-                    panic!(
-                        "Failed to create IndexWriter with 8 threads and 512MB buffer for {:?}: {}",
-                        DATA_DIR_MANAGER.eml_dir, e
-                    )
-                }),
-        ));
+        let index_writer = Arc::new(Mutex::new(Self::create_writer(
+            &index,
+            &DATA_DIR_MANAGER.eml_dir,
+        )));
         let reader = index.reader().unwrap_or_else(|e| {
             panic!(
                 "Failed to create IndexReader for {:?}: {}",
@@ -1082,6 +2795,10 @@ impl EmlIndexManager {
                                     EML_INDEX_MANAGER.drain_and_commit(&mut buffer).await;
                                 }
                             }
+                            Some(WriteMessage::Flush(done)) => {
+                                EML_INDEX_MANAGER.drain_and_commit(&mut buffer).await;
+                                let _ = done.send(());
+                            }
                             Some(WriteMessage::Shutdown) => {
                                 EML_INDEX_MANAGER.drain_and_commit(&mut buffer).await;
                                 break;
@@ -1107,29 +2824,254 @@ impl EmlIndexManager {
         }
     }
 
+    /// Queues a message, given as a document with its `f_id`, `f_account_id`,
+    /// `f_mailbox_id` and raw `f_eml`. Its body and large attachments are only stored if
+    /// not already there.
     pub async fn add_document(&self, eid: u64, doc: TantivyDocument) {
         let _ = self.sender.send(WriteMessage::Document((eid, doc))).await;
     }
 
+    /// Waits until the messages queued so far are committed and readable, or left queued
+    /// after a failure.
+    pub async fn flush(&self) {
+        let (done, committed) = oneshot::channel();
+        if self.sender.send(WriteMessage::Flush(done)).await.is_ok() && committed.await.is_ok()
+        {
+            if let Err(e) = self.reader.reload() {
+                tracing::warn!("Failed to reload the EML store: {:#?}", e);
+            }
+        }
+    }
+
     fn open_or_create_index(index_dir: &PathBuf) -> Index {
+        Self::prepare_index_dir(index_dir);
         if !index_dir.exists() {
-            std::fs::create_dir_all(&index_dir).unwrap_or_else(|e| {
-                panic!("Failed to create index directory {:?}: {}", index_dir, e)
-            });
-            IndexBuilder::new()
-                .schema(SchemaTools::eml_schema())
-                .settings(IndexSettings {
-                    docstore_compression: Compressor::Zstd(ZstdCompressor {
-                        compression_level: Some(6),
-                    }),
-                    docstore_compress_dedicated_thread: true,
-                    docstore_blocksize: 2_097_152,
-                })
-                .create_in_dir(&index_dir)
-                .unwrap_or_else(|e| panic!("Failed to create index in {:?}: {}", index_dir, e))
-        } else {
-            open(&index_dir)
+            return Self::create_index(index_dir);
+        }
+        let index = open(index_dir);
+        if is_legacy_store(&index) {
+            panic!(
+                "The EML store {:?} uses an earlier layout, convert it with --bichon-convert-eml-store",
+                index_dir
+            );
+        }
+        index
+    }
+
+    /// Fails when the EML store was created with an earlier layout, which the server
+    /// cannot read until it is converted with `bichon_convert_eml_store`.
+    pub fn check_layout() -> BichonResult<()> {
+        let index_dir = &DATA_DIR_MANAGER.eml_dir;
+        Self::prepare_index_dir(index_dir);
+        if index_dir.exists() && is_legacy_store(&open(index_dir)) {
+            return Err(raise_error!(
+                format!(
+                    "The EML store {:?} uses an earlier layout. Stop the server and run it once with --bichon-convert-eml-store to convert it.",
+                    index_dir
+                ),
+                ErrorCode::InternalError
+            ));
+        }
+        Ok(())
+    }
+
+    fn create_index(index_dir: &PathBuf) -> Index {
+        std::fs::create_dir_all(index_dir)
+            .unwrap_or_else(|e| panic!("Failed to create index directory {:?}: {}", index_dir, e));
+        IndexBuilder::new()
+            .schema(SchemaTools::eml_schema())
+            .settings(IndexSettings {
+                docstore_compression: Compressor::Zstd(ZstdCompressor {
+                    compression_level: Some(6),
+                }),
+                docstore_compress_dedicated_thread: true,
+                docstore_blocksize: 2_097_152,
+            })
+            .create_in_dir(index_dir)
+            .unwrap_or_else(|e| panic!("Failed to create index in {:?}: {}", index_dir, e))
+    }
+
+    fn create_writer(index: &Index, index_dir: &PathBuf) -> IndexWriter {
+        index
+            .writer_with_num_threads(8, 536_870_912)
+            .unwrap_or_else(|e| {
+                panic!(
+                    "Failed to create IndexWriter with 8 threads and 512MB buffer for {:?}: {}",
+                    index_dir, e
+                )
+            })
+    }
+
+    /// Completes a conversion interrupted while the stores were swapped, and removes the
+    /// leftovers of one interrupted before. The converted store is only moved into place
+    /// once complete, so it is complete whenever the live directory is missing.
+    fn prepare_index_dir(index_dir: &PathBuf) {
+        let rebuild_dir = index_dir.with_extension(REBUILD_DIR_EXTENSION);
+        let old_dir = index_dir.with_extension(OLD_DIR_EXTENSION);
+        if rebuild_dir.exists() {
+            if index_dir.exists() {
+                tracing::warn!("Removing incomplete EML store conversion {:?}", rebuild_dir);
+                let _ = std::fs::remove_dir_all(&rebuild_dir);
+            } else {
+                std::fs::rename(&rebuild_dir, index_dir).unwrap_or_else(|e| {
+                    panic!(
+                        "Failed to move converted EML store {:?}: {}",
+                        rebuild_dir, e
+                    )
+                });
+            }
+        }
+        if old_dir.exists() {
+            let _ = std::fs::remove_dir_all(&old_dir);
+        }
+    }
+
+    /// Converts a store created with an earlier layout, such as one copy of the message
+    /// per envelope, into a new store next to it. Once every message is found in the new
+    /// store, it replaces the previous one, which is removed. Meant to run while the server
+    /// is stopped.
+    pub fn convert_legacy_store() -> BichonResult<()> {
+        let index_dir = &DATA_DIR_MANAGER.eml_dir;
+        Self::prepare_index_dir(index_dir);
+        if !index_dir.exists() {
+            tracing::info!("There is no EML store to convert in {:?}", index_dir);
+            return Ok(());
+        }
+        let legacy = open(index_dir);
+        if !is_legacy_store(&legacy) {
+            tracing::info!("The EML store {:?} is already converted", index_dir);
+            return Ok(());
+        }
+        tracing::info!(
+            "Converting the EML store {:?} to deduplicated messages and attachments",
+            index_dir
+        );
+        let rebuild_dir = index_dir.with_extension(REBUILD_DIR_EXTENSION);
+        let index = Self::create_index(&rebuild_dir);
+        let mut writer = Self::create_writer(&index, &rebuild_dir);
+        let reader = legacy
+            .reader()
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        let searcher = reader.searcher();
+        let mut blobs = KnownBlobs::new(None);
+        let mut messages = 0u64;
+        for (segment_ord, segment_reader) in searcher.segment_readers().iter().enumerate() {
+            let Some(ids) = segment_reader
+                .fast_fields()
+                .column_opt::<u64>(F_ID)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+            else {
+                continue;
+            };
+            for doc_id in segment_reader.doc_ids_alive() {
+                let Some(eid) = ids.first(doc_id) else {
+                    continue;
+                };
+                let doc: TantivyDocument = searcher
+                    .doc(DocAddress::new(segment_ord as u32, doc_id))
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                let Some(message) = Self::legacy_message(&searcher, eid, &doc) else {
+                    tracing::warn!("Skipping incomplete stored message {}", eid);
+                    continue;
+                };
+                let hash = message.content_hash();
+                writer
+                    .run(message.operations(&hash, &mut blobs, true).0)
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                messages += 1;
+            }
+        }
+        writer
+            .commit()
+            .and_then(|_| writer.wait_merging_threads())
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        drop(searcher);
+        drop(reader);
+        drop(legacy);
+
+        let converted = Self::count_messages(&index)?;
+        drop(index);
+        if converted != messages {
+            return Err(raise_error!(
+                format!(
+                    "The converted EML store {:?} holds {} messages instead of {}, the EML store {:?} is left unchanged.",
+                    rebuild_dir, converted, messages, index_dir
+                ),
+                ErrorCode::InternalError
+            ));
+        }
+        tracing::warn!(
+            "Replacing the EML store {:?} with the converted one and removing the previous store",
+            index_dir
+        );
+        let old_dir = index_dir.with_extension(OLD_DIR_EXTENSION);
+        std::fs::rename(index_dir, &old_dir)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        // the converted store is complete, a crash from here on is recovered at startup
+        std::fs::rename(&rebuild_dir, index_dir)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        if let Err(e) = std::fs::remove_dir_all(&old_dir) {
+            tracing::warn!("Failed to remove the previous EML store: {:#?}", e);
+        }
+        tracing::info!(
+            "Converted {} stored messages into {} distinct bodies and attachments",
+            messages,
+            blobs.added.len()
+        );
+        Ok(())
+    }
+
+    /// Number of messages referenced in a store, segments holding only message bodies have
+    /// no ids.
+    fn count_messages(index: &Index) -> BichonResult<u64> {
+        let reader = index
+            .reader()
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        let mut messages = 0;
+        for segment_reader in reader.searcher().segment_readers() {
+            let Some(ids) = segment_reader
+                .fast_fields()
+                .column_opt::<u64>(F_ID)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+            else {
+                continue;
+            };
+            messages += segment_reader
+                .doc_ids_alive()
+                .filter(|doc_id| ids.first(*doc_id).is_some())
+                .count() as u64;
+        }
+        Ok(messages)
+    }
+
+    /// Reads a message from a store being converted. The first stores kept the raw
+    /// message in the reference itself, later ones in a separate body document.
+    fn legacy_message(
+        searcher: &Searcher,
+        eid: u64,
+        doc: &TantivyDocument,
+    ) -> Option<StoredMessage> {
+        if let Some(message) = StoredMessage::from_document(eid, doc) {
+            return Some(message);
         }
+        let fields = SchemaTools::eml_fields();
+        let hash = doc.get_first(fields.f_content_hash)?.as_bytes()?;
+        let query = TermQuery::new(
+            Term::from_field_bytes(fields.f_blob_hash, hash),
+            IndexRecordOption::Basic,
+        );
+        let (_, address) = searcher
+            .search(&query, &TopDocs::with_limit(1))
+            .ok()?
+            .into_iter()
+            .next()?;
+        let body: TantivyDocument = searcher.doc(address).ok()?;
+        Some(StoredMessage {
+            eid,
+            account_id: doc.get_first(fields.f_account_id)?.as_u64()?,
+            mailbox_id: doc.get_first(fields.f_mailbox_id)?.as_u64()?,
+            eml: body.get_first(fields.f_eml)?.as_bytes()?.to_vec(),
+        })
     }
 
     fn envelope_query(&self, account_id: u64, eid: u64) -> Box<dyn Query> {
@@ -1148,6 +3090,43 @@ impl EmlIndexManager {
         Box::new(boolean_query)
     }
 
+    fn references_query(hash: &[u8]) -> TermQuery {
+        TermQuery::new(
+            Term::from_field_bytes(SchemaTools::eml_fields().f_content_hash, hash),
+            IndexRecordOption::Basic,
+        )
+    }
+
+    /// Snapshot of the EML store, to walk through every stored message.
+    pub fn searcher(&self) -> Searcher {
+        self.reader.searcher()
+    }
+
+    /// Blocks writes to the EML store, including the blobs they add or remove, and opens
+    /// the files of its last commit, to back it up. Writes resume once the pause is
+    /// dropped; the files stay readable.
+    pub async fn pause_writes(&self) -> BichonResult<(WritePause, IndexSnapshot)> {
+        let guard = self.index_writer.clone().lock_owned().await;
+        let searcher = self.reader.searcher();
+        let snapshot =
+            IndexSnapshot::capture(searcher.index(), &DATA_DIR_MANAGER.eml_dir, &[])?;
+        Ok((WritePause::new(guard), snapshot))
+    }
+
+    /// Searcher seeing every commit made so far, for decisions taken under the writer
+    /// lock.
+    fn fresh_searcher(&self) -> BichonResult<Searcher> {
+        self.reader
+            .reload()
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        Ok(self.reader.searcher())
+    }
+
+    /// Whether a message with envelope id `eid` has been committed to the EML store.
+    pub fn contains(&self, eid: u64) -> BichonResult<bool> {
+        is_stored(&self.fresh_searcher()?, eid)
+    }
+
     pub async fn get(&self, account_id: u64, eid: u64) -> BichonResult<Option<Vec<u8>>> {
         let searcher = self.reader.searcher();
         let query = self.envelope_query(account_id, eid);
@@ -1165,44 +3144,127 @@ impl EmlIndexManager {
             .await
             .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
         let fields = SchemaTools::eml_fields();
-        let value = doc.get_first(fields.f_eml).ok_or_else(|| {
-            raise_error!(
-                format!("miss '{}' field in tantivy document", stringify!(field)),
-                ErrorCode::InternalError
-            )
-        })?;
-        let bytes = value.as_bytes().ok_or_else(|| {
+        let hash = bytes_value(&doc, fields.f_content_hash, F_CONTENT_HASH)?;
+        let body = self
+            .blob(&searcher, KIND_BODY, hash)
+            .await?
+            .ok_or_else(|| {
+                raise_error!(
+                    format!(
+                        "Stored message body missing: account_id={}, eid={}",
+                        account_id, eid
+                    ),
+                    ErrorCode::InternalError
+                )
+            })?;
+        let skeleton = self.blob_content(&body, KIND_BODY, hash).await?;
+        let Some(layout) = body.get_first(fields.f_layout).and_then(|v| v.as_bytes()) else {
+            return Ok(Some(skeleton));
+        };
+
+        // large attachments were stored apart and go back where they were taken from
+        let layout: Vec<SplitPart> = serde_json::from_slice(layout)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        let mut attachments = Vec::with_capacity(layout.len());
+        for key in body
+            .get_all(fields.f_attachment_hash)
+            .filter_map(|v| v.as_bytes())
+        {
+            let attachment = match skeleton::parse_attachment_key(key) {
+                Some((hash, _)) => self
+                    .blob(&searcher, KIND_ATTACHMENT, hash)
+                    .await?
+                    .map(|doc| (hash, doc)),
+                None => None,
+            };
+            let (hash, attachment) = attachment.ok_or_else(|| {
+                raise_error!(
+                    format!(
+                        "Stored attachment missing: account_id={}, eid={}",
+                        account_id, eid
+                    ),
+                    ErrorCode::InternalError
+                )
+            })?;
+            attachments.push(
+                self.blob_content(&attachment, KIND_ATTACHMENT, hash)
+                    .await?,
+            );
+        }
+        let contents: Vec<&[u8]> = attachments.iter().map(Vec::as_slice).collect();
+        skeleton::reassemble(&skeleton, &layout, &contents).map(Some)
+    }
+
+    /// Content of a body or attachment document, kept in the document itself by stores
+    /// written before blob stores, in the blob store otherwise.
+    async fn blob_content(
+        &self,
+        doc: &TantivyDocument,
+        kind: u64,
+        hash: &[u8],
+    ) -> BichonResult<Vec<u8>> {
+        if let Some(content) = doc
+            .get_first(SchemaTools::eml_fields().f_eml)
+            .and_then(|v| v.as_bytes())
+        {
+            return Ok(content.to_vec());
+        }
+        let key = blob_key(blob_namespace(kind), hash);
+        BLOB_STORE.get(&key).await?.ok_or_else(|| {
             raise_error!(
-                format!("'{}' field is not a bytes", stringify!(field)),
+                format!("Blob '{}' missing from the blob store", key),
                 ErrorCode::InternalError
             )
-        })?;
+        })
+    }
+
+    /// Body or attachment stored under `hash`.
+    async fn blob(
+        &self,
+        searcher: &Searcher,
+        kind: u64,
+        hash: &[u8],
+    ) -> BichonResult<Option<TantivyDocument>> {
+        let docs = searcher
+            .search(&blob_query(kind, hash), &TopDocs::with_limit(1))
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        let Some((_, address)) = docs.first() else {
+            return Ok(None);
+        };
+        let doc = searcher
+            .doc_async(*address)
+            .await
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        Ok(Some(doc))
+    }
 
-        Ok(Some(bytes.to_vec()))
+    /// Raw message, decrypted with `key` if its account encrypts messages at rest.
+    pub async fn get_plain(
+        &self,
+        account_id: u64,
+        eid: u64,
+        key: Option<&PgpSecretKey>,
+    ) -> BichonResult<Option<Vec<u8>>> {
+        self.get(account_id, eid)
+            .await?
+            .map(|eml| pgp::open_eml(eml, key))
+            .transpose()
     }
 
-    pub async fn get_reader(&self, account_id: u64, eid: u64) -> BichonResult<File> {
-        let data = self.get(account_id, eid).await?.ok_or_else(|| {
+    /// Raw message to download, decrypted with `key`. Kept in memory, never written to
+    /// disk, so the plaintext of an encrypted message does not outlive the request.
+    pub async fn get_raw(
+        &self,
+        account_id: u64,
+        eid: u64,
+        key: Option<&PgpSecretKey>,
+    ) -> BichonResult<Vec<u8>> {
+        self.get_plain(account_id, eid, key).await?.ok_or_else(|| {
             raise_error!(
                 format!("Email not found: account_id={}, eid={}", account_id, eid),
                 ErrorCode::ResourceNotFound
             )
-        })?;
-        let mut path = DATA_DIR_MANAGER.temp_dir.clone();
-
-        path.push(format!("{eid}.eml"));
-        {
-            let mut file = File::create(&path)
-                .await
-                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-            file.write_all(&data)
-                .await
-                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-        }
-        let file = File::open(&path)
-            .await
-            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-        Ok(file)
+        })
     }
 
     pub async fn get_attachment(
@@ -1210,8 +3272,9 @@ impl EmlIndexManager {
         account_id: u64,
         eid: u64,
         file_name: &str,
-    ) -> BichonResult<File> {
-        let data = self.get(account_id, eid).await?.ok_or_else(|| {
+        key: Option<&PgpSecretKey>,
+    ) -> BichonResult<Vec<u8>> {
+        let data = self.get_plain(account_id, eid, key).await?.ok_or_else(|| {
             raise_error!(
                 format!("Email not found: account_id={}, eid={}", account_id, eid),
                 ErrorCode::ResourceNotFound
@@ -1238,20 +3301,7 @@ impl EmlIndexManager {
                 ))
             }
         };
-        let mut path = DATA_DIR_MANAGER.temp_dir.clone();
-        path.push(format!("{eid}.{file_name}.eml"));
-        {
-            let mut file = File::create(&path)
-                .await
-                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-            file.write_all(content)
-                .await
-                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-        }
-        let file = File::open(&path)
-            .await
-            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-        Ok(file)
+        Ok(content.to_vec())
     }
 
     fn account_query(&self, account_id: u64) -> Box<TermQuery> {
@@ -1260,15 +3310,8 @@ impl EmlIndexManager {
     }
 
     pub async fn delete_account_envelopes(&self, account_id: u64) -> BichonResult<()> {
-        let query = self.account_query(account_id);
-        let mut writer = self.index_writer.lock().await;
-        writer
-            .delete_query(query)
-            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-        writer
-            .commit()
-            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-        Ok(())
+        self.delete_messages(vec![self.account_query(account_id)])
+            .await
     }
 
     fn mailbox_query(&self, account_id: u64, mailbox_id: u64) -> Box<dyn Query> {
@@ -1300,7 +3343,41 @@ impl EmlIndexManager {
         for mailbox_id in mailbox_ids {
             queries.push(self.mailbox_query(account_id, mailbox_id));
         }
+        self.delete_messages(queries).await
+    }
+
+    pub async fn delete_email_multi_account(
+        &self,
+        deletes: &HashMap<u64, Vec<u64>>, // HashMap<account_id, envelope_ids>
+    ) -> BichonResult<()> {
+        if deletes.is_empty() {
+            tracing::warn!("delete_envelopes_multi_account: deletes is empty, nothing to delete");
+            return Ok(());
+        }
+
+        let mut queries: Vec<Box<dyn Query>> = Vec::new();
+        for (account_id, envelope_ids) in deletes {
+            let unique_ids: HashSet<u64> = envelope_ids.iter().copied().collect();
+            for eid in unique_ids {
+                queries.push(self.envelope_query(*account_id, eid));
+            }
+        }
+        self.delete_messages(queries).await
+    }
+
+    /// Deletes the messages matching `queries`, then the bodies no other message refers
+    /// to.
+    async fn delete_messages(&self, queries: Vec<Box<dyn Query>>) -> BichonResult<()> {
         let mut writer = self.index_writer.lock().await;
+        let searcher = self.fresh_searcher()?;
+        let mut hashes = HashSet::new();
+        for query in &queries {
+            hashes.extend(stored_keys(
+                &searcher,
+                query.as_ref(),
+                SchemaTools::eml_fields().f_content_hash,
+            )?);
+        }
         for query in queries {
             writer
                 .delete_query(query)
@@ -1309,37 +3386,320 @@ impl EmlIndexManager {
         writer
             .commit()
             .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
-        Ok(())
+        self.remove_unreferenced(&mut writer, hashes).await
     }
 
-    pub async fn delete_email_multi_account(
+    /// Removes the bodies among `hashes` that no message refers to anymore, then the
+    /// attachments no remaining body refers to. Must run under the writer lock, once the
+    /// deleted references are committed, so that no new reference to them can be added
+    /// meanwhile. Their content is removed from the blob store once the index no longer
+    /// refers to it.
+    async fn remove_unreferenced(
         &self,
-        deletes: &HashMap<u64, Vec<u64>>, // HashMap<account_id, envelope_ids>
+        writer: &mut IndexWriter,
+        hashes: HashSet<Vec<u8>>,
     ) -> BichonResult<()> {
-        if deletes.is_empty() {
-            tracing::warn!("delete_envelopes_multi_account: deletes is empty, nothing to delete");
+        if hashes.is_empty() {
+            return Ok(());
+        }
+        let fields = SchemaTools::eml_fields();
+        let searcher = self.fresh_searcher()?;
+        let mut attachments = HashSet::new();
+        let mut removed = Vec::new();
+        for hash in hashes {
+            let references = searcher
+                .search(&Self::references_query(&hash), &Count)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            if references == 0 {
+                let body = blob_query(KIND_BODY, &hash);
+                attachments.extend(stored_keys(&searcher, &body, fields.f_attachment_hash)?);
+                writer
+                    .delete_query(Box::new(body))
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                removed.push(blob_key(blob_namespace(KIND_BODY), &hash));
+            }
+        }
+        if removed.is_empty() {
+            return Ok(());
+        }
+        writer
+            .commit()
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        delete_blobs(&removed).await;
+        if attachments.is_empty() {
             return Ok(());
         }
 
-        let mut writer = self.index_writer.lock().await;
+        let searcher = self.fresh_searcher()?;
+        let mut removed = Vec::new();
+        for key in attachments {
+            let query = TermQuery::new(
+                Term::from_field_bytes(fields.f_attachment_hash, &key),
+                IndexRecordOption::Basic,
+            );
+            let references = searcher
+                .search(&query, &Count)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            if let (0, Some((hash, _))) = (references, skeleton::parse_attachment_key(&key)) {
+                writer
+                    .delete_query(Box::new(blob_query(KIND_ATTACHMENT, hash)))
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                removed.push(blob_key(blob_namespace(KIND_ATTACHMENT), hash));
+            }
+        }
+        if !removed.is_empty() {
+            writer
+                .commit()
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            delete_blobs(&removed).await;
+        }
+        Ok(())
+    }
 
-        for (account_id, envelope_ids) in deletes {
-            let unique_ids: HashSet<u64> = envelope_ids.iter().copied().collect();
-            if unique_ids.is_empty() {
+    /// Copies every stored body and attachment to `target`, reading it from the
+    /// document holding it inline or else from `source`. Without `source`, the blobs not
+    /// held inline are already in `target`. `source` is left untouched, and may be
+    /// `target` itself to write every blob again. The inline copies are dropped only when
+    /// `target` is the store blobs are read from, without `source` or when it is `source`
+    /// itself. `progress` is given the number of blobs processed and known so far.
+    pub async fn migrate_blobs(
+        &self,
+        source: Option<&dyn BlobStore>,
+        target: &dyn BlobStore,
+        progress: impl Fn(u64, u64),
+    ) -> BichonResult<u64> {
+        const COMMIT_BATCH: usize = 500;
+        let fields = SchemaTools::eml_fields();
+        // a commit in flight may have sealed its blobs with a data key retired once they
+        // are all written again, they are only listed once it completes
+        let searcher = {
+            let _writer = self.index_writer.lock().await;
+            self.fresh_searcher()?
+        };
+        let references = TermQuery::new(
+            Term::from_field_u64(fields.f_kind, KIND_REFERENCE),
+            IndexRecordOption::Basic,
+        );
+        let mut pending: Vec<(u64, Vec<u8>)> =
+            stored_keys(&searcher, &references, fields.f_content_hash)?
+                .into_iter()
+                .map(|hash| (KIND_BODY, hash))
+                .collect();
+        // left inline until the server reads `target`, the only other copy
+        let keep_inline = source.is_some_and(|source| !std::ptr::addr_eq(source, target));
+        let mut attachments = HashSet::new();
+        let mut processed = 0;
+        let mut migrated = 0;
+        let mut rewritten = 0;
+        while let Some((kind, hash)) = pending.pop() {
+            processed += 1;
+            progress(processed, processed + pending.len() as u64);
+            let Some(doc) = self.blob(&searcher, kind, &hash).await? else {
+                tracing::warn!(
+                    "Skipping missing {} {}",
+                    blob_namespace(kind),
+                    hex::encode(&hash)
+                );
                 continue;
+            };
+            if kind == KIND_BODY {
+                for key in doc
+                    .get_all(fields.f_attachment_hash)
+                    .filter_map(|v| v.as_bytes())
+                {
+                    if let Some((hash, _)) = skeleton::parse_attachment_key(key) {
+                        if attachments.insert(hash.to_vec()) {
+                            pending.push((KIND_ATTACHMENT, hash.to_vec()));
+                        }
+                    }
+                }
             }
-            for eid in unique_ids {
-                let query = self.envelope_query(*account_id, eid);
+
+            let key = blob_key(blob_namespace(kind), &hash);
+            let inline = doc.get_first(fields.f_eml).and_then(|v| v.as_bytes());
+            let content = match (inline, source) {
+                (Some(content), _) => content.to_vec(),
+                (None, Some(source)) => source.get(&key).await?.ok_or_else(|| {
+                    raise_error!(
+                        format!("Blob '{}' missing from the source blob store", key),
+                        ErrorCode::InternalError
+                    )
+                })?,
+                (None, None) => continue,
+            };
+            target.put(&key, &content).await?;
+            migrated += 1;
+            if inline.is_none() || keep_inline {
+                continue;
+            }
+
+            // the same document, without the content now held by the blob store
+            let mut stored = doc!(
+                fields.f_kind => kind,
+                fields.f_blob_hash => hash.as_slice(),
+                fields.f_size => content.len() as u64
+            );
+            for (field, value) in doc.field_values() {
+                if field != fields.f_eml {
+                    stored.add_field_value(field, value);
+                }
+            }
+            // the server may be running, a document removed meanwhile stays removed
+            let mut writer = self.index_writer.lock().await;
+            let query = blob_query(kind, &hash);
+            let current = self
+                .fresh_searcher()?
+                .search(&query, &Count)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            if current == 0 {
+                continue;
+            }
+            writer
+                .delete_query(Box::new(query))
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            writer
+                .add_document(stored)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            rewritten += 1;
+            if rewritten % COMMIT_BATCH == 0 {
                 writer
-                    .delete_query(query)
+                    .commit()
                     .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                tracing::info!("Migrated {} blobs so far", migrated);
             }
         }
-        writer
+        self.index_writer
+            .lock()
+            .await
             .commit()
             .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        Ok(migrated)
+    }
 
-        Ok(())
+    pub fn storage_stats(&self) -> BichonResult<EmlStorageStats> {
+        let searcher = self.reader.searcher();
+        let mut stats = EmlStorageStats::default();
+        // references to each body, with the size of the message, read from the terms
+        let mut references: HashMap<Vec<u8>, (u64, u64)> = HashMap::new();
+        for segment_reader in searcher.segment_readers() {
+            let fast_fields = segment_reader.fast_fields();
+            let kinds = fast_fields
+                .column_opt::<u64>(F_KIND)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            let sizes = fast_fields
+                .column_opt::<u64>(F_SIZE)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            let (Some(kinds), Some(sizes)) = (kinds, sizes) else {
+                continue;
+            };
+            for doc_id in segment_reader.doc_ids_alive() {
+                if kinds.first(doc_id) == Some(KIND_REFERENCE) {
+                    stats.messages += 1;
+                } else {
+                    stats.stored_bytes += sizes.first(doc_id).unwrap_or(0);
+                }
+            }
+            // only references hold a content hash
+            let inverted_index = segment_reader
+                .inverted_index(SchemaTools::eml_fields().f_content_hash)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            let mut terms = inverted_index
+                .terms()
+                .stream()
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            while terms.advance() {
+                let mut postings = inverted_index
+                    .read_postings_from_terminfo(terms.value(), IndexRecordOption::Basic)
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                let (mut alive, mut size) = (0, 0);
+                let mut doc = postings.doc();
+                while doc != TERMINATED {
+                    if !segment_reader.is_deleted(doc) {
+                        alive += 1;
+                        size = sizes.first(doc).unwrap_or(0);
+                    }
+                    doc = postings.advance();
+                }
+                if alive > 0 {
+                    let entry = references.entry(terms.key().to_vec()).or_default();
+                    entry.0 += alive;
+                    entry.1 = size;
+                }
+            }
+        }
+        stats.shared_body_bytes = references
+            .into_values()
+            .map(|(copies, size)| size * (copies - 1))
+            .sum();
+        Ok(stats)
+    }
+
+    /// Attachments stored once for several messages, with the bytes this saves, the
+    /// `limit` largest savings first.
+    pub async fn deduplicated_attachments(
+        &self,
+        limit: usize,
+    ) -> BichonResult<(u64, Vec<DeduplicatedAttachment>)> {
+        let searcher = self.reader.searcher();
+        let field = SchemaTools::eml_fields().f_attachment_hash;
+        // bodies referring to each attachment, read from the terms alone
+        let mut copies: HashMap<Vec<u8>, u64> = HashMap::new();
+        for segment_reader in searcher.segment_readers() {
+            let inverted_index = segment_reader
+                .inverted_index(field)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            let mut terms = inverted_index
+                .terms()
+                .stream()
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            while terms.advance() {
+                let mut postings = inverted_index
+                    .read_postings_from_terminfo(terms.value(), IndexRecordOption::Basic)
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                let mut alive = 0;
+                let mut doc = postings.doc();
+                while doc != TERMINATED {
+                    if !segment_reader.is_deleted(doc) {
+                        alive += 1;
+                    }
+                    doc = postings.advance();
+                }
+                if alive > 0 {
+                    *copies.entry(terms.key().to_vec()).or_default() += alive;
+                }
+            }
+        }
+
+        let mut savings: Vec<(Vec<u8>, u64, u64, u64)> = copies
+            .into_iter()
+            .filter(|(_, copies)| *copies > 1)
+            .filter_map(|(key, copies)| {
+                let (hash, size) = skeleton::parse_attachment_key(&key)?;
+                Some((hash.to_vec(), size, copies, size * (copies - 1)))
+            })
+            .collect();
+        let total = savings.iter().map(|(.., saved)| saved).sum();
+        savings.sort_by_key(|(.., saved)| std::cmp::Reverse(*saved));
+
+        let mut attachments = Vec::with_capacity(limit.min(savings.len()));
+        for (hash, size_bytes, copies, saved_bytes) in savings.into_iter().take(limit) {
+            let name = self
+                .blob(&searcher, KIND_ATTACHMENT, &hash)
+                .await?
+                .and_then(|doc| {
+                    doc.get_first(SchemaTools::eml_fields().f_attachment_name)
+                        .and_then(|v| v.as_str())
+                        .map(String::from)
+                })
+                .unwrap_or_default();
+            attachments.push(DeduplicatedAttachment {
+                name,
+                size_bytes,
+                copies,
+                saved_bytes,
+            });
+        }
+        Ok((total, attachments))
     }
 
     async fn drain_and_commit(&self, buffer: &mut HashMap<u64, TantivyDocument>) {
@@ -1347,12 +3707,85 @@ impl EmlIndexManager {
             return;
         }
         let mut writer = self.index_writer.lock().await;
+        // without an up to date view, blobs are added again rather than risk referring
+        // to one that was just removed
+        let searcher = self
+            .fresh_searcher()
+            .inspect_err(|e| tracing::warn!("Failed to reload the EML store: {:#?}", e))
+            .ok();
+        let mut blobs = KnownBlobs::new(searcher.as_ref());
         let mut operations = Vec::new();
+        let mut replaced = HashSet::new();
+        let mut failed = Vec::new();
+        let mut recorded = Vec::new();
+        let mut batch = Vec::new();
 
         for (eid, doc) in buffer.drain() {
+            let Some(message) = StoredMessage::from_document(eid, &doc) else {
+                tracing::warn!("Skipping incomplete message document {}", eid);
+                continue;
+            };
+            if SETTINGS.bichon_worm_mode {
+                // a stored message is never replaced in WORM mode
+                match &searcher {
+                    Some(searcher) => match is_stored(searcher, eid) {
+                        Ok(true) => continue,
+                        Ok(false) => {}
+                        Err(e) => {
+                            tracing::warn!("Failed to look up message {}: {:#?}", eid, e);
+                            failed.push((eid, doc));
+                            continue;
+                        }
+                    },
+                    None => {
+                        failed.push((eid, doc));
+                        continue;
+                    }
+                }
+            }
+            let account_id = message.account_id;
+            let hash = message.content_hash();
+            let (message_operations, pending) = message.operations(&hash, &mut blobs, false);
+            // content goes to the blob store first, so that no committed document refers
+            // to a missing blob
+            if let Err(e) = put_blobs(&pending).await {
+                tracing::warn!("Failed to store message {}, retrying later: {:#?}", eid, e);
+                for blob in pending {
+                    blobs.added.remove(&(blob.kind, blob.hash));
+                }
+                failed.push((eid, doc));
+                continue;
+            }
             let delete_term = Term::from_field_u64(SchemaTools::eml_fields().f_id, eid);
+            if let Some(searcher) = &searcher {
+                // a message stored again may now have another body, the previous one is
+                // removed below if nothing else refers to it
+                let previous = TermQuery::new(delete_term.clone(), IndexRecordOption::Basic);
+                let field = SchemaTools::eml_fields().f_content_hash;
+                match stored_keys(searcher, &previous, field) {
+                    Ok(hashes) => replaced.extend(hashes.into_iter().filter(|h| *h != hash)),
+                    Err(e) => tracing::warn!("Failed to look up message {}: {:#?}", eid, e),
+                }
+            }
             operations.push(UserOperation::Delete(delete_term));
-            operations.push(UserOperation::Add(doc));
+            operations.extend(message_operations);
+            recorded.push((LedgerAction::Ingest, account_id, eid, hex::encode(&hash)));
+            batch.push((eid, doc));
+        }
+        buffer.extend(failed);
+        if operations.is_empty() {
+            return;
+        }
+        // in WORM mode, no message is stored without its ledger entry
+        if SETTINGS.bichon_worm_mode {
+            if let Err(e) = WORM_LEDGER.append(recorded).await {
+                tracing::error!(
+                    "Failed to record stored messages in the WORM ledger, retrying later: {:#?}",
+                    e
+                );
+                buffer.extend(batch);
+                return;
+            }
         }
         if let Err(e) = writer.run(operations) {
             eprintln!("[FATAL] Tantivy run failed: {e:?}");
@@ -1360,7 +3793,94 @@ impl EmlIndexManager {
         }
 
         fatal_commit(&mut writer);
+        if let Err(e) = self.remove_unreferenced(&mut writer, replaced).await {
+            tracing::warn!("Failed to remove replaced message bodies: {:#?}", e);
+        }
+    }
+}
+
+async fn put_blobs(blobs: &[PendingBlob]) -> BichonResult<()> {
+    for blob in blobs {
+        BLOB_STORE.put(&blob.key(), &blob.content).await?;
+    }
+    Ok(())
+}
+
+/// Removes blobs the index no longer refers to. A blob left behind only takes space.
+async fn delete_blobs(keys: &[String]) {
+    for key in keys {
+        if let Err(e) = BLOB_STORE.delete(key).await {
+            tracing::warn!("Failed to remove blob '{}': {:#?}", key, e);
+        }
+    }
+}
+
+/// Body or attachment document stored under `hash`.
+fn blob_query(kind: u64, hash: &[u8]) -> BooleanQuery {
+    let fields = SchemaTools::eml_fields();
+    BooleanQuery::new(vec![
+        (
+            Occur::Must,
+            Box::new(TermQuery::new(
+                Term::from_field_u64(fields.f_kind, kind),
+                IndexRecordOption::Basic,
+            )),
+        ),
+        (
+            Occur::Must,
+            Box::new(TermQuery::new(
+                Term::from_field_bytes(fields.f_blob_hash, hash),
+                IndexRecordOption::Basic,
+            )),
+        ),
+    ])
+}
+
+/// Values of the bytes `field` in the documents matching `query`.
+fn stored_keys(
+    searcher: &Searcher,
+    query: &dyn Query,
+    field: Field,
+) -> BichonResult<HashSet<Vec<u8>>> {
+    let addresses = searcher
+        .search(query, &DocSetCollector)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    let mut keys = HashSet::new();
+    for address in addresses {
+        let doc: TantivyDocument = searcher
+            .doc(address)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        keys.extend(
+            doc.get_all(field)
+                .filter_map(|v| v.as_bytes())
+                .map(<[u8]>::to_vec),
+        );
     }
+    Ok(keys)
+}
+
+/// Whether the EML store holds a message with envelope id `eid`.
+fn is_stored(searcher: &Searcher, eid: u64) -> BichonResult<bool> {
+    let term = Term::from_field_u64(SchemaTools::eml_fields().f_id, eid);
+    let count = searcher
+        .search(&TermQuery::new(term, IndexRecordOption::Basic), &Count)
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    Ok(count > 0)
+}
+
+fn bytes_value<'a>(doc: &'a TantivyDocument, field: Field, name: &str) -> BichonResult<&'a [u8]> {
+    let value = doc.get_first(field).ok_or_else(|| {
+        raise_error!(
+            format!("miss '{}' field in tantivy document", name),
+            ErrorCode::InternalError
+        )
+    })?;
+    value.as_bytes().ok_or_else(|| {
+        raise_error!(
+            format!("'{}' field is not a bytes", name),
+            ErrorCode::InternalError
+        )
+    })
 }
 
 fn fatal_commit(writer: &mut IndexWriter) {
@@ -1406,7 +3926,77 @@ fn fatal_commit(writer: &mut IndexWriter) {
     }
 }
 
+/// Stores of an earlier layout have another schema.
+fn is_legacy_store(index: &Index) -> bool {
+    index.schema().num_fields() != SchemaTools::eml_schema().num_fields()
+}
+
 fn open(index_dir: &PathBuf) -> Index {
     Index::open_in_dir(index_dir)
         .unwrap_or_else(|e| panic!("Failed to open index in {:?}: {}", index_dir, e))
 }
+
+#[cfg(test)]
+mod test {
+    use tantivy::{
+        doc,
+        schema::{Schema, Value, STORED, STRING},
+        Index, IndexWriter, TantivyDocument,
+    };
+
+    use super::LiveIndex;
+    use crate::modules::indexer::{
+        schema::SchemaTools, tokenizer::register_tokenizers, version::SchemaVersion,
+    };
+
+    #[tokio::test]
+    async fn test_open_index_with_retyped_field() {
+        let dir = tempfile::tempdir().unwrap();
+        let f = SchemaTools::envelope_fields();
+        // mailbox ids used to be indexed as text
+        let mut builder = Schema::builder();
+        for (field, entry) in SchemaTools::envelope_schema().fields() {
+            if field == f.f_mailbox_id {
+                builder.add_text_field(entry.name(), STRING | STORED);
+            } else {
+                builder.add_field(entry.clone());
+            }
+        }
+        let index = Index::create_in_dir(dir.path(), builder.build()).unwrap();
+        register_tokenizers(&index);
+        let mut writer: IndexWriter = index.writer_with_num_threads(1, 15_000_000).unwrap();
+        writer
+            .add_document(doc!(f.f_id => 7u64, f.f_mailbox_id => "INBOX", f.f_uid => 42u64))
+            .unwrap();
+        writer.commit().unwrap();
+        drop(writer);
+        SchemaVersion {
+            version: 1,
+            fingerprint: 0,
+        }
+        .write(dir.path())
+        .unwrap();
+
+        let (served, live) = LiveIndex::open(dir.path().to_path_buf());
+        let legacy = live.legacy.as_ref().expect("the index is incompatible");
+        assert_eq!(live.schema_version.version, 1);
+        assert_eq!(
+            served.schema().num_fields(),
+            SchemaTools::envelope_schema().num_fields()
+        );
+        assert_eq!(live.reader.searcher().num_docs(), 0);
+        // left untouched until the rebuild replaces it
+        assert_eq!(legacy.reader.searcher().num_docs(), 1);
+
+        // the UID kept its type and is carried over, the mailbox id is the rebuilt one
+        let mut rebuilt = TantivyDocument::new();
+        rebuilt.add_u64(f.f_id, 7);
+        rebuilt.add_u64(f.f_mailbox_id, 3);
+        let carried = legacy.carried_over(7, &rebuilt).await.unwrap();
+        assert_eq!(carried.get_first(f.f_uid).and_then(|v| v.as_u64()), Some(42));
+        assert_eq!(
+            carried.get_first(f.f_mailbox_id).and_then(|v| v.as_u64()),
+            Some(3)
+        );
+    }
+}
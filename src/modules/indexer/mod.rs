@@ -17,9 +17,15 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 
+pub mod cursor;
 pub mod envelope;
 pub mod fields;
 pub mod manager;
+pub mod reindex;
 pub mod schema;
+pub mod skeleton;
+pub mod snapshot;
+pub mod tokenizer;
+pub mod version;
 #[cfg(test)]
 mod tests;
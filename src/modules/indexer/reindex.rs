@@ -0,0 +1,291 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+use std::{
+    collections::HashSet,
+    sync::{LazyLock, RwLock},
+};
+
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use tantivy::{schema::Value, DocAddress, TantivyDocument};
+
+use crate::{
+    modules::{
+        account::pgp::is_encrypted,
+        common::signal::SIGNAL_MANAGER,
+        context::Initialize,
+        envelope::{attachment::ATTACHMENT_TEXT_PIPELINE, extractor::extract_envelope_from_eml},
+        error::{code::ErrorCode, BichonResult},
+        indexer::{
+            envelope::Envelope,
+            fields::F_ID,
+            manager::{EML_INDEX_MANAGER, ENVELOPE_INDEX_MANAGER},
+            schema::SchemaTools,
+            version::ENVELOPE_SCHEMA_VERSION,
+        },
+        settings::cli::SETTINGS,
+    },
+    raise_error, utc_now,
+};
+
+/// Rebuilt envelopes are written to the new index in batches of this size.
+const REINDEX_BATCH_SIZE: usize = 200;
+
+pub static ENVELOPE_REINDEXER: LazyLock<EnvelopeReindexer> = LazyLock::new(EnvelopeReindexer::new);
+
+/// Progress of the envelope index rebuild.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Deserialize, Serialize, Object)]
+pub struct ReindexStatus {
+    /// Schema version of this build.
+    pub schema_version: u32,
+    /// Schema version the envelope index was built with. `0` for indexes created before
+    /// schema versioning.
+    pub index_schema_version: u32,
+    /// Whether the envelope index was built with another schema and should be rebuilt.
+    pub outdated: bool,
+    /// Whether that schema is incompatible, in which case search returns nothing until
+    /// the rebuild completes.
+    pub incompatible: bool,
+    pub running: bool,
+    /// Number of messages in the EML store when the rebuild started.
+    pub total: u64,
+    /// Number of messages processed so far.
+    pub processed: u64,
+    /// Number of messages that could not be parsed. Their envelopes are copied from the
+    /// previous index as they are.
+    pub failed: u64,
+    pub started_at: Option<i64>,
+    pub finished_at: Option<i64>,
+    /// Reason the last rebuild stopped, if it did not complete.
+    pub error: Option<String>,
+}
+
+/// Rebuilds the envelope index from the EML store, in the background and while the
+/// current index keeps serving searches and receiving new mail.
+pub struct EnvelopeReindexer {
+    status: RwLock<ReindexStatus>,
+}
+
+impl Initialize for EnvelopeReindexer {
+    async fn initialize() -> BichonResult<()> {
+        let status = ENVELOPE_REINDEXER.status();
+        // an incompatible index cannot be searched at all, it is rebuilt regardless
+        if status.incompatible || (status.outdated && SETTINGS.bichon_auto_reindex) {
+            tracing::info!(
+                "Rebuilding the envelope index from schema version {} to {}",
+                status.index_schema_version,
+                status.schema_version
+            );
+            ENVELOPE_REINDEXER.start().await?;
+        }
+        Ok(())
+    }
+}
+
+impl EnvelopeReindexer {
+    fn new() -> Self {
+        Self {
+            status: RwLock::new(ReindexStatus::default()),
+        }
+    }
+
+    pub fn status(&self) -> ReindexStatus {
+        let mut status = self.status.read().unwrap().clone();
+        let (version, outdated) = ENVELOPE_INDEX_MANAGER.schema_version();
+        status.schema_version = ENVELOPE_SCHEMA_VERSION;
+        status.index_schema_version = version.version;
+        status.outdated = outdated;
+        status.incompatible = ENVELOPE_INDEX_MANAGER.is_incompatible();
+        status
+    }
+
+    /// Starts rebuilding the envelope index. Fails if a rebuild is already running.
+    pub async fn start(&'static self) -> BichonResult<()> {
+        {
+            let mut status = self.status.write().unwrap();
+            if status.running {
+                return Err(raise_error!(
+                    "The envelope index is already being rebuilt.".into(),
+                    ErrorCode::InvalidParameter
+                ));
+            }
+            *status = ReindexStatus {
+                running: true,
+                total: EML_INDEX_MANAGER.storage_stats()?.messages,
+                started_at: Some(utc_now!()),
+                ..Default::default()
+            };
+        }
+        if let Err(e) = ENVELOPE_INDEX_MANAGER.begin_rebuild().await {
+            self.finish(Some(format!("{:#?}", e)));
+            return Err(e);
+        }
+        tokio::spawn(async move {
+            match self.run().await {
+                Ok(()) => {
+                    tracing::info!("Envelope index rebuild completed");
+                    self.finish(None);
+                }
+                Err(e) => {
+                    tracing::error!("Envelope index rebuild failed: {:#?}", e);
+                    ENVELOPE_INDEX_MANAGER.cancel_rebuild().await;
+                    self.finish(Some(format!("{:#?}", e)));
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn finish(&self, error: Option<String>) {
+        let mut status = self.status.write().unwrap();
+        status.running = false;
+        status.finished_at = Some(utc_now!());
+        status.error = error;
+    }
+
+    async fn run(&self) -> BichonResult<()> {
+        let mut shutdown = SIGNAL_MANAGER.subscribe();
+        let mut rebuilt = HashSet::new();
+        let mut batch = Vec::with_capacity(REINDEX_BATCH_SIZE);
+
+        // every stored message is parsed again, segments holding only message bodies have
+        // no ids
+        let searcher = EML_INDEX_MANAGER.searcher();
+        for (segment_ord, segment_reader) in searcher.segment_readers().iter().enumerate() {
+            let Some(ids) = segment_reader
+                .fast_fields()
+                .column_opt::<u64>(F_ID)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+            else {
+                continue;
+            };
+            let alive: Vec<u32> = segment_reader.doc_ids_alive().collect();
+            for doc_id in alive {
+                if shutdown.try_recv().is_ok() {
+                    return Err(raise_error!(
+                        "Interrupted by shutdown.".into(),
+                        ErrorCode::InternalError
+                    ));
+                }
+                let Some(eid) = ids.first(doc_id) else {
+                    continue;
+                };
+                let doc: TantivyDocument = searcher
+                    .doc_async(DocAddress::new(segment_ord as u32, doc_id))
+                    .await
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                match Self::rebuild_document(&doc, eid).await {
+                    Ok(Some(document)) => {
+                        rebuilt.insert(eid);
+                        batch.push((eid, document));
+                    }
+                    Ok(None) => {}
+                    Err(e) => {
+                        tracing::warn!("Failed to reindex message {}: {:#?}", eid, e);
+                        self.status.write().unwrap().failed += 1;
+                    }
+                }
+                self.status.write().unwrap().processed += 1;
+                if batch.len() >= REINDEX_BATCH_SIZE {
+                    ENVELOPE_INDEX_MANAGER
+                        .add_rebuilt_documents(std::mem::take(&mut batch))
+                        .await?;
+                }
+            }
+        }
+
+        // envelopes without a stored message, or whose message could not be parsed or is
+        // encrypted at rest, are kept as they are
+        let searcher = ENVELOPE_INDEX_MANAGER.live_searcher()?;
+        for (segment_ord, segment_reader) in searcher.segment_readers().iter().enumerate() {
+            let ids = segment_reader
+                .fast_fields()
+                .u64(F_ID)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            let alive: Vec<u32> = segment_reader.doc_ids_alive().collect();
+            for doc_id in alive {
+                let Some(eid) = ids.first(doc_id) else {
+                    continue;
+                };
+                if rebuilt.contains(&eid) {
+                    continue;
+                }
+                let doc: TantivyDocument = searcher
+                    .doc_async(DocAddress::new(segment_ord as u32, doc_id))
+                    .await
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                batch.push((eid, Envelope::restore_document(&doc)));
+                if batch.len() >= REINDEX_BATCH_SIZE {
+                    ENVELOPE_INDEX_MANAGER
+                        .add_rebuilt_documents(std::mem::take(&mut batch))
+                        .await?;
+                }
+            }
+        }
+        if !batch.is_empty() {
+            ENVELOPE_INDEX_MANAGER.add_rebuilt_documents(batch).await?;
+        }
+
+        ENVELOPE_INDEX_MANAGER.finish_rebuild().await
+    }
+
+    /// Builds the envelope document of a stored message. Values only the live index knows,
+    /// such as tags, are carried over when the document is written to the new index.
+    /// Returns `None` for messages encrypted at rest, which the server cannot read.
+    async fn rebuild_document(
+        doc: &TantivyDocument,
+        eid: u64,
+    ) -> BichonResult<Option<TantivyDocument>> {
+        let fields = SchemaTools::eml_fields();
+        let account_id = doc
+            .get_first(fields.f_account_id)
+            .and_then(|v| v.as_u64())
+            .ok_or_else(|| raise_error!("missing account id".into(), ErrorCode::InternalError))?;
+        let mailbox_id = doc
+            .get_first(fields.f_mailbox_id)
+            .and_then(|v| v.as_u64())
+            .ok_or_else(|| raise_error!("missing mailbox id".into(), ErrorCode::InternalError))?;
+        let eml = EML_INDEX_MANAGER
+            .get(account_id, eid)
+            .await?
+            .ok_or_else(|| raise_error!("missing eml".into(), ErrorCode::InternalError))?;
+        if is_encrypted(&eml) {
+            return Ok(None);
+        }
+        envelope_document(&eml, account_id, mailbox_id, eid)
+            .await
+            .map(Some)
+    }
+}
+
+/// Derives the envelope document of message `eid` from its raw content, which must not be
+/// encrypted at rest.
+pub async fn envelope_document(
+    eml: &[u8],
+    account_id: u64,
+    mailbox_id: u64,
+    eid: u64,
+) -> BichonResult<TantivyDocument> {
+    let mut envelope = extract_envelope_from_eml(eml, account_id, mailbox_id)?;
+    envelope.id = eid;
+    ATTACHMENT_TEXT_PIPELINE
+        .to_document(&envelope, mailbox_id, eml, false)
+        .await
+}
@@ -20,8 +20,12 @@
 use std::sync::{Arc, LazyLock};
 
 use crate::modules::indexer::fields::{EnvelopeFields, *};
-use tantivy::schema::{FacetOptions, Field, INDEXED};
-use tantivy::schema::{Schema, FAST, STORED, STRING, TEXT};
+use crate::modules::indexer::tokenizer::{
+    analyzer_name, EMAIL_TOKENIZER, HEADER_TOKENIZER, STEM_TOKENIZER,
+};
+use crate::modules::settings::cli::SETTINGS;
+use tantivy::schema::{FacetOptions, Field, IndexRecordOption, JsonObjectOptions, INDEXED};
+use tantivy::schema::{Schema, TextFieldIndexing, TextOptions, FAST, STORED, STRING, TEXT};
 
 static ENVELOPE_FIELDS: LazyLock<Arc<EnvelopeFields>> = LazyLock::new(|| {
     let (_, fields) = SchemaTools::create_envelope_schema();
@@ -56,9 +60,21 @@ impl SchemaTools {
 
     pub fn envelope_default_fields() -> Vec<Field> {
         let fields = Self::envelope_fields();
-        vec![fields.f_subject, fields.f_text, fields.f_attachments]
+        let mut default_fields = vec![
+            fields.f_subject,
+            fields.f_text,
+            fields.f_attachments,
+            fields.f_attachment_content,
+        ];
+        if SETTINGS.bichon_stemming {
+            default_fields.push(fields.f_stemmed);
+        }
+        default_fields
     }
 
+    /// New fields must be appended at the end, and existing fields never removed, renamed
+    /// or retyped: an index built with an older version of this schema is then still served
+    /// while it is rebuilt. Bump `ENVELOPE_SCHEMA_VERSION` with every change.
     pub fn create_envelope_schema() -> (Schema, EnvelopeFields) {
         let mut builder = Schema::builder();
         let f_id = builder.add_u64_field(F_ID, INDEXED | STORED | FAST);
@@ -67,9 +83,12 @@ impl SchemaTools {
         let f_mailbox_id = builder.add_u64_field(F_MAILBOX_ID, INDEXED | STORED | FAST);
         // UID: numeric, locate message
         let f_uid = builder.add_u64_field(F_UID, INDEXED | STORED | FAST);
-        // Subject/body: tokenized for full-text search
-        let f_subject = builder.add_text_field(F_SUBJECT, TEXT | STORED);
-        let f_text = builder.add_text_field(F_TEXT, TEXT | STORED);
+        // Subject/body: tokenized for full-text search with the configured analyzers
+        let subject_analyzer = analyzer_name(SETTINGS.bichon_subject_analyzer);
+        let body_analyzer = analyzer_name(SETTINGS.bichon_body_analyzer);
+        let f_subject =
+            builder.add_text_field(F_SUBJECT, Self::analyzed(subject_analyzer).set_stored());
+        let f_text = builder.add_text_field(F_TEXT, Self::analyzed(body_analyzer).set_stored());
         // Email addresses: exact match search
         let f_from = builder.add_text_field(F_FROM, STRING | STORED | FAST);
         let f_to = builder.add_text_field(F_TO, STRING | STORED);
@@ -88,6 +107,55 @@ impl SchemaTools {
         let f_attachments = builder.add_text_field(F_ATTACHMENTS, TEXT | STORED);
         let f_has_attachment = builder.add_bool_field(F_HAS_ATTACHMENT, INDEXED | STORED | FAST);
         let f_tags = builder.add_facet_field(F_TAGS, FacetOptions::default().set_stored());
+        // Addresses split into full address, local part and domains, case-insensitive
+        let address_options = TextOptions::default().set_indexing_options(
+            TextFieldIndexing::default()
+                .set_tokenizer(EMAIL_TOKENIZER)
+                .set_index_option(IndexRecordOption::Basic),
+        );
+        let f_from_addr = builder.add_text_field(F_FROM_ADDR, address_options.clone());
+        let f_to_addr = builder.add_text_field(F_TO_ADDR, address_options.clone());
+        let f_cc_addr = builder.add_text_field(F_CC_ADDR, address_options.clone());
+        let f_bcc_addr = builder.add_text_field(F_BCC_ADDR, address_options);
+        // Lowercased sender domain: exact match and aggregation
+        let f_from_domain = builder.add_text_field(F_FROM_DOMAIN, STRING | FAST);
+        // Display names: tokenized for full-text search
+        let f_from_name = builder.add_text_field(F_FROM_NAME, TEXT | STORED);
+        let f_recipient_names = builder.add_text_field(F_RECIPIENT_NAMES, TEXT | STORED);
+        // Extracted attachment text, one value per attachment, with the attachment names
+        // stored in the same order to report which one matched
+        let f_attachment_content = builder.add_text_field(
+            F_ATTACHMENT_CONTENT,
+            Self::analyzed(body_analyzer).set_stored(),
+        );
+        let f_attachment_content_name = builder.add_text_field(F_ATTACHMENT_CONTENT_NAME, STORED);
+        // Subject, body and attachment text stemmed in the message language, search only
+        let f_stemmed = builder.add_text_field(F_STEMMED, Self::analyzed(STEM_TOKENIZER));
+        // Detected language of the message (ISO 639-3), exact match and aggregation
+        let f_language = builder.add_text_field(F_LANGUAGE, STRING | STORED | FAST);
+        // Headers listed in `bichon_indexed_headers`, as objects keyed by lowercase header
+        // name: whole values matched case-insensitively, and tokenized values
+        let f_headers = builder.add_json_field(
+            F_HEADERS,
+            JsonObjectOptions::default()
+                .set_stored()
+                .set_indexing_options(
+                    TextFieldIndexing::default()
+                        .set_tokenizer(HEADER_TOKENIZER)
+                        .set_index_option(IndexRecordOption::Basic),
+                ),
+        );
+        let f_headers_text = builder.add_json_field(
+            F_HEADERS_TEXT,
+            JsonObjectOptions::default()
+                .set_stored()
+                .set_indexing_options(
+                    TextFieldIndexing::default()
+                        .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+                ),
+        );
+        // When the message was first indexed, in milliseconds since the Unix epoch
+        let f_archived_at = builder.add_i64_field(F_ARCHIVED_AT, INDEXED | STORED | FAST);
         let fields = EnvelopeFields {
             f_id,
             f_account_id,
@@ -107,21 +175,64 @@ impl SchemaTools {
             f_attachments,
             f_has_attachment,
             f_tags,
+            f_from_addr,
+            f_to_addr,
+            f_cc_addr,
+            f_bcc_addr,
+            f_from_domain,
+            f_from_name,
+            f_recipient_names,
+            f_attachment_content,
+            f_attachment_content_name,
+            f_stemmed,
+            f_language,
+            f_headers,
+            f_headers_text,
+            f_archived_at,
         };
         (builder.build(), fields)
     }
 
+    /// Full-text indexing with positions, for phrase queries and highlighting.
+    fn analyzed(tokenizer: &str) -> TextOptions {
+        TextOptions::default().set_indexing_options(
+            TextFieldIndexing::default()
+                .set_tokenizer(tokenizer)
+                .set_index_option(IndexRecordOption::WithFreqsAndPositions),
+        )
+    }
+
+    /// The EML store holds three kinds of documents: one reference per envelope, with its
+    /// id, account, mailbox and the SHA-256 of its raw message, one body per distinct
+    /// hash, with the raw message, and one document per distinct large attachment. Large
+    /// attachments are taken out of the bodies, which keep their layout to reassemble the
+    /// raw message. Identical messages and attachments are stored once. Their content
+    /// lives in the blob store; stores written before it keep it in `f_eml`.
     pub fn create_eml_schema() -> (Schema, EmlFields) {
         let mut builder = Schema::builder();
         let f_id = builder.add_u64_field(F_ID, INDEXED | FAST);
         let f_account_id = builder.add_u64_field(F_ACCOUNT_ID, INDEXED | STORED | FAST);
         let f_mailbox_id = builder.add_u64_field(F_MAILBOX_ID, INDEXED | STORED | FAST);
         let f_eml = builder.add_bytes_field(F_EML, STORED);
+        let f_content_hash = builder.add_bytes_field(F_CONTENT_HASH, INDEXED | STORED);
+        let f_blob_hash = builder.add_bytes_field(F_BLOB_HASH, INDEXED);
+        let f_size = builder.add_u64_field(F_SIZE, FAST);
+        let f_kind = builder.add_u64_field(F_KIND, INDEXED | FAST);
+        let f_attachment_hash = builder.add_bytes_field(F_ATTACHMENT_HASH, INDEXED | STORED);
+        let f_layout = builder.add_bytes_field(F_LAYOUT, STORED);
+        let f_attachment_name = builder.add_text_field(F_ATTACHMENT_NAME, STORED);
         let fields = EmlFields {
             f_id,
             f_account_id,
             f_mailbox_id,
             f_eml,
+            f_content_hash,
+            f_blob_hash,
+            f_size,
+            f_kind,
+            f_attachment_hash,
+            f_layout,
+            f_attachment_name,
         };
         (builder.build(), fields)
     }
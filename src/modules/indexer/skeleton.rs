@@ -0,0 +1,258 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use mail_parser::{MessageParser, MimeHeaders, PartType};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    base64_encode,
+    modules::error::{code::ErrorCode, BichonResult},
+    raise_error,
+};
+
+/// Attachments smaller than this stay in the message they came with.
+const MIN_SPLIT_SIZE: usize = 32 * 1024;
+
+/// Where a split attachment goes back into the skeleton, and how its content was encoded.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SplitPart {
+    /// Position in the skeleton.
+    pub offset: usize,
+    /// Length of the base64 lines, all but the last one.
+    pub line_length: usize,
+    pub crlf: bool,
+}
+
+/// Decoded content of an attachment taken out of a message.
+#[derive(Clone, Debug)]
+pub struct SplitAttachment {
+    /// SHA-256 of `content`.
+    pub hash: Vec<u8>,
+    pub name: String,
+    pub content: Vec<u8>,
+}
+
+/// A message with its large attachments taken out. The skeleton is the raw message
+/// without the encoded attachment bodies; `layout` and `attachments` list them in order.
+#[derive(Clone, Debug)]
+pub struct SplitMessage {
+    pub skeleton: Vec<u8>,
+    pub layout: Vec<SplitPart>,
+    pub attachments: Vec<SplitAttachment>,
+}
+
+/// Takes the large base64 attachments out of a raw message. Only attachments whose
+/// encoding can be reproduced exactly from their content are taken out, so that the
+/// reassembled message is byte-identical. Returns `None` when nothing can be split.
+pub fn split(eml: &[u8]) -> Option<SplitMessage> {
+    let message = MessageParser::default().parse(eml)?;
+    let mut found = Vec::new();
+    for part in message.attachments() {
+        if !matches!(part.body, PartType::Binary(_) | PartType::InlineBinary(_))
+            || !part
+                .content_transfer_encoding()
+                .is_some_and(|encoding| encoding.eq_ignore_ascii_case("base64"))
+        {
+            continue;
+        }
+        let content = part.contents();
+        if content.len() < MIN_SPLIT_SIZE {
+            continue;
+        }
+        let start = part.raw_body_offset() as usize;
+        let end = (part.raw_end_offset() as usize).min(eml.len());
+        if start >= end {
+            continue;
+        }
+        // line breaks after the last line belong to the MIME structure
+        let raw = &eml[start..end];
+        let length = raw.len()
+            - raw
+                .iter()
+                .rev()
+                .take_while(|b| matches!(b, b'\r' | b'\n'))
+                .count();
+        let encoded = &raw[..length];
+        let crlf = encoded.windows(2).any(|w| w == b"\r\n");
+        let line_length = match encoded.iter().position(|b| *b == b'\n') {
+            Some(position) if crlf => position.saturating_sub(1),
+            Some(position) => position,
+            None => encoded.len(),
+        };
+        if line_length == 0 || encode(content, line_length, crlf) != encoded {
+            continue;
+        }
+        found.push((
+            start,
+            start + length,
+            SplitPart {
+                offset: 0,
+                line_length,
+                crlf,
+            },
+            SplitAttachment {
+                hash: digest::digest(&digest::SHA256, content).as_ref().to_vec(),
+                name: part.attachment_name().unwrap_or_default().to_string(),
+                content: content.to_vec(),
+            },
+        ));
+    }
+    found.sort_by_key(|(start, ..)| *start);
+
+    let mut skeleton = Vec::with_capacity(eml.len());
+    let mut layout = Vec::new();
+    let mut attachments = Vec::new();
+    let mut position = 0;
+    for (start, end, mut part, attachment) in found {
+        if start < position {
+            continue;
+        }
+        skeleton.extend_from_slice(&eml[position..start]);
+        part.offset = skeleton.len();
+        layout.push(part);
+        attachments.push(attachment);
+        position = end;
+    }
+    if attachments.is_empty() {
+        return None;
+    }
+    skeleton.extend_from_slice(&eml[position..]);
+
+    let contents: Vec<&[u8]> = attachments.iter().map(|a| a.content.as_slice()).collect();
+    match reassemble(&skeleton, &layout, &contents) {
+        Ok(reassembled) if reassembled == eml => Some(SplitMessage {
+            skeleton,
+            layout,
+            attachments,
+        }),
+        _ => None,
+    }
+}
+
+/// Puts the attachments taken out by `split` back into the skeleton.
+pub fn reassemble(
+    skeleton: &[u8],
+    layout: &[SplitPart],
+    contents: &[&[u8]],
+) -> BichonResult<Vec<u8>> {
+    if layout.len() != contents.len() {
+        return Err(raise_error!(
+            format!(
+                "Expected {} attachments to reassemble the message, got {}",
+                layout.len(),
+                contents.len()
+            ),
+            ErrorCode::InternalError
+        ));
+    }
+    let mut eml = Vec::with_capacity(
+        skeleton.len() + contents.iter().map(|c| c.len()).sum::<usize>() * 4 / 3,
+    );
+    let mut position = 0;
+    for (part, content) in layout.iter().zip(contents) {
+        if part.offset < position || part.offset > skeleton.len() {
+            return Err(raise_error!(
+                "Invalid message layout".into(),
+                ErrorCode::InternalError
+            ));
+        }
+        eml.extend_from_slice(&skeleton[position..part.offset]);
+        eml.extend_from_slice(&encode(content, part.line_length, part.crlf));
+        position = part.offset;
+    }
+    eml.extend_from_slice(&skeleton[position..]);
+    Ok(eml)
+}
+
+/// Key under which a message refers to one of its split attachments: the SHA-256 of the
+/// content followed by its size, so that sizes can be read from the index terms alone.
+pub fn attachment_key(hash: &[u8], size: u64) -> Vec<u8> {
+    let mut key = hash.to_vec();
+    key.extend_from_slice(&size.to_be_bytes());
+    key
+}
+
+/// Splits a key built by `attachment_key` into the hash and the size.
+pub fn parse_attachment_key(key: &[u8]) -> Option<(&[u8], u64)> {
+    let (hash, size) = key.split_at(key.len().checked_sub(8)?);
+    Some((hash, u64::from_be_bytes(size.try_into().ok()?)))
+}
+
+fn encode(content: &[u8], line_length: usize, crlf: bool) -> Vec<u8> {
+    let encoded = base64_encode!(content);
+    let separator: &[u8] = if crlf { b"\r\n" } else { b"\n" };
+    let mut wrapped = Vec::with_capacity(encoded.len() + encoded.len() / line_length * 2);
+    for (i, line) in encoded.as_bytes().chunks(line_length).enumerate() {
+        if i > 0 {
+            wrapped.extend_from_slice(separator);
+        }
+        wrapped.extend_from_slice(line);
+    }
+    wrapped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn message(subject: &str, content: &[u8], line_length: usize) -> Vec<u8> {
+        let encoded = encode(content, line_length, true);
+        let mut eml = format!(
+            "From: alice@example.com\r\nTo: bob@example.com\r\nSubject: {subject}\r\n\
+             MIME-Version: 1.0\r\nContent-Type: multipart/mixed; boundary=\"b1\"\r\n\r\n\
+             --b1\r\nContent-Type: text/plain\r\n\r\nSee attached.\r\n\
+             --b1\r\nContent-Type: application/octet-stream\r\n\
+             Content-Disposition: attachment; filename=\"deck.bin\"\r\n\
+             Content-Transfer-Encoding: base64\r\n\r\n"
+        )
+        .into_bytes();
+        eml.extend_from_slice(&encoded);
+        eml.extend_from_slice(b"\r\n--b1--\r\n");
+        eml
+    }
+
+    #[test]
+    fn test_split_and_reassemble() {
+        let content: Vec<u8> = (0..100_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        let first = message("Deck", &content, 76);
+        let forwarded = message("Fwd: Deck", &content, 76);
+
+        let a = split(&first).unwrap();
+        let b = split(&forwarded).unwrap();
+        assert_eq!(a.attachments.len(), 1);
+        assert_eq!(a.attachments[0].hash, b.attachments[0].hash);
+        assert_eq!(a.attachments[0].name, "deck.bin");
+        assert!(a.skeleton.len() < 1024);
+
+        let contents = [a.attachments[0].content.as_slice()];
+        assert_eq!(
+            reassemble(&a.skeleton, &a.layout, &contents).unwrap(),
+            first
+        );
+
+        // small attachments stay in place
+        assert!(split(&message("Small", &content[..1000], 76)).is_none());
+
+        let key = attachment_key(&a.attachments[0].hash, 100_000);
+        assert_eq!(
+            parse_attachment_key(&key),
+            Some((a.attachments[0].hash.as_slice(), 100_000))
+        );
+    }
+}
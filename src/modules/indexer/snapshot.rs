@@ -0,0 +1,115 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+use std::path::{Path, PathBuf};
+
+use tantivy::{
+    directory::{error::OpenReadError, FileSlice, MmapDirectory},
+    index::SegmentComponent,
+    Directory, Index,
+};
+
+use crate::{
+    modules::error::{code::ErrorCode, BichonResult},
+    raise_error,
+};
+
+/// Name of the file listing the segments of the last commit of an index.
+pub const META_FILE: &str = "meta.json";
+/// Attempts to open the files of the last commit, which a merge may replace meanwhile.
+const SNAPSHOT_ATTEMPTS: usize = 10;
+
+/// Writes to an index stay blocked until this is dropped.
+pub struct WritePause {
+    _guard: Box<dyn Send>,
+}
+
+impl WritePause {
+    pub fn new(guard: impl Send + 'static) -> Self {
+        Self {
+            _guard: Box::new(guard),
+        }
+    }
+}
+
+/// Files of an index at its last commit, opened while its writes were paused. They stay
+/// readable once a later commit or merge replaces and removes them.
+pub struct IndexSnapshot {
+    /// Content of `meta.json` for the commit.
+    pub meta: Vec<u8>,
+    /// Segment files, by path relative to the index directory. They are never modified
+    /// once written, and a new delete file is written under a new name.
+    pub files: Vec<(PathBuf, FileSlice)>,
+    /// Other files kept in the index directory, which may be rewritten in place.
+    pub extra: Vec<(PathBuf, FileSlice)>,
+}
+
+impl IndexSnapshot {
+    /// Opens the files of the last commit of `index`, along with the `extra` files of its
+    /// directory `dir` that exist.
+    pub fn capture(index: &Index, dir: &Path, extra: &[&str]) -> BichonResult<Self> {
+        // opened as written, with their footers, which the index directory strips
+        let directory = MmapDirectory::open(dir)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        'attempt: for _ in 0..SNAPSHOT_ATTEMPTS {
+            let metas = index
+                .load_metas()
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            let mut files = Vec::new();
+            for segment in &metas.segments {
+                let delete_file = segment.relative_path(SegmentComponent::Delete);
+                for path in segment.list_files() {
+                    match directory.open_read(&path) {
+                        Ok(slice) => files.push((path, slice)),
+                        // listed even when the segment has no deletes
+                        Err(OpenReadError::FileDoesNotExist(_))
+                            if path == delete_file && !segment.has_deletes() => {}
+                        // merged meanwhile, its files are removed
+                        Err(OpenReadError::FileDoesNotExist(_)) => continue 'attempt,
+                        Err(e) => {
+                            return Err(raise_error!(format!("{:#?}", e), ErrorCode::InternalError))
+                        }
+                    }
+                }
+            }
+            // read as they are, the index directory only opens files written by tantivy
+            let mut extra_files = Vec::new();
+            for name in extra {
+                match std::fs::read(dir.join(name)) {
+                    Ok(data) => extra_files.push((PathBuf::from(name), FileSlice::from(data))),
+                    Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+                    Err(e) => {
+                        return Err(raise_error!(format!("{:#?}", e), ErrorCode::InternalError))
+                    }
+                }
+            }
+            let meta = serde_json::to_vec_pretty(&metas)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            return Ok(Self {
+                meta,
+                files,
+                extra: extra_files,
+            });
+        }
+        Err(raise_error!(
+            "The index kept changing while its files were opened.".into(),
+            ErrorCode::InternalError
+        ))
+    }
+}
@@ -220,3 +220,151 @@ async fn test2() {
         }
     }
 }
+
+#[test]
+fn test_email_tokenizer() {
+    use crate::modules::indexer::tokenizer::EmailTokenizer;
+    use tantivy::tokenizer::{TokenStream, Tokenizer};
+
+    let mut tokenizer = EmailTokenizer;
+    let mut tokens = Vec::new();
+    tokenizer
+        .token_stream("John.Smith@Mail.Acme.com")
+        .process(&mut |token| tokens.push(token.text.clone()));
+    assert_eq!(
+        tokens,
+        vec![
+            "john.smith@mail.acme.com",
+            "john.smith",
+            "john",
+            "smith",
+            "@mail.acme.com",
+            "@acme.com",
+            "mail",
+            "acme",
+        ]
+    );
+}
+
+#[test]
+fn test_cjk_tokenizer() {
+    use crate::modules::indexer::tokenizer::CjkTokenizer;
+    use tantivy::tokenizer::{TokenStream, Tokenizer};
+
+    let mut tokenizer = CjkTokenizer;
+    let mut tokens = Vec::new();
+    tokenizer
+        .token_stream("Meeting: 東京都庁 at 9am, 会")
+        .process(&mut |token| tokens.push(token.text.clone()));
+    assert_eq!(
+        tokens,
+        vec!["Meeting", "東京", "京都", "都庁", "at", "9am", "会"]
+    );
+}
+
+#[test]
+fn test_stemmed_text() {
+    use crate::modules::indexer::tokenizer::stemmed_text;
+
+    let terms = |text, language| -> Vec<String> {
+        stemmed_text(text, language)
+            .tokens
+            .into_iter()
+            .map(|token| token.text)
+            .collect()
+    };
+    assert_eq!(terms("Les Maisons", Some("fra")), vec!["le", "maison"]);
+    // unknown language, stemmed in `bichon_default_language`
+    assert_eq!(terms("Running meetings", None), vec!["run", "meet"]);
+}
+
+#[test]
+fn test_schema_compatibility() {
+    use crate::modules::indexer::version::is_compatible;
+    use tantivy::schema::{FAST, INDEXED, STORED, STRING, TEXT};
+
+    let old = {
+        let mut builder = Schema::builder();
+        builder.add_u64_field("id", INDEXED | STORED | FAST);
+        builder.add_text_field("subject", TEXT | STORED);
+        builder.build()
+    };
+    let grown = {
+        let mut builder = Schema::builder();
+        builder.add_u64_field("id", INDEXED | STORED | FAST);
+        builder.add_text_field("subject", TEXT | STORED);
+        builder.add_text_field("language", STRING | STORED);
+        builder.build()
+    };
+    let retyped = {
+        let mut builder = Schema::builder();
+        builder.add_text_field("id", STRING | STORED);
+        builder.add_text_field("subject", TEXT | STORED);
+        builder.add_text_field("language", STRING | STORED);
+        builder.build()
+    };
+
+    assert!(is_compatible(&old, &grown));
+    assert!(!is_compatible(&grown, &old));
+    assert!(!is_compatible(&old, &retyped));
+}
+
+#[test]
+fn test_sort_by_sender_across_segments() {
+    use crate::modules::{
+        indexer::{
+            cursor::{SearchCursor, SortKeyScorer, SortValue},
+            fields::{F_FROM, F_ID},
+        },
+        message::search::SortField,
+    };
+    use tantivy::schema::{FAST, INDEXED, STRING};
+
+    let mut builder = Schema::builder();
+    let id = builder.add_u64_field(F_ID, INDEXED | FAST);
+    let from = builder.add_text_field(F_FROM, STRING | FAST);
+    let index = Index::create_in_ram(builder.build());
+    let mut writer: IndexWriter = index.writer_with_num_threads(1, 15_000_000).unwrap();
+    // one segment per commit, senders interleaved between them
+    for batch in [[(1u64, "carol"), (2, "alice")], [(3, "bob"), (4, "alice")]] {
+        for (doc_id, sender) in batch {
+            writer
+                .add_document(doc!(id => doc_id, from => sender))
+                .unwrap();
+        }
+        writer.commit().unwrap();
+    }
+    let searcher = index.reader().unwrap().searcher();
+    assert_eq!(searcher.segment_readers().len(), 2);
+
+    let page = |after: Option<&SearchCursor>| {
+        let scorer = SortKeyScorer::new(SortField::From, false, after);
+        searcher
+            .search(&AllQuery, &TopDocs::with_limit(2).custom_score(scorer))
+            .unwrap()
+            .into_iter()
+            .filter_map(|(key, _)| key)
+            .map(|key| SearchCursor::from_key(SortField::From, key))
+            .collect::<Vec<_>>()
+    };
+    let first = page(None);
+    assert_eq!(
+        first.iter().map(|c| c.value.clone()).collect::<Vec<_>>(),
+        vec![
+            SortValue::Str("alice".into()),
+            SortValue::Str("alice".into())
+        ]
+    );
+    assert_eq!(first.iter().map(|c| c.id).collect::<Vec<_>>(), vec![2, 4]);
+    let second = page(first.last());
+    assert_eq!(
+        second
+            .iter()
+            .map(|c| (c.value.clone(), c.id))
+            .collect::<Vec<_>>(),
+        vec![
+            (SortValue::Str("bob".into()), 3),
+            (SortValue::Str("carol".into()), 1)
+        ]
+    );
+}
@@ -0,0 +1,375 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+use std::cell::RefCell;
+
+use tantivy::{
+    tokenizer::{
+        AsciiFoldingFilter, Language, LowerCaser, PreTokenizedString, RawTokenizer,
+        RemoveLongFilter, SimpleTokenizer, Stemmer, TextAnalyzer, Token, TokenStream, Tokenizer,
+        TokenizerManager,
+    },
+    Index,
+};
+use whatlang::Lang;
+
+use crate::modules::settings::cli::{TextAnalyzerKind, SETTINGS};
+
+/// Tokenizer used by the `*_addr` fields, see [`EmailTokenizer`].
+pub const EMAIL_TOKENIZER: &str = "email";
+/// Simple tokenizer with ASCII folding, so `café` matches `cafe`.
+pub const FOLDING_TOKENIZER: &str = "folding";
+/// [`CjkTokenizer`] with lowercasing and ASCII folding.
+pub const CJK_TOKENIZER: &str = "cjk";
+/// Stemming analyzer of `bichon_default_language`, used by the `stemmed` field. Documents
+/// are stemmed in their own language before indexing, see [`stemmed_text`].
+pub const STEM_TOKENIZER: &str = "stem";
+/// Whole lowercased value, used by the `headers` field.
+pub const HEADER_TOKENIZER: &str = "header";
+const DEFAULT_TOKENIZER: &str = "default";
+/// Same limit as the `default` tokenizer, longer tokens are usually encoded data.
+const MAX_TOKEN_LENGTH: usize = 40;
+const LANGUAGE_SAMPLE_CHARS: usize = 2000;
+
+/// Registers the custom analyzers referenced by the envelope schema. Tokenizers are not
+/// persisted with the index, so this must run every time the index is opened.
+///
+/// Every analyzer is registered whatever the current settings, so an index created with
+/// other analyzer settings can still be opened and searched.
+pub fn register_tokenizers(index: &Index) {
+    let tokenizers = index.tokenizers();
+    tokenizers.register(EMAIL_TOKENIZER, TextAnalyzer::from(EmailTokenizer));
+    tokenizers.register(
+        FOLDING_TOKENIZER,
+        TextAnalyzer::builder(SimpleTokenizer::default())
+            .filter(RemoveLongFilter::limit(MAX_TOKEN_LENGTH))
+            .filter(LowerCaser)
+            .filter(AsciiFoldingFilter)
+            .build(),
+    );
+    tokenizers.register(
+        CJK_TOKENIZER,
+        TextAnalyzer::builder(CjkTokenizer)
+            .filter(RemoveLongFilter::limit(MAX_TOKEN_LENGTH))
+            .filter(LowerCaser)
+            .filter(AsciiFoldingFilter)
+            .build(),
+    );
+    tokenizers.register(
+        HEADER_TOKENIZER,
+        TextAnalyzer::builder(RawTokenizer::default())
+            .filter(LowerCaser)
+            .build(),
+    );
+    tokenizers.register(STEM_TOKENIZER, stem_analyzer(default_stem_language()));
+}
+
+thread_local! {
+    /// Stemming analyzers built so far on this thread, by language.
+    static STEM_ANALYZERS: RefCell<Vec<(Option<Language>, TextAnalyzer)>> =
+        const { RefCell::new(Vec::new()) };
+}
+
+/// Tokenizes `text` for the `stemmed` field, stemmed in `language` (an ISO 639-3 code) or
+/// in `bichon_default_language` when the language is unknown. Texts in a language without
+/// a stemmer are only lowercased and folded.
+pub fn stemmed_text(text: &str, language: Option<&str>) -> PreTokenizedString {
+    let language = match language.and_then(Lang::from_code) {
+        Some(lang) => stem_language(lang),
+        None => default_stem_language(),
+    };
+    let mut tokens = Vec::new();
+    STEM_ANALYZERS.with(|analyzers| {
+        let mut analyzers = analyzers.borrow_mut();
+        let position = match analyzers.iter().position(|(l, _)| *l == language) {
+            Some(position) => position,
+            None => {
+                analyzers.push((language, stem_analyzer(language)));
+                analyzers.len() - 1
+            }
+        };
+        analyzers[position]
+            .1
+            .token_stream(text)
+            .process(&mut |token| tokens.push(token.clone()));
+    });
+    PreTokenizedString {
+        text: text.to_string(),
+        tokens,
+    }
+}
+
+/// ISO 639-3 codes of the languages with a stemmer, other than `bichon_default_language`,
+/// with the tokenizers to parse a query stemmed in each of them.
+pub fn query_stem_languages() -> Vec<(&'static str, TokenizerManager)> {
+    let default = default_stem_language();
+    Lang::all()
+        .iter()
+        .filter_map(|&lang| stem_language(lang).map(|language| (lang, language)))
+        .filter(|(_, language)| Some(*language) != default)
+        .map(|(lang, language)| {
+            let tokenizers = TokenizerManager::new();
+            tokenizers.register(STEM_TOKENIZER, stem_analyzer(Some(language)));
+            (lang.code(), tokenizers)
+        })
+        .collect()
+}
+
+fn default_stem_language() -> Option<Language> {
+    Lang::from_code(SETTINGS.bichon_default_language.as_str()).and_then(stem_language)
+}
+
+fn stem_analyzer(language: Option<Language>) -> TextAnalyzer {
+    let mut builder = TextAnalyzer::builder(CjkTokenizer)
+        .filter(RemoveLongFilter::limit(MAX_TOKEN_LENGTH))
+        .filter(LowerCaser)
+        .dynamic();
+    if let Some(language) = language {
+        builder = builder.filter_dynamic(Stemmer::new(language));
+    }
+    builder.filter_dynamic(AsciiFoldingFilter).build()
+}
+
+/// Name of the tokenizer implementing an analyzer setting.
+pub fn analyzer_name(kind: TextAnalyzerKind) -> &'static str {
+    match kind {
+        TextAnalyzerKind::Default => DEFAULT_TOKENIZER,
+        TextAnalyzerKind::AsciiFolding => FOLDING_TOKENIZER,
+        TextAnalyzerKind::Cjk => CJK_TOKENIZER,
+    }
+}
+
+/// Detects the language of a text, returned as an ISO 639-3 code such as `eng` or `cmn`.
+/// Returns `None` when the detection is not reliable, which is common for short texts.
+pub fn detect_language(text: &str) -> Option<&'static str> {
+    detect(text).map(|lang| lang.code())
+}
+
+fn detect(text: &str) -> Option<Lang> {
+    // the beginning of a long text is as telling as the whole of it
+    let text = match text.char_indices().nth(LANGUAGE_SAMPLE_CHARS) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    };
+    whatlang::detect(text)
+        .filter(|info| info.is_reliable())
+        .map(|info| info.lang())
+}
+
+/// Stemmer available for a detected language, if any.
+fn stem_language(lang: Lang) -> Option<Language> {
+    let language = match lang {
+        Lang::Ara => Language::Arabic,
+        Lang::Dan => Language::Danish,
+        Lang::Nld => Language::Dutch,
+        Lang::Eng => Language::English,
+        Lang::Fin => Language::Finnish,
+        Lang::Fra => Language::French,
+        Lang::Deu => Language::German,
+        Lang::Ell => Language::Greek,
+        Lang::Hun => Language::Hungarian,
+        Lang::Ita => Language::Italian,
+        Lang::Nob => Language::Norwegian,
+        Lang::Por => Language::Portuguese,
+        Lang::Ron => Language::Romanian,
+        Lang::Rus => Language::Russian,
+        Lang::Spa => Language::Spanish,
+        Lang::Swe => Language::Swedish,
+        Lang::Tam => Language::Tamil,
+        Lang::Tur => Language::Turkish,
+        _ => return None,
+    };
+    Some(language)
+}
+
+/// Splits `text` the same way the `default` analyzer does when indexing `TEXT` fields.
+pub fn default_terms(text: &str) -> Vec<String> {
+    let mut terms = Vec::new();
+    if let Some(mut analyzer) = TokenizerManager::default().get(DEFAULT_TOKENIZER) {
+        analyzer
+            .token_stream(text)
+            .process(&mut |token| terms.push(token.text.clone()));
+    }
+    terms
+}
+
+/// Normalizes a header value the way [`HEADER_TOKENIZER`] indexes it, once folded lines
+/// and runs of whitespace are collapsed.
+pub fn header_term(value: &str) -> String {
+    value
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase()
+}
+
+/// Normalizes a domain filter value into the token emitted by [`EmailTokenizer`].
+pub fn domain_term(domain: &str) -> String {
+    format!("@{}", domain.trim().trim_start_matches('@').to_lowercase())
+}
+
+/// Tokenizes an email address for case-insensitive matching on its parts.
+///
+/// `John.Smith@Mail.Acme.com` produces `john.smith@mail.acme.com`, `john.smith`, `john`,
+/// `smith`, `@mail.acme.com`, `@acme.com`, `mail` and `acme`, so a filter can target the
+/// full address, the local part or any of its words, or a domain including its subdomains.
+#[derive(Clone, Default)]
+pub struct EmailTokenizer;
+
+impl Tokenizer for EmailTokenizer {
+    type TokenStream<'a> = BufferedTokenStream;
+
+    fn token_stream<'a>(&'a mut self, text: &'a str) -> Self::TokenStream<'a> {
+        let mut terms: Vec<String> = Vec::new();
+        let mut push = |term: &str| {
+            if !term.is_empty() && !terms.iter().any(|t| t == term) {
+                terms.push(term.to_string());
+            }
+        };
+        let address = text.trim().to_lowercase();
+        push(&address);
+        if let Some((local, domain)) = address.rsplit_once('@') {
+            push(local);
+            for part in local.split(['.', '_', '-', '+']) {
+                push(part);
+            }
+            let labels: Vec<&str> = domain.split('.').collect();
+            // "@mail.acme.com", "@acme.com" but not the bare TLD
+            for start in 0..labels.len().saturating_sub(1).max(1) {
+                push(&format!("@{}", labels[start..].join(".")));
+            }
+            for label in labels.iter().take(labels.len().saturating_sub(1)) {
+                push(label);
+            }
+        }
+
+        let tokens = terms
+            .into_iter()
+            .enumerate()
+            .map(|(position, term)| Token {
+                offset_from: 0,
+                offset_to: text.len(),
+                position,
+                text: term,
+                position_length: 1,
+            })
+            .collect();
+        BufferedTokenStream::new(tokens)
+    }
+}
+
+/// Splits text into words like [`SimpleTokenizer`], except for runs of Chinese, Japanese or
+/// Korean characters, which are not separated by spaces and are indexed as overlapping
+/// bigrams: `東京都庁` produces `東京`, `京都` and `都庁`. A query on any word of two or more
+/// characters then matches as a phrase of its bigrams, without a dictionary.
+#[derive(Clone, Default)]
+pub struct CjkTokenizer;
+
+impl Tokenizer for CjkTokenizer {
+    type TokenStream<'a> = BufferedTokenStream;
+
+    fn token_stream<'a>(&'a mut self, text: &'a str) -> Self::TokenStream<'a> {
+        let mut tokens: Vec<Token> = Vec::new();
+        let mut push = |from: usize, to: usize| {
+            tokens.push(Token {
+                offset_from: from,
+                offset_to: to,
+                position: tokens.len(),
+                text: text[from..to].to_string(),
+                position_length: 1,
+            })
+        };
+        let mut chars = text.char_indices().peekable();
+        while let Some((start, c)) = chars.next() {
+            if is_cjk(c) {
+                let mut run = vec![(start, c)];
+                while let Some(&(i, c)) = chars.peek() {
+                    if !is_cjk(c) {
+                        break;
+                    }
+                    run.push((i, c));
+                    chars.next();
+                }
+                if run.len() == 1 {
+                    push(start, start + c.len_utf8());
+                }
+                for pair in run.windows(2) {
+                    push(pair[0].0, pair[1].0 + pair[1].1.len_utf8());
+                }
+            } else if c.is_alphanumeric() {
+                let mut end = start + c.len_utf8();
+                while let Some(&(i, c)) = chars.peek() {
+                    if is_cjk(c) || !c.is_alphanumeric() {
+                        break;
+                    }
+                    end = i + c.len_utf8();
+                    chars.next();
+                }
+                push(start, end);
+            }
+        }
+        BufferedTokenStream::new(tokens)
+    }
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(
+        c as u32,
+        0x1100..=0x11FF // Hangul Jamo
+            | 0x3040..=0x30FF // Hiragana, Katakana
+            | 0x3130..=0x318F // Hangul Compatibility Jamo
+            | 0x3400..=0x4DBF // CJK Unified Ideographs Extension A
+            | 0x4E00..=0x9FFF // CJK Unified Ideographs
+            | 0xAC00..=0xD7AF // Hangul Syllables
+            | 0xF900..=0xFAFF // CJK Compatibility Ideographs
+            | 0xFF66..=0xFF9F // Halfwidth Katakana
+            | 0x20000..=0x2FA1F // CJK Unified Ideographs Extensions B to F
+    )
+}
+
+/// Token stream over tokens computed upfront.
+pub struct BufferedTokenStream {
+    tokens: Vec<Token>,
+    index: usize,
+}
+
+impl BufferedTokenStream {
+    fn new(tokens: Vec<Token>) -> Self {
+        Self { tokens, index: 0 }
+    }
+}
+
+impl TokenStream for BufferedTokenStream {
+    fn advance(&mut self) -> bool {
+        if self.index < self.tokens.len() {
+            self.index += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn token(&self) -> &Token {
+        &self.tokens[self.index - 1]
+    }
+
+    fn token_mut(&mut self) -> &mut Token {
+        &mut self.tokens[self.index - 1]
+    }
+}
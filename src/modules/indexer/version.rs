@@ -0,0 +1,113 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tantivy::schema::Schema;
+
+use crate::{
+    calculate_hash,
+    modules::{
+        error::{code::ErrorCode, BichonResult},
+        indexer::schema::SchemaTools,
+        settings::cli::{IndexedHeaders, SETTINGS},
+    },
+    raise_error,
+};
+
+/// Version of `SchemaTools::create_envelope_schema`. Bump it whenever fields are added or
+/// their indexing changes, so existing indexes get rebuilt.
+///
+/// 1: initial schema, 2: address, display name and attachment content fields, configurable
+/// analyzers, stemming and language, 3: indexed headers, 4: archive time.
+pub const ENVELOPE_SCHEMA_VERSION: u32 = 4;
+pub const SCHEMA_VERSION_FILE: &str = "schema_version.json";
+
+/// Schema an envelope index was built with, stored next to the index files.
+///
+/// The fingerprint covers what the version number cannot, such as analyzer settings or
+/// the list of indexed headers changed between two runs.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchemaVersion {
+    pub version: u32,
+    pub fingerprint: u64,
+}
+
+impl SchemaVersion {
+    pub fn current() -> Self {
+        Self {
+            version: ENVELOPE_SCHEMA_VERSION,
+            fingerprint: fingerprint(
+                &SchemaTools::envelope_schema(),
+                &SETTINGS.bichon_indexed_headers,
+            ),
+        }
+    }
+
+    /// Indexes created before schema versioning have no version file and are reported
+    /// as version 0.
+    pub fn read(index_dir: &Path) -> Self {
+        std::fs::read(index_dir.join(SCHEMA_VERSION_FILE))
+            .ok()
+            .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+            .unwrap_or(Self {
+                version: 0,
+                fingerprint: 0,
+            })
+    }
+
+    pub fn exists(index_dir: &Path) -> bool {
+        index_dir.join(SCHEMA_VERSION_FILE).exists()
+    }
+
+    /// Writes the version file through a rename, so it is either absent or complete.
+    pub fn write(&self, index_dir: &Path) -> BichonResult<()> {
+        let json = serde_json::to_vec(self)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        let tmp = index_dir.join(format!("{}.tmp", SCHEMA_VERSION_FILE));
+        std::fs::write(&tmp, json)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        std::fs::rename(&tmp, index_dir.join(SCHEMA_VERSION_FILE))
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))
+    }
+}
+
+fn fingerprint(schema: &Schema, headers: &IndexedHeaders) -> u64 {
+    calculate_hash!(&format!(
+        "{}\n{}",
+        serde_json::to_string(schema).unwrap_or_default(),
+        headers
+    ))
+}
+
+/// An index built with an older schema can still be searched and written to while it is
+/// rebuilt, as long as the schema only grew: every field it has must still exist with the
+/// same id, name and type. Fields added since then are simply missing from it.
+pub fn is_compatible(index_schema: &Schema, schema: &Schema) -> bool {
+    index_schema.num_fields() <= schema.num_fields()
+        && index_schema
+            .fields()
+            .zip(schema.fields())
+            .all(|((old_field, old), (field, new))| {
+                old_field == field
+                    && old.name() == new.name()
+                    && old.field_type().value_type() == new.field_type().value_type()
+            })
+}
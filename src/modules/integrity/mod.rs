@@ -0,0 +1,541 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{LazyLock, RwLock},
+};
+
+use itertools::Itertools;
+use poem_openapi::{Enum, Object};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    modules::{
+        account::{
+            migration::{AccountModel, AccountType},
+            pgp::is_encrypted,
+        },
+        cache::imap::{
+            mailbox::MailBox,
+            sync::flow::{generate_uid_sequence_hashset, BATCH_SIZE},
+        },
+        common::signal::SIGNAL_MANAGER,
+        context::executors::MAIL_CONTEXT,
+        database::{list_all_impl, manager::DB_MANAGER},
+        envelope::extractor::extract_envelope_from_eml,
+        error::{code::ErrorCode, BichonResult},
+        indexer::{
+            fields::{F_ACCOUNT_ID, F_ID, F_MAILBOX_ID, F_UID},
+            manager::{EML_INDEX_MANAGER, ENVELOPE_INDEX_MANAGER},
+            reindex::envelope_document,
+        },
+        message::search::SearchFilter,
+        settings::cli::SETTINGS,
+    },
+    raise_error, utc_now,
+};
+
+/// Issues listed in a check report, others are only counted.
+const MAX_REPORTED_ISSUES: usize = 1000;
+
+pub static INTEGRITY_CHECK: LazyLock<IntegrityCheck> = LazyLock::new(IntegrityCheck::new);
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Enum)]
+pub enum IntegrityIssueKind {
+    /// An envelope whose message is not in the EML store.
+    #[default]
+    MissingEml,
+    /// A message of the EML store without an envelope, which cannot be listed or searched.
+    MissingEnvelope,
+    /// A mailbox of an account that no longer exists.
+    OrphanMailbox,
+    /// A stored message that cannot be read or parsed.
+    UnparsableEml,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct IntegrityIssue {
+    pub kind: IntegrityIssueKind,
+    pub account_id: u64,
+    pub mailbox_id: Option<u64>,
+    /// The envelope id of the message, `None` for a mailbox.
+    pub eid: Option<u64>,
+    /// The UID of the message in its mailbox, when its envelope holds one.
+    pub uid: Option<u64>,
+    /// The name of an orphan mailbox, or why a message cannot be parsed or repaired.
+    pub detail: Option<String>,
+    /// Whether the issue was repaired. A refetched message is stored again once the
+    /// server has sent it.
+    pub repaired: bool,
+}
+
+/// Repairs made by an integrity check. With none, the check only reports.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct IntegrityRepair {
+    /// Derive the missing envelopes from their stored message. Messages encrypted at rest
+    /// cannot be read by the server and are left as they are.
+    pub reindex: bool,
+    /// Fetch the messages missing from the EML store, or that cannot be parsed, from the
+    /// IMAP server again, by the UID of their envelope. In WORM mode, a stored message is
+    /// never replaced.
+    pub refetch: bool,
+    /// Remove the mailboxes of deleted accounts.
+    pub remove_orphans: bool,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct IntegrityCheckStatus {
+    pub running: bool,
+    pub repair: IntegrityRepair,
+    /// Envelopes checked so far.
+    pub envelopes: u64,
+    /// Stored messages checked so far.
+    pub messages: u64,
+    pub missing_emls: u64,
+    pub missing_envelopes: u64,
+    pub orphan_mailboxes: u64,
+    pub unparsable_emls: u64,
+    pub repaired: u64,
+    /// The first issues found.
+    pub issues: Vec<IntegrityIssue>,
+    pub started_at: Option<i64>,
+    pub finished_at: Option<i64>,
+    /// Reason the last check stopped, if it did not complete.
+    pub error: Option<String>,
+}
+
+/// Checks in the background that the envelope index, the EML store and the mailboxes
+/// agree, as a message is written to both indexes separately and a crash in between
+/// leaves only one of them.
+pub struct IntegrityCheck {
+    status: RwLock<IntegrityCheckStatus>,
+}
+
+impl IntegrityCheck {
+    fn new() -> Self {
+        Self {
+            status: RwLock::new(IntegrityCheckStatus::default()),
+        }
+    }
+
+    pub fn status(&self) -> IntegrityCheckStatus {
+        self.status.read().unwrap().clone()
+    }
+
+    /// Starts a check, making the given repairs. Fails if one is already running.
+    pub fn start(&'static self, repair: IntegrityRepair) -> BichonResult<()> {
+        {
+            let mut status = self.status.write().unwrap();
+            if status.running {
+                return Err(raise_error!(
+                    "The archive is already being checked.".into(),
+                    ErrorCode::InvalidParameter
+                ));
+            }
+            *status = IntegrityCheckStatus {
+                running: true,
+                repair,
+                started_at: Some(utc_now!()),
+                ..Default::default()
+            };
+        }
+        tokio::spawn(async move {
+            let result = self.run(repair).await;
+            let mut status = self.status.write().unwrap();
+            match result {
+                Ok(()) => tracing::info!(
+                    "Integrity check completed: {} missing EMLs, {} missing envelopes, \
+                    {} orphan mailboxes, {} unparsable EMLs, {} repaired",
+                    status.missing_emls,
+                    status.missing_envelopes,
+                    status.orphan_mailboxes,
+                    status.unparsable_emls,
+                    status.repaired
+                ),
+                Err(e) => {
+                    tracing::error!("Integrity check failed: {:#?}", e);
+                    status.error = Some(format!("{:#?}", e));
+                }
+            }
+            status.running = false;
+            status.finished_at = Some(utc_now!());
+        });
+        Ok(())
+    }
+
+    async fn run(&self, repair: IntegrityRepair) -> BichonResult<()> {
+        let mut shutdown = SIGNAL_MANAGER.subscribe();
+        let orphans = Self::orphan_mailboxes(repair.remove_orphans).await?;
+        self.report(orphans);
+
+        // mailbox and UID of every envelope, by account and envelope id
+        let mut envelopes: HashMap<(u64, u64), (u64, u64)> = HashMap::new();
+        let searcher = ENVELOPE_INDEX_MANAGER.live_searcher()?;
+        for segment_reader in searcher.segment_readers() {
+            let fast_fields = segment_reader.fast_fields();
+            let ids = fast_fields
+                .u64(F_ID)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            let accounts = fast_fields
+                .u64(F_ACCOUNT_ID)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            let mailboxes = fast_fields
+                .u64(F_MAILBOX_ID)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            let uids = fast_fields
+                .u64(F_UID)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            for doc_id in segment_reader.doc_ids_alive() {
+                let (Some(eid), Some(account_id)) = (ids.first(doc_id), accounts.first(doc_id))
+                else {
+                    continue;
+                };
+                let mailbox_id = mailboxes.first(doc_id).unwrap_or_default();
+                let uid = uids.first(doc_id).unwrap_or_default();
+                envelopes.insert((account_id, eid), (mailbox_id, uid));
+            }
+            self.status.write().unwrap().envelopes = envelopes.len() as u64;
+        }
+
+        // every stored message is read and parsed, segments holding only message bodies
+        // have no ids
+        let mut stored = HashSet::new();
+        let mut missing_envelopes = Vec::new();
+        let mut unparsable = Vec::new();
+        let searcher = EML_INDEX_MANAGER.searcher();
+        for segment_reader in searcher.segment_readers() {
+            let fast_fields = segment_reader.fast_fields();
+            let Some(ids) = fast_fields
+                .column_opt::<u64>(F_ID)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+            else {
+                continue;
+            };
+            let accounts = fast_fields
+                .u64(F_ACCOUNT_ID)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            let mailboxes = fast_fields
+                .u64(F_MAILBOX_ID)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            let alive: Vec<u32> = segment_reader.doc_ids_alive().collect();
+            for doc_id in alive {
+                if shutdown.try_recv().is_ok() {
+                    return Err(raise_error!(
+                        "Interrupted by shutdown.".into(),
+                        ErrorCode::InternalError
+                    ));
+                }
+                let (Some(eid), Some(account_id)) = (ids.first(doc_id), accounts.first(doc_id))
+                else {
+                    continue;
+                };
+                let mailbox_id = mailboxes.first(doc_id).unwrap_or_default();
+                stored.insert((account_id, eid));
+                let envelope = envelopes.get(&(account_id, eid));
+                if envelope.is_none() {
+                    missing_envelopes.push(IntegrityIssue {
+                        kind: IntegrityIssueKind::MissingEnvelope,
+                        account_id,
+                        mailbox_id: Some(mailbox_id),
+                        eid: Some(eid),
+                        ..Default::default()
+                    });
+                }
+                if let Some(detail) = Self::parse_error(account_id, mailbox_id, eid).await {
+                    unparsable.push(IntegrityIssue {
+                        kind: IntegrityIssueKind::UnparsableEml,
+                        account_id,
+                        mailbox_id: Some(mailbox_id),
+                        eid: Some(eid),
+                        uid: envelope.map(|(_, uid)| *uid).filter(|uid| *uid > 0),
+                        detail: Some(detail),
+                        ..Default::default()
+                    });
+                }
+                self.status.write().unwrap().messages += 1;
+            }
+        }
+
+        let mut missing_emls = Vec::new();
+        for ((account_id, eid), (mailbox_id, uid)) in envelopes {
+            if !stored.contains(&(account_id, eid)) {
+                missing_emls.push(IntegrityIssue {
+                    kind: IntegrityIssueKind::MissingEml,
+                    account_id,
+                    mailbox_id: Some(mailbox_id),
+                    eid: Some(eid),
+                    uid: Some(uid).filter(|uid| *uid > 0),
+                    ..Default::default()
+                });
+            }
+        }
+        // messages being stored or deleted while the indexes were read are not issues
+        let missing_emls = Self::still_missing_emls(missing_emls).await?;
+        let mut missing_envelopes = Self::still_missing_envelopes(missing_envelopes).await?;
+
+        if repair.reindex {
+            for issue in missing_envelopes.iter_mut() {
+                Self::reindex(issue).await;
+            }
+        }
+        let mut refetched: Vec<IntegrityIssue> = missing_emls;
+        if SETTINGS.bichon_worm_mode {
+            self.report(unparsable);
+        } else {
+            refetched.extend(unparsable);
+        }
+        if repair.refetch {
+            Self::refetch(&mut refetched).await;
+        }
+        self.report(missing_envelopes);
+        self.report(refetched);
+        Ok(())
+    }
+
+    fn report(&self, issues: Vec<IntegrityIssue>) {
+        let mut status = self.status.write().unwrap();
+        for issue in issues {
+            tracing::warn!(
+                "Integrity check: {:?} in account {}, mailbox {:?}, message {:?}{}",
+                issue.kind,
+                issue.account_id,
+                issue.mailbox_id,
+                issue.eid,
+                if issue.repaired { ", repaired" } else { "" }
+            );
+            match issue.kind {
+                IntegrityIssueKind::MissingEml => status.missing_emls += 1,
+                IntegrityIssueKind::MissingEnvelope => status.missing_envelopes += 1,
+                IntegrityIssueKind::OrphanMailbox => status.orphan_mailboxes += 1,
+                IntegrityIssueKind::UnparsableEml => status.unparsable_emls += 1,
+            }
+            if issue.repaired {
+                status.repaired += 1;
+            }
+            if status.issues.len() < MAX_REPORTED_ISSUES {
+                status.issues.push(issue);
+            }
+        }
+    }
+
+    /// Mailboxes whose account no longer exists, removed with `remove`.
+    async fn orphan_mailboxes(remove: bool) -> BichonResult<Vec<IntegrityIssue>> {
+        let accounts: HashSet<u64> = AccountModel::list_all()
+            .await?
+            .into_iter()
+            .map(|a| a.id)
+            .collect();
+        let mailboxes: Vec<MailBox> = list_all_impl(DB_MANAGER.envelope_db()).await?;
+        let mut issues: Vec<IntegrityIssue> = mailboxes
+            .into_iter()
+            .filter(|m| !accounts.contains(&m.account_id))
+            .map(|m| IntegrityIssue {
+                kind: IntegrityIssueKind::OrphanMailbox,
+                account_id: m.account_id,
+                mailbox_id: Some(m.id),
+                detail: Some(m.name),
+                ..Default::default()
+            })
+            .collect();
+        if remove {
+            let account_ids: Vec<u64> = issues.iter().map(|i| i.account_id).unique().collect();
+            for account_id in account_ids {
+                let result = MailBox::clean(account_id).await;
+                for issue in issues.iter_mut().filter(|i| i.account_id == account_id) {
+                    match &result {
+                        Ok(()) => issue.repaired = true,
+                        Err(e) => issue.detail = Some(format!("{:#?}", e)),
+                    }
+                }
+            }
+        }
+        Ok(issues)
+    }
+
+    /// Why stored message `eid` cannot be read or parsed, if it cannot. Messages encrypted
+    /// at rest are only read.
+    async fn parse_error(account_id: u64, mailbox_id: u64, eid: u64) -> Option<String> {
+        match EML_INDEX_MANAGER.get(account_id, eid).await {
+            Ok(Some(eml)) if is_encrypted(&eml) => None,
+            Ok(Some(eml)) => extract_envelope_from_eml(&eml, account_id, mailbox_id)
+                .err()
+                .map(|e| format!("{:#?}", e)),
+            Ok(None) => None,
+            Err(e) => Some(format!("{:#?}", e)),
+        }
+    }
+
+    /// The issues whose envelope still exists and whose message is still not stored.
+    async fn still_missing_emls(issues: Vec<IntegrityIssue>) -> BichonResult<Vec<IntegrityIssue>> {
+        let existing = Self::existing_envelopes(&issues).await?;
+        let mut missing = Vec::new();
+        for issue in issues {
+            let Some(eid) = issue.eid else {
+                continue;
+            };
+            if existing.contains(&(issue.account_id, eid)) && !EML_INDEX_MANAGER.contains(eid)? {
+                missing.push(issue);
+            }
+        }
+        Ok(missing)
+    }
+
+    /// The issues whose envelope is still missing.
+    async fn still_missing_envelopes(
+        issues: Vec<IntegrityIssue>,
+    ) -> BichonResult<Vec<IntegrityIssue>> {
+        let existing = Self::existing_envelopes(&issues).await?;
+        Ok(issues
+            .into_iter()
+            .filter(|issue| {
+                !issue
+                    .eid
+                    .is_some_and(|eid| existing.contains(&(issue.account_id, eid)))
+            })
+            .collect())
+    }
+
+    /// Account and envelope id of the envelopes of `issues` found in the envelope index.
+    async fn existing_envelopes(issues: &[IntegrityIssue]) -> BichonResult<HashSet<(u64, u64)>> {
+        let mut ids: HashMap<u64, Vec<u64>> = HashMap::new();
+        for issue in issues {
+            if let Some(eid) = issue.eid {
+                ids.entry(issue.account_id).or_default().push(eid);
+            }
+        }
+        let existing = ENVELOPE_INDEX_MANAGER
+            .matching_among(&ids, SearchFilter::default())
+            .await?;
+        Ok(existing
+            .into_iter()
+            .flat_map(|(account_id, eids)| eids.into_iter().map(move |eid| (account_id, eid)))
+            .collect())
+    }
+
+    /// Derives the missing envelope of a stored message.
+    async fn reindex(issue: &mut IntegrityIssue) {
+        let (Some(mailbox_id), Some(eid)) = (issue.mailbox_id, issue.eid) else {
+            return;
+        };
+        let eml = match EML_INDEX_MANAGER.get(issue.account_id, eid).await {
+            Ok(Some(eml)) => eml,
+            Ok(None) => return,
+            Err(e) => {
+                issue.detail = Some(format!("{:#?}", e));
+                return;
+            }
+        };
+        if is_encrypted(&eml) {
+            issue.detail = Some("encrypted at rest, the envelope cannot be derived".into());
+            return;
+        }
+        match envelope_document(&eml, issue.account_id, mailbox_id, eid).await {
+            Ok(document) => {
+                ENVELOPE_INDEX_MANAGER.add_document(eid, document).await;
+                issue.repaired = true;
+            }
+            Err(e) => issue.detail = Some(format!("{:#?}", e)),
+        }
+    }
+
+    /// Fetches the messages of `issues` from the IMAP server again, by mailbox.
+    async fn refetch(issues: &mut [IntegrityIssue]) {
+        let mut mailboxes: HashMap<(u64, u64), Vec<usize>> = HashMap::new();
+        for (index, issue) in issues.iter_mut().enumerate() {
+            match (issue.mailbox_id, issue.uid) {
+                (Some(mailbox_id), Some(_)) => mailboxes
+                    .entry((issue.account_id, mailbox_id))
+                    .or_default()
+                    .push(index),
+                _ => {
+                    if issue.detail.is_none() {
+                        issue.detail = Some("no UID to fetch the message by".into());
+                    }
+                }
+            }
+        }
+        for ((account_id, mailbox_id), indexes) in mailboxes {
+            let uids: Vec<u32> = indexes
+                .iter()
+                .filter_map(|index| issues[*index].uid)
+                .map(|uid| uid as u32)
+                .sorted()
+                .collect();
+            let result = Self::refetch_mailbox(account_id, mailbox_id, uids).await;
+            for index in indexes {
+                match &result {
+                    Ok(()) => issues[index].repaired = true,
+                    Err(e) => issues[index].detail = Some(format!("{:#?}", e)),
+                }
+            }
+        }
+        // the server may return nothing for a UID, a message only counts as repaired once
+        // it is stored and parses
+        EML_INDEX_MANAGER.flush().await;
+        for issue in issues.iter_mut().filter(|issue| issue.repaired) {
+            let (Some(mailbox_id), Some(eid)) = (issue.mailbox_id, issue.eid) else {
+                continue;
+            };
+            // also reloads the EML store, for the message to be parsed again
+            let detail = match EML_INDEX_MANAGER.contains(eid) {
+                Ok(true) => Self::parse_error(issue.account_id, mailbox_id, eid).await,
+                Ok(false) => Some("the server returned no message for its UID".into()),
+                Err(e) => Some(format!("{:#?}", e)),
+            };
+            if detail.is_some() {
+                issue.repaired = false;
+                issue.detail = detail;
+            }
+        }
+    }
+
+    async fn refetch_mailbox(account_id: u64, mailbox_id: u64, uids: Vec<u32>) -> BichonResult<()> {
+        let account = AccountModel::get(account_id).await?;
+        if !matches!(account.account_type, AccountType::IMAP) {
+            return Err(raise_error!(
+                format!(
+                    "Account {} is not synchronized from an IMAP server",
+                    account_id
+                ),
+                ErrorCode::InvalidParameter
+            ));
+        }
+        let mailbox = MailBox::list_all(account_id)
+            .await?
+            .into_iter()
+            .find(|m| m.id == mailbox_id)
+            .ok_or_else(|| {
+                raise_error!(
+                    format!(
+                        "Mailbox with id={} not found in account {}",
+                        mailbox_id, account_id
+                    ),
+                    ErrorCode::ResourceNotFound
+                )
+            })?;
+        let executor = MAIL_CONTEXT.imap(account_id).await?;
+        for batch in generate_uid_sequence_hashset(uids, BATCH_SIZE as usize, false) {
+            executor
+                .uid_batch_retrieve_emails(account_id, mailbox_id, &batch, &mailbox.encoded_name())
+                .await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,455 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, LazyLock, RwLock},
+};
+
+use itertools::Itertools;
+use native_db::*;
+use native_model::{native_model, Model};
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    id,
+    modules::{
+        account::migration::AccountModel,
+        cache::imap::mailbox::MailBox,
+        context::Initialize,
+        database::{delete_impl, insert_impl, list_all_impl, manager::DB_MANAGER},
+        error::{code::ErrorCode, BichonResult},
+        indexer::{envelope::Envelope, manager::ENVELOPE_INDEX_MANAGER},
+        message::{
+            saved::{filter_json, SavedSearch},
+            search::SearchFilter,
+        },
+    },
+    raise_error, utc_now,
+};
+
+pub static LEGAL_HOLDS: LazyLock<LegalHolds> = LazyLock::new(LegalHolds::new);
+
+/// Pins the messages of an account, of a mailbox or matching a saved search: they cannot
+/// be deleted, by the API, retention or synchronization, until the hold is released.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+#[native_model(id = 13, version = 1)]
+#[native_db]
+pub struct LegalHold {
+    /// The unique identifier for this hold.
+    #[primary_key]
+    pub id: u64,
+    pub name: String,
+    /// Case or matter the hold was placed for.
+    pub description: Option<String>,
+    pub account_id: Option<u64>,
+    pub mailbox_id: Option<u64>,
+    pub saved_search_id: Option<u64>,
+    /// Messages pinned by the hold. For a saved search, its filter when the hold was
+    /// placed, so that editing or deleting the search does not release any message.
+    #[serde(with = "filter_json")]
+    pub filter: SearchFilter,
+    /// Owner id of the access token that placed the hold, or `root`.
+    pub created_by: String,
+    /// The creation timestamp of this record, represented as milliseconds since the Unix epoch.
+    pub created_at: i64,
+}
+
+/// Set `account_id` to hold an account, `account_id` and `mailbox_id` to hold a mailbox,
+/// or only `saved_search_id` to hold the messages matching a saved search.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct LegalHoldRequest {
+    #[oai(validator(min_length = "1", max_length = "128"))]
+    pub name: String,
+    pub description: Option<String>,
+    pub account_id: Option<u64>,
+    pub mailbox_id: Option<u64>,
+    pub saved_search_id: Option<u64>,
+}
+
+impl LegalHold {
+    pub async fn new(created_by: String, request: LegalHoldRequest) -> BichonResult<Self> {
+        let filter = match (
+            request.account_id,
+            request.mailbox_id,
+            request.saved_search_id,
+        ) {
+            (Some(account_id), mailbox_id, None) => {
+                AccountModel::check_account_exists(account_id).await?;
+                if let Some(mailbox_id) = mailbox_id {
+                    let mailboxes = MailBox::list_all(account_id).await?;
+                    if !mailboxes.iter().any(|m| m.id == mailbox_id) {
+                        return Err(raise_error!(
+                            format!(
+                                "Mailbox with id={} not found in account {}",
+                                mailbox_id, account_id
+                            ),
+                            ErrorCode::ResourceNotFound
+                        ));
+                    }
+                }
+                SearchFilter {
+                    account_id: Some(account_id),
+                    mailbox_id,
+                    ..Default::default()
+                }
+            }
+            (None, None, Some(saved_search_id)) => {
+                SavedSearch::find(saved_search_id)
+                    .await?
+                    .ok_or_else(|| {
+                        raise_error!(
+                            format!("Saved search with id={} not found", saved_search_id),
+                            ErrorCode::ResourceNotFound
+                        )
+                    })?
+                    .filter
+            }
+            _ => {
+                return Err(raise_error!(
+                    "Set either account_id, with an optional mailbox_id, or saved_search_id."
+                        .into(),
+                    ErrorCode::InvalidParameter
+                ))
+            }
+        };
+        Ok(Self {
+            id: id!(64),
+            name: request.name,
+            description: request.description,
+            account_id: request.account_id,
+            mailbox_id: request.mailbox_id,
+            saved_search_id: request.saved_search_id,
+            filter,
+            created_by,
+            created_at: utc_now!(),
+        })
+    }
+
+    pub async fn list() -> BichonResult<Vec<LegalHold>> {
+        let holds: Vec<LegalHold> = list_all_impl(DB_MANAGER.meta_db()).await?;
+        Ok(holds.into_iter().sorted_by_key(|h| h.created_at).collect())
+    }
+
+    pub async fn save(&self) -> BichonResult<()> {
+        insert_impl(DB_MANAGER.meta_db(), self.to_owned()).await?;
+        tracing::info!(
+            "Legal hold '{}' ({}) placed by {}",
+            self.name,
+            self.id,
+            self.created_by
+        );
+        LEGAL_HOLDS.reload().await
+    }
+
+    /// Releases a hold; its messages can be deleted again unless another hold pins them.
+    pub async fn release(id: u64, released_by: &str) -> BichonResult<()> {
+        delete_impl(DB_MANAGER.meta_db(), move |rw| {
+            rw.get()
+                .primary::<LegalHold>(id)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                .ok_or_else(|| {
+                    raise_error!(
+                        format!("Legal hold with id={} not found", id),
+                        ErrorCode::ResourceNotFound
+                    )
+                })
+        })
+        .await?;
+        tracing::info!("Legal hold {} released by {}", id, released_by);
+        LEGAL_HOLDS.reload().await
+    }
+}
+
+/// Legal holds, kept in memory so that deletes do not read the meta database when there
+/// are none.
+pub struct LegalHolds {
+    holds: RwLock<Arc<Vec<LegalHold>>>,
+}
+
+impl Initialize for LegalHolds {
+    async fn initialize() -> BichonResult<()> {
+        LEGAL_HOLDS.reload().await
+    }
+}
+
+impl LegalHolds {
+    fn new() -> Self {
+        Self {
+            holds: RwLock::new(Arc::new(Vec::new())),
+        }
+    }
+
+    pub fn holds(&self) -> Arc<Vec<LegalHold>> {
+        self.holds.read().unwrap().clone()
+    }
+
+    pub async fn reload(&self) -> BichonResult<()> {
+        let holds = LegalHold::list().await?;
+        *self.holds.write().unwrap() = Arc::new(holds);
+        Ok(())
+    }
+
+    /// Filters of the held messages, to leave them out of bulk deletions.
+    pub fn filters(&self) -> Vec<SearchFilter> {
+        self.holds().iter().map(|h| h.filter.clone()).collect()
+    }
+
+    /// The messages of `ids`, envelope ids by account, pinned by a hold, along with the
+    /// holds pinning each of them.
+    async fn held(
+        &self,
+        ids: &HashMap<u64, Vec<u64>>,
+    ) -> BichonResult<HashMap<(u64, u64), Vec<u64>>> {
+        let mut held: HashMap<(u64, u64), Vec<u64>> = HashMap::new();
+        for hold in self.holds().iter() {
+            let matching = ENVELOPE_INDEX_MANAGER
+                .matching_among(ids, hold.filter.clone())
+                .await?;
+            for (account_id, eids) in matching {
+                for eid in eids {
+                    held.entry((account_id, eid)).or_default().push(hold.id);
+                }
+            }
+        }
+        Ok(held)
+    }
+
+    /// Fails if any message of `ids`, envelope ids by account, is pinned by a hold.
+    pub async fn ensure_not_held(&self, ids: &HashMap<u64, Vec<u64>>) -> BichonResult<()> {
+        if self.holds().is_empty() {
+            return Ok(());
+        }
+        let held = self.held(ids).await?;
+        if held.is_empty() {
+            return Ok(());
+        }
+        let holds: HashSet<u64> = held.values().flatten().copied().collect();
+        Err(raise_error!(
+            format!(
+                "{} of the messages are under legal hold {:?} and cannot be deleted",
+                held.len(),
+                holds.into_iter().sorted().collect::<Vec<_>>()
+            ),
+            ErrorCode::LegalHold
+        ))
+    }
+
+    /// Fails if a hold pins the account, one of its mailboxes or any of its messages.
+    pub async fn ensure_account_not_held(&self, account_id: u64) -> BichonResult<()> {
+        for hold in self.holds().iter() {
+            let held = match hold.filter.account_id {
+                Some(held_account) => held_account == account_id,
+                None => {
+                    let mut filter = hold.filter.clone();
+                    filter.account_id = Some(account_id);
+                    ENVELOPE_INDEX_MANAGER.count(filter).await? > 0
+                }
+            };
+            if held {
+                return Err(raise_error!(
+                    format!(
+                        "Account {} is under legal hold '{}' ({}) and cannot be deleted",
+                        account_id, hold.name, hold.id
+                    ),
+                    ErrorCode::LegalHold
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets `Envelope::legal_holds` on the envelopes pinned by a hold.
+    pub async fn annotate(&self, envelopes: &mut [Envelope]) -> BichonResult<()> {
+        if self.holds().is_empty() || envelopes.is_empty() {
+            return Ok(());
+        }
+        let mut ids: HashMap<u64, Vec<u64>> = HashMap::new();
+        for envelope in envelopes.iter() {
+            ids.entry(envelope.account_id)
+                .or_default()
+                .push(envelope.id);
+        }
+        let mut held = self.held(&ids).await?;
+        for envelope in envelopes {
+            envelope.legal_holds = held.remove(&(envelope.account_id, envelope.id));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use super::{LegalHold, LegalHoldRequest, LEGAL_HOLDS};
+    use crate::{
+        id,
+        modules::{
+            error::code::ErrorCode,
+            indexer::manager::{EML_INDEX_MANAGER, ENVELOPE_INDEX_MANAGER},
+            message::{
+                delete::delete_messages_impl,
+                saved::{SavedSearch, SavedSearchRequest},
+                search::SearchFilter,
+            },
+            testing::{self, error_code},
+        },
+        utc_now,
+    };
+
+    const DATE: &str = "Mon, 1 Jan 2024 10:00:00 +0000";
+
+    fn saved_search(filter: SearchFilter) -> SavedSearchRequest {
+        SavedSearchRequest {
+            name: "counsel".into(),
+            filter,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_hold_blocks_deletes_until_released() {
+        testing::run(async {
+            let account_id = testing::account_id();
+            let counsel = testing::eml("counsel@acme.com", "ceo@acme.com", "Merger", DATE, "");
+            let news = testing::eml("news@shop.com", "ceo@acme.com", "Sale", DATE, "");
+            let (counsel_id, news_id) = (id!(64), id!(64));
+            testing::archive(account_id, 1, counsel_id, &counsel).await;
+            testing::archive(account_id, 1, news_id, &news).await;
+            testing::commit().await;
+
+            let search = SavedSearch::new(
+                "root".into(),
+                saved_search(SearchFilter {
+                    account_id: Some(account_id),
+                    from: Some("counsel@acme.com".into()),
+                    ..Default::default()
+                }),
+            );
+            search.save().await.unwrap();
+            let hold = LegalHold::new(
+                "root".into(),
+                LegalHoldRequest {
+                    name: "merger".into(),
+                    saved_search_id: Some(search.id),
+                    ..Default::default()
+                },
+            )
+            .await
+            .unwrap();
+            hold.save().await.unwrap();
+            // the hold keeps the filter the search had when it was placed
+            SavedSearch::update(
+                "root",
+                search.id,
+                saved_search(SearchFilter {
+                    account_id: Some(account_id),
+                    from: Some("nobody@acme.com".into()),
+                    ..Default::default()
+                }),
+            )
+            .await
+            .unwrap();
+
+            let held = HashMap::from([(account_id, vec![counsel_id, news_id])]);
+            assert_eq!(
+                error_code(delete_messages_impl(held.clone()).await),
+                ErrorCode::LegalHold
+            );
+            assert!(EML_INDEX_MANAGER.contains(counsel_id).unwrap());
+            delete_messages_impl(HashMap::from([(account_id, vec![news_id])]))
+                .await
+                .unwrap();
+
+            LegalHold::release(hold.id, "root").await.unwrap();
+            LEGAL_HOLDS.ensure_not_held(&held).await.unwrap();
+            delete_messages_impl(HashMap::from([(account_id, vec![counsel_id])]))
+                .await
+                .unwrap();
+            let filter = SearchFilter {
+                account_id: Some(account_id),
+                ..Default::default()
+            };
+            assert_eq!(ENVELOPE_INDEX_MANAGER.count(filter).await.unwrap(), 0);
+            assert!(!EML_INDEX_MANAGER.contains(counsel_id).unwrap());
+        })
+    }
+
+    #[test]
+    fn test_hold_blocks_account_deletes() {
+        testing::run(async {
+            let held_account = testing::account_id();
+            let searched_account = testing::account_id();
+            let other_account = testing::account_id();
+            let sender = format!("{}@counsel.com", id!(64));
+            let counsel = testing::eml(&sender, "ceo@acme.com", "Merger", DATE, "");
+            testing::archive(searched_account, 1, id!(64), &counsel).await;
+            testing::commit().await;
+
+            let account_hold = LegalHold {
+                id: id!(64),
+                name: "account".into(),
+                account_id: Some(held_account),
+                filter: SearchFilter {
+                    account_id: Some(held_account),
+                    ..Default::default()
+                },
+                created_by: "root".into(),
+                created_at: utc_now!(),
+                ..Default::default()
+            };
+            account_hold.save().await.unwrap();
+            // a hold across accounts pins the accounts holding one of its messages
+            let sender_hold = LegalHold {
+                id: id!(64),
+                name: "sender".into(),
+                filter: SearchFilter {
+                    from: Some(sender),
+                    ..Default::default()
+                },
+                created_by: "root".into(),
+                created_at: utc_now!(),
+                ..Default::default()
+            };
+            sender_hold.save().await.unwrap();
+
+            for account_id in [held_account, searched_account] {
+                assert_eq!(
+                    error_code(LEGAL_HOLDS.ensure_account_not_held(account_id).await),
+                    ErrorCode::LegalHold
+                );
+            }
+            LEGAL_HOLDS
+                .ensure_account_not_held(other_account)
+                .await
+                .unwrap();
+
+            LegalHold::release(account_hold.id, "root").await.unwrap();
+            LegalHold::release(sender_hold.id, "root").await.unwrap();
+            for account_id in [held_account, searched_account] {
+                LEGAL_HOLDS
+                    .ensure_account_not_held(account_id)
+                    .await
+                    .unwrap();
+            }
+        })
+    }
+}
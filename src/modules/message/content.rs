@@ -19,6 +19,8 @@
 
 use crate::base64_encode;
 use crate::modules::account::migration::AccountModel;
+use crate::modules::account::pgp::PgpSecretKey;
+use crate::modules::envelope::headers::raw_header_block;
 use crate::modules::error::code::ErrorCode;
 use crate::modules::indexer::manager::EML_INDEX_MANAGER;
 use crate::{modules::error::BichonResult, raise_error};
@@ -64,13 +66,37 @@ pub struct FullMessageContent {
     pub attachments: Option<Vec<AttachmentInfo>>,
 }
 
+/// Returns the raw header block of a stored message, folded lines and encoded words
+/// included.
+pub async fn retrieve_raw_headers(
+    account_id: u64,
+    id: u64,
+    key: Option<&PgpSecretKey>,
+) -> BichonResult<String> {
+    AccountModel::check_account_exists(account_id).await?;
+    let eml = EML_INDEX_MANAGER
+        .get_plain(account_id, id, key)
+        .await?
+        .ok_or_else(|| {
+            raise_error!(
+                format!(
+                    "Email record not found: account_id={} id={}",
+                    account_id, id
+                ),
+                ErrorCode::ResourceNotFound
+            )
+        })?;
+    Ok(String::from_utf8_lossy(raw_header_block(&eml)).into_owned())
+}
+
 pub async fn retrieve_email_content(
     account_id: u64,
     id: u64,
+    key: Option<&PgpSecretKey>,
 ) -> BichonResult<FullMessageContent> {
     AccountModel::check_account_exists(account_id).await?;
     let eml = EML_INDEX_MANAGER
-        .get(account_id, id)
+        .get_plain(account_id, id, key)
         .await?
         .ok_or_else(|| {
             raise_error!(
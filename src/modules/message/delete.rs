@@ -19,13 +19,40 @@
 
 use crate::modules::error::BichonResult;
 use crate::modules::indexer::manager::{EML_INDEX_MANAGER, ENVELOPE_INDEX_MANAGER};
+use crate::modules::legal_hold::LEGAL_HOLDS;
+use crate::modules::settings::cli::SETTINGS;
+use crate::modules::worm::{ensure_writable, LedgerAction, WORM_LEDGER};
 use std::collections::HashMap;
 
 pub async fn delete_messages_impl(request: HashMap<u64, Vec<u64>>) -> BichonResult<()> {
+    ensure_writable("messages")?;
+    LEGAL_HOLDS.ensure_not_held(&request).await?;
+    remove_messages(&request).await
+}
+
+/// Deletes messages whose retention rule expired, the only deletion allowed in WORM mode,
+/// where each purge is recorded in the ledger before the messages are removed: a purge
+/// that fails midway leaves recorded messages behind, never unrecorded deletions.
+pub async fn purge_expired_messages(request: HashMap<u64, Vec<u64>>) -> BichonResult<()> {
+    LEGAL_HOLDS.ensure_not_held(&request).await?;
+    if SETTINGS.bichon_worm_mode {
+        let records = request
+            .iter()
+            .flat_map(|(account_id, eids)| {
+                eids.iter()
+                    .map(|eid| (LedgerAction::Purge, *account_id, *eid, String::new()))
+            })
+            .collect();
+        WORM_LEDGER.append(records).await?;
+    }
+    remove_messages(&request).await
+}
+
+async fn remove_messages(request: &HashMap<u64, Vec<u64>>) -> BichonResult<()> {
     EML_INDEX_MANAGER
-        .delete_email_multi_account(&request)
+        .delete_email_multi_account(request)
         .await?;
     ENVELOPE_INDEX_MANAGER
-        .delete_envelopes_multi_account(&request)
+        .delete_envelopes_multi_account(request)
         .await
 }
@@ -22,6 +22,7 @@ use crate::{
         account::migration::AccountModel,
         error::{code::ErrorCode, BichonResult},
         indexer::{envelope::Envelope, manager::ENVELOPE_INDEX_MANAGER},
+        legal_hold::LEGAL_HOLDS,
         rest::response::DataPage,
     },
     raise_error,
@@ -35,9 +36,11 @@ pub async fn list_messages_impl(
 ) -> BichonResult<DataPage<Envelope>> {
     AccountModel::check_account_exists(account_id).await?;
     validate_pagination_params(page, page_size)?;
-    ENVELOPE_INDEX_MANAGER
+    let mut result = ENVELOPE_INDEX_MANAGER
         .list_mailbox_envelopes(account_id, mailbox_id, page, page_size, true)
-        .await
+        .await?;
+    LEGAL_HOLDS.annotate(&mut result.items).await?;
+    Ok(result)
 }
 
 fn validate_pagination_params(page: u64, page_size: u64) -> BichonResult<()> {
@@ -63,7 +66,9 @@ pub async fn get_thread_messages(
     page_size: u64,
 ) -> BichonResult<DataPage<Envelope>> {
     AccountModel::check_account_exists(account_id).await?;
-    ENVELOPE_INDEX_MANAGER
+    let mut result = ENVELOPE_INDEX_MANAGER
         .list_thread_envelopes(account_id, thread_id, page, page_size, true)
-        .await
+        .await?;
+    LEGAL_HOLDS.annotate(&mut result.items).await?;
+    Ok(result)
 }
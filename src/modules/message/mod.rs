@@ -20,5 +20,7 @@
 pub mod content;
 pub mod delete;
 pub mod list;
+pub mod saved;
 pub mod search;
+pub mod similar;
 pub mod tags;
@@ -0,0 +1,318 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use itertools::Itertools;
+use native_db::*;
+use native_model::{native_model, Model};
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    id,
+    modules::{
+        common::auth::ClientContext,
+        database::{
+            async_find_impl, batch_delete_impl, delete_impl, filter_by_secondary_key_impl,
+            insert_impl, manager::DB_MANAGER, update_impl,
+        },
+        error::{code::ErrorCode, BichonResult},
+        indexer::manager::ENVELOPE_INDEX_MANAGER,
+        message::search::{
+            search_messages_impl, SearchFilter, SearchRequest, SearchResult, SortField, SortOrder,
+        },
+    },
+    raise_error, utc_now,
+};
+
+/// A named search filter, private to the access token that created it. The WebUI shows
+/// saved searches as virtual folders.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+#[native_model(id = 9, version = 1)]
+#[native_db]
+pub struct SavedSearch {
+    /// The unique identifier for this saved search.
+    #[primary_key]
+    pub id: u64,
+    /// Owner id of the access token owning this saved search, or `root`.
+    /// See `AccessToken::owner_id`.
+    #[secondary_key]
+    #[oai(skip)]
+    pub owner: String,
+    pub name: String,
+    /// Stored as JSON, so that searches saved before `SearchFilter` gained a field still load.
+    #[serde(with = "filter_json")]
+    pub filter: SearchFilter,
+    /// Defaults to `InternalDate`.
+    pub sort_by: Option<SortField>,
+    /// Defaults to `Desc`.
+    pub sort_order: Option<SortOrder>,
+    /// The creation timestamp of this record, represented as milliseconds since the Unix epoch.
+    pub created_at: i64,
+    /// The last update timestamp of this record, represented as milliseconds since the Unix epoch.
+    pub updated_at: i64,
+    /// When the search was last executed, represented as milliseconds since the Unix epoch.
+    pub last_viewed_at: Option<i64>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct SavedSearchRequest {
+    #[oai(validator(min_length = "1", max_length = "128"))]
+    pub name: String,
+    pub filter: SearchFilter,
+    pub sort_by: Option<SortField>,
+    pub sort_order: Option<SortOrder>,
+}
+
+/// A saved search along with the number of messages it matches that arrived since it was
+/// last viewed.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct SavedSearchFolder {
+    pub search: SavedSearch,
+    /// Matching messages archived after `last_viewed_at`; every matching message if the
+    /// search was never viewed.
+    pub unread: u64,
+}
+
+impl SavedSearch {
+    pub fn new(owner: String, request: SavedSearchRequest) -> Self {
+        Self {
+            id: id!(64),
+            owner,
+            name: request.name,
+            filter: request.filter,
+            sort_by: request.sort_by,
+            sort_order: request.sort_order,
+            created_at: utc_now!(),
+            updated_at: utc_now!(),
+            last_viewed_at: None,
+        }
+    }
+
+    /// Returns the saved search `id` if it belongs to `owner`.
+    pub async fn get(owner: &str, id: u64) -> BichonResult<SavedSearch> {
+        async_find_impl::<SavedSearch>(DB_MANAGER.meta_db(), id)
+            .await?
+            .filter(|s| s.owner == owner)
+            .ok_or_else(|| {
+                raise_error!(
+                    format!("Saved search with id={} not found", id),
+                    ErrorCode::ResourceNotFound
+                )
+            })
+    }
+
+    /// Returns the saved search `id` whatever its owner.
+    pub async fn find(id: u64) -> BichonResult<Option<SavedSearch>> {
+        async_find_impl(DB_MANAGER.meta_db(), id).await
+    }
+
+    pub async fn list(owner: &str) -> BichonResult<Vec<SavedSearch>> {
+        let searches: Vec<SavedSearch> = filter_by_secondary_key_impl(
+            DB_MANAGER.meta_db(),
+            SavedSearchKey::owner,
+            owner.to_string(),
+        )
+        .await?;
+        // `start_with` on a string key also matches longer owners sharing the prefix
+        Ok(searches
+            .into_iter()
+            .filter(|s| s.owner == owner)
+            .sorted_by(|a, b| a.name.cmp(&b.name))
+            .collect())
+    }
+
+    pub async fn save(&self) -> BichonResult<()> {
+        insert_impl(DB_MANAGER.meta_db(), self.to_owned()).await
+    }
+
+    pub async fn update(owner: &str, id: u64, request: SavedSearchRequest) -> BichonResult<()> {
+        Self::get(owner, id).await?;
+        update_impl(
+            DB_MANAGER.meta_db(),
+            move |rw| {
+                rw.get()
+                    .primary::<SavedSearch>(id)
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                    .ok_or_else(|| {
+                        raise_error!(
+                            format!("Saved search with id={} not found", id),
+                            ErrorCode::ResourceNotFound
+                        )
+                    })
+            },
+            move |current| {
+                let mut updated = current.clone();
+                updated.name = request.name;
+                updated.filter = request.filter;
+                updated.sort_by = request.sort_by;
+                updated.sort_order = request.sort_order;
+                updated.updated_at = utc_now!();
+                Ok(updated)
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    pub async fn delete(owner: &str, id: u64) -> BichonResult<()> {
+        Self::get(owner, id).await?;
+        delete_impl(DB_MANAGER.meta_db(), move |rw| {
+            rw.get()
+                .primary::<SavedSearch>(id)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                .ok_or_else(|| {
+                    raise_error!("saved search missing".into(), ErrorCode::InternalError)
+                })
+        })
+        .await
+    }
+
+    /// Deletes every saved search of `owner`, when its access token is deleted.
+    pub async fn clean(owner: &str) -> BichonResult<()> {
+        let owner = owner.to_string();
+        batch_delete_impl(DB_MANAGER.meta_db(), move |rw| {
+            let searches: Vec<SavedSearch> = rw
+                .scan()
+                .secondary::<SavedSearch>(SavedSearchKey::owner)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                .start_with(owner.clone())
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                .try_collect()
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            Ok(searches.into_iter().filter(|s| s.owner == owner).collect())
+        })
+        .await?;
+        Ok(())
+    }
+
+    /// Runs the search and records it as viewed.
+    pub async fn execute(&self, page: u64, page_size: u64) -> BichonResult<SearchResult> {
+        let request = SearchRequest::new(
+            self.filter.clone(),
+            page,
+            page_size,
+            self.sort_by,
+            self.sort_order,
+        );
+        let result = search_messages_impl(request).await?;
+        let id = self.id;
+        update_impl(
+            DB_MANAGER.meta_db(),
+            move |rw| {
+                rw.get()
+                    .primary::<SavedSearch>(id)
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                    .ok_or_else(|| {
+                        raise_error!(
+                            format!("Saved search with id={} not found", id),
+                            ErrorCode::ResourceNotFound
+                        )
+                    })
+            },
+            |current| {
+                let mut updated = current.clone();
+                updated.last_viewed_at = Some(utc_now!());
+                Ok(updated)
+            },
+        )
+        .await?;
+        Ok(result)
+    }
+
+    /// Number of matching messages archived after the search was last viewed, whatever
+    /// their date: a mailbox synced late still shows its old mail as unread.
+    pub async fn unread(&self) -> BichonResult<u64> {
+        let mut filter = self.filter.clone();
+        if let Some(viewed) = self.last_viewed_at {
+            filter.archived_since = Some(
+                filter
+                    .archived_since
+                    .map_or(viewed + 1, |since| since.max(viewed + 1)),
+            );
+        }
+        ENVELOPE_INDEX_MANAGER.count(filter).await
+    }
+
+    /// Checks that the caller may run `filter`. Searching across accounts requires root, so
+    /// other tokens must restrict their saved searches to one of their accounts.
+    pub fn authorize(context: &ClientContext, filter: &SearchFilter) -> BichonResult<()> {
+        let Some(accounts) = context.accessible_accounts()? else {
+            return Ok(());
+        };
+        match filter.account_id {
+            Some(account_id) if accounts.iter().any(|a| a.id == account_id) => Ok(()),
+            Some(account_id) => Err(raise_error!(
+                format!(
+                    "You do not have permission to access the requested email account (ID: {}).",
+                    account_id
+                ),
+                ErrorCode::PermissionDenied
+            )),
+            None => Err(raise_error!(
+                "Saved searches of non-root access tokens must set filter.account_id.".into(),
+                ErrorCode::PermissionDenied
+            )),
+        }
+    }
+}
+
+pub(crate) mod filter_json {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    use crate::modules::message::search::SearchFilter;
+
+    pub fn serialize<S: Serializer>(
+        filter: &SearchFilter,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let json = serde_json::to_string(filter).map_err(serde::ser::Error::custom)?;
+        serializer.serialize_str(&json)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<SearchFilter, D::Error> {
+        let json = String::deserialize(deserializer)?;
+        serde_json::from_str(&json).map_err(D::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SavedSearch, SavedSearchRequest};
+    use crate::modules::message::search::SearchFilter;
+
+    #[test]
+    fn test_saved_search_roundtrip() {
+        let search = SavedSearch::new(
+            "root".into(),
+            SavedSearchRequest {
+                name: "invoices".into(),
+                filter: SearchFilter {
+                    text: Some("invoice".into()),
+                    from_domain: Some("acme.com".into()),
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+        );
+        let bytes = native_model::encode(&search).unwrap();
+        let (decoded, _) = native_model::decode::<SavedSearch>(bytes).unwrap();
+        assert_eq!(decoded, search);
+    }
+}
@@ -17,14 +17,18 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 
-use poem_openapi::Object;
+use std::{collections::HashMap, time::Duration};
+
+use poem_openapi::{Enum, Object};
 use serde::{Deserialize, Serialize};
 
 use crate::{
     modules::{
+        dashboard::{Group, TimeBucket},
         error::{code::ErrorCode, BichonResult},
-        indexer::{envelope::Envelope, manager::ENVELOPE_INDEX_MANAGER},
-        rest::response::DataPage,
+        indexer::{cursor::SearchCursor, envelope::Envelope, manager::ENVELOPE_INDEX_MANAGER},
+        legal_hold::LEGAL_HOLDS,
+        message::tags::TagCount,
     },
     raise_error,
 };
@@ -32,20 +36,211 @@ use crate::{
 #[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
 pub struct SearchFilter {
     pub text: Option<String>,
+    /// Address filters match, case-insensitively, a full address, its local part or one of
+    /// its words, or a domain written as `@acme.com` (subdomains included).
     pub from: Option<String>,
     pub to: Option<String>,
     pub cc: Option<String>,
     pub bcc: Option<String>,
+    /// Sender domain, subdomains included.
+    pub from_domain: Option<String>,
+    /// Domain of any `to`, `cc` or `bcc` recipient, subdomains included.
+    pub to_domain: Option<String>,
+    /// Words of the sender display name.
+    pub from_name: Option<String>,
+    /// Words of any recipient display name.
+    pub to_name: Option<String>,
     pub since: Option<i64>,
     pub before: Option<i64>,
+    /// Messages archived at or after this time, in milliseconds since the Unix epoch.
+    pub archived_since: Option<i64>,
     pub account_id: Option<u64>,
     pub mailbox_id: Option<u64>,
     pub min_size: Option<u64>,
     pub max_size: Option<u64>,
     pub message_id: Option<String>,
+    /// Detected message language, as an ISO 639-3 code such as `eng` or `jpn`.
+    pub language: Option<String>,
+    /// Header name to value, for headers listed in `bichon_indexed_headers`. Headers indexed
+    /// as `exact` match the whole value, case-insensitively; headers indexed as `text`
+    /// match the value as a phrase.
+    pub headers: Option<HashMap<String, String>>,
     pub has_attachment: Option<bool>,
     pub attachment_name: Option<String>,
     pub tags: Option<Vec<String>>,
+    /// How `tags` are combined. Defaults to `Any`.
+    pub tags_match: Option<TagsMatch>,
+    /// Messages matching any of these values are left out of the results.
+    pub exclude: Option<ExcludeFilter>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize, Enum)]
+pub enum TagsMatch {
+    /// Messages carrying at least one of the tags
+    #[default]
+    Any,
+    /// Messages carrying every tag
+    All,
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct ExcludeFilter {
+    pub account_ids: Option<Vec<u64>>,
+    pub mailbox_ids: Option<Vec<u64>>,
+    /// Sender addresses, matched like `SearchFilter::from`, e.g. `@acme.com`
+    pub from: Option<Vec<String>>,
+    /// Addresses matched against `to`, `cc` and `bcc`, like `SearchFilter::to`
+    pub recipients: Option<Vec<String>>,
+    pub tags: Option<Vec<String>>,
+}
+
+/// Controls how matched terms are highlighted in the snippets returned with search results.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct HighlightOptions {
+    /// Maximum number of characters in each generated fragment. Defaults to 150.
+    pub fragment_size: Option<u32>,
+    /// Marker inserted before each highlighted term. Defaults to `<b>`.
+    pub pre_tag: Option<String>,
+    /// Marker inserted after each highlighted term. Defaults to `</b>`.
+    pub post_tag: Option<String>,
+}
+
+impl HighlightOptions {
+    pub fn fragment_size(&self) -> usize {
+        self.fragment_size
+            .map(|s| s as usize)
+            .unwrap_or(DEFAULT_FRAGMENT_SIZE)
+    }
+
+    pub fn pre_tag(&self) -> &str {
+        self.pre_tag.as_deref().unwrap_or(DEFAULT_PRE_TAG)
+    }
+
+    pub fn post_tag(&self) -> &str {
+        self.post_tag.as_deref().unwrap_or(DEFAULT_POST_TAG)
+    }
+}
+
+/// Highlighted fragments of the subject, body and attachment contents that matched the
+/// full-text query.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct SearchHighlights {
+    pub subject: Option<String>,
+    pub text: Option<String>,
+    /// Fragment of the first matching attachment, see `Envelope::matched_attachments`.
+    pub attachment: Option<String>,
+}
+
+/// Field used to order search results.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize, Enum)]
+pub enum SortField {
+    /// Time the message was received by the server
+    #[default]
+    InternalDate,
+    /// Date from the message `Date` header
+    Date,
+    /// Size of the raw message in bytes
+    Size,
+    /// Sender address
+    From,
+    /// BM25 relevance score of the full-text query
+    Score,
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize, Enum)]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// Facets that can be computed over the whole result set of a search.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize, Enum)]
+pub enum SearchFacet {
+    /// Message counts per account id
+    Account,
+    /// Message counts per mailbox id
+    Mailbox,
+    /// Message counts per sender domain
+    SenderDomain,
+    /// Message counts per tag
+    Tag,
+    /// Message counts with and without attachments
+    HasAttachment,
+    /// Message counts over time, bucketed by `internal_date`
+    DateHistogram,
+}
+
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq, Serialize, Deserialize, Enum)]
+pub enum HistogramInterval {
+    Hour,
+    #[default]
+    Day,
+    Week,
+    /// Fixed 30-day buckets
+    Month,
+}
+
+impl HistogramInterval {
+    pub fn millis(&self) -> u64 {
+        const HOUR: u64 = 3_600_000;
+        match self {
+            HistogramInterval::Hour => HOUR,
+            HistogramInterval::Day => 24 * HOUR,
+            HistogramInterval::Week => 7 * 24 * HOUR,
+            HistogramInterval::Month => 30 * 24 * HOUR,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct FacetRequest {
+    pub facets: Vec<SearchFacet>,
+    /// Maximum number of buckets returned per facet, histogram excluded. Defaults to 10.
+    pub size: Option<u32>,
+    /// Bucket width of the date histogram. Defaults to `Day`.
+    pub interval: Option<HistogramInterval>,
+}
+
+impl FacetRequest {
+    pub fn size(&self) -> usize {
+        self.size.map(|s| s as usize).unwrap_or(DEFAULT_FACET_SIZE)
+    }
+
+    pub fn contains(&self, facet: SearchFacet) -> bool {
+        self.facets.contains(&facet)
+    }
+}
+
+/// Facet counts over every message matching the search, not only the returned page.
+/// Only the requested facets are set.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct SearchFacets {
+    pub accounts: Option<Vec<Group>>,
+    pub mailboxes: Option<Vec<Group>>,
+    pub sender_domains: Option<Vec<Group>>,
+    pub tags: Option<Vec<TagCount>>,
+    /// Keyed by `true` and `false`.
+    pub has_attachment: Option<Vec<Group>>,
+    pub date_histogram: Option<Vec<TimeBucket>>,
+}
+
+const DEFAULT_FACET_SIZE: usize = 10;
+const MAX_FACET_SIZE: u32 = 100;
+const DEFAULT_FRAGMENT_SIZE: usize = 150;
+const MAX_FRAGMENT_SIZE: u32 = 1000;
+const DEFAULT_PRE_TAG: &str = "<b>";
+const DEFAULT_POST_TAG: &str = "</b>";
+
+const DEFAULT_SCROLL_KEEP_ALIVE_SECS: u64 = 60;
+const MAX_SCROLL_KEEP_ALIVE_SECS: u64 = 600;
+
+/// Ordering and presentation options shared by paged, cursor and scroll searches.
+#[derive(Debug, Clone, Default)]
+pub struct SearchOptions {
+    pub sort_by: SortField,
+    pub desc: bool,
+    pub highlight: Option<HighlightOptions>,
 }
 
 #[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
@@ -53,8 +248,37 @@ pub struct SearchRequest {
     filter: SearchFilter,
     page: u64,
     page_size: u64,
+    /// Defaults to `InternalDate`.
+    sort_by: Option<SortField>,
+    /// Defaults to `Desc`.
+    sort_order: Option<SortOrder>,
+    /// When set and `filter.text` is present, each result carries highlighted snippets.
+    highlight: Option<HighlightOptions>,
+    /// `next_cursor` of a previous response. When set, `page` is ignored and results start
+    /// right after the cursor position. The sort options must match the previous request.
+    search_after: Option<String>,
+    /// Facets to compute over the whole result set.
+    facets: Option<FacetRequest>,
 }
+
 impl SearchRequest {
+    pub fn new(
+        filter: SearchFilter,
+        page: u64,
+        page_size: u64,
+        sort_by: Option<SortField>,
+        sort_order: Option<SortOrder>,
+    ) -> Self {
+        Self {
+            filter,
+            page,
+            page_size,
+            sort_by,
+            sort_order,
+            ..Default::default()
+        }
+    }
+
     pub fn validate(&self) -> BichonResult<()> {
         if self.page == 0 || self.page_size == 0 {
             return Err(raise_error!(
@@ -62,19 +286,177 @@ impl SearchRequest {
                 ErrorCode::InvalidParameter
             ));
         }
-        if self.page_size > 500 {
+        validate_page_size(self.page_size)?;
+        if let Some(size) = self.facets.as_ref().and_then(|f| f.size) {
+            if size == 0 || size > MAX_FACET_SIZE {
+                return Err(raise_error!(
+                    format!("The facet size must be between 1 and {}.", MAX_FACET_SIZE),
+                    ErrorCode::InvalidParameter
+                ));
+            }
+        }
+        validate_highlight(&self.highlight)
+    }
+
+    fn options(&self) -> SearchOptions {
+        SearchOptions {
+            sort_by: self.sort_by.unwrap_or_default(),
+            desc: self.sort_order.unwrap_or_default() == SortOrder::Desc,
+            highlight: self.highlight.clone(),
+        }
+    }
+}
+
+/// Opens a scroll session that iterates over every hit of a query against a fixed
+/// snapshot of the index, unaffected by messages synced in the meantime.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct ScrollRequest {
+    filter: SearchFilter,
+    /// Number of messages returned by each scroll call.
+    page_size: u64,
+    /// Defaults to `InternalDate`.
+    sort_by: Option<SortField>,
+    /// Defaults to `Desc`.
+    sort_order: Option<SortOrder>,
+    highlight: Option<HighlightOptions>,
+    /// Seconds the session is kept open after each call. Defaults to 60, at most 600.
+    keep_alive: Option<u64>,
+}
+
+impl ScrollRequest {
+    pub fn validate(&self) -> BichonResult<()> {
+        if self.page_size == 0 {
+            return Err(raise_error!(
+                "The page_size must be greater than 0.".into(),
+                ErrorCode::InvalidParameter
+            ));
+        }
+        validate_page_size(self.page_size)?;
+        if let Some(keep_alive) = self.keep_alive {
+            if keep_alive == 0 || keep_alive > MAX_SCROLL_KEEP_ALIVE_SECS {
+                return Err(raise_error!(
+                    format!(
+                        "The keep_alive must be between 1 and {} seconds.",
+                        MAX_SCROLL_KEEP_ALIVE_SECS
+                    ),
+                    ErrorCode::InvalidParameter
+                ));
+            }
+        }
+        validate_highlight(&self.highlight)
+    }
+}
+
+/// A page of search hits.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct SearchResult {
+    /// The current page number (starting from 1). Not set for cursor-based requests.
+    pub current_page: Option<u64>,
+    /// The number of items per page.
+    pub page_size: Option<u64>,
+    /// The total number of items matching the query.
+    pub total_items: u64,
+    /// The list of items returned on the current page.
+    pub items: Vec<Envelope>,
+    /// The total number of pages.
+    pub total_pages: Option<u64>,
+    /// Cursor positioned after the last item, to be passed as `search_after`.
+    /// Not set once the last hit has been returned.
+    pub next_cursor: Option<String>,
+    /// Requested facets, computed over all matching messages.
+    pub facets: Option<SearchFacets>,
+}
+
+/// A batch of hits returned by a scroll session.
+#[derive(Debug, Clone, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct ScrollResult {
+    /// Identifier of the scroll session, used to fetch the next batch.
+    pub scroll_id: String,
+    /// The total number of items matching the query in the pinned snapshot.
+    pub total_items: u64,
+    pub items: Vec<Envelope>,
+    /// `false` once every hit has been returned; the session is closed at that point.
+    pub has_more: bool,
+}
+
+fn validate_page_size(page_size: u64) -> BichonResult<()> {
+    if page_size > 500 {
+        return Err(raise_error!(
+            "The page_size exceeds the maximum allowed limit of 500.".into(),
+            ErrorCode::InvalidParameter
+        ));
+    }
+    Ok(())
+}
+
+fn validate_highlight(highlight: &Option<HighlightOptions>) -> BichonResult<()> {
+    if let Some(size) = highlight.as_ref().and_then(|h| h.fragment_size) {
+        if size == 0 || size > MAX_FRAGMENT_SIZE {
             return Err(raise_error!(
-                "The page_size exceeds the maximum allowed limit of 500.".into(),
+                format!(
+                    "The fragment_size must be between 1 and {}.",
+                    MAX_FRAGMENT_SIZE
+                ),
                 ErrorCode::InvalidParameter
             ));
         }
-        Ok(())
     }
+    Ok(())
 }
 
-pub async fn search_messages_impl(request: SearchRequest) -> BichonResult<DataPage<Envelope>> {
+pub async fn search_messages_impl(request: SearchRequest) -> BichonResult<SearchResult> {
     request.validate()?;
-    ENVELOPE_INDEX_MANAGER
-        .search(request.filter, request.page, request.page_size, true)
-        .await
+    let options = request.options();
+    let search_after = match &request.search_after {
+        Some(token) => {
+            let cursor = SearchCursor::decode(token)?;
+            if cursor.sort_by != options.sort_by || cursor.desc != options.desc {
+                return Err(raise_error!(
+                    "The search_after cursor was created with different sort options.".into(),
+                    ErrorCode::InvalidParameter
+                ));
+            }
+            Some(cursor)
+        }
+        None => None,
+    };
+    let mut result = ENVELOPE_INDEX_MANAGER
+        .search(
+            request.filter,
+            request.page,
+            request.page_size,
+            options,
+            search_after,
+            request.facets,
+        )
+        .await?;
+    LEGAL_HOLDS.annotate(&mut result.items).await?;
+    Ok(result)
+}
+
+pub async fn open_scroll_impl(request: ScrollRequest) -> BichonResult<ScrollResult> {
+    request.validate()?;
+    let options = SearchOptions {
+        sort_by: request.sort_by.unwrap_or_default(),
+        desc: request.sort_order.unwrap_or_default() == SortOrder::Desc,
+        highlight: request.highlight,
+    };
+    let keep_alive = request.keep_alive.unwrap_or(DEFAULT_SCROLL_KEEP_ALIVE_SECS);
+    let mut result = ENVELOPE_INDEX_MANAGER
+        .open_scroll(
+            request.filter,
+            request.page_size,
+            options,
+            Duration::from_secs(keep_alive),
+        )
+        .await?;
+    LEGAL_HOLDS.annotate(&mut result.items).await?;
+    Ok(result)
+}
+
+/// Returns the next batch of an open scroll session.
+pub async fn next_scroll_impl(scroll_id: &str) -> BichonResult<ScrollResult> {
+    let mut result = ENVELOPE_INDEX_MANAGER.scroll(scroll_id).await?;
+    LEGAL_HOLDS.annotate(&mut result.items).await?;
+    Ok(result)
 }
@@ -0,0 +1,327 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::{
+    cmp::Reverse,
+    collections::{HashMap, HashSet},
+};
+
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    modules::{
+        error::{code::ErrorCode, BichonResult},
+        indexer::{envelope::Envelope, manager::ENVELOPE_INDEX_MANAGER},
+        message::search::SearchFilter,
+    },
+    raise_error,
+};
+
+const DEFAULT_SIMILAR_LIMIT: u32 = 20;
+const MAX_SIMILAR_LIMIT: u32 = 100;
+const DEFAULT_DUPLICATE_THRESHOLD: f32 = 0.8;
+const DEFAULT_DUPLICATE_SCAN: u32 = 2000;
+const MAX_DUPLICATE_SCAN: u32 = 10000;
+
+/// Words per shingle.
+const SHINGLE_SIZE: usize = 5;
+/// Hash functions of a MinHash signature, split into `BANDS` bands for candidate lookup.
+const NUM_HASHES: usize = 64;
+const BANDS: usize = 16;
+const ROWS: usize = NUM_HASHES / BANDS;
+
+/// A message similar to the one given, with its more-like-this score.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, Object)]
+pub struct SimilarEnvelope {
+    pub score: f32,
+    pub envelope: Envelope,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, Object)]
+pub struct NearDuplicateRequest {
+    pub filter: SearchFilter,
+    /// Minimum estimated Jaccard similarity of the normalized texts, between 0.5 and 1.
+    /// Defaults to 0.8.
+    pub threshold: Option<f32>,
+    /// Number of most recent matching messages compared. Defaults to 2000, at most 10000.
+    pub max_messages: Option<u32>,
+}
+
+/// Copies of the same message, e.g. forwarded or re-sent. The earliest copy comes first.
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, Object)]
+pub struct NearDuplicateGroup {
+    pub envelopes: Vec<Envelope>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize, Object)]
+pub struct NearDuplicateResult {
+    /// The total number of messages matching the filter.
+    pub total_items: u64,
+    /// The number of messages compared, the most recent ones first.
+    pub scanned: u64,
+    /// Groups of two or more near-duplicates, largest first.
+    pub groups: Vec<NearDuplicateGroup>,
+}
+
+impl NearDuplicateRequest {
+    pub fn validate(&self) -> BichonResult<()> {
+        if let Some(threshold) = self.threshold {
+            if !(0.5..=1.0).contains(&threshold) {
+                return Err(raise_error!(
+                    "The threshold must be between 0.5 and 1.".into(),
+                    ErrorCode::InvalidParameter
+                ));
+            }
+        }
+        if let Some(max) = self.max_messages {
+            if max == 0 || max > MAX_DUPLICATE_SCAN {
+                return Err(raise_error!(
+                    format!(
+                        "The max_messages must be between 1 and {}.",
+                        MAX_DUPLICATE_SCAN
+                    ),
+                    ErrorCode::InvalidParameter
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+pub async fn similar_messages_impl(
+    account_id: u64,
+    id: u64,
+    accounts: Option<Vec<u64>>,
+    limit: Option<u32>,
+) -> BichonResult<Vec<SimilarEnvelope>> {
+    let limit = limit.unwrap_or(DEFAULT_SIMILAR_LIMIT);
+    if limit == 0 || limit > MAX_SIMILAR_LIMIT {
+        return Err(raise_error!(
+            format!("The limit must be between 1 and {}.", MAX_SIMILAR_LIMIT),
+            ErrorCode::InvalidParameter
+        ));
+    }
+    ENVELOPE_INDEX_MANAGER
+        .more_like_this(account_id, id, accounts, limit as usize)
+        .await
+}
+
+pub async fn near_duplicates_impl(
+    request: NearDuplicateRequest,
+) -> BichonResult<NearDuplicateResult> {
+    request.validate()?;
+    let threshold = request.threshold.unwrap_or(DEFAULT_DUPLICATE_THRESHOLD);
+    let max = request.max_messages.unwrap_or(DEFAULT_DUPLICATE_SCAN);
+    let (total_items, envelopes) = ENVELOPE_INDEX_MANAGER
+        .latest_envelopes(request.filter, max as usize)
+        .await?;
+    let scanned = envelopes.len() as u64;
+    let signatures: Vec<Option<Signature>> = envelopes
+        .iter()
+        .map(|(e, text)| signature(&normalized_words(&e.subject, text)))
+        .collect();
+
+    let mut slots: Vec<Option<Envelope>> = envelopes.into_iter().map(|(e, _)| Some(e)).collect();
+    let mut groups: Vec<NearDuplicateGroup> = group_signatures(&signatures, threshold)
+        .into_iter()
+        .map(|members| {
+            let mut envelopes: Vec<Envelope> = members
+                .into_iter()
+                .filter_map(|i| slots[i].take())
+                .collect();
+            envelopes.sort_by_key(|e| e.internal_date);
+            NearDuplicateGroup { envelopes }
+        })
+        .collect();
+    groups.sort_by_key(|g| Reverse(g.envelopes.len()));
+    Ok(NearDuplicateResult {
+        total_items,
+        scanned,
+        groups,
+    })
+}
+
+type Signature = [u64; NUM_HASHES];
+
+/// Lowercase words of the subject and body, leaving out reply and forward prefixes,
+/// quoted lines and the header blocks that forwarding clients insert.
+fn normalized_words(subject: &str, text: &str) -> Vec<String> {
+    let mut subject = subject.trim();
+    loop {
+        let lower = subject.to_lowercase();
+        let stripped = ["re:", "fw:", "fwd:", "aw:", "wg:", "tr:"]
+            .iter()
+            .find(|prefix| lower.starts_with(*prefix));
+        match stripped {
+            Some(prefix) => subject = subject[prefix.len()..].trim_start(),
+            None => break,
+        }
+    }
+
+    let body = text.lines().filter(|line| {
+        let line = line.trim_start();
+        let lower = line.to_lowercase();
+        !(line.starts_with('>')
+            || lower.starts_with("-----original message")
+            || lower.starts_with("---------- forwarded message")
+            || ["from:", "sent:", "to:", "cc:", "date:", "subject:"]
+                .iter()
+                .any(|header| lower.starts_with(header)))
+    });
+
+    std::iter::once(subject)
+        .chain(body)
+        .flat_map(|line| line.split(|c: char| !c.is_alphanumeric()))
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// MinHash signature of the word shingles, `None` for an empty text.
+fn signature(words: &[String]) -> Option<Signature> {
+    if words.is_empty() {
+        return None;
+    }
+    let shingles: HashSet<u64> = words
+        .windows(SHINGLE_SIZE.min(words.len()))
+        .map(|shingle| fnv1a(shingle.join(" ").as_bytes()))
+        .collect();
+    let mut signature = [u64::MAX; NUM_HASHES];
+    for shingle in shingles {
+        for (i, min) in signature.iter_mut().enumerate() {
+            *min = (*min).min(splitmix64(shingle ^ splitmix64(i as u64)));
+        }
+    }
+    Some(signature)
+}
+
+/// Estimated Jaccard similarity of the shingle sets.
+fn similarity(a: &Signature, b: &Signature) -> f32 {
+    let equal = a.iter().zip(b.iter()).filter(|(x, y)| x == y).count();
+    equal as f32 / NUM_HASHES as f32
+}
+
+/// Indexes of the signatures in each group of near-duplicates. Candidates share at least
+/// one band, and are grouped when their similarity reaches `threshold`.
+fn group_signatures(signatures: &[Option<Signature>], threshold: f32) -> Vec<Vec<usize>> {
+    let mut parents: Vec<usize> = (0..signatures.len()).collect();
+    fn find(parents: &mut [usize], mut i: usize) -> usize {
+        while parents[i] != i {
+            parents[i] = parents[parents[i]];
+            i = parents[i];
+        }
+        i
+    }
+
+    let mut buckets: HashMap<(usize, &[u64]), Vec<usize>> = HashMap::new();
+    for (i, signature) in signatures.iter().enumerate() {
+        let Some(signature) = signature else {
+            continue;
+        };
+        for band in 0..BANDS {
+            buckets
+                .entry((band, &signature[band * ROWS..(band + 1) * ROWS]))
+                .or_default()
+                .push(i);
+        }
+    }
+    // every pair of a bucket, two members can be close while the first is far from both
+    for members in buckets.values() {
+        for (n, &i) in members.iter().enumerate() {
+            for &j in &members[n + 1..] {
+                let (Some(a), Some(b)) = (&signatures[i], &signatures[j]) else {
+                    continue;
+                };
+                let (root_i, root_j) = (find(&mut parents, i), find(&mut parents, j));
+                if root_i != root_j && similarity(a, b) >= threshold {
+                    parents[root_i] = root_j;
+                }
+            }
+        }
+    }
+
+    let mut groups: HashMap<usize, Vec<usize>> = HashMap::new();
+    for i in 0..signatures.len() {
+        let root = find(&mut parents, i);
+        groups.entry(root).or_default().push(i);
+    }
+    groups
+        .into_values()
+        .filter(|members| members.len() > 1)
+        .collect()
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{group_signatures, normalized_words, signature, Signature, BANDS, ROWS};
+
+    #[test]
+    fn test_group_forwarded_copies() {
+        let body = "Please find attached the signed agreement for the Q3 supply contract. \
+                    Payment terms are net thirty days from the invoice date and delivery \
+                    is expected before the end of the month at the Rotterdam warehouse.";
+        let forwarded = format!(
+            "FYI\n\n---------- Forwarded message ---------\nFrom: Alice <alice@example.com>\n\
+             Date: Mon, 6 Oct 2025\nSubject: Supply contract\nTo: Bob <bob@example.com>\n\n{body}"
+        );
+        let other = "The quarterly all-hands meeting moves to Thursday afternoon in the main \
+                     auditorium, with a live stream for remote staff and questions at the end.";
+        let signatures = vec![
+            signature(&normalized_words("Supply contract", body)),
+            signature(&normalized_words("Fwd: Supply contract", &forwarded)),
+            signature(&normalized_words("All-hands", other)),
+            signature(&normalized_words("", "")),
+        ];
+        assert!(signatures[3].is_none());
+        let groups = group_signatures(&signatures, 0.8);
+        assert_eq!(groups.len(), 1);
+        let mut members = groups[0].clone();
+        members.sort();
+        assert_eq!(members, vec![0, 1]);
+    }
+
+    #[test]
+    fn test_group_later_members_of_a_bucket() {
+        // the three only share the first band, and just the second and third are close
+        let first: Signature = std::array::from_fn(|h| if h < ROWS { 0 } else { 1 });
+        let second: Signature = std::array::from_fn(|h| if h < ROWS { 0 } else { 2 });
+        let mut third = second;
+        for band in 1..BANDS {
+            third[band * ROWS] = 3;
+        }
+        let groups = group_signatures(&[Some(first), Some(second), Some(third)], 0.7);
+        assert_eq!(groups.len(), 1);
+        let mut members = groups[0].clone();
+        members.sort();
+        assert_eq!(members, vec![1, 2]);
+    }
+}
@@ -17,7 +17,10 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 pub mod account;
+pub mod alert;
 pub mod autoconfig;
+pub mod backup;
+pub mod blob;
 pub mod cache;
 pub mod common;
 pub mod context;
@@ -28,13 +31,19 @@ pub mod error;
 pub mod imap;
 pub mod import;
 pub mod indexer;
+pub mod integrity;
+pub mod legal_hold;
 pub mod logger;
 pub mod mailbox;
 pub mod message;
 pub mod oauth2;
 pub mod rest;
+pub mod retention;
 pub mod settings;
 pub mod tasks;
+#[cfg(test)]
+pub mod testing;
 pub mod token;
 pub mod utils;
 pub mod version;
+pub mod worm;
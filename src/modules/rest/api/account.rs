@@ -21,7 +21,9 @@ use std::collections::BTreeSet;
 use crate::modules::account::migration::AccountModel;
 use crate::modules::account::payload::{
     filter_accessible_accounts, AccountCreateRequest, AccountUpdateRequest, MinimalAccount,
+    PgpUnlockRequest,
 };
+use crate::modules::account::pgp::{lock_account, unlock_account};
 use crate::modules::account::state::AccountRunningState;
 use crate::modules::common::auth::ClientContext;
 use crate::modules::common::paginated::paginate_vec;
@@ -112,6 +114,50 @@ impl AccountApi {
         Ok(AccountModel::update(account_id, payload.0, true).await?)
     }
 
+    /// Unlocks the messages of an account encrypted at rest for the caller, for an hour
+    #[oai(
+        path = "/account-pgp-unlock/:account_id",
+        method = "post",
+        operation_id = "unlock_account_pgp"
+    )]
+    async fn unlock_account_pgp(
+        &self,
+        /// The account ID to unlock
+        account_id: Path<u64>,
+        /// Secret key matching the account's PGP key
+        payload: Json<PgpUnlockRequest>,
+        context: ClientContext,
+    ) -> ApiResult<()> {
+        let account_id = account_id.0;
+        context.require_account_access(account_id)?;
+        let request = payload.0;
+        Ok(unlock_account(
+            &context,
+            account_id,
+            &request.private_key,
+            request.passphrase.unwrap_or_default(),
+        )
+        .await?)
+    }
+
+    /// Forgets the secret key the caller unlocked an account with
+    #[oai(
+        path = "/account-pgp-lock/:account_id",
+        method = "post",
+        operation_id = "lock_account_pgp"
+    )]
+    async fn lock_account_pgp(
+        &self,
+        /// The account ID to lock
+        account_id: Path<u64>,
+        context: ClientContext,
+    ) -> ApiResult<()> {
+        let account_id = account_id.0;
+        context.require_account_access(account_id)?;
+        lock_account(&context, account_id);
+        Ok(())
+    }
+
     /// List accounts with optional pagination parameters
     #[oai(path = "/accounts", method = "get", operation_id = "list_accounts")]
     async fn list_accounts(
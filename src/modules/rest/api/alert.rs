@@ -0,0 +1,142 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::modules::alert::{AlertNotification, SearchAlert, SearchAlertRequest};
+use crate::modules::common::auth::ClientContext;
+use crate::modules::message::saved::SavedSearch;
+use crate::modules::rest::api::ApiTags;
+use crate::modules::rest::ApiResult;
+use poem_openapi::param::Path;
+use poem_openapi::payload::Json;
+use poem_openapi::OpenApi;
+
+pub struct AlertApi;
+
+#[OpenApi(prefix_path = "/api/v1", tag = "ApiTags::Alert")]
+impl AlertApi {
+    /// Lists the search alerts of the calling access token.
+    #[oai(
+        path = "/list-search-alerts",
+        method = "get",
+        operation_id = "list_search_alerts"
+    )]
+    async fn list_search_alerts(
+        &self,
+        context: ClientContext,
+    ) -> ApiResult<Json<Vec<SearchAlert>>> {
+        Ok(Json(SearchAlert::list(&context.principal()).await?))
+    }
+
+    /// Gets a search alert of the calling access token.
+    #[oai(
+        path = "/search-alert/:id",
+        method = "get",
+        operation_id = "get_search_alert"
+    )]
+    async fn get_search_alert(
+        &self,
+        id: Path<u64>,
+        context: ClientContext,
+    ) -> ApiResult<Json<SearchAlert>> {
+        Ok(Json(SearchAlert::get(&context.principal(), id.0).await?))
+    }
+
+    /// Creates a search alert, evaluated against every batch of newly archived mail.
+    #[oai(
+        path = "/search-alert",
+        method = "post",
+        operation_id = "create_search_alert"
+    )]
+    async fn create_search_alert(
+        &self,
+        payload: Json<SearchAlertRequest>,
+        context: ClientContext,
+    ) -> ApiResult<Json<SearchAlert>> {
+        let request = payload.0;
+        SavedSearch::authorize(&context, &request.filter)?;
+        request.channels.authorize(&context)?;
+        let alert = SearchAlert::new(context.principal(), request);
+        alert.save().await?;
+        Ok(Json(alert))
+    }
+
+    /// Replaces the name, filter and channels of a search alert.
+    #[oai(
+        path = "/search-alert/:id",
+        method = "post",
+        operation_id = "update_search_alert"
+    )]
+    async fn update_search_alert(
+        &self,
+        id: Path<u64>,
+        payload: Json<SearchAlertRequest>,
+        context: ClientContext,
+    ) -> ApiResult<()> {
+        let request = payload.0;
+        SavedSearch::authorize(&context, &request.filter)?;
+        request.channels.authorize(&context)?;
+        Ok(SearchAlert::update(&context.principal(), id.0, request).await?)
+    }
+
+    /// Deletes a search alert.
+    #[oai(
+        path = "/search-alert/:id",
+        method = "delete",
+        operation_id = "remove_search_alert"
+    )]
+    async fn remove_search_alert(&self, id: Path<u64>, context: ClientContext) -> ApiResult<()> {
+        Ok(SearchAlert::delete(&context.principal(), id.0).await?)
+    }
+
+    /// Lists the in-app alert notifications of the calling access token, newest first.
+    #[oai(
+        path = "/alert-notifications",
+        method = "get",
+        operation_id = "list_alert_notifications"
+    )]
+    async fn list_alert_notifications(
+        &self,
+        context: ClientContext,
+    ) -> ApiResult<Json<Vec<AlertNotification>>> {
+        Ok(Json(AlertNotification::list(&context.principal()).await?))
+    }
+
+    /// Marks an alert notification as read.
+    #[oai(
+        path = "/alert-notification/:id/read",
+        method = "post",
+        operation_id = "mark_alert_notification_read"
+    )]
+    async fn mark_alert_notification_read(
+        &self,
+        id: Path<u64>,
+        context: ClientContext,
+    ) -> ApiResult<()> {
+        Ok(AlertNotification::mark_read(&context.principal(), id.0).await?)
+    }
+
+    /// Deletes every alert notification of the calling access token.
+    #[oai(
+        path = "/alert-notifications",
+        method = "delete",
+        operation_id = "clear_alert_notifications"
+    )]
+    async fn clear_alert_notifications(&self, context: ClientContext) -> ApiResult<()> {
+        Ok(AlertNotification::clear(&context.principal()).await?)
+    }
+}
@@ -0,0 +1,58 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+use crate::modules::backup::{BackupRequest, BackupStatus, BACKUP};
+use crate::modules::common::auth::ClientContext;
+use crate::modules::rest::api::ApiTags;
+use crate::modules::rest::ApiResult;
+use poem_openapi::payload::Json;
+use poem_openapi::OpenApi;
+
+pub struct BackupApi;
+
+#[OpenApi(prefix_path = "/api/v1", tag = "ApiTags::Backup")]
+impl BackupApi {
+    /// Back up the data root in the background while the server runs. Requires root
+    /// permission.
+    ///
+    /// Writes are paused while the databases are copied and the index files of the last
+    /// commit are opened, so the backup holds a single point in time. Restore it with
+    /// `bichon_restore_from` while the server is stopped.
+    ///
+    /// An incremental backup copies only the index segments and blobs added since the last
+    /// backup of its directory, and needs the backups it builds on to be restored. With
+    /// `bichon_backup_keep_sets`, older sets of `bichon_backup_dir` are removed afterwards.
+    #[oai(path = "/backup", method = "post", operation_id = "start_backup")]
+    async fn start_backup(
+        &self,
+        payload: Json<BackupRequest>,
+        context: ClientContext,
+    ) -> ApiResult<()> {
+        context.require_root()?;
+        Ok(BACKUP.start(payload.0)?)
+    }
+
+    /// Get the progress of the running backup and the manifest of the last one completed.
+    /// Requires root permission.
+    #[oai(path = "/backup", method = "get", operation_id = "get_backup_status")]
+    async fn get_backup_status(&self, context: ClientContext) -> ApiResult<Json<BackupStatus>> {
+        context.require_root()?;
+        Ok(Json(BACKUP.status()))
+    }
+}
@@ -0,0 +1,71 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::modules::common::auth::ClientContext;
+use crate::modules::legal_hold::{LegalHold, LegalHoldRequest};
+use crate::modules::rest::api::ApiTags;
+use crate::modules::rest::ApiResult;
+use poem_openapi::param::Path;
+use poem_openapi::payload::Json;
+use poem_openapi::OpenApi;
+
+pub struct LegalHoldApi;
+
+#[OpenApi(prefix_path = "/api/v1", tag = "ApiTags::LegalHold")]
+impl LegalHoldApi {
+    /// Lists the legal holds in place. Requires root permission.
+    #[oai(
+        path = "/list-legal-holds",
+        method = "get",
+        operation_id = "list_legal_holds"
+    )]
+    async fn list_legal_holds(&self, context: ClientContext) -> ApiResult<Json<Vec<LegalHold>>> {
+        context.require_root()?;
+        Ok(Json(LegalHold::list().await?))
+    }
+
+    /// Places a legal hold on an account, a mailbox or the messages matching a saved
+    /// search. Held messages cannot be deleted until the hold is released. Requires root
+    /// permission.
+    #[oai(
+        path = "/legal-hold",
+        method = "post",
+        operation_id = "place_legal_hold"
+    )]
+    async fn place_legal_hold(
+        &self,
+        payload: Json<LegalHoldRequest>,
+        context: ClientContext,
+    ) -> ApiResult<Json<LegalHold>> {
+        context.require_root()?;
+        let hold = LegalHold::new(context.principal(), payload.0).await?;
+        hold.save().await?;
+        Ok(Json(hold))
+    }
+
+    /// Releases a legal hold. Requires root permission.
+    #[oai(
+        path = "/legal-hold/:id",
+        method = "delete",
+        operation_id = "release_legal_hold"
+    )]
+    async fn release_legal_hold(&self, id: Path<u64>, context: ClientContext) -> ApiResult<()> {
+        context.require_root()?;
+        Ok(LegalHold::release(id.0, &context.principal()).await?)
+    }
+}
@@ -17,14 +17,24 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use crate::modules::account::migration::AccountModel;
+use crate::modules::account::pgp::PgpCredentials;
 use crate::modules::common::auth::ClientContext;
 use crate::modules::indexer::envelope::Envelope;
 use crate::modules::indexer::manager::EML_INDEX_MANAGER;
 use crate::modules::indexer::manager::ENVELOPE_INDEX_MANAGER;
-use crate::modules::message::content::{retrieve_email_content, FullMessageContent};
+use crate::modules::message::content::{
+    retrieve_email_content, retrieve_raw_headers, FullMessageContent,
+};
 use crate::modules::message::delete::delete_messages_impl;
 use crate::modules::message::list::{get_thread_messages, list_messages_impl};
-use crate::modules::message::search::{search_messages_impl, SearchRequest};
+use crate::modules::message::search::{
+    next_scroll_impl, open_scroll_impl, search_messages_impl, ScrollRequest, ScrollResult,
+    SearchRequest, SearchResult,
+};
+use crate::modules::message::similar::{
+    near_duplicates_impl, similar_messages_impl, NearDuplicateRequest, NearDuplicateResult,
+    SimilarEnvelope,
+};
 use crate::modules::message::tags::TagCount;
 use crate::modules::message::tags::UpdateTagsRequest;
 use crate::modules::rest::api::ApiTags;
@@ -35,7 +45,7 @@ use crate::raise_error;
 use poem::web::Path;
 use poem::Body;
 use poem_openapi::param::Query;
-use poem_openapi::payload::{Attachment, AttachmentType, Json};
+use poem_openapi::payload::{Attachment, AttachmentType, Json, PlainText};
 use poem_openapi::OpenApi;
 use std::collections::HashMap;
 use tantivy::schema::Facet;
@@ -95,11 +105,98 @@ impl MessageApi {
         &self,
         payload: Json<SearchRequest>,
         context: ClientContext,
-    ) -> ApiResult<Json<DataPage<Envelope>>> {
+    ) -> ApiResult<Json<SearchResult>> {
         context.require_root()?;
         Ok(Json(search_messages_impl(payload.0).await?))
     }
 
+    /// Opens a scroll session over a snapshot of the search results and returns the first batch.
+    #[oai(
+        path = "/search-messages/scroll",
+        method = "post",
+        operation_id = "open_search_scroll"
+    )]
+    async fn open_search_scroll(
+        &self,
+        payload: Json<ScrollRequest>,
+        context: ClientContext,
+    ) -> ApiResult<Json<ScrollResult>> {
+        context.require_root()?;
+        Ok(Json(open_scroll_impl(payload.0).await?))
+    }
+
+    /// Returns the next batch of an open scroll session.
+    #[oai(
+        path = "/search-messages/scroll/:scroll_id",
+        method = "get",
+        operation_id = "next_search_scroll"
+    )]
+    async fn next_search_scroll(
+        &self,
+        scroll_id: Path<String>,
+        context: ClientContext,
+    ) -> ApiResult<Json<ScrollResult>> {
+        context.require_root()?;
+        Ok(Json(next_scroll_impl(&scroll_id.0).await?))
+    }
+
+    /// Closes a scroll session before it expires.
+    #[oai(
+        path = "/search-messages/scroll/:scroll_id",
+        method = "delete",
+        operation_id = "clear_search_scroll"
+    )]
+    async fn clear_search_scroll(
+        &self,
+        scroll_id: Path<String>,
+        context: ClientContext,
+    ) -> ApiResult<()> {
+        context.require_root()?;
+        ENVELOPE_INDEX_MANAGER.clear_scroll(&scroll_id.0);
+        Ok(())
+    }
+
+    /// Returns messages whose subject and body resemble those of the given message, across
+    /// every account the caller can access.
+    #[oai(
+        path = "/similar-messages/:account_id",
+        method = "get",
+        operation_id = "similar_messages"
+    )]
+    async fn similar_messages(
+        &self,
+        account_id: Path<u64>,
+        id: Query<u64>,
+        /// Maximum number of messages returned. Defaults to 20, at most 100.
+        limit: Query<Option<u32>>,
+        context: ClientContext,
+    ) -> ApiResult<Json<Vec<SimilarEnvelope>>> {
+        let account_id = account_id.0;
+        context.require_account_access(account_id)?;
+        let accounts = context
+            .accessible_accounts()?
+            .map(|accounts| accounts.iter().map(|a| a.id).collect());
+        Ok(Json(
+            similar_messages_impl(account_id, id.0, accounts, limit.0).await?,
+        ))
+    }
+
+    /// Groups the most recent messages matching a filter into sets of near-duplicates,
+    /// such as forwarded or re-sent copies.
+    #[oai(
+        path = "/near-duplicates",
+        method = "post",
+        operation_id = "find_near_duplicates"
+    )]
+    async fn find_near_duplicates(
+        &self,
+        payload: Json<NearDuplicateRequest>,
+        context: ClientContext,
+    ) -> ApiResult<Json<NearDuplicateResult>> {
+        context.require_root()?;
+        Ok(Json(near_duplicates_impl(payload.0).await?))
+    }
+
     /// Get thread's envelopes in a specified mailbox for the given account.
     #[oai(
         path = "/get-thread-messages/:account_id",
@@ -127,6 +224,10 @@ impl MessageApi {
     }
 
     /// Fetches the content of a specific email for the given account.
+    ///
+    /// Messages of an account encrypting them at rest are decrypted with the key sent in
+    /// the `x-bichon-pgp-key` and `x-bichon-pgp-passphrase` headers, or the key the
+    /// account was unlocked with. The same applies to headers and downloads.
     #[oai(
         path = "/message-content/:account_id",
         method = "get",
@@ -137,10 +238,35 @@ impl MessageApi {
         account_id: Path<u64>,
         id: Query<u64>,
         context: ClientContext,
+        pgp: PgpCredentials,
     ) -> ApiResult<Json<FullMessageContent>> {
         let account_id = account_id.0;
         context.require_account_access(account_id)?;
-        Ok(Json(retrieve_email_content(account_id, id.0).await?))
+        let key = pgp.resolve(&context, account_id);
+        Ok(Json(
+            retrieve_email_content(account_id, id.0, key.as_deref()).await?,
+        ))
+    }
+
+    /// Fetches the raw header block of a specific email, as stored.
+    #[oai(
+        path = "/message-headers/:account_id",
+        method = "get",
+        operation_id = "fetch_message_headers"
+    )]
+    async fn fetch_message_headers(
+        &self,
+        account_id: Path<u64>,
+        id: Query<u64>,
+        context: ClientContext,
+        pgp: PgpCredentials,
+    ) -> ApiResult<PlainText<String>> {
+        let account_id = account_id.0;
+        context.require_account_access(account_id)?;
+        let key = pgp.resolve(&context, account_id);
+        Ok(PlainText(
+            retrieve_raw_headers(account_id, id.0, key.as_deref()).await?,
+        ))
     }
 
     /// Fetches the full content of a specific email for the given account.
@@ -154,13 +280,17 @@ impl MessageApi {
         account_id: Path<u64>,
         id: Query<u64>,
         context: ClientContext,
+        pgp: PgpCredentials,
     ) -> ApiResult<Attachment<Body>> {
         let account_id = account_id.0;
         AccountModel::check_account_exists(account_id).await?;
         context.require_account_access(account_id)?;
         let id = id.0;
-        let reader = EML_INDEX_MANAGER.get_reader(account_id, id).await?;
-        let body = Body::from_async_read(reader);
+        let key = pgp.resolve(&context, account_id);
+        let data = EML_INDEX_MANAGER
+            .get_raw(account_id, id, key.as_deref())
+            .await?;
+        let body = Body::from_vec(data);
         let attachment = Attachment::new(body)
             .attachment_type(AttachmentType::Attachment)
             .filename(format!("{id}.eml"));
@@ -179,16 +309,18 @@ impl MessageApi {
         id: Query<u64>,
         name: Query<String>,
         context: ClientContext,
+        pgp: PgpCredentials,
     ) -> ApiResult<Attachment<Body>> {
         let account_id = account_id.0;
         AccountModel::check_account_exists(account_id).await?;
         context.require_account_access(account_id)?;
         let email_id = id.0;
         let name = name.0.trim();
-        let reader = EML_INDEX_MANAGER
-            .get_attachment(account_id, email_id, name)
+        let key = pgp.resolve(&context, account_id);
+        let data = EML_INDEX_MANAGER
+            .get_attachment(account_id, email_id, name, key.as_deref())
             .await?;
-        let body = Body::from_async_read(reader);
+        let body = Body::from_vec(data);
         let attachment = Attachment::new(body)
             .attachment_type(AttachmentType::Attachment)
             .filename(name);
@@ -18,22 +18,32 @@
 
 use access_token::AccessTokenApi;
 use account::AccountApi;
+use alert::AlertApi;
 use auto_config::AutoConfigApi;
+use backup::BackupApi;
+use legal_hold::LegalHoldApi;
 use mailbox::MailBoxApi;
 use message::MessageApi;
 use oauth2::OAuth2Api;
 use poem_openapi::{OpenApiService, Tags};
+use retention::RetentionApi;
+use saved_search::SavedSearchApi;
 use system::SystemApi;
 
 use crate::{bichon_version, modules::rest::api::import::ImportApi};
 
 pub mod access_token;
 pub mod account;
+pub mod alert;
 pub mod auto_config;
+pub mod backup;
 pub mod import;
+pub mod legal_hold;
 pub mod mailbox;
 pub mod message;
 pub mod oauth2;
+pub mod retention;
+pub mod saved_search;
 pub mod system;
 
 #[derive(Tags)]
@@ -46,6 +56,11 @@ pub enum ApiTags {
     Message,
     System,
     Import,
+    SavedSearch,
+    Alert,
+    Retention,
+    LegalHold,
+    Backup,
 }
 
 type RustMailOpenApi = (
@@ -57,6 +72,11 @@ type RustMailOpenApi = (
     OAuth2Api,
     MessageApi,
     ImportApi,
+    SavedSearchApi,
+    AlertApi,
+    RetentionApi,
+    LegalHoldApi,
+    BackupApi,
 );
 
 pub fn create_openapi_service() -> OpenApiService<RustMailOpenApi, ()> {
@@ -70,6 +90,11 @@ pub fn create_openapi_service() -> OpenApiService<RustMailOpenApi, ()> {
             OAuth2Api,
             MessageApi,
             ImportApi,
+            SavedSearchApi,
+            AlertApi,
+            RetentionApi,
+            LegalHoldApi,
+            BackupApi,
         ),
         "BichonApi",
         bichon_version!(),
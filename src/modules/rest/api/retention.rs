@@ -0,0 +1,145 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::modules::common::auth::ClientContext;
+use crate::modules::rest::api::ApiTags;
+use crate::modules::rest::ApiResult;
+use crate::modules::retention::{
+    apply_retention, RetentionReport, RetentionRule, RetentionRuleRequest,
+};
+use poem_openapi::param::Path;
+use poem_openapi::payload::Json;
+use poem_openapi::OpenApi;
+
+pub struct RetentionApi;
+
+#[OpenApi(prefix_path = "/api/v1", tag = "ApiTags::Retention")]
+impl RetentionApi {
+    /// Lists the retention rules in evaluation order. Requires root permission.
+    #[oai(
+        path = "/list-retention-rules",
+        method = "get",
+        operation_id = "list_retention_rules"
+    )]
+    async fn list_retention_rules(
+        &self,
+        context: ClientContext,
+    ) -> ApiResult<Json<Vec<RetentionRule>>> {
+        context.require_root()?;
+        Ok(Json(RetentionRule::list().await?))
+    }
+
+    /// Gets a retention rule. Requires root permission.
+    #[oai(
+        path = "/retention-rule/:id",
+        method = "get",
+        operation_id = "get_retention_rule"
+    )]
+    async fn get_retention_rule(
+        &self,
+        id: Path<u64>,
+        context: ClientContext,
+    ) -> ApiResult<Json<RetentionRule>> {
+        context.require_root()?;
+        Ok(Json(RetentionRule::get(id.0).await?))
+    }
+
+    /// Creates a retention rule, applied by the next purge. In WORM mode, the rule must not
+    /// take precedence over a rule keeping messages longer. Requires root permission.
+    #[oai(
+        path = "/retention-rule",
+        method = "post",
+        operation_id = "create_retention_rule"
+    )]
+    async fn create_retention_rule(
+        &self,
+        payload: Json<RetentionRuleRequest>,
+        context: ClientContext,
+    ) -> ApiResult<Json<RetentionRule>> {
+        context.require_root()?;
+        let rule = RetentionRule::new(payload.0);
+        rule.save().await?;
+        Ok(Json(rule))
+    }
+
+    /// Replaces the name, filter, duration and priority of a retention rule. In WORM mode,
+    /// only the name and a longer duration are accepted. Requires root permission.
+    #[oai(
+        path = "/retention-rule/:id",
+        method = "post",
+        operation_id = "update_retention_rule"
+    )]
+    async fn update_retention_rule(
+        &self,
+        id: Path<u64>,
+        payload: Json<RetentionRuleRequest>,
+        context: ClientContext,
+    ) -> ApiResult<()> {
+        context.require_root()?;
+        Ok(RetentionRule::update(id.0, payload.0).await?)
+    }
+
+    /// Deletes a retention rule, except in WORM mode. Requires root permission.
+    #[oai(
+        path = "/retention-rule/:id",
+        method = "delete",
+        operation_id = "remove_retention_rule"
+    )]
+    async fn remove_retention_rule(&self, id: Path<u64>, context: ClientContext) -> ApiResult<()> {
+        context.require_root()?;
+        Ok(RetentionRule::delete(id.0).await?)
+    }
+
+    /// Lists the messages the retention rules would purge now, without deleting them.
+    /// Requires root permission.
+    #[oai(
+        path = "/retention/dry-run",
+        method = "get",
+        operation_id = "retention_dry_run"
+    )]
+    async fn retention_dry_run(&self, context: ClientContext) -> ApiResult<Json<RetentionReport>> {
+        context.require_root()?;
+        Ok(Json(apply_retention(true).await?))
+    }
+
+    /// Purges the messages expired under the retention rules now, instead of waiting for
+    /// the periodic purge. Requires root permission.
+    #[oai(
+        path = "/retention/purge",
+        method = "post",
+        operation_id = "retention_purge"
+    )]
+    async fn retention_purge(&self, context: ClientContext) -> ApiResult<Json<RetentionReport>> {
+        context.require_root()?;
+        Ok(Json(apply_retention(false).await?))
+    }
+
+    /// Gets the report of the last purge, if any ran. Requires root permission.
+    #[oai(
+        path = "/retention/last-report",
+        method = "get",
+        operation_id = "get_last_retention_report"
+    )]
+    async fn get_last_retention_report(
+        &self,
+        context: ClientContext,
+    ) -> ApiResult<Json<Option<RetentionReport>>> {
+        context.require_root()?;
+        Ok(Json(RetentionReport::last().await?))
+    }
+}
@@ -0,0 +1,132 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::modules::common::auth::ClientContext;
+use crate::modules::message::saved::{SavedSearch, SavedSearchFolder, SavedSearchRequest};
+use crate::modules::message::search::SearchResult;
+use crate::modules::rest::api::ApiTags;
+use crate::modules::rest::ApiResult;
+use poem_openapi::param::{Path, Query};
+use poem_openapi::payload::Json;
+use poem_openapi::OpenApi;
+
+pub struct SavedSearchApi;
+
+#[OpenApi(prefix_path = "/api/v1", tag = "ApiTags::SavedSearch")]
+impl SavedSearchApi {
+    /// Lists the saved searches of the calling access token, with the number of messages
+    /// each one matched since it was last viewed.
+    #[oai(
+        path = "/list-saved-searches",
+        method = "get",
+        operation_id = "list_saved_searches"
+    )]
+    async fn list_saved_searches(
+        &self,
+        context: ClientContext,
+    ) -> ApiResult<Json<Vec<SavedSearchFolder>>> {
+        let mut folders = Vec::new();
+        for search in SavedSearch::list(&context.principal()).await? {
+            // a search that can no longer run, e.g. because the token lost access to its
+            // account or its header is no longer indexed, is still listed
+            let unread = match SavedSearch::authorize(&context, &search.filter) {
+                Ok(()) => search.unread().await.unwrap_or_default(),
+                Err(_) => 0,
+            };
+            folders.push(SavedSearchFolder { search, unread });
+        }
+        Ok(Json(folders))
+    }
+
+    /// Gets a saved search of the calling access token.
+    #[oai(
+        path = "/saved-search/:id",
+        method = "get",
+        operation_id = "get_saved_search"
+    )]
+    async fn get_saved_search(
+        &self,
+        id: Path<u64>,
+        context: ClientContext,
+    ) -> ApiResult<Json<SavedSearch>> {
+        Ok(Json(SavedSearch::get(&context.principal(), id.0).await?))
+    }
+
+    /// Saves a search filter under a name.
+    #[oai(
+        path = "/saved-search",
+        method = "post",
+        operation_id = "create_saved_search"
+    )]
+    async fn create_saved_search(
+        &self,
+        payload: Json<SavedSearchRequest>,
+        context: ClientContext,
+    ) -> ApiResult<Json<SavedSearch>> {
+        let request = payload.0;
+        SavedSearch::authorize(&context, &request.filter)?;
+        let search = SavedSearch::new(context.principal(), request);
+        search.save().await?;
+        Ok(Json(search))
+    }
+
+    /// Replaces the name, filter and sort options of a saved search.
+    #[oai(
+        path = "/saved-search/:id",
+        method = "post",
+        operation_id = "update_saved_search"
+    )]
+    async fn update_saved_search(
+        &self,
+        id: Path<u64>,
+        payload: Json<SavedSearchRequest>,
+        context: ClientContext,
+    ) -> ApiResult<()> {
+        let request = payload.0;
+        SavedSearch::authorize(&context, &request.filter)?;
+        Ok(SavedSearch::update(&context.principal(), id.0, request).await?)
+    }
+
+    /// Deletes a saved search.
+    #[oai(
+        path = "/saved-search/:id",
+        method = "delete",
+        operation_id = "remove_saved_search"
+    )]
+    async fn remove_saved_search(&self, id: Path<u64>, context: ClientContext) -> ApiResult<()> {
+        Ok(SavedSearch::delete(&context.principal(), id.0).await?)
+    }
+
+    /// Runs a saved search and marks it as viewed.
+    #[oai(
+        path = "/saved-search/:id/execute",
+        method = "post",
+        operation_id = "execute_saved_search"
+    )]
+    async fn execute_saved_search(
+        &self,
+        id: Path<u64>,
+        page: Query<u64>,
+        page_size: Query<u64>,
+        context: ClientContext,
+    ) -> ApiResult<Json<SearchResult>> {
+        let search = SavedSearch::get(&context.principal(), id.0).await?;
+        SavedSearch::authorize(&context, &search.filter)?;
+        Ok(Json(search.execute(page.0, page_size.0).await?))
+    }
+}
@@ -17,15 +17,20 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 
+use crate::modules::blob::crypto::{EmlEncryptionStatus, BLOB_REENCRYPTION};
 use crate::modules::common::auth::ClientContext;
 use crate::modules::dashboard::DashboardStats;
 use crate::modules::error::code::ErrorCode;
+use crate::modules::indexer::reindex::{ReindexStatus, ENVELOPE_REINDEXER};
+use crate::modules::integrity::{IntegrityCheckStatus, IntegrityRepair, INTEGRITY_CHECK};
 use crate::modules::rest::api::ApiTags;
 use crate::modules::rest::ApiResult;
+use crate::modules::settings::master_key::{master_key_status, rotate_master_key, MasterKeyStatus};
 use crate::modules::settings::proxy::Proxy;
 use crate::modules::version::{fetch_notifications, Notifications};
+use crate::modules::worm::{WormVerificationStatus, WORM_VERIFICATION};
 use crate::raise_error;
-use poem_openapi::param::Path;
+use poem_openapi::param::{Path, Query};
 use poem_openapi::payload::{Json, PlainText};
 use poem_openapi::OpenApi;
 
@@ -106,6 +111,167 @@ impl SystemApi {
         Ok(entity.save().await?)
     }
 
+    /// Rebuild the envelope index from the stored EML files. Requires root permission.
+    ///
+    /// The rebuild runs in the background. The current index keeps serving searches and
+    /// receiving new mail until it is replaced by the rebuilt one.
+    #[oai(path = "/reindex", method = "post", operation_id = "start_reindex")]
+    async fn start_reindex(&self, context: ClientContext) -> ApiResult<()> {
+        context.require_root()?;
+        Ok(ENVELOPE_REINDEXER.start().await?)
+    }
+
+    /// Get the schema version of the envelope index and the progress of its rebuild.
+    /// Requires root permission.
+    #[oai(
+        path = "/reindex-status",
+        method = "get",
+        operation_id = "get_reindex_status"
+    )]
+    async fn get_reindex_status(&self, context: ClientContext) -> ApiResult<Json<ReindexStatus>> {
+        context.require_root()?;
+        Ok(Json(ENVELOPE_REINDEXER.status()))
+    }
+
+    /// Check in the background that the envelope index, the EML store and the mailboxes
+    /// agree. Requires root permission.
+    ///
+    /// Reports envelopes without a stored message, stored messages without an envelope,
+    /// mailboxes of deleted accounts and stored messages that cannot be parsed. With
+    /// `reindex`, missing envelopes are derived from the stored messages; with `refetch`,
+    /// missing or unparsable messages are fetched from the IMAP server again; with
+    /// `remove_orphans`, mailboxes of deleted accounts are removed.
+    #[oai(
+        path = "/integrity-check",
+        method = "post",
+        operation_id = "start_integrity_check"
+    )]
+    async fn start_integrity_check(
+        &self,
+        reindex: Query<Option<bool>>,
+        refetch: Query<Option<bool>>,
+        remove_orphans: Query<Option<bool>>,
+        context: ClientContext,
+    ) -> ApiResult<()> {
+        context.require_root()?;
+        Ok(INTEGRITY_CHECK.start(IntegrityRepair {
+            reindex: reindex.0.unwrap_or(false),
+            refetch: refetch.0.unwrap_or(false),
+            remove_orphans: remove_orphans.0.unwrap_or(false),
+        })?)
+    }
+
+    /// Get the progress of the integrity check and the issues it found. Requires root
+    /// permission.
+    #[oai(
+        path = "/integrity-check",
+        method = "get",
+        operation_id = "get_integrity_check_status"
+    )]
+    async fn get_integrity_check_status(
+        &self,
+        context: ClientContext,
+    ) -> ApiResult<Json<IntegrityCheckStatus>> {
+        context.require_root()?;
+        Ok(Json(INTEGRITY_CHECK.status()))
+    }
+
+    /// Re-encrypt every stored message body and attachment in the background. Requires
+    /// root permission.
+    ///
+    /// With `rotate`, a new data key is created first. Blobs are written again with the
+    /// active data key, or unencrypted if `bichon_eml_encryption` is off, and data keys
+    /// no longer used are retired once done.
+    #[oai(
+        path = "/eml-encryption/reencrypt",
+        method = "post",
+        operation_id = "start_eml_reencryption"
+    )]
+    async fn start_eml_reencryption(
+        &self,
+        rotate: Query<Option<bool>>,
+        context: ClientContext,
+    ) -> ApiResult<()> {
+        context.require_root()?;
+        Ok(BLOB_REENCRYPTION.start(rotate.0.unwrap_or(false)).await?)
+    }
+
+    /// Get the data keys of the EML store and the progress of its re-encryption. Requires
+    /// root permission.
+    #[oai(
+        path = "/eml-encryption",
+        method = "get",
+        operation_id = "get_eml_encryption_status"
+    )]
+    async fn get_eml_encryption_status(
+        &self,
+        context: ClientContext,
+    ) -> ApiResult<Json<EmlEncryptionStatus>> {
+        context.require_root()?;
+        Ok(Json(BLOB_REENCRYPTION.status()))
+    }
+
+    /// Re-encrypt the stored secrets with the current master key. Requires root permission.
+    ///
+    /// IMAP passwords, OAuth2 client secrets and tokens and the root password encrypted with
+    /// `bichon_previous_encrypt_password`, or stored before key ids were recorded, are
+    /// re-encrypted with `bichon_encrypt_password` in one transaction. Returns the number
+    /// of secrets re-encrypted.
+    #[oai(
+        path = "/master-key/rotate",
+        method = "post",
+        operation_id = "rotate_master_key"
+    )]
+    async fn rotate_master_key(&self, context: ClientContext) -> ApiResult<Json<u64>> {
+        context.require_root()?;
+        Ok(Json(rotate_master_key().await?))
+    }
+
+    /// Count the stored secrets by the master key they are encrypted with. Requires root
+    /// permission.
+    #[oai(
+        path = "/master-key",
+        method = "get",
+        operation_id = "get_master_key_status"
+    )]
+    async fn get_master_key_status(
+        &self,
+        context: ClientContext,
+    ) -> ApiResult<Json<MasterKeyStatus>> {
+        context.require_root()?;
+        Ok(Json(master_key_status().await?))
+    }
+
+    /// Verify the WORM ledger and the stored messages in the background. Requires root
+    /// permission.
+    ///
+    /// Every ledger entry is checked against the previous one, and the SHA-256 of every
+    /// message still stored is recomputed and compared with the recorded one.
+    #[oai(
+        path = "/worm/verify",
+        method = "post",
+        operation_id = "start_worm_verification"
+    )]
+    async fn start_worm_verification(&self, context: ClientContext) -> ApiResult<()> {
+        context.require_root()?;
+        Ok(WORM_VERIFICATION.start()?)
+    }
+
+    /// Get the progress of the WORM verification and the mismatches it found. Requires
+    /// root permission.
+    #[oai(
+        path = "/worm/verify",
+        method = "get",
+        operation_id = "get_worm_verification_status"
+    )]
+    async fn get_worm_verification_status(
+        &self,
+        context: ClientContext,
+    ) -> ApiResult<Json<WormVerificationStatus>> {
+        context.require_root()?;
+        Ok(Json(WORM_VERIFICATION.status()))
+    }
+
     /// Update the URL of a specific proxy by ID. Requires root permission.
     #[oai(path = "/proxy/:id", method = "post", operation_id = "update_proxy")]
     async fn update_proxy(
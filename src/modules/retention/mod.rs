@@ -0,0 +1,683 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::{collections::HashMap, sync::LazyLock};
+
+use itertools::Itertools;
+use native_db::*;
+use native_model::{native_model, Model};
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::{
+    id,
+    modules::{
+        database::{
+            async_find_impl, delete_impl, insert_impl, list_all_impl, manager::DB_MANAGER,
+            update_impl,
+        },
+        error::{code::ErrorCode, BichonResult},
+        indexer::manager::ENVELOPE_INDEX_MANAGER,
+        legal_hold::LEGAL_HOLDS,
+        message::{delete::purge_expired_messages, saved::filter_json, search::SearchFilter},
+        settings::{cli::SETTINGS, system::SystemSetting},
+        worm::ensure_writable,
+    },
+    raise_error, utc_now,
+};
+
+pub mod task;
+
+/// System setting holding the report of the last purge, as JSON.
+const LAST_REPORT_SETTING: &str = "retention_last_report";
+/// Envelopes deleted at once by a purge.
+const PURGE_BATCH_SIZE: usize = 1000;
+/// Envelope ids listed per account and rule in a report.
+const REPORT_SAMPLE_SIZE: usize = 100;
+const DAY_MILLIS: i64 = 24 * 60 * 60 * 1000;
+
+/// Held while a purge runs, so the periodic task and a manual run never overlap.
+static PURGE_LOCK: LazyLock<Mutex<()>> = LazyLock::new(|| Mutex::new(()));
+
+/// Messages matching `filter` are purged once their `internal_date` is older than
+/// `retention_days`. A message is governed by the first enabled rule it matches, by
+/// ascending `priority`: a rule keeping legal mail 10 years placed before one keeping
+/// everything else 7 years protects legal mail from the latter. Messages under legal hold
+/// are never purged.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+#[native_model(id = 12, version = 1)]
+#[native_db]
+pub struct RetentionRule {
+    /// The unique identifier for this rule.
+    #[primary_key]
+    pub id: u64,
+    pub name: String,
+    /// Messages the rule applies to, e.g. an `account_id`, a `mailbox_id` or `tags`; an
+    /// empty filter matches every message.
+    #[serde(with = "filter_json")]
+    pub filter: SearchFilter,
+    pub retention_days: u32,
+    /// Rules with a lower priority are evaluated first.
+    pub priority: u32,
+    pub enabled: bool,
+    /// The creation timestamp of this record, represented as milliseconds since the Unix epoch.
+    pub created_at: i64,
+    /// The last update timestamp of this record, represented as milliseconds since the Unix epoch.
+    pub updated_at: i64,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct RetentionRuleRequest {
+    #[oai(validator(min_length = "1", max_length = "128"))]
+    pub name: String,
+    pub filter: SearchFilter,
+    #[oai(validator(minimum(value = "1")))]
+    pub retention_days: u32,
+    /// Defaults to `0`.
+    pub priority: Option<u32>,
+    /// Defaults to `true`.
+    pub enabled: Option<bool>,
+}
+
+/// Messages of one account purged, or to be purged, by a rule.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct RetentionAccountReport {
+    pub account_id: u64,
+    pub count: u64,
+    /// The lowest envelope ids among them, at most 100: reports are kept, and a purge
+    /// may remove millions of messages.
+    pub sample_ids: Vec<u64>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct RetentionRuleReport {
+    pub rule_id: u64,
+    pub rule_name: String,
+    /// Messages received before this time are expired, represented as milliseconds since
+    /// the Unix epoch.
+    pub cutoff: i64,
+    pub purged: u64,
+    pub accounts: Vec<RetentionAccountReport>,
+    /// Reason the rule could not be applied, if it failed.
+    pub error: Option<String>,
+}
+
+/// Outcome of a purge, or of a dry run listing what a purge would remove.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct RetentionReport {
+    pub dry_run: bool,
+    pub started_at: i64,
+    pub finished_at: i64,
+    pub purged: u64,
+    pub rules: Vec<RetentionRuleReport>,
+}
+
+impl RetentionRule {
+    pub fn new(request: RetentionRuleRequest) -> Self {
+        Self {
+            id: id!(64),
+            name: request.name,
+            filter: request.filter,
+            retention_days: request.retention_days,
+            priority: request.priority.unwrap_or_default(),
+            enabled: request.enabled.unwrap_or(true),
+            created_at: utc_now!(),
+            updated_at: utc_now!(),
+        }
+    }
+
+    pub async fn get(id: u64) -> BichonResult<RetentionRule> {
+        async_find_impl(DB_MANAGER.meta_db(), id)
+            .await?
+            .ok_or_else(|| {
+                raise_error!(
+                    format!("Retention rule with id={} not found", id),
+                    ErrorCode::ResourceNotFound
+                )
+            })
+    }
+
+    /// Every rule, in evaluation order.
+    pub async fn list() -> BichonResult<Vec<RetentionRule>> {
+        let rules: Vec<RetentionRule> = list_all_impl(DB_MANAGER.meta_db()).await?;
+        Ok(rules
+            .into_iter()
+            .sorted_by_key(|r| (r.priority, r.created_at))
+            .collect())
+    }
+
+    /// In WORM mode, fails if the new rule could purge messages earlier than a rule
+    /// evaluated after it.
+    pub async fn save(&self) -> BichonResult<()> {
+        if SETTINGS.bichon_worm_mode {
+            self.ensure_preempts_no_longer_rule(&Self::list().await?)?;
+        }
+        insert_impl(DB_MANAGER.meta_db(), self.to_owned()).await
+    }
+
+    /// In WORM mode, only the name can be changed and the duration lengthened: any other
+    /// change could purge messages earlier.
+    pub async fn update(id: u64, request: RetentionRuleRequest) -> BichonResult<()> {
+        update_impl(
+            DB_MANAGER.meta_db(),
+            move |rw| {
+                rw.get()
+                    .primary::<RetentionRule>(id)
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                    .ok_or_else(|| {
+                        raise_error!(
+                            format!("Retention rule with id={} not found", id),
+                            ErrorCode::ResourceNotFound
+                        )
+                    })
+            },
+            move |current| {
+                let mut updated = current.clone();
+                updated.name = request.name;
+                updated.filter = request.filter;
+                updated.retention_days = request.retention_days;
+                if let Some(priority) = request.priority {
+                    updated.priority = priority;
+                }
+                if let Some(enabled) = request.enabled {
+                    updated.enabled = enabled;
+                }
+                if SETTINGS.bichon_worm_mode {
+                    current.ensure_kept_as_long(&updated)?;
+                }
+                updated.updated_at = utc_now!();
+                Ok(updated)
+            },
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Fails in WORM mode, where the messages of the rule would fall to later rules.
+    pub async fn delete(id: u64) -> BichonResult<()> {
+        ensure_writable("retention rules")?;
+        delete_impl(DB_MANAGER.meta_db(), move |rw| {
+            rw.get()
+                .primary::<RetentionRule>(id)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                .ok_or_else(|| {
+                    raise_error!(
+                        format!("Retention rule with id={} not found", id),
+                        ErrorCode::ResourceNotFound
+                    )
+                })
+        })
+        .await
+    }
+
+    /// Fails if this new rule, evaluated before the enabled `rules` of a higher priority,
+    /// keeps messages a shorter time than one of them.
+    fn ensure_preempts_no_longer_rule(&self, rules: &[RetentionRule]) -> BichonResult<()> {
+        if !self.enabled {
+            return Ok(());
+        }
+        match rules
+            .iter()
+            .filter(|r| r.enabled && r.priority > self.priority)
+            .find(|r| r.retention_days > self.retention_days)
+        {
+            Some(preempted) => Err(raise_error!(
+                format!(
+                    "The archive is in WORM mode, a rule evaluated before '{}' ({}) must keep messages at least {} days",
+                    preempted.name, preempted.id, preempted.retention_days
+                ),
+                ErrorCode::ArchiveImmutable
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Fails if `updated` could purge a message earlier than this rule: its filter,
+    /// priority or state changed, or its duration was shortened.
+    fn ensure_kept_as_long(&self, updated: &RetentionRule) -> BichonResult<()> {
+        let reason = if updated.retention_days < self.retention_days {
+            "its duration cannot be shortened"
+        } else if updated.filter != self.filter {
+            "its filter cannot be changed"
+        } else if updated.priority != self.priority {
+            "its priority cannot be changed"
+        } else if updated.enabled != self.enabled {
+            "it cannot be enabled or disabled"
+        } else {
+            return Ok(());
+        };
+        Err(raise_error!(
+            format!(
+                "The archive is in WORM mode, retention rule '{}' ({}): {}",
+                self.name, self.id, reason
+            ),
+            ErrorCode::ArchiveImmutable
+        ))
+    }
+
+    /// The rule filter restricted to messages received before `cutoff`.
+    fn expired_filter(&self, cutoff: i64) -> SearchFilter {
+        let mut filter = self.filter.clone();
+        filter.before = Some(filter.before.map_or(cutoff, |before| before.min(cutoff)));
+        filter
+    }
+}
+
+impl RetentionReport {
+    pub async fn last() -> BichonResult<Option<RetentionReport>> {
+        SystemSetting::get_existing_value(LAST_REPORT_SETTING)?
+            .map(|value| {
+                serde_json::from_str(&value)
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))
+            })
+            .transpose()
+    }
+
+    async fn save(&self) -> BichonResult<()> {
+        let value = serde_json::to_string(self)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        SystemSetting::set_value(LAST_REPORT_SETTING, value).await
+    }
+}
+
+/// Applies the enabled retention rules, deleting expired messages through the same path
+/// as the message API, also in WORM mode. With `dry_run`, only reports what would be
+/// deleted.
+pub async fn apply_retention(dry_run: bool) -> BichonResult<RetentionReport> {
+    let _guard = if dry_run {
+        None
+    } else {
+        Some(PURGE_LOCK.try_lock().map_err(|_| {
+            raise_error!(
+                "A retention purge is already running.".into(),
+                ErrorCode::TooManyRequest
+            )
+        })?)
+    };
+    let rules: Vec<RetentionRule> = RetentionRule::list()
+        .await?
+        .into_iter()
+        .filter(|r| r.enabled)
+        .collect();
+    let report = apply_rules(&Archive, &rules, dry_run, utc_now!()).await;
+    if !dry_run {
+        report.save().await?;
+    }
+    Ok(report)
+}
+
+/// Where a purge finds and deletes expired messages.
+trait RetentionStore {
+    /// Envelope ids, by account, of the envelopes matching `filter` and none of `excluded`.
+    async fn matching_ids(
+        &self,
+        filter: SearchFilter,
+        excluded: Vec<SearchFilter>,
+    ) -> BichonResult<HashMap<u64, Vec<u64>>>;
+
+    /// Filters of the messages under legal hold.
+    fn held(&self) -> Vec<SearchFilter>;
+
+    async fn purge(&self, account_id: u64, envelope_ids: &[u64]) -> BichonResult<()>;
+}
+
+/// The envelope index and EML store.
+struct Archive;
+
+impl RetentionStore for Archive {
+    async fn matching_ids(
+        &self,
+        filter: SearchFilter,
+        excluded: Vec<SearchFilter>,
+    ) -> BichonResult<HashMap<u64, Vec<u64>>> {
+        ENVELOPE_INDEX_MANAGER.matching_ids(filter, excluded).await
+    }
+
+    fn held(&self) -> Vec<SearchFilter> {
+        LEGAL_HOLDS.filters()
+    }
+
+    async fn purge(&self, account_id: u64, envelope_ids: &[u64]) -> BichonResult<()> {
+        purge_expired_messages(HashMap::from([(account_id, envelope_ids.to_vec())])).await
+    }
+}
+
+/// Applies `rules`, in order, as of `started_at`.
+async fn apply_rules(
+    store: &impl RetentionStore,
+    rules: &[RetentionRule],
+    dry_run: bool,
+    started_at: i64,
+) -> RetentionReport {
+    let mut report = RetentionReport {
+        dry_run,
+        started_at,
+        ..Default::default()
+    };
+    for (index, rule) in rules.iter().enumerate() {
+        let cutoff = started_at - rule.retention_days as i64 * DAY_MILLIS;
+        let mut rule_report = RetentionRuleReport {
+            rule_id: rule.id,
+            rule_name: rule.name.clone(),
+            cutoff,
+            ..Default::default()
+        };
+        // Messages governed by an earlier rule are left to that rule, held ones are kept.
+        let mut excluded: Vec<SearchFilter> =
+            rules[..index].iter().map(|r| r.filter.clone()).collect();
+        excluded.extend(store.held());
+        match purge_rule(store, rule, cutoff, excluded, dry_run).await {
+            Ok(accounts) => {
+                rule_report.purged = accounts.iter().map(|a| a.count).sum();
+                rule_report.accounts = accounts;
+            }
+            Err(e) => {
+                tracing::error!("Retention rule '{}' failed: {:#?}", rule.name, e);
+                rule_report.error = Some(format!("{:#?}", e));
+            }
+        }
+        report.purged += rule_report.purged;
+        report.rules.push(rule_report);
+    }
+    report.finished_at = utc_now!();
+    report
+}
+
+async fn purge_rule(
+    store: &impl RetentionStore,
+    rule: &RetentionRule,
+    cutoff: i64,
+    excluded: Vec<SearchFilter>,
+    dry_run: bool,
+) -> BichonResult<Vec<RetentionAccountReport>> {
+    let expired = store
+        .matching_ids(rule.expired_filter(cutoff), excluded)
+        .await?;
+    let mut accounts = Vec::with_capacity(expired.len());
+    for (account_id, mut envelope_ids) in expired.into_iter().sorted_by_key(|(id, _)| *id) {
+        envelope_ids.sort_unstable();
+        if !dry_run {
+            for chunk in envelope_ids.chunks(PURGE_BATCH_SIZE) {
+                store.purge(account_id, chunk).await?;
+            }
+            tracing::info!(
+                "Retention rule '{}' purged {} messages of account {} received before {}",
+                rule.name,
+                envelope_ids.len(),
+                account_id,
+                cutoff
+            );
+            tracing::debug!(
+                "Retention rule '{}' purged envelopes {:?} of account {}",
+                rule.name,
+                envelope_ids,
+                account_id
+            );
+        }
+        accounts.push(RetentionAccountReport {
+            account_id,
+            count: envelope_ids.len() as u64,
+            sample_ids: envelope_ids.into_iter().take(REPORT_SAMPLE_SIZE).collect(),
+        });
+    }
+    Ok(accounts)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashMap, sync::Mutex};
+
+    use super::{
+        apply_rules, Archive, RetentionRule, RetentionRuleRequest, RetentionStore, DAY_MILLIS,
+        REPORT_SAMPLE_SIZE,
+    };
+    use crate::{
+        id,
+        modules::{
+            error::BichonResult,
+            indexer::manager::{EML_INDEX_MANAGER, ENVELOPE_INDEX_MANAGER},
+            legal_hold::LegalHold,
+            message::search::SearchFilter,
+            testing,
+        },
+        utc_now,
+    };
+
+    const NOW: i64 = 1_800_000_000_000;
+
+    /// Envelopes as (account id, mailbox id, envelope id, internal date).
+    #[derive(Default)]
+    struct MemoryStore {
+        envelopes: Mutex<Vec<(u64, u64, u64, i64)>>,
+        held_mailbox: Option<u64>,
+    }
+
+    fn matches(
+        filter: &SearchFilter,
+        (account_id, mailbox_id, _, date): (u64, u64, u64, i64),
+    ) -> bool {
+        filter.account_id.is_none_or(|id| id == account_id)
+            && filter.mailbox_id.is_none_or(|id| id == mailbox_id)
+            && filter.before.is_none_or(|before| date <= before)
+    }
+
+    impl MemoryStore {
+        fn add(&self, account_id: u64, mailbox_id: u64, eid: u64, age_days: i64) {
+            self.envelopes.lock().unwrap().push((
+                account_id,
+                mailbox_id,
+                eid,
+                NOW - age_days * DAY_MILLIS,
+            ));
+        }
+
+        fn remaining(&self) -> Vec<u64> {
+            let mut eids: Vec<u64> = self.envelopes.lock().unwrap().iter().map(|e| e.2).collect();
+            eids.sort_unstable();
+            eids
+        }
+    }
+
+    impl RetentionStore for MemoryStore {
+        async fn matching_ids(
+            &self,
+            filter: SearchFilter,
+            excluded: Vec<SearchFilter>,
+        ) -> BichonResult<HashMap<u64, Vec<u64>>> {
+            let mut ids: HashMap<u64, Vec<u64>> = HashMap::new();
+            for &envelope in self.envelopes.lock().unwrap().iter() {
+                if matches(&filter, envelope) && !excluded.iter().any(|f| matches(f, envelope)) {
+                    ids.entry(envelope.0).or_default().push(envelope.2);
+                }
+            }
+            Ok(ids)
+        }
+
+        fn held(&self) -> Vec<SearchFilter> {
+            self.held_mailbox
+                .map(|mailbox_id| SearchFilter {
+                    mailbox_id: Some(mailbox_id),
+                    ..Default::default()
+                })
+                .into_iter()
+                .collect()
+        }
+
+        async fn purge(&self, account_id: u64, envelope_ids: &[u64]) -> BichonResult<()> {
+            self.envelopes
+                .lock()
+                .unwrap()
+                .retain(|e| e.0 != account_id || !envelope_ids.contains(&e.2));
+            Ok(())
+        }
+    }
+
+    fn rule(name: &str, mailbox_id: Option<u64>, retention_days: u32) -> RetentionRule {
+        RetentionRule::new(RetentionRuleRequest {
+            name: name.into(),
+            filter: SearchFilter {
+                mailbox_id,
+                ..Default::default()
+            },
+            retention_days,
+            ..Default::default()
+        })
+    }
+
+    #[tokio::test]
+    async fn test_first_matching_rule_wins() {
+        let store = MemoryStore {
+            held_mailbox: Some(30),
+            ..Default::default()
+        };
+        store.add(1, 10, 1, 400); // legal mailbox, kept 10 years
+        store.add(1, 20, 2, 400); // expired under the catch-all rule
+        store.add(1, 20, 3, 10);
+        store.add(1, 30, 4, 400); // under legal hold
+        let rules = [rule("legal", Some(10), 3650), rule("everything", None, 365)];
+
+        let dry_run = apply_rules(&store, &rules, true, NOW).await;
+        assert_eq!(dry_run.purged, 1);
+        assert_eq!(store.remaining(), vec![1, 2, 3, 4]);
+
+        let report = apply_rules(&store, &rules, false, NOW).await;
+        assert_eq!(report.purged, 1);
+        assert_eq!(report.rules[0].purged, 0);
+        assert_eq!(report.rules[1].purged, 1);
+        assert_eq!(report.rules[1].accounts[0].sample_ids, vec![2]);
+        assert_eq!(store.remaining(), vec![1, 3, 4]);
+
+        // the same rules in the other order let the catch-all rule purge legal mail
+        let rules = [rule("everything", None, 365), rule("legal", Some(10), 3650)];
+        let report = apply_rules(&store, &rules, false, NOW).await;
+        assert_eq!(report.rules[0].purged, 1);
+        assert_eq!(store.remaining(), vec![3, 4]);
+    }
+
+    #[test]
+    fn test_worm_rule_changes_never_shorten_retention() {
+        let mut legal = rule("legal", Some(10), 3650);
+        legal.priority = 1;
+        let mut everything = rule("everything", None, 365);
+        everything.priority = 2;
+        let rules = [legal.clone(), everything];
+
+        // a new rule may not take precedence over a rule keeping messages longer
+        let mut first = rule("first", Some(20), 365);
+        assert!(first.ensure_preempts_no_longer_rule(&rules).is_err());
+        first.retention_days = 3650;
+        assert!(first.ensure_preempts_no_longer_rule(&rules).is_ok());
+        first.retention_days = 30;
+        first.enabled = false;
+        assert!(first.ensure_preempts_no_longer_rule(&rules).is_ok());
+        let mut last = rule("last", None, 30);
+        last.priority = 2;
+        assert!(last.ensure_preempts_no_longer_rule(&rules).is_ok());
+
+        let mut updated = legal.clone();
+        updated.name = "legal mail".into();
+        updated.retention_days = 3660;
+        assert!(legal.ensure_kept_as_long(&updated).is_ok());
+        updated.retention_days = 3000;
+        assert!(legal.ensure_kept_as_long(&updated).is_err());
+        let mut widened = legal.clone();
+        widened.filter = SearchFilter::default();
+        assert!(legal.ensure_kept_as_long(&widened).is_err());
+        let mut reordered = legal.clone();
+        reordered.priority = 3;
+        assert!(legal.ensure_kept_as_long(&reordered).is_err());
+        let mut disabled = legal.clone();
+        disabled.enabled = false;
+        assert!(legal.ensure_kept_as_long(&disabled).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_purge_reports_counts_and_a_sample() {
+        let store = MemoryStore::default();
+        let expired = REPORT_SAMPLE_SIZE as u64 + 50;
+        for eid in (1..=expired).rev() {
+            store.add(7, 1, eid, 100);
+        }
+        store.add(8, 1, 1000, 100);
+        store.add(8, 1, 1001, 1);
+
+        let report = apply_rules(&store, &[rule("all", None, 30)], false, NOW).await;
+        assert_eq!(report.purged, expired + 1);
+        let accounts = &report.rules[0].accounts;
+        assert_eq!(accounts[0].account_id, 7);
+        assert_eq!(accounts[0].count, expired);
+        assert_eq!(
+            accounts[0].sample_ids,
+            (1..=REPORT_SAMPLE_SIZE as u64).collect::<Vec<_>>()
+        );
+        assert_eq!(accounts[1].account_id, 8);
+        assert_eq!(accounts[1].count, 1);
+        assert_eq!(store.remaining(), vec![1001]);
+    }
+
+    #[test]
+    fn test_purge_skips_held_messages() {
+        testing::run(async {
+            let account_id = testing::account_id();
+            let (held, expired) = (id!(64), id!(64));
+            let eml = |subject| {
+                testing::eml(
+                    "a@acme.com",
+                    "b@acme.com",
+                    subject,
+                    "Mon, 1 Jan 2024 10:00:00 +0000",
+                    "",
+                )
+            };
+            testing::archive(account_id, 1, held, &eml("held")).await;
+            testing::archive(account_id, 2, expired, &eml("expired")).await;
+            testing::commit().await;
+            let hold = LegalHold {
+                id: id!(64),
+                name: "mailbox".into(),
+                account_id: Some(account_id),
+                mailbox_id: Some(1),
+                filter: SearchFilter {
+                    account_id: Some(account_id),
+                    mailbox_id: Some(1),
+                    ..Default::default()
+                },
+                created_by: "root".into(),
+                created_at: utc_now!(),
+                ..Default::default()
+            };
+            hold.save().await.unwrap();
+
+            let mut rule = rule("account", None, 30);
+            rule.filter.account_id = Some(account_id);
+            let report = apply_rules(&Archive, &[rule], false, utc_now!()).await;
+            LegalHold::release(hold.id, "root").await.unwrap();
+            assert_eq!(report.rules[0].error, None);
+            assert_eq!(report.purged, 1);
+            assert_eq!(report.rules[0].accounts[0].sample_ids, vec![expired]);
+            let filter = SearchFilter {
+                account_id: Some(account_id),
+                ..Default::default()
+            };
+            assert_eq!(ENVELOPE_INDEX_MANAGER.count(filter).await.unwrap(), 1);
+            assert!(EML_INDEX_MANAGER.contains(held).unwrap());
+            assert!(!EML_INDEX_MANAGER.contains(expired).unwrap());
+        })
+    }
+}
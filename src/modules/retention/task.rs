@@ -0,0 +1,45 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use crate::modules::{
+    common::periodic::PeriodicTask, context::RustMailTask, retention::apply_retention,
+};
+use std::time::Duration;
+
+const TASK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// This task purges the messages expired under the retention rules.
+pub struct RetentionTask;
+
+impl RustMailTask for RetentionTask {
+    fn start() {
+        let periodic_task = PeriodicTask::new("retention-purger");
+
+        let task = move |_: Option<u64>| {
+            Box::pin(async move {
+                let report = apply_retention(false).await?;
+                if report.purged > 0 {
+                    tracing::info!("Retention purge removed {} messages", report.purged);
+                }
+                Ok(())
+            })
+        };
+
+        periodic_task.start(task, None, TASK_INTERVAL, false, false);
+    }
+}
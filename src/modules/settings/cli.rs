@@ -17,9 +17,13 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use clap::{builder::ValueParser, Parser, ValueEnum};
-use std::{collections::HashSet, env, fmt, path::PathBuf, sync::LazyLock};
+use std::{collections::HashSet, env, fmt, path::PathBuf, str::FromStr, sync::LazyLock};
 
+#[cfg(not(test))]
 pub static SETTINGS: LazyLock<Settings> = LazyLock::new(Settings::parse);
+/// Tests run against an archive of their own, see `testing::settings`.
+#[cfg(test)]
+pub static SETTINGS: LazyLock<Settings> = LazyLock::new(crate::modules::testing::settings);
 
 #[derive(Debug, Parser)]
 #[clap(
@@ -215,6 +219,210 @@ pub struct Settings {
         value_parser = clap::value_parser!(u16).range(1..)
     )]
     pub bichon_sync_concurrency: Option<u16>,
+
+    #[clap(
+        long,
+        default_value = "true",
+        env,
+        help = "Extract and index the text content of PDF, office, HTML and plain text attachments"
+    )]
+    pub bichon_attachment_text_extraction: bool,
+
+    #[clap(
+        long,
+        default_value = "20971520",
+        env,
+        help = "Attachments larger than this size in bytes are not text-extracted (default: 20 MiB)"
+    )]
+    pub bichon_attachment_text_max_size: usize,
+
+    #[clap(
+        long,
+        default_value = "30",
+        env,
+        help = "Maximum time in seconds spent extracting attachment text from a single message",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub bichon_attachment_text_timeout: u64,
+
+    #[clap(
+        long,
+        default_value = "false",
+        hide = true,
+        help = "Run as an attachment text extraction worker, reading a message on stdin. Started by the server itself"
+    )]
+    pub bichon_attachment_text_worker: bool,
+
+    #[clap(
+        long,
+        default_value = "default",
+        env,
+        help = "Analyzer of the subject field: default, ascii-folding, or cjk (CJK bigram segmentation with ASCII folding)"
+    )]
+    pub bichon_subject_analyzer: TextAnalyzerKind,
+
+    #[clap(
+        long,
+        default_value = "default",
+        env,
+        help = "Analyzer of the body and attachment content fields: default, ascii-folding, or cjk (CJK bigram segmentation with ASCII folding)"
+    )]
+    pub bichon_body_analyzer: TextAnalyzerKind,
+
+    #[clap(
+        long,
+        default_value = "true",
+        env,
+        help = "Also index subject, body and attachment text stemmed in the language detected for the message"
+    )]
+    pub bichon_stemming: bool,
+
+    #[clap(
+        long,
+        default_value = "eng",
+        env,
+        help = "ISO 639-3 code of the stemming language used when the language of a message cannot be detected reliably; queries are stemmed in it and in the other indexed languages"
+    )]
+    pub bichon_default_language: String,
+
+    #[clap(
+        long,
+        default_value = "true",
+        env,
+        help = "Rebuild the envelope index from the stored EML in the background when it was built with an older schema. An index built with an incompatible schema is always rebuilt"
+    )]
+    pub bichon_auto_reindex: bool,
+
+    #[clap(
+        long,
+        default_value = "list-id,x-mailer,return-path,received:text,x-original-to,auto-submitted",
+        env,
+        help = "Comma-separated list of message headers to index, each optionally followed by ':exact' (whole value, case-insensitive, the default), ':text' (full-text) or ':both'"
+    )]
+    pub bichon_indexed_headers: IndexedHeaders,
+
+    #[clap(
+        long,
+        default_value = "127.0.0.1:25",
+        env,
+        help = "Address (host:port) of the SMTP relay used to email search alerts, without authentication or TLS"
+    )]
+    pub bichon_alert_smtp_relay: String,
+
+    #[clap(
+        long,
+        default_value = "bichon@localhost",
+        env,
+        help = "Sender address of search alert emails"
+    )]
+    pub bichon_alert_mail_from: String,
+
+    #[clap(
+        long,
+        default_value = "filesystem",
+        env,
+        help = "Where raw message bodies and attachments are stored: 'filesystem' or 's3'"
+    )]
+    pub bichon_blob_store: BlobStoreKind,
+
+    #[clap(
+        long,
+        env,
+        help = "Directory of the filesystem blob store (default: <bichon_root_dir>/blobs)"
+    )]
+    pub bichon_blob_dir: Option<String>,
+
+    #[clap(
+        long,
+        env,
+        help = "Endpoint of the S3-compatible blob store, e.g. https://s3.us-east-1.amazonaws.com or http://127.0.0.1:9000. Buckets are addressed path-style"
+    )]
+    pub bichon_s3_endpoint: Option<String>,
+
+    #[clap(long, env, help = "Bucket of the S3-compatible blob store")]
+    pub bichon_s3_bucket: Option<String>,
+
+    #[clap(
+        long,
+        default_value = "us-east-1",
+        env,
+        help = "Region of the S3-compatible blob store"
+    )]
+    pub bichon_s3_region: String,
+
+    #[clap(long, env, help = "Access key of the S3-compatible blob store")]
+    pub bichon_s3_access_key: Option<String>,
+
+    #[clap(long, env, help = "Secret key of the S3-compatible blob store")]
+    pub bichon_s3_secret_key: Option<String>,
+
+    #[clap(
+        long,
+        default_value = "",
+        env,
+        help = "Prefix of the object keys in the S3-compatible blob store, e.g. 'bichon/'"
+    )]
+    pub bichon_s3_prefix: String,
+
+    #[clap(
+        long,
+        env,
+        help = "Copy every stored message body and attachment to the given blob store ('filesystem' or 's3'), then exit. Run it while the server is stopped"
+    )]
+    pub bichon_migrate_blobs_to: Option<BlobStoreKind>,
+
+    #[clap(
+        long,
+        default_value = "false",
+        env,
+        help = "Convert an EML store created by an earlier version, holding one copy of each message, to deduplicated bodies and attachments, then exit. The previous store is removed once every message is found in the converted one. Run it while the server is stopped"
+    )]
+    pub bichon_convert_eml_store: bool,
+
+    #[clap(
+        long,
+        env,
+        help = "Restore a backup made by the backup API, a directory or a tarball, into the empty bichon_root_dir, then exit. The backups an incremental backup builds on must be in the same directory. Run it while the server is stopped"
+    )]
+    pub bichon_restore_from: Option<String>,
+
+    #[clap(
+        long,
+        env,
+        help = "Directory the backup API writes backups to when no path is given, and incremental backups find the backup they build on"
+    )]
+    pub bichon_backup_dir: Option<String>,
+
+    #[clap(
+        long,
+        env,
+        help = "Number of backup sets, a full backup and the incremental backups built on it, kept in bichon_backup_dir. Older sets are removed after each backup (default: keep all)",
+        value_parser = clap::value_parser!(u32).range(1..)
+    )]
+    pub bichon_backup_keep_sets: Option<u32>,
+
+    #[clap(
+        long,
+        default_value = "false",
+        env,
+        help = "Encrypt stored message bodies and attachments with AES-256-GCM data keys, themselves encrypted with bichon_encrypt_password. The search index still keeps the subject, body and attachment text in plaintext; use an encrypted disk or account PGP keys to protect it"
+    )]
+    pub bichon_eml_encryption: bool,
+
+    #[clap(
+        long,
+        default_value = "false",
+        env,
+        help = "Write-once archive: stored messages are never replaced, only deleted on retention expiry, and recorded in a hash-chained ledger that can be verified"
+    )]
+    pub bichon_worm_mode: bool,
+
+    #[clap(
+        long,
+        env,
+        help = "Previous value of bichon_encrypt_password, after changing it. The stored secrets and the data keys of the EML store are re-encrypted with the new password at startup"
+    )]
+    pub bichon_previous_encrypt_password: Option<String>,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
@@ -231,6 +439,91 @@ pub enum CompressionAlgorithm {
     Deflate,
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum BlobStoreKind {
+    #[clap(name = "filesystem")]
+    Filesystem,
+    #[clap(name = "s3")]
+    S3,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum TextAnalyzerKind {
+    #[clap(name = "default")]
+    Default,
+    #[clap(name = "ascii-folding")]
+    AsciiFolding,
+    #[clap(name = "cjk")]
+    Cjk,
+}
+
+/// Message header indexed for search, see `bichon_indexed_headers`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IndexedHeader {
+    /// Lowercase header name.
+    pub name: String,
+    /// Indexed as a whole value, matched case-insensitively.
+    pub exact: bool,
+    /// Indexed as full text.
+    pub text: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IndexedHeaders(pub Vec<IndexedHeader>);
+
+impl IndexedHeaders {
+    pub fn get(&self, name: &str) -> Option<&IndexedHeader> {
+        let name = name.trim().to_lowercase();
+        self.0.iter().find(|header| header.name == name)
+    }
+}
+
+impl FromStr for IndexedHeaders {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut headers: Vec<IndexedHeader> = Vec::new();
+        for entry in s.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let (name, mode) = entry.split_once(':').unwrap_or((entry, "exact"));
+            let name = name.trim().to_lowercase();
+            if name.is_empty() || !name.bytes().all(|b| b.is_ascii_graphic() && b != b':') {
+                return Err(format!("Invalid header name '{}'.", name));
+            }
+            let (exact, text) = match mode.trim() {
+                "exact" => (true, false),
+                "text" => (false, true),
+                "both" => (true, true),
+                other => {
+                    return Err(format!(
+                        "Invalid mode '{}' for header '{}', expected exact, text or both.",
+                        other, name
+                    ))
+                }
+            };
+            if headers.iter().any(|header| header.name == name) {
+                return Err(format!("Header '{}' is listed more than once.", name));
+            }
+            headers.push(IndexedHeader { name, exact, text });
+        }
+        Ok(Self(headers))
+    }
+}
+
+impl fmt::Display for IndexedHeaders {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let entries: Vec<String> = self
+            .0
+            .iter()
+            .map(|header| match (header.exact, header.text) {
+                (true, true) => format!("{}:both", header.name),
+                (false, true) => format!("{}:text", header.name),
+                _ => format!("{}:exact", header.name),
+            })
+            .collect();
+        write!(f, "{}", entries.join(","))
+    }
+}
+
 impl fmt::Display for CompressionAlgorithm {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
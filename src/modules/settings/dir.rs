@@ -28,8 +28,9 @@ use std::sync::LazyLock;
 
 pub const META_FILE: &str = "meta.db";
 pub const MAILBOX_FILE: &str = "mailbox.db";
-const ENVELOPE_DIR: &str = "envelope";
-const EML_DIR: &str = "eml";
+pub const ENVELOPE_DIR: &str = "envelope";
+pub const EML_DIR: &str = "eml";
+pub const BLOB_DIR: &str = "blobs";
 const TMP_DIR: &str = "tmp";
 const LOG_DIR: &str = "logs";
 const TLS_CERT: &str = "cert.pem";
@@ -49,6 +50,7 @@ pub struct DataDirManager {
     pub tls_key: PathBuf,
     pub envelope_dir: PathBuf,
     pub eml_dir: PathBuf,
+    pub blob_dir: PathBuf,
     pub log_dir: PathBuf
 }
 
@@ -60,6 +62,15 @@ impl Initialize for DataDirManager {
             .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
         std::fs::create_dir_all(&DATA_DIR_MANAGER.temp_dir)
             .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        // downloads were once written here, in plaintext for encrypted messages
+        let entries = std::fs::read_dir(&DATA_DIR_MANAGER.temp_dir)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "eml") {
+                let _ = std::fs::remove_file(path);
+            }
+        }
         Ok(())
     }
 }
@@ -76,6 +87,7 @@ impl DataDirManager {
             envelope_dir: root_dir.join(ENVELOPE_DIR),
             temp_dir: root_dir.join(TMP_DIR),
             eml_dir: root_dir.join(EML_DIR),
+            blob_dir: root_dir.join(BLOB_DIR),
         }
     }
 }
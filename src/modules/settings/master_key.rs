@@ -0,0 +1,221 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use itertools::Itertools;
+use native_db::{
+    transaction::{RTransaction, RwTransaction},
+    ToInput,
+};
+use poem_openapi::Object;
+use serde::{Deserialize, Serialize};
+
+use crate::modules::{
+    account::migration::AccountModel,
+    context::Initialize,
+    database::manager::DB_MANAGER,
+    error::{code::ErrorCode, BichonResult},
+    oauth2::{entity::OAuth2, token::OAuth2AccessToken},
+    settings::{cli::SETTINGS, system::SystemSetting},
+    token::root::ROOT_PASSWORD,
+    utils::encrypt::{
+        current_master_key_id, master_key_id, reencrypt_string, secret_key_state, SecretKeyState,
+    },
+};
+use crate::raise_error;
+
+/// Re-encrypts the stored secrets with `bichon_encrypt_password` at startup, when
+/// `bichon_previous_encrypt_password` is set.
+pub struct MasterKey;
+
+/// Master keys the stored secrets are encrypted with.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct MasterKeyStatus {
+    /// Id of the key derived from `bichon_encrypt_password`.
+    pub key_id: String,
+    /// Id of the key derived from `bichon_previous_encrypt_password`, if set.
+    pub previous_key_id: Option<String>,
+    /// Secrets encrypted with the current key.
+    pub current: u64,
+    /// Secrets encrypted with the previous key, still to be rotated.
+    pub previous: u64,
+    /// Secrets stored before key ids were recorded, still to be rotated.
+    pub legacy: u64,
+    /// Secrets encrypted with another key, they can no longer be decrypted.
+    pub unknown: u64,
+}
+
+impl MasterKeyStatus {
+    fn count(&mut self, secret: &str) {
+        match secret_key_state(secret) {
+            SecretKeyState::Current => self.current += 1,
+            SecretKeyState::Previous => self.previous += 1,
+            SecretKeyState::Legacy => self.legacy += 1,
+            SecretKeyState::Unknown => self.unknown += 1,
+        }
+    }
+}
+
+fn account_secrets(account: &mut AccountModel) -> Vec<&mut String> {
+    account
+        .imap
+        .as_mut()
+        .and_then(|imap| imap.auth.password.as_mut())
+        .into_iter()
+        .collect()
+}
+
+fn oauth2_secrets(oauth2: &mut OAuth2) -> Vec<&mut String> {
+    vec![&mut oauth2.client_secret]
+}
+
+fn token_secrets(token: &mut OAuth2AccessToken) -> Vec<&mut String> {
+    token
+        .access_token
+        .as_mut()
+        .into_iter()
+        .chain(token.refresh_token.as_mut())
+        .collect()
+}
+
+fn setting_secrets(setting: &mut SystemSetting) -> Vec<&mut String> {
+    if setting.key == ROOT_PASSWORD {
+        vec![&mut setting.value]
+    } else {
+        Vec::new()
+    }
+}
+
+fn scan_all<T: ToInput>(rw: &RwTransaction) -> BichonResult<Vec<T>> {
+    rw.scan()
+        .primary()
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+        .all()
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+        .try_collect()
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))
+}
+
+fn count_secrets<T: ToInput>(
+    r: &RTransaction,
+    secrets: fn(&mut T) -> Vec<&mut String>,
+    status: &mut MasterKeyStatus,
+) -> BichonResult<()> {
+    let entities: Vec<T> = r
+        .scan()
+        .primary()
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+        .all()
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+        .try_collect()
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+    for mut entity in entities {
+        for secret in secrets(&mut entity) {
+            status.count(secret);
+        }
+    }
+    Ok(())
+}
+
+/// Re-encrypts the secrets of every `T` not encrypted with the current key, returning how
+/// many were.
+fn rotate_secrets<T: ToInput + Clone>(
+    rw: &RwTransaction,
+    secrets: fn(&mut T) -> Vec<&mut String>,
+) -> BichonResult<u64> {
+    let mut rotated = 0;
+    for entity in scan_all::<T>(rw)? {
+        let mut updated = entity.clone();
+        let mut changed = false;
+        for secret in secrets(&mut updated) {
+            if let Some(reencrypted) = reencrypt_string(secret)? {
+                *secret = reencrypted;
+                rotated += 1;
+                changed = true;
+            }
+        }
+        if changed {
+            rw.update(entity, updated)
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        }
+    }
+    Ok(rotated)
+}
+
+/// Counts the stored secrets by the master key they are encrypted with. Proxy URLs are
+/// stored in plain text and are not counted.
+pub async fn master_key_status() -> BichonResult<MasterKeyStatus> {
+    tokio::task::spawn_blocking(|| {
+        let r = DB_MANAGER
+            .meta_db()
+            .r_transaction()
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        let mut status = MasterKeyStatus {
+            key_id: current_master_key_id().to_string(),
+            previous_key_id: SETTINGS
+                .bichon_previous_encrypt_password
+                .as_deref()
+                .map(master_key_id),
+            ..Default::default()
+        };
+        count_secrets::<AccountModel>(&r, account_secrets, &mut status)?;
+        count_secrets::<OAuth2>(&r, oauth2_secrets, &mut status)?;
+        count_secrets::<OAuth2AccessToken>(&r, token_secrets, &mut status)?;
+        count_secrets::<SystemSetting>(&r, setting_secrets, &mut status)?;
+        Ok(status)
+    })
+    .await
+    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+}
+
+/// Re-encrypts the IMAP passwords, OAuth2 client secrets and tokens and the root password
+/// with the current master key, in one transaction: if any secret cannot be decrypted,
+/// none is changed. Returns the number of secrets re-encrypted.
+pub async fn rotate_master_key() -> BichonResult<u64> {
+    tokio::task::spawn_blocking(|| {
+        let rw = DB_MANAGER
+            .meta_db()
+            .rw_transaction()
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        let rotated = rotate_secrets::<AccountModel>(&rw, account_secrets)?
+            + rotate_secrets::<OAuth2>(&rw, oauth2_secrets)?
+            + rotate_secrets::<OAuth2AccessToken>(&rw, token_secrets)?
+            + rotate_secrets::<SystemSetting>(&rw, setting_secrets)?;
+        rw.commit()
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        Ok(rotated)
+    })
+    .await
+    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+}
+
+impl Initialize for MasterKey {
+    async fn initialize() -> BichonResult<()> {
+        if SETTINGS.bichon_previous_encrypt_password.is_none() {
+            return Ok(());
+        }
+        let rotated = rotate_master_key().await?;
+        if rotated > 0 {
+            tracing::info!(
+                "Re-encrypted {} secrets with master key {}",
+                rotated,
+                current_master_key_id()
+            );
+        }
+        Ok(())
+    }
+}
@@ -19,5 +19,6 @@
 
 pub mod cli;
 pub mod dir;
+pub mod master_key;
 pub mod proxy;
 pub mod system;
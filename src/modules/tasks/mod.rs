@@ -19,11 +19,13 @@
 
 use crate::modules::context::RustMailTask;
 use crate::modules::oauth2::{refresh::OAuth2RefreshTask, task::OAuth2CleanTask};
+use crate::modules::retention::task::RetentionTask;
 pub struct PeriodicTasks;
 
 impl PeriodicTasks {
     pub fn start_background_tasks() {
         OAuth2CleanTask::start();
         OAuth2RefreshTask::start();
+        RetentionTask::start();
     }
 }
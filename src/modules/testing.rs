@@ -0,0 +1,141 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Tests against an archive in a temporary directory created for the test process. The
+//! indexes and databases are shared by every test of the process: each test works on
+//! accounts of its own, see `account_id`.
+
+use std::{fmt::Debug, future::Future, sync::LazyLock};
+
+use clap::Parser;
+use tempfile::TempDir;
+use tokio::{runtime::Runtime, sync::OnceCell};
+
+use crate::{
+    id,
+    modules::{
+        blob::crypto::DataKeys,
+        context::Initialize,
+        error::{code::ErrorCode, BichonError, BichonResult},
+        indexer::{
+            manager::{EML_INDEX_MANAGER, ENVELOPE_INDEX_MANAGER},
+            reindex::envelope_document,
+            schema::SchemaTools,
+        },
+        legal_hold::LegalHolds,
+        settings::{cli::Settings, dir::DataDirManager},
+        worm::WormLedger,
+    },
+};
+use tantivy::doc;
+
+/// Root directory of the archive, left behind for inspection once the process exits.
+static ROOT_DIR: LazyLock<TempDir> = LazyLock::new(|| {
+    tempfile::Builder::new()
+        .prefix("bichon-test-")
+        .tempdir()
+        .expect("Failed to create the test root directory")
+});
+
+/// The index writers run on this runtime, which outlives every test.
+static RUNTIME: LazyLock<Runtime> = LazyLock::new(|| {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .expect("Failed to build the test runtime")
+});
+static INITIALIZED: OnceCell<()> = OnceCell::const_new();
+
+/// Runs `test` once the archive is initialized.
+pub fn run<F: Future>(test: F) -> F::Output {
+    RUNTIME.block_on(async {
+        INITIALIZED
+            .get_or_init(|| async {
+                DataDirManager::initialize().await.unwrap();
+                DataKeys::initialize().await.unwrap();
+                LegalHolds::initialize().await.unwrap();
+                WormLedger::initialize().await.unwrap();
+            })
+            .await;
+        test.await
+    })
+}
+
+/// Settings of the test process: the defaults, with the archive in `ROOT_DIR`. The
+/// arguments of the test harness are left out, and so is `BICHON_ROOT_DIR`.
+pub fn settings() -> Settings {
+    let root_dir = ROOT_DIR.path().to_string_lossy().into_owned();
+    Settings::parse_from(["bichon", "--bichon-root-dir", &root_dir])
+}
+
+/// An account id no other test uses.
+pub fn account_id() -> u64 {
+    id!(64)
+}
+
+/// A plain text message.
+pub fn eml(from: &str, to: &str, subject: &str, date: &str, body: &str) -> Vec<u8> {
+    format!(
+        "From: {from}\r\nTo: {to}\r\nSubject: {subject}\r\nDate: {date}\r\n\
+        Message-ID: <{}@bichon.test>\r\nMIME-Version: 1.0\r\n\
+        Content-Type: text/plain; charset=utf-8\r\n\r\n{body}\r\n",
+        id!(64)
+    )
+    .into_bytes()
+}
+
+/// Queues the envelope and the message of `eml`, see `commit`.
+pub async fn archive(account_id: u64, mailbox_id: u64, eid: u64, eml: &[u8]) {
+    archive_envelope(account_id, mailbox_id, eid, eml).await;
+    archive_eml(account_id, mailbox_id, eid, eml).await;
+}
+
+pub async fn archive_envelope(account_id: u64, mailbox_id: u64, eid: u64, eml: &[u8]) {
+    let document = envelope_document(eml, account_id, mailbox_id, eid)
+        .await
+        .unwrap();
+    ENVELOPE_INDEX_MANAGER.add_document(eid, document).await;
+}
+
+pub async fn archive_eml(account_id: u64, mailbox_id: u64, eid: u64, eml: &[u8]) {
+    let fields = SchemaTools::eml_fields();
+    EML_INDEX_MANAGER
+        .add_document(
+            eid,
+            doc!(
+                fields.f_id => eid,
+                fields.f_account_id => account_id,
+                fields.f_mailbox_id => mailbox_id,
+                fields.f_eml => eml.to_vec()
+            ),
+        )
+        .await;
+}
+
+/// Waits until everything queued is committed to both indexes.
+pub async fn commit() {
+    ENVELOPE_INDEX_MANAGER.flush().await;
+    EML_INDEX_MANAGER.flush().await;
+}
+
+/// The code of the error `result` must hold.
+pub fn error_code<T: Debug>(result: BichonResult<T>) -> ErrorCode {
+    match result.expect_err("an error was expected") {
+        BichonError::Generic { code, .. } => code,
+    }
+}
@@ -18,9 +18,11 @@
 
 
 use crate::modules::account::migration::AccountModel;
+use crate::modules::alert::SearchAlert;
 use crate::modules::database::delete_impl;
 use crate::modules::database::manager::DB_MANAGER;
 use crate::modules::database::{insert_impl, list_all_impl, update_impl};
+use crate::modules::message::saved::SavedSearch;
 use crate::modules::token::payload::AccessTokenUpdateRequest;
 use crate::raise_error;
 use crate::{
@@ -29,6 +31,7 @@ use crate::{
 };
 use native_db::*;
 use native_model::{native_model, Model};
+use ring::digest;
 use poem_openapi::Object;
 use serde::{Deserialize, Serialize};
 use std::collections::BTreeSet;
@@ -147,6 +150,13 @@ impl AccessToken {
         }
     }
 
+    /// Identifies `token` as the owner of data kept per access token, such as saved
+    /// searches and alerts, without storing the token itself.
+    pub fn owner_id(token: &str) -> String {
+        let hash = digest::digest(&digest::SHA256, token.as_bytes());
+        format!("token-{}", hex::encode(&hash.as_ref()[..12]))
+    }
+
     pub async fn try_update_access_timestamp(token: &str) -> BichonResult<AccessToken> {
         let token = token.to_string();
         update_impl(
@@ -287,6 +297,7 @@ impl AccessToken {
     }
 
     pub async fn delete(token: &str) -> BichonResult<()> {
+        let owner = Self::owner_id(token);
         let token = token.to_string();
         delete_impl(DB_MANAGER.meta_db(), move |rw| {
             rw.get()
@@ -299,7 +310,9 @@ impl AccessToken {
                     )
                 })
         })
-        .await
+        .await?;
+        SavedSearch::clean(&owner).await?;
+        SearchAlert::clean(&owner).await
     }
 
     pub async fn list_all() -> BichonResult<Vec<AccessToken>> {
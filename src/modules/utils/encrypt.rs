@@ -22,6 +22,7 @@ use ring::aead::{Aad, BoundKey, Nonce, NonceSequence, OpeningKey, SealingKey, AE
 use ring::pbkdf2::{self, derive};
 use ring::rand::{SecureRandom, SystemRandom};
 use std::num::NonZeroU32;
+use std::sync::LazyLock;
 
 use crate::modules::error::code::ErrorCode;
 use crate::modules::error::BichonResult;
@@ -42,13 +43,115 @@ impl NonceSequence for SingleNonceSequence {
     }
 }
 
+/// Id of the master key derived from `bichon_encrypt_password`, prefixed to the secrets
+/// it encrypts.
+static MASTER_KEY_ID: LazyLock<String> =
+    LazyLock::new(|| master_key_id(&SETTINGS.bichon_encrypt_password));
+static PREVIOUS_MASTER_KEY_ID: LazyLock<Option<String>> = LazyLock::new(|| {
+    SETTINGS
+        .bichon_previous_encrypt_password
+        .as_deref()
+        .map(master_key_id)
+});
+
+/// Master key a stored secret is encrypted with.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SecretKeyState {
+    /// `bichon_encrypt_password`.
+    Current,
+    /// `bichon_previous_encrypt_password`.
+    Previous,
+    /// Encrypted before secrets had a key id, with either password.
+    Legacy,
+    /// Another password, the secret cannot be decrypted.
+    Unknown,
+}
+
+/// Short id of the master key derived from `password`. Derived with PBKDF2 and truncated
+/// to 32 bits, it tells keys apart without helping to guess the password.
+pub fn master_key_id(password: &str) -> String {
+    let mut id = [0u8; 4];
+    derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        NonZeroU32::new(100_000).unwrap(),
+        b"bichon-master-key-id",
+        password.as_bytes(),
+        &mut id,
+    );
+    hex::encode(id)
+}
+
+pub fn current_master_key_id() -> &'static str {
+    &MASTER_KEY_ID
+}
+
+/// Whether `id` is the id of `bichon_encrypt_password` or `bichon_previous_encrypt_password`.
+pub fn is_known_master_key(id: &str) -> bool {
+    id == MASTER_KEY_ID.as_str() || PREVIOUS_MASTER_KEY_ID.as_deref() == Some(id)
+}
+
+/// Splits a stored secret into its key id, if it has one, and the encrypted value.
+fn split_key_id(data: &str) -> (Option<&str>, &str) {
+    match data.split_once(':') {
+        Some((id, value)) if id.len() == 8 && id.bytes().all(|b| b.is_ascii_hexdigit()) => {
+            (Some(id), value)
+        }
+        _ => (None, data),
+    }
+}
+
+pub fn secret_key_state(data: &str) -> SecretKeyState {
+    match split_key_id(data) {
+        (Some(id), _) if id == MASTER_KEY_ID.as_str() => SecretKeyState::Current,
+        (Some(id), _) if PREVIOUS_MASTER_KEY_ID.as_deref() == Some(id) => SecretKeyState::Previous,
+        (Some(_), _) => SecretKeyState::Unknown,
+        (None, _) => SecretKeyState::Legacy,
+    }
+}
+
+/// Encrypts a secret with the master key, prefixed with the key id.
 pub fn encrypt_string(plaintext: &str) -> BichonResult<String> {
-    internal_encrypt_string(&SETTINGS.bichon_encrypt_password, plaintext)
-        .map_err(|_| raise_error!("Failed to encrypt string.".into(), ErrorCode::InternalError))
+    let encrypted = encrypt_string_with(&SETTINGS.bichon_encrypt_password, plaintext)?;
+    Ok(format!("{}:{}", *MASTER_KEY_ID, encrypted))
 }
 
+/// Decrypts a secret with the master key it was encrypted with, the previous one included
+/// while a rotation is pending.
 pub fn decrypt_string(data: &str) -> BichonResult<String> {
-    internal_decrypt_string(&SETTINGS.bichon_encrypt_password, data).map_err(|_| {
+    let (id, value) = split_key_id(data);
+    let current = SETTINGS.bichon_encrypt_password.as_str();
+    let previous = SETTINGS.bichon_previous_encrypt_password.as_deref();
+    match (secret_key_state(data), previous) {
+        (SecretKeyState::Current, _) => decrypt_string_with(current, value),
+        (SecretKeyState::Previous, Some(previous)) => decrypt_string_with(previous, value),
+        (SecretKeyState::Legacy, Some(previous)) => decrypt_string_with(current, value)
+            .or_else(|_| decrypt_string_with(previous, value)),
+        (SecretKeyState::Legacy, None) => decrypt_string_with(current, value),
+        _ => Err(raise_error!(
+            format!(
+                "Secret encrypted with unknown master key {}, set bichon_previous_encrypt_password to the password it was encrypted with",
+                id.unwrap_or_default()
+            ),
+            ErrorCode::InternalError
+        )),
+    }
+}
+
+/// Encrypts a secret again with the current master key, or returns `None` if it already is.
+pub fn reencrypt_string(data: &str) -> BichonResult<Option<String>> {
+    if secret_key_state(data) == SecretKeyState::Current {
+        return Ok(None);
+    }
+    encrypt_string(&decrypt_string(data)?).map(Some)
+}
+
+pub fn encrypt_string_with(password: &str, plaintext: &str) -> BichonResult<String> {
+    internal_encrypt_string(password, plaintext)
+        .map_err(|_| raise_error!("Failed to encrypt string.".into(), ErrorCode::InternalError))
+}
+
+pub fn decrypt_string_with(password: &str, data: &str) -> BichonResult<String> {
+    internal_decrypt_string(password, data).map_err(|_| {
         raise_error!(
             "Decryption failed, likely due to incorrect encryption key or corrupted data".into(),
             ErrorCode::InternalError
@@ -56,6 +159,55 @@ pub fn decrypt_string(data: &str) -> BichonResult<String> {
     })
 }
 
+/// Encrypts `data` with a 256-bit key, as a random nonce followed by the ciphertext and
+/// its tag.
+pub fn encrypt_bytes(key: &[u8; 32], data: &[u8]) -> BichonResult<Vec<u8>> {
+    internal_encrypt_bytes(key, data)
+        .map_err(|_| raise_error!("Failed to encrypt data.".into(), ErrorCode::InternalError))
+}
+
+pub fn decrypt_bytes(key: &[u8; 32], data: &[u8]) -> BichonResult<Vec<u8>> {
+    internal_decrypt_bytes(key, data).map_err(|_| {
+        raise_error!(
+            "Decryption failed, likely due to incorrect data key or corrupted data".into(),
+            ErrorCode::InternalError
+        )
+    })
+}
+
+fn internal_encrypt_bytes(
+    key: &[u8; 32],
+    data: &[u8],
+) -> Result<Vec<u8>, ring::error::Unspecified> {
+    let mut nonce_bytes = [0u8; 12];
+    SystemRandom::new().fill(&mut nonce_bytes)?;
+    let unbound_key = ring::aead::UnboundKey::new(&AES_256_GCM, key)?;
+    let mut sealing_key = SealingKey::new(unbound_key, SingleNonceSequence::new(nonce_bytes));
+    let mut in_out = data.to_vec();
+    sealing_key.seal_in_place_append_tag(Aad::empty(), &mut in_out)?;
+    let mut result = Vec::with_capacity(12 + in_out.len());
+    result.extend_from_slice(&nonce_bytes);
+    result.extend_from_slice(&in_out);
+    Ok(result)
+}
+
+fn internal_decrypt_bytes(
+    key: &[u8; 32],
+    data: &[u8],
+) -> Result<Vec<u8>, ring::error::Unspecified> {
+    if data.len() < 12 {
+        return Err(ring::error::Unspecified);
+    }
+    let nonce_bytes: [u8; 12] = data[0..12]
+        .try_into()
+        .map_err(|_| ring::error::Unspecified)?;
+    let unbound_key = ring::aead::UnboundKey::new(&AES_256_GCM, key)?;
+    let mut opening_key = OpeningKey::new(unbound_key, SingleNonceSequence::new(nonce_bytes));
+    let mut in_out = data[12..].to_vec();
+    let decrypted = opening_key.open_in_place(Aad::empty(), &mut in_out)?;
+    Ok(decrypted.to_vec())
+}
+
 fn internal_encrypt_string(
     password: &str,
     plaintext: &str,
@@ -128,4 +280,23 @@ mod tests {
         let decrypted = internal_decrypt_string(password, &encrypted).unwrap();
         assert_eq!(decrypted, plaintext);
     }
+
+    #[test]
+    fn test_split_key_id() {
+        assert_eq!(split_key_id("0a1b2c3d:abc="), (Some("0a1b2c3d"), "abc="));
+        assert_eq!(split_key_id("abc_-="), (None, "abc_-="));
+        assert_eq!(split_key_id("zz:abc"), (None, "zz:abc"));
+        assert_eq!(master_key_id("secret").len(), 8);
+        assert_ne!(master_key_id("secret"), master_key_id("other"));
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_bytes() {
+        let key = [7u8; 32];
+        let data = b"From: a@example.com\r\n\r\nbody";
+        let encrypted = encrypt_bytes(&key, data).unwrap();
+        assert_ne!(&encrypted[12..], &data[..]);
+        assert_eq!(decrypt_bytes(&key, &encrypted).unwrap(), data);
+        assert!(decrypt_bytes(&[8u8; 32], &encrypted).is_err());
+    }
 }
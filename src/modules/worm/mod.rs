@@ -0,0 +1,493 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{LazyLock, RwLock},
+};
+
+use itertools::Itertools;
+use native_db::*;
+use native_model::{native_model, Model};
+use poem_openapi::{Enum, Object};
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::{
+    modules::{
+        context::Initialize,
+        database::manager::DB_MANAGER,
+        error::{code::ErrorCode, BichonResult},
+        indexer::{
+            fields::{F_ACCOUNT_ID, F_ID},
+            manager::EML_INDEX_MANAGER,
+        },
+        settings::{cli::SETTINGS, system::SystemSetting},
+    },
+    raise_error, utc_now,
+};
+
+/// System setting holding the last entry of the ledger, so that entries removed from its
+/// end are detected.
+const LEDGER_HEAD_SETTING: &str = "worm_ledger_head";
+/// Hash the first entry of the ledger is chained to.
+const GENESIS_HASH: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+/// Ledger entries read at once by a verification.
+const VERIFY_CHUNK_SIZE: usize = 10_000;
+/// Mismatches listed in a verification report, others are only counted.
+const MAX_REPORTED_MISMATCHES: usize = 1000;
+
+pub static WORM_LEDGER: LazyLock<WormLedger> = LazyLock::new(WormLedger::new);
+pub static WORM_VERIFICATION: LazyLock<WormVerification> = LazyLock::new(WormVerification::new);
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Enum)]
+pub enum LedgerAction {
+    /// The message was stored.
+    #[default]
+    Ingest,
+    /// The message was deleted on expiry of its retention rule.
+    Purge,
+}
+
+/// An entry of the append-only ledger of the WORM archive. Each entry is hashed along with
+/// the hash of the previous one, so that altering, removing or reordering entries breaks
+/// the chain.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+#[native_model(id = 14, version = 1)]
+#[native_db]
+pub struct LedgerEntry {
+    #[primary_key]
+    pub seq: u64,
+    pub action: LedgerAction,
+    pub account_id: u64,
+    pub eid: u64,
+    /// SHA-256 of the stored message, hex encoded; empty for a purge.
+    pub content_hash: String,
+    /// When the entry was recorded, represented as milliseconds since the Unix epoch.
+    pub recorded_at: i64,
+    pub prev_hash: String,
+    pub entry_hash: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct LedgerHead {
+    seq: u64,
+    hash: String,
+}
+
+impl Default for LedgerHead {
+    fn default() -> Self {
+        Self {
+            seq: 0,
+            hash: GENESIS_HASH.to_string(),
+        }
+    }
+}
+
+impl LedgerEntry {
+    fn compute_hash(&self) -> String {
+        let input = format!(
+            "{}|{}|{:?}|{}|{}|{}|{}",
+            self.prev_hash,
+            self.seq,
+            self.action,
+            self.account_id,
+            self.eid,
+            self.content_hash,
+            self.recorded_at
+        );
+        hex::encode(digest::digest(&digest::SHA256, input.as_bytes()))
+    }
+}
+
+/// Fails in WORM mode, where stored messages can only be deleted on retention expiry.
+pub fn ensure_writable(what: &str) -> BichonResult<()> {
+    if SETTINGS.bichon_worm_mode {
+        return Err(raise_error!(
+            format!("The archive is in WORM mode, {} cannot be deleted", what),
+            ErrorCode::ArchiveImmutable
+        ));
+    }
+    Ok(())
+}
+
+/// Appends entries to the ledger of the WORM archive.
+pub struct WormLedger {
+    head: Mutex<LedgerHead>,
+}
+
+impl Initialize for WormLedger {
+    async fn initialize() -> BichonResult<()> {
+        let head = read_head()?;
+        if SETTINGS.bichon_worm_mode {
+            tracing::info!(
+                "WORM mode enabled, ledger at entry {} ({})",
+                head.seq,
+                head.hash
+            );
+        }
+        *WORM_LEDGER.head.lock().await = head;
+        Ok(())
+    }
+}
+
+fn read_head() -> BichonResult<LedgerHead> {
+    match SystemSetting::get_existing_value(LEDGER_HEAD_SETTING)? {
+        Some(value) => serde_json::from_str(&value)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError)),
+        None => Ok(LedgerHead::default()),
+    }
+}
+
+impl WormLedger {
+    fn new() -> Self {
+        Self {
+            head: Mutex::new(LedgerHead::default()),
+        }
+    }
+
+    /// Records `(action, account_id, eid, content_hash)` entries, in one transaction
+    /// along with the new head of the ledger.
+    pub async fn append(&self, records: Vec<(LedgerAction, u64, u64, String)>) -> BichonResult<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        let mut head = self.head.lock().await;
+        let recorded_at = utc_now!();
+        let mut next = head.clone();
+        let mut entries = Vec::with_capacity(records.len());
+        for (action, account_id, eid, content_hash) in records {
+            let mut entry = LedgerEntry {
+                seq: next.seq + 1,
+                action,
+                account_id,
+                eid,
+                content_hash,
+                recorded_at,
+                prev_hash: next.hash.clone(),
+                entry_hash: String::new(),
+            };
+            entry.entry_hash = entry.compute_hash();
+            next = LedgerHead {
+                seq: entry.seq,
+                hash: entry.entry_hash.clone(),
+            };
+            entries.push(entry);
+        }
+        let value = serde_json::to_string(&next)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        tokio::task::spawn_blocking(move || {
+            let rw = DB_MANAGER
+                .meta_db()
+                .rw_transaction()
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            for entry in entries {
+                rw.insert(entry)
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            }
+            rw.upsert(SystemSetting::new(LEDGER_HEAD_SETTING.to_string(), value))
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+            rw.commit()
+                .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))
+        })
+        .await
+        .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))??;
+        *head = next;
+        Ok(())
+    }
+}
+
+/// A ledger entry, or a message it records, that does not match the archive.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct WormMismatch {
+    /// `0` for a stored message the ledger does not record.
+    pub seq: u64,
+    pub account_id: u64,
+    pub eid: u64,
+    pub reason: String,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize, Object)]
+pub struct WormVerificationStatus {
+    /// Whether the archive is in WORM mode, see `bichon_worm_mode`.
+    pub enabled: bool,
+    pub running: bool,
+    /// Ledger entries checked so far.
+    pub entries: u64,
+    /// Stored messages whose hash was recomputed so far.
+    pub messages: u64,
+    /// Messages recorded as purged on retention expiry.
+    pub purged: u64,
+    pub mismatch_count: u64,
+    /// The first mismatches found.
+    pub mismatches: Vec<WormMismatch>,
+    pub started_at: Option<i64>,
+    pub finished_at: Option<i64>,
+    /// Reason the last verification stopped, if it did not complete.
+    pub error: Option<String>,
+}
+
+/// Checks in the background that the ledger is unbroken, that every message it records
+/// is still stored with the same SHA-256 and that every stored message is recorded.
+pub struct WormVerification {
+    status: RwLock<WormVerificationStatus>,
+}
+
+impl WormVerification {
+    fn new() -> Self {
+        Self {
+            status: RwLock::new(WormVerificationStatus::default()),
+        }
+    }
+
+    pub fn status(&self) -> WormVerificationStatus {
+        let mut status = self.status.read().unwrap().clone();
+        status.enabled = SETTINGS.bichon_worm_mode;
+        status
+    }
+
+    /// Starts a verification. Fails if one is already running.
+    pub fn start(&'static self) -> BichonResult<()> {
+        {
+            let mut status = self.status.write().unwrap();
+            if status.running {
+                return Err(raise_error!(
+                    "The archive is already being verified.".into(),
+                    ErrorCode::InvalidParameter
+                ));
+            }
+            *status = WormVerificationStatus {
+                running: true,
+                started_at: Some(utc_now!()),
+                ..Default::default()
+            };
+        }
+        tokio::spawn(async move {
+            let result = self.run().await;
+            let mut status = self.status.write().unwrap();
+            match result {
+                Ok(()) => tracing::info!(
+                    "Archive verification completed, {} mismatches",
+                    status.mismatch_count
+                ),
+                Err(e) => {
+                    tracing::error!("Archive verification failed: {:#?}", e);
+                    status.error = Some(format!("{:#?}", e));
+                }
+            }
+            status.running = false;
+            status.finished_at = Some(utc_now!());
+        });
+        Ok(())
+    }
+
+    fn mismatch(&self, seq: u64, account_id: u64, eid: u64, reason: String) {
+        tracing::warn!(
+            "Archive verification: entry {} (account {}, message {}): {}",
+            seq,
+            account_id,
+            eid,
+            reason
+        );
+        let mut status = self.status.write().unwrap();
+        status.mismatch_count += 1;
+        if status.mismatches.len() < MAX_REPORTED_MISMATCHES {
+            status.mismatches.push(WormMismatch {
+                seq,
+                account_id,
+                eid,
+                reason,
+            });
+        }
+    }
+
+    async fn run(&self) -> BichonResult<()> {
+        // read before the ledger: a message is recorded before it is committed, so every
+        // message seen here has its entry among those read below
+        let committed = committed_messages()?;
+        // latest entry of every message still recorded as stored
+        let mut stored: HashMap<(u64, u64), (u64, String)> = HashMap::new();
+        // every message recorded as stored, even if purged since
+        let mut recorded: HashSet<(u64, u64)> = HashSet::new();
+        let mut expected = LedgerHead::default();
+        loop {
+            let from = expected.seq + 1;
+            let entries: Vec<LedgerEntry> = tokio::task::spawn_blocking(move || {
+                let r = DB_MANAGER
+                    .meta_db()
+                    .r_transaction()
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+                r.scan()
+                    .primary()
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                    .range(from..)
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+                    .take(VERIFY_CHUNK_SIZE)
+                    .try_collect()
+                    .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))
+            })
+            .await
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))??;
+            if entries.is_empty() {
+                break;
+            }
+            for entry in entries {
+                if entry.seq != expected.seq + 1 {
+                    self.mismatch(
+                        entry.seq,
+                        entry.account_id,
+                        entry.eid,
+                        format!(
+                            "entries {} to {} are missing",
+                            expected.seq + 1,
+                            entry.seq - 1
+                        ),
+                    );
+                }
+                if entry.prev_hash != expected.hash {
+                    self.mismatch(
+                        entry.seq,
+                        entry.account_id,
+                        entry.eid,
+                        "not chained to the previous entry".into(),
+                    );
+                }
+                if entry.compute_hash() != entry.entry_hash {
+                    self.mismatch(
+                        entry.seq,
+                        entry.account_id,
+                        entry.eid,
+                        "entry altered".into(),
+                    );
+                }
+                let key = (entry.account_id, entry.eid);
+                match entry.action {
+                    LedgerAction::Ingest => {
+                        stored.insert(key, (entry.seq, entry.content_hash.clone()));
+                        recorded.insert(key);
+                    }
+                    LedgerAction::Purge => {
+                        // a purge is recorded before the message is removed, one still
+                        // stored after a failed purge is not checked but not an issue
+                        stored.remove(&key);
+                        self.status.write().unwrap().purged += 1;
+                    }
+                }
+                expected = LedgerHead {
+                    seq: entry.seq,
+                    hash: entry.entry_hash,
+                };
+                self.status.write().unwrap().entries += 1;
+            }
+        }
+        let head = read_head()?;
+        if head.seq != expected.seq || head.hash != expected.hash {
+            self.mismatch(
+                head.seq,
+                0,
+                0,
+                format!(
+                    "the ledger ends at entry {} but its head is entry {}",
+                    expected.seq, head.seq
+                ),
+            );
+        }
+
+        for ((account_id, eid), (seq, content_hash)) in
+            stored.into_iter().sorted_by_key(|(_, (seq, _))| *seq)
+        {
+            match EML_INDEX_MANAGER.get(account_id, eid).await {
+                Ok(Some(eml)) => {
+                    let hash = hex::encode(digest::digest(&digest::SHA256, &eml));
+                    if hash != content_hash {
+                        self.mismatch(
+                            seq,
+                            account_id,
+                            eid,
+                            format!("stored content hashes to {}", hash),
+                        );
+                    }
+                }
+                Ok(None) => self.mismatch(seq, account_id, eid, "message missing".into()),
+                Err(e) => self.mismatch(seq, account_id, eid, format!("{:#?}", e)),
+            }
+            self.status.write().unwrap().messages += 1;
+        }
+
+        for (account_id, eid) in committed
+            .into_iter()
+            .filter(|key| !recorded.contains(key))
+            .sorted()
+        {
+            self.mismatch(0, account_id, eid, "stored without a ledger entry".into());
+        }
+        Ok(())
+    }
+}
+
+/// Account and envelope id of every message in the EML store, segments holding only
+/// message bodies have no ids.
+fn committed_messages() -> BichonResult<HashSet<(u64, u64)>> {
+    let mut committed = HashSet::new();
+    let searcher = EML_INDEX_MANAGER.searcher();
+    for segment_reader in searcher.segment_readers() {
+        let fast_fields = segment_reader.fast_fields();
+        let Some(ids) = fast_fields
+            .column_opt::<u64>(F_ID)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?
+        else {
+            continue;
+        };
+        let accounts = fast_fields
+            .u64(F_ACCOUNT_ID)
+            .map_err(|e| raise_error!(format!("{:#?}", e), ErrorCode::InternalError))?;
+        for doc_id in segment_reader.doc_ids_alive() {
+            if let (Some(eid), Some(account_id)) = (ids.first(doc_id), accounts.first(doc_id)) {
+                committed.insert((account_id, eid));
+            }
+        }
+    }
+    Ok(committed)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_entry_hash_covers_fields() {
+        let mut entry = LedgerEntry {
+            seq: 1,
+            account_id: 7,
+            eid: 42,
+            content_hash: "ab".into(),
+            recorded_at: 1,
+            prev_hash: GENESIS_HASH.into(),
+            ..Default::default()
+        };
+        let hash = entry.compute_hash();
+        assert_eq!(hash.len(), 64);
+        entry.action = LedgerAction::Purge;
+        assert_ne!(entry.compute_hash(), hash);
+        entry.action = LedgerAction::Ingest;
+        entry.prev_hash = hash.clone();
+        assert_ne!(entry.compute_hash(), hash);
+    }
+}
@@ -0,0 +1,93 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+import axiosInstance from "@/api/axiosInstance";
+
+export interface AlertChannels {
+    webhook?: string;
+    email?: string[];
+    in_app: boolean;
+}
+
+export interface SearchAlert {
+    id: number;
+    name: string;
+    filter: Record<string, any>;
+    channels: AlertChannels;
+    enabled: boolean;
+    created_at: number;
+    updated_at: number;
+}
+
+export interface AlertMatch {
+    id: number;
+    account_id: number;
+    mailbox_id: number;
+    subject: string;
+    from: string;
+    internal_date: number;
+}
+
+export interface AlertEvent {
+    alert_id: number;
+    alert_name: string;
+    matches: AlertMatch[];
+    triggered_at: number;
+}
+
+export interface AlertNotification {
+    id: number;
+    event: AlertEvent;
+    read: boolean;
+}
+
+export const list_search_alerts = async () => {
+    const response = await axiosInstance.get<SearchAlert[]>("/api/v1/list-search-alerts");
+    return response.data;
+};
+
+export const create_search_alert = async (data: Record<string, any>) => {
+    const response = await axiosInstance.post<SearchAlert>("/api/v1/search-alert", data);
+    return response.data;
+};
+
+export const update_search_alert = async (id: number, data: Record<string, any>) => {
+    const response = await axiosInstance.post(`/api/v1/search-alert/${id}`, data);
+    return response.data;
+};
+
+export const delete_search_alert = async (id: number) => {
+    const response = await axiosInstance.delete(`/api/v1/search-alert/${id}`);
+    return response.data;
+};
+
+export const list_alert_notifications = async () => {
+    const response = await axiosInstance.get<AlertNotification[]>("/api/v1/alert-notifications");
+    return response.data;
+};
+
+export const mark_alert_notification_read = async (id: number) => {
+    const response = await axiosInstance.post(`/api/v1/alert-notification/${id}/read`);
+    return response.data;
+};
+
+export const clear_alert_notifications = async () => {
+    const response = await axiosInstance.delete("/api/v1/alert-notifications");
+    return response.data;
+};
@@ -43,4 +43,14 @@ export interface EmailEnvelope {
   thread_id: number,
   attachments: string[];
   tags: string[];
+  language?: string;
+  headers?: Record<string, string[]>;
+  highlights?: SearchHighlights;
+  matched_attachments?: string[];
+}
+
+export interface SearchHighlights {
+  subject?: string;
+  text?: string;
+  attachment?: string;
 }
\ No newline at end of file
@@ -87,6 +87,11 @@ export const load_message = async (accountId: number, id: number) => {
     return response.data;
 };
 
+export const load_message_headers = async (accountId: number, id: number) => {
+    const response = await axiosInstance.get<string>(`/api/v1/message-headers/${accountId}?id=${id}`, { responseType: 'text' });
+    return response.data;
+};
+
 export const delete_messages = async (payload: Record<string, number[]>) => {
     const response = await axiosInstance.post("/api/v1/delete-messages", payload);
     return response.data;
@@ -41,3 +41,73 @@ export const update_tags = async (data: Record<string, any>) => {
 };
 
 
+
+export interface SavedSearch {
+    id: number;
+    name: string;
+    filter: Record<string, any>;
+    sort_by?: string;
+    sort_order?: string;
+    created_at: number;
+    updated_at: number;
+    last_viewed_at?: number;
+}
+
+export interface SavedSearchFolder {
+    search: SavedSearch;
+    unread: number;
+}
+
+export const list_saved_searches = async () => {
+    const response = await axiosInstance.get<SavedSearchFolder[]>("/api/v1/list-saved-searches");
+    return response.data;
+};
+
+export const create_saved_search = async (data: Record<string, any>) => {
+    const response = await axiosInstance.post<SavedSearch>("/api/v1/saved-search", data);
+    return response.data;
+};
+
+export const delete_saved_search = async (id: number) => {
+    const response = await axiosInstance.delete(`/api/v1/saved-search/${id}`);
+    return response.data;
+};
+
+export const execute_saved_search = async (id: number, page: number, page_size: number) => {
+    const params = new URLSearchParams({
+        page: String(page),
+        page_size: String(page_size),
+    });
+
+    const response = await axiosInstance.post<PaginatedResponse<EmailEnvelope>>(
+        `/api/v1/saved-search/${id}/execute?${params.toString()}`
+    );
+    return response.data;
+};
+
+export interface SimilarEnvelope {
+    score: number;
+    envelope: EmailEnvelope;
+}
+
+export const similar_messages = async (accountId: number, id: number, limit?: number) => {
+    const params = new URLSearchParams({ id: String(id) });
+    if (limit) {
+        params.set("limit", String(limit));
+    }
+    const response = await axiosInstance.get<SimilarEnvelope[]>(
+        `/api/v1/similar-messages/${accountId}?${params.toString()}`
+    );
+    return response.data;
+};
+
+export interface NearDuplicateResult {
+    total_items: number;
+    scanned: number;
+    groups: { envelopes: EmailEnvelope[] }[];
+}
+
+export const find_near_duplicates = async (payload: Record<string, any>) => {
+    const response = await axiosInstance.post<NearDuplicateResult>("/api/v1/near-duplicates", payload);
+    return response.data;
+};
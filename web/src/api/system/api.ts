@@ -47,6 +47,7 @@ export interface DashboardStats {
     email_count: number;                   // Total number of emails
     total_size_bytes: number;              // Total size of all emails (in bytes)
     storage_usage_bytes: number;           // Actual storage used (in bytes)
+    deduplicated_bytes: number;            // Bytes saved by deduplication
     index_usage_bytes: number;             // Index storage size (in bytes)
     recent_activity: TimeBucket[];        // Email activity over recent days
     top_senders: Group[];            // Top 10 senders
@@ -54,6 +55,8 @@ export interface DashboardStats {
     with_attachment_count: number;         // Emails with attachments
     without_attachment_count: number;      // Emails without attachments
     top_largest_emails: LargestEmail[];    // Top 10 largest emails
+    attachment_deduplicated_bytes: number; // Part of the deduplication savings on attachments
+    top_deduplicated_attachments: DeduplicatedAttachment[]; // Top 10 attachment savings
     system_version: string, //The semantic version string of the currently running backend service
     commit_hash: string //Git commit hash used to build this system version
 }
@@ -73,11 +76,42 @@ export interface LargestEmail {
     size_bytes: number;     // Email size in bytes
 }
 
+export interface DeduplicatedAttachment {
+    name: string;           // File name the attachment was first stored with
+    size_bytes: number;     // Decoded attachment size in bytes
+    copies: number;         // Number of distinct stored emails containing it
+    saved_bytes: number;    // Bytes saved by storing it once
+}
+
 export const get_dashboard_stats = async () => {
     const response = await axiosInstance.get<DashboardStats>(`/api/v1/dashboard-stats`);
     return response.data;
 };
 
+export interface ReindexStatus {
+    schema_version: number;        // Envelope schema version of the running server
+    index_schema_version: number;  // Schema version the envelope index was built with (0 if unknown)
+    outdated: boolean;             // Whether the envelope index should be rebuilt
+    incompatible: boolean;         // Whether search returns nothing until the rebuild completes
+    running: boolean;
+    total: number;                 // Messages in the EML store when the rebuild started
+    processed: number;
+    failed: number;
+    started_at?: number;
+    finished_at?: number;
+    error?: string;
+}
+
+export const get_reindex_status = async () => {
+    const response = await axiosInstance.get<ReindexStatus>(`/api/v1/reindex-status`);
+    return response.data;
+};
+
+export const start_reindex = async () => {
+    const response = await axiosInstance.post(`/api/v1/reindex`);
+    return response.data;
+};
+
 export const list_proxy = async () => {
     const response = await axiosInstance.get<Proxy[]>(`/api/v1/list-proxy`);
     return response.data;
@@ -26,8 +26,9 @@ import {
   PopoverTrigger,
 } from "@/components/ui/popover";
 import { ScrollArea } from "@/components/ui/scroll-area";
-import { useQuery } from "@tanstack/react-query";
+import { useMutation, useQuery, useQueryClient } from "@tanstack/react-query";
 import { get_notifications } from "@/api/system/api";
+import { AlertEvent, list_alert_notifications, mark_alert_notification_read } from "@/api/alert/api";
 import ReactMarkdown from 'react-markdown';
 import remarkGfm from 'remark-gfm';
 import { useMemo } from "react";
@@ -48,7 +49,13 @@ interface ReleaseNotification extends BaseNotification {
   data: Release;
 }
 
-type ActiveNotification = ReleaseNotification;
+interface SearchAlertNotification extends BaseNotification {
+  type: 'search-alert';
+  id: number;
+  data: AlertEvent;
+}
+
+type ActiveNotification = ReleaseNotification | SearchAlertNotification;
 
 
 export function NotificationPopover() {
@@ -58,12 +65,33 @@ export function NotificationPopover() {
     staleTime: 1000 * 60 * 30, // 30 minutes
   });
 
-  const activeNotifications = useMemo((): ActiveNotification[] => {
-    if (!data) return [];
+  const queryClient = useQueryClient();
+  const { data: alertNotifications } = useQuery({
+    queryKey: ['alert-notifications'],
+    queryFn: list_alert_notifications,
+    refetchInterval: 1000 * 60, // 1 minute
+    retry: false,
+  });
 
+  const markReadMutation = useMutation({
+    mutationFn: mark_alert_notification_read,
+    onSuccess: () => queryClient.invalidateQueries({ queryKey: ['alert-notifications'] }),
+  });
+
+  const activeNotifications = useMemo((): ActiveNotification[] => {
     const notifications: ActiveNotification[] = [];
 
-    if (data.release.is_newer && data.release.latest) {
+    for (const notification of alertNotifications ?? []) {
+      if (!notification.read) {
+        notifications.push({
+          type: 'search-alert',
+          id: notification.id,
+          data: notification.event
+        });
+      }
+    }
+
+    if (data?.release.is_newer && data.release.latest) {
       notifications.push({
         type: 'new-release',
         data: {
@@ -75,7 +103,7 @@ export function NotificationPopover() {
       });
     }
     return notifications;
-  }, [data]);
+  }, [data, alertNotifications]);
 
   const showNotificationBadge = activeNotifications.length > 0;
 
@@ -128,7 +156,14 @@ export function NotificationPopover() {
             <div className="divide-y">
               {activeNotifications.map((notification, index) => (
                 <div key={index} className="p-4">
-                  <ReleaseNotificationView data={notification.data} />
+                  {notification.type === 'new-release' ? (
+                    <ReleaseNotificationView data={notification.data} />
+                  ) : (
+                    <SearchAlertNotificationView
+                      data={notification.data}
+                      onRead={() => markReadMutation.mutate(notification.id)}
+                    />
+                  )}
                 </div>
               ))}
             </div>
@@ -176,4 +211,35 @@ function ReleaseNotificationView({ data }: { data: Release }) {
       )}
     </div>
   );
-}
\ No newline at end of file
+}
+
+function SearchAlertNotificationView({ data, onRead }: { data: AlertEvent; onRead: () => void }) {
+  return (
+    <div className="space-y-2">
+      <div className="flex items-center justify-between">
+        <h3 className="text-sm font-semibold truncate" title={data.alert_name}>
+          {data.alert_name}
+        </h3>
+        <span className="text-xs bg-blue-100 text-blue-800 px-2 py-1 rounded-full">
+          {data.matches.length} new
+        </span>
+      </div>
+      <p className="text-xs text-muted-foreground">
+        {new Date(data.triggered_at).toLocaleString()}
+      </p>
+      <ul className="space-y-1 text-sm">
+        {data.matches.slice(0, 5).map((m) => (
+          <li key={m.id} className="truncate" title={m.subject}>
+            <span className="text-muted-foreground">{m.from}</span> · {m.subject}
+          </li>
+        ))}
+        {data.matches.length > 5 && (
+          <li className="text-xs text-muted-foreground">+{data.matches.length - 5} more</li>
+        )}
+      </ul>
+      <Button variant="outline" size="sm" onClick={onRead}>
+        Mark as read
+      </Button>
+    </div>
+  );
+}
@@ -118,6 +118,7 @@ export default function MailArchiveDashboard() {
   const hasTopSenders = stats?.top_senders && stats.top_senders.length > 0;
   const hasTopEmails = stats?.top_largest_emails && stats.top_largest_emails.length > 0;
   const hasTopAccounts = stats?.top_accounts && stats.top_accounts.length > 0;
+  const hasDeduplicatedAttachments = stats?.top_deduplicated_attachments && stats.top_deduplicated_attachments.length > 0;
 
   const attachmentData = totalAttachments > 0
     ? [
@@ -214,7 +215,11 @@ export default function MailArchiveDashboard() {
               </CardHeader>
               <CardContent>
                 <div className="text-2xl font-bold">{formatBytes(stats!.storage_usage_bytes)}</div>
-                <p className="text-xs text-muted-foreground">{t('dashboard.actualDiskUsage')}</p>
+                <p className="text-xs text-muted-foreground">
+                  {stats!.deduplicated_bytes > 0
+                    ? t('dashboard.deduplicatedSavings', { size: formatBytes(stats!.deduplicated_bytes) })
+                    : t('dashboard.actualDiskUsage')}
+                </p>
               </CardContent>
             </Card>
 
@@ -454,6 +459,40 @@ export default function MailArchiveDashboard() {
                     )}
                   </CardContent>
                 </Card>
+                <Card className="md:col-span-1">
+                  <CardHeader>
+                    <CardTitle>{t('dashboard.top10DeduplicatedAttachments')}</CardTitle>
+                    <CardDescription>
+                      {t('dashboard.attachmentSavings', { size: formatBytes(stats!.attachment_deduplicated_bytes) })}
+                    </CardDescription>
+                  </CardHeader>
+                  <CardContent>
+                    {hasDeduplicatedAttachments ? (
+                      <Table>
+                        <TableHeader>
+                          <TableRow>
+                            <TableHead>{t('dashboard.attachment')}</TableHead>
+                            <TableHead className="text-right">{t('dashboard.copies')}</TableHead>
+                            <TableHead className="text-right">{t('dashboard.saved')}</TableHead>
+                          </TableRow>
+                        </TableHeader>
+                        <TableBody>
+                          {stats!.top_deduplicated_attachments.map((a, index) => (
+                            <TableRow key={index}>
+                              <TableCell className="font-medium max-w-[160px] truncate" title={a.name}>
+                                {a.name || formatBytes(a.size_bytes)}
+                              </TableCell>
+                              <TableCell className="text-right">{formatNumber(a.copies)}</TableCell>
+                              <TableCell className="text-right">{formatBytes(a.saved_bytes)}</TableCell>
+                            </TableRow>
+                          ))}
+                        </TableBody>
+                      </Table>
+                    ) : (
+                      <EmptyTable title={t('dashboard.noDeduplicatedAttachments')} />
+                    )}
+                  </CardContent>
+                </Card>
               </div>
             </TabsContent>
           </Tabs>
@@ -35,6 +35,7 @@ import { ScrollArea } from '@/components/ui/scroll-area';
 import { Sheet, SheetContent, SheetHeader, SheetTitle, SheetTrigger } from '@/components/ui/sheet';
 import { Button } from '@/components/ui/button';
 import { EnvelopeTags } from './tag-facet';
+import { SavedSearches } from './saved-searches';
 import { EditTagsDialog } from './add-tag-dialog';
 import { useTranslation } from 'react-i18next';
 
@@ -58,7 +59,9 @@ export default function Search() {
     setPageSize,
     onSubmit,
     reset,
-    filter
+    filter,
+    savedSearch,
+    openSavedSearch
   } = useSearchMessages();
 
   const handleSetPageSize = (pageSize: number) => {
@@ -110,7 +113,14 @@ export default function Search() {
             </div>
 
             <div className="flex gap-6">
-              <aside className="hidden lg:block w-64 flex-shrink-0">
+              <aside className="hidden lg:block w-64 flex-shrink-0 space-y-4">
+                <div className="rounded-lg border bg-card p-4">
+                  <SavedSearches
+                    filter={filter}
+                    current={savedSearch}
+                    onOpen={openSavedSearch}
+                  />
+                </div>
                 <div className="rounded-lg border bg-card p-4">
                   <EnvelopeTags
                     selectedTags={selectedTags}
@@ -0,0 +1,151 @@
+//
+// Copyright (c) 2025 rustmailer.com (https://rustmailer.com)
+//
+// This file is part of the Bichon Email Archiving Project
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU Affero General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU Affero General Public License for more details.
+//
+// You should have received a copy of the GNU Affero General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+import { Badge } from '@/components/ui/badge';
+import { Button } from '@/components/ui/button';
+import { Input } from '@/components/ui/input';
+import { Collapsible, CollapsibleContent, CollapsibleTrigger } from '@/components/ui/collapsible';
+import { ChevronDown, ChevronUp, FolderSearch, Trash2 } from 'lucide-react';
+import React from 'react';
+import { useMutation, useQuery, useQueryClient } from '@tanstack/react-query';
+import {
+  create_saved_search,
+  delete_saved_search,
+  list_saved_searches,
+  SavedSearch,
+  SavedSearchFolder,
+} from '@/api/search/api';
+import { toast } from '@/hooks/use-toast';
+import { cn } from '@/lib/utils';
+import { useTranslation } from 'react-i18next';
+
+interface SavedSearchesProps {
+  filter: Record<string, any>;
+  current?: SavedSearch;
+  onOpen: (search: SavedSearch) => void;
+}
+
+export function SavedSearches({ filter, current, onOpen }: SavedSearchesProps) {
+  const { t } = useTranslation()
+  const queryClient = useQueryClient()
+  const [open, setOpen] = React.useState(true);
+  const [name, setName] = React.useState('');
+
+  const { data: folders = [], isLoading } = useQuery<SavedSearchFolder[]>({
+    queryKey: ['saved-searches'],
+    queryFn: list_saved_searches,
+    refetchInterval: 60 * 1000,
+    retry: false,
+  });
+
+  const onError = (error: any) => {
+    toast({
+      title: t('search.savedSearches.failedTitle'),
+      description: `${error.message}`,
+      variant: 'destructive',
+    })
+  }
+
+  const createMutation = useMutation({
+    mutationFn: create_saved_search,
+    retry: false,
+    onSuccess: () => {
+      queryClient.invalidateQueries({ queryKey: ['saved-searches'] })
+      setName('')
+      toast({ title: t('search.savedSearches.savedTitle') })
+    },
+    onError,
+  })
+
+  const deleteMutation = useMutation({
+    mutationFn: delete_saved_search,
+    retry: false,
+    onSuccess: () => {
+      queryClient.invalidateQueries({ queryKey: ['saved-searches'] })
+    },
+    onError,
+  })
+
+  const canSave = !current && Object.keys(filter).length > 0 && name.trim().length > 0;
+
+  return (
+    <Collapsible open={open} onOpenChange={setOpen} className="space-y-2">
+      <CollapsibleTrigger className="flex w-full items-center justify-between text-sm font-medium hover:text-primary transition-colors">
+        <div className="flex items-center gap-2">
+          <FolderSearch className="w-4 h-4" />
+          {t('search.savedSearches.title')}
+        </div>
+        {open ? <ChevronUp className="w-4 h-4" /> : <ChevronDown className="w-4 h-4" />}
+      </CollapsibleTrigger>
+
+      <CollapsibleContent className="space-y-1">
+        {!isLoading && folders.length === 0 && (
+          <p className="py-2 pl-2 text-sm text-muted-foreground">{t('search.savedSearches.none')}</p>
+        )}
+        {folders.map(({ search, unread }) => (
+          <div
+            key={search.id}
+            className={cn(
+              "flex items-center gap-2 px-2 py-0.5 hover:bg-accent/80 rounded-md transition-colors cursor-pointer group",
+              current?.id === search.id && "bg-accent"
+            )}
+            onClick={() => onOpen(search)}
+          >
+            <span className="flex-1 truncate text-sm font-medium" title={search.name}>
+              {search.name}
+            </span>
+            {unread > 0 && (
+              <Badge className="h-5 px-1.5 text-xs font-medium min-w-[1.75rem] text-center">
+                {unread}
+              </Badge>
+            )}
+            <Button
+              variant="ghost"
+              size="icon"
+              className="h-6 w-6 opacity-0 group-hover:opacity-100"
+              title={t('search.savedSearches.delete')}
+              onClick={(e) => {
+                e.stopPropagation();
+                deleteMutation.mutate(search.id);
+              }}
+            >
+              <Trash2 className="h-3.5 w-3.5" />
+            </Button>
+          </div>
+        ))}
+        <div className="flex items-center gap-2 pt-2">
+          <Input
+            className="h-8 text-sm"
+            placeholder={t('search.savedSearches.namePlaceholder')}
+            value={name}
+            maxLength={128}
+            onChange={(e) => setName(e.target.value)}
+          />
+          <Button
+            size="sm"
+            disabled={!canSave || createMutation.isPending}
+            onClick={() => createMutation.mutate({ name: name.trim(), filter })}
+          >
+            {t('search.savedSearches.save')}
+          </Button>
+        </div>
+      </CollapsibleContent>
+    </Collapsible>
+  );
+}
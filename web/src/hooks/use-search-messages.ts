@@ -18,17 +18,19 @@
 
 
 import { EmailEnvelope, PaginatedResponse } from '@/api';
-import { search_messages } from '@/api/search/api';
-import { useQuery } from '@tanstack/react-query';
+import { execute_saved_search, SavedSearch, search_messages } from '@/api/search/api';
+import { useQuery, useQueryClient } from '@tanstack/react-query';
 import { useState } from 'react';
 
 
 
 export function useSearchMessages() {
-    // const queryClient = useQueryClient();
+    const queryClient = useQueryClient();
     const [filter, setFilter] = useState<Record<string, any>>({});
     const [page, setPage] = useState(1);
     const [pageSize, setPageSize] = useState(30);
+    // set while a saved search is open, so that running it marks it as viewed
+    const [savedSearch, setSavedSearch] = useState<SavedSearch | undefined>(undefined);
 
     const onSubmit = (cleaned: Record<string, any>) => {
         if ('has_attachment' in cleaned && cleaned.has_attachment === false) {
@@ -45,11 +47,19 @@ export function useSearchMessages() {
         } else {
             setFilter({});
         }
+        setSavedSearch(undefined);
+    };
+
+    const openSavedSearch = (search: SavedSearch) => {
+        setSavedSearch(search);
+        setFilter(search.filter);
+        setPage(1);
     };
 
     const reset = () => {
         setFilter({});
         setPage(1);
+        setSavedSearch(undefined);
     }
 
     const {
@@ -59,13 +69,18 @@ export function useSearchMessages() {
         error,
         isFetching,
     } = useQuery<PaginatedResponse<EmailEnvelope>>({
-        queryKey: ['search-messages', filter, page, pageSize],
+        queryKey: ['search-messages', filter, page, pageSize, savedSearch?.id],
         queryFn: () =>
-            search_messages({
-                filter: filter,
-                page,
-                page_size: pageSize,
-            }),
+            savedSearch
+                ? execute_saved_search(savedSearch.id, page, pageSize).then((result) => {
+                    queryClient.invalidateQueries({ queryKey: ['saved-searches'] });
+                    return result;
+                })
+                : search_messages({
+                    filter: filter,
+                    page,
+                    page_size: pageSize,
+                }),
         staleTime: 1000,
         retry: false,
     });
@@ -84,6 +99,8 @@ export function useSearchMessages() {
         setPage,
         onSubmit,
         reset,
-        filter
+        filter,
+        savedSearch,
+        openSavedSearch
     };
 }
\ No newline at end of file